@@ -0,0 +1,236 @@
+//! Compression of the values of the tables setting [`TableSchema::VALUE_COMPRESSION`].
+//!
+//! Each value of such a table starts with a one-byte header naming how the rest is stored, so
+//! that the values written under another setting still read after it changes.
+
+use std::borrow::Cow;
+
+use super::{
+    serde::{Decode, Encode},
+    TableSchema,
+};
+use crate::errors::{DecResult, DecodeError};
+
+const HEADER_RAW: u8 = 0;
+const HEADER_SNAPPY: u8 = 1;
+const HEADER_ZSTD: u8 = 2;
+
+/// How the values of a table are compressed when written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Snappy,
+    /// Zstandard with the given level.
+    Zstd(i32),
+}
+
+impl Compression {
+    // `None` if the value is not worth compressing
+    fn compress(self, raw: &[u8]) -> Option<(u8, Vec<u8>)> {
+        let (header, compressed) = match self {
+            Compression::None => return None,
+            Compression::Snappy => (
+                HEADER_SNAPPY,
+                snap::raw::Encoder::new().compress_vec(raw).ok()?,
+            ),
+            Compression::Zstd(level) => (HEADER_ZSTD, zstd::encode_all(raw, level).ok()?),
+        };
+        (compressed.len() < raw.len()).then_some((header, compressed))
+    }
+}
+
+pub(crate) fn encode_value<T: TableSchema>(value: Cow<T::Value>) -> Vec<u8> {
+    let raw = <T::Value as Encode>::encode_cow(value);
+    let Some(compression) = T::VALUE_COMPRESSION else {
+        return raw.into_owned();
+    };
+
+    let (header, body) = match compression.compress(&raw) {
+        Some((header, compressed)) => (header, Cow::Owned(compressed)),
+        None => (HEADER_RAW, raw),
+    };
+    let mut output = Vec::with_capacity(body.len() + 1);
+    output.push(header);
+    output.extend_from_slice(&body);
+    output
+}
+
+pub(crate) fn decode_value<T: TableSchema>(input: &[u8]) -> DecResult<Cow<T::Value>> {
+    if T::VALUE_COMPRESSION.is_none() {
+        return <T::Value>::decode(input);
+    }
+
+    match decompress(input)? {
+        Cow::Borrowed(raw) => <T::Value>::decode(raw),
+        Cow::Owned(raw) => Ok(Cow::Owned(<T::Value>::decode_owned(raw)?)),
+    }
+}
+
+pub(crate) fn decode_value_owned<T: TableSchema>(
+    mut input: Vec<u8>,
+) -> DecResult<<T::Value as ToOwned>::Owned> {
+    if T::VALUE_COMPRESSION.is_none() {
+        return <T::Value>::decode_owned(input);
+    }
+
+    let raw = match decompress(&input)? {
+        Cow::Borrowed(_) => {
+            input.remove(0);
+            input
+        }
+        Cow::Owned(raw) => raw,
+    };
+    <T::Value>::decode_owned(raw)
+}
+
+fn decompress(input: &[u8]) -> DecResult<Cow<[u8]>> {
+    let (&header, body) = input.split_first().ok_or(DecodeError::TooShortHeader)?;
+    match header {
+        HEADER_RAW => Ok(Cow::Borrowed(body)),
+        HEADER_SNAPPY => snap::raw::Decoder::new()
+            .decompress_vec(body)
+            .map(Cow::Owned)
+            .map_err(|_| DecodeError::Custom("corrupted snappy value")),
+        HEADER_ZSTD => zstd::decode_all(body)
+            .map(Cow::Owned)
+            .map_err(|_| DecodeError::Custom("corrupted zstd value")),
+        _ => Err(DecodeError::Custom("unknown value compression")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::Compression;
+    use crate::backends::{
+        DatabaseTrait, InMemoryDatabase, InMemorySubkeyDatabase, TableName, TableRead, TableSchema,
+        WriteSchemaTrait,
+    };
+
+    macro_rules! table {
+        ($name:ident, $compression:expr) => {
+            #[derive(Clone, Copy)]
+            struct $name;
+            impl TableSchema for $name {
+                const NAME: TableName = TableName::CommitAlias;
+                const VALUE_COMPRESSION: Option<Compression> = $compression;
+                type Key = [u8];
+                type Value = [u8];
+            }
+        };
+    }
+
+    table!(Plain, None);
+    table!(Raw, Some(Compression::None));
+    table!(Snappy, Some(Compression::Snappy));
+    table!(Zstd, Some(Compression::Zstd(3)));
+
+    fn compressible() -> Vec<u8> {
+        b"0123456789abcdef".repeat(64)
+    }
+
+    fn write<D: DatabaseTrait, T: TableSchema<Key = [u8], Value = [u8]>>(
+        db: &mut D,
+        rows: &[(&[u8], &[u8])],
+    ) {
+        let write_schema = D::write_schema();
+        for (key, value) in rows {
+            write_schema.write::<T>((Cow::Borrowed(*key), Some(Cow::Borrowed(*value))));
+        }
+        db.commit(write_schema).unwrap();
+    }
+
+    fn read<D: DatabaseTrait, T: TableSchema<Key = [u8], Value = [u8]>>(
+        db: &D,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let table = db.view::<T>().unwrap();
+        let rows: Vec<_> = table
+            .iter_from_start()
+            .unwrap()
+            .map(|item| {
+                let (k, v) = item.unwrap();
+                (k.into_owned(), v.into_owned())
+            })
+            .collect();
+        for (key, value) in &rows {
+            assert_eq!(table.get(key).unwrap().unwrap().as_ref(), value.as_slice());
+        }
+        rows
+    }
+
+    fn stored_size<D: DatabaseTrait>(db: &D) -> u64 {
+        let sizes = db.approximate_sizes().unwrap();
+        sizes
+            .iter()
+            .find(|(name, _)| *name == TableName::CommitAlias)
+            .unwrap()
+            .1
+    }
+
+    fn check_round_trip<D: DatabaseTrait, T: TableSchema<Key = [u8], Value = [u8]>>(
+        mut db: D,
+    ) -> u64 {
+        let value = compressible();
+        let rows: [(&[u8], &[u8]); 3] = [(b"a", &value), (b"b", b""), (b"c", b"short")];
+        write::<D, T>(&mut db, &rows);
+        let expected: Vec<_> = rows.iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+        assert_eq!(read::<D, T>(&db), expected);
+        stored_size(&db)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let plain = check_round_trip::<_, Plain>(InMemoryDatabase::empty());
+        // a header for each of the 3 values
+        assert_eq!(
+            check_round_trip::<_, Raw>(InMemoryDatabase::empty()),
+            plain + 3
+        );
+        assert!(check_round_trip::<_, Snappy>(InMemoryDatabase::empty()) < plain);
+        assert!(check_round_trip::<_, Zstd>(InMemoryDatabase::empty()) < plain);
+
+        check_round_trip::<_, Plain>(InMemorySubkeyDatabase::empty());
+        check_round_trip::<_, Raw>(InMemorySubkeyDatabase::empty());
+        check_round_trip::<_, Snappy>(InMemorySubkeyDatabase::empty());
+        check_round_trip::<_, Zstd>(InMemorySubkeyDatabase::empty());
+    }
+
+    fn check_change_setting<Old, New>()
+    where
+        Old: TableSchema<Key = [u8], Value = [u8]>,
+        New: TableSchema<Key = [u8], Value = [u8]>,
+    {
+        let old_value = compressible();
+        let new_value = b"fedcba9876543210".repeat(64);
+        let mut db = InMemoryDatabase::empty();
+        write::<_, Old>(&mut db, &[(b"old", &old_value)]);
+        write::<_, New>(&mut db, &[(b"new", &new_value)]);
+
+        let expected = vec![
+            (b"new".to_vec(), new_value.clone()),
+            (b"old".to_vec(), old_value.clone()),
+        ];
+        assert_eq!(read::<_, New>(&db), expected);
+        assert_eq!(read::<_, Old>(&db), expected);
+    }
+
+    #[test]
+    fn test_change_setting() {
+        check_change_setting::<Raw, Snappy>();
+        check_change_setting::<Raw, Zstd>();
+        check_change_setting::<Snappy, Zstd>();
+        check_change_setting::<Zstd, Raw>();
+        check_change_setting::<Snappy, Raw>();
+    }
+
+    #[test]
+    fn test_corrupted_value() {
+        let mut db = InMemoryDatabase::empty();
+        write::<_, Plain>(&mut db, &[(b"a", b"\x01not snappy"), (b"b", b"\x09")]);
+        let table = db.view::<Snappy>().unwrap();
+        assert!(table.get(b"a").is_err());
+        assert!(table.get(b"b").is_err());
+    }
+}
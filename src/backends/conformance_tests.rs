@@ -0,0 +1,453 @@
+//! Seek and iteration assertions shared by every [`DatabaseTrait`] implementation, so that the
+//! backends agree on the semantics the upper layers rely on: rows are ordered by their encoded
+//! keys, `iter` starts at the first key not less than the sought one and `iter_rev` at the last
+//! key not greater than it, iteration never leaves the table, and an outstanding write schema is
+//! not visible. The tables stored with subkeys read the
+//! same as through the concatenated keys. A backend iterating only forward fails the reverse
+//! iterations as unsupported, and one unable to compact or size its tables fails both.
+
+use std::borrow::Cow;
+
+use super::{
+    DatabaseTrait, InMemoryDatabase, InMemorySubkeyDatabase, TableIter, TableName, TableRead,
+    TableSchema, TieredDatabase, VersionedKVName, WriteSchemaTrait,
+};
+use crate::{
+    errors::{Result, StorageError},
+    middlewares::ChangeKey,
+    test_utils::empty_rocksdb,
+};
+
+#[derive(Clone, Copy)]
+struct Table;
+impl TableSchema for Table {
+    const NAME: TableName = TableName::CommitAlias;
+    type Key = [u8];
+    type Value = [u8];
+}
+
+// the tables with the columns next to `Table`
+#[derive(Clone, Copy)]
+struct PrevTable;
+impl TableSchema for PrevTable {
+    const NAME: TableName = TableName::AuthNodeChange;
+    type Key = [u8];
+    type Value = [u8];
+}
+
+#[derive(Clone, Copy)]
+struct NextTable;
+impl TableSchema for NextTable {
+    const NAME: TableName = TableName::DemotionJournal;
+    type Key = [u8];
+    type Value = [u8];
+}
+
+#[derive(Clone, Copy)]
+struct ChangeTable;
+impl TableSchema for ChangeTable {
+    const NAME: TableName = TableName::HistoryChange(VersionedKVName::FlatKV);
+    const SUPPORTS_SUBKEY: bool = true;
+    type Key = ChangeKey<u64, Box<[u8]>>;
+    type Value = [u8];
+}
+
+type Row = (Vec<u8>, Vec<u8>);
+
+fn put<D: DatabaseTrait, T: TableSchema<Key = [u8], Value = [u8]>>(
+    write_schema: &D::WriteSchema,
+    rows: &[(&[u8], Option<&[u8]>)],
+) {
+    for (key, value) in rows {
+        write_schema.write::<T>((Cow::Borrowed(*key), value.map(Cow::Borrowed)));
+    }
+}
+
+fn collect(iter: TableIter<Table>) -> Vec<Row> {
+    iter.map(|item| {
+        let (k, v) = item.unwrap();
+        (k.into_owned(), v.into_owned())
+    })
+    .collect()
+}
+
+fn rows(expected: &[(&[u8], &[u8])]) -> Vec<Row> {
+    expected
+        .iter()
+        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+        .collect()
+}
+
+fn reversed<R: Clone>(rows: &[R]) -> Vec<R> {
+    rows.iter().rev().cloned().collect()
+}
+
+fn assert_unsupported<T>(res: Result<T>) {
+    assert!(matches!(res, Err(StorageError::Unsupported(_))));
+}
+
+// what a backend may fail as unsupported
+#[derive(Clone, Copy)]
+struct Supports {
+    reverse: bool,
+    // `compact` and `approximate_sizes`
+    compaction: bool,
+}
+
+impl Supports {
+    const ALL: Self = Supports {
+        reverse: true,
+        compaction: true,
+    };
+}
+
+fn check_seek_and_iterate<D: DatabaseTrait>(db: &mut D, reverse: bool) {
+    let write_schema = D::write_schema();
+    put::<D, PrevTable>(&write_schema, &[(b"\xff\xff", Some(b"prev"))]);
+    put::<D, NextTable>(&write_schema, &[(b"", Some(b"next"))]);
+    // shorter keys and keys sharing a prefix, not written in order
+    put::<D, Table>(
+        &write_schema,
+        &[
+            (b"b", Some(b"2")),
+            (b"\x00", Some(b"0")),
+            (b"ab", Some(b"1b")),
+            (b"a", Some(b"1")),
+            (b"\xff", Some(b"3")),
+            (b"a\x00", Some(b"1z")),
+            (b"c", Some(b"deleted")),
+        ],
+    );
+    db.commit(write_schema).unwrap();
+
+    let write_schema = D::write_schema();
+    put::<D, Table>(&write_schema, &[(b"c", None), (b"missing", None)]);
+    db.commit(write_schema).unwrap();
+
+    let all = rows(&[
+        (b"\x00", b"0"),
+        (b"a", b"1"),
+        (b"a\x00", b"1z"),
+        (b"ab", b"1b"),
+        (b"b", b"2"),
+        (b"\xff", b"3"),
+    ]);
+
+    let table = db.view::<Table>().unwrap();
+    assert_eq!(collect(table.iter_from_start().unwrap()), all);
+    assert_eq!(collect(table.iter(b"").unwrap()), all);
+
+    // seeking an existing key includes it
+    assert_eq!(collect(table.iter(b"a").unwrap()), all[1..]);
+    assert_eq!(collect(table.iter(b"\xff").unwrap()), all[5..]);
+    // seeking a missing key starts at the next one by the encoded order
+    assert_eq!(collect(table.iter(b"a\x00\x00").unwrap()), all[3..]);
+    assert_eq!(collect(table.iter(b"aa").unwrap()), all[3..]);
+    assert_eq!(collect(table.iter(b"c").unwrap()), all[5..]);
+    // seeking past the last key does not reach the next table
+    assert_eq!(collect(table.iter(b"\xff\x00").unwrap()), vec![]);
+
+    assert_eq!(table.get(b"a\x00").unwrap().unwrap().as_ref(), b"1z");
+    assert!(table.get(b"c").unwrap().is_none());
+    assert!(table.get(b"missing").unwrap().is_none());
+
+    if !reverse {
+        assert_unsupported(table.iter_from_end());
+        assert_unsupported(table.iter_rev(b"a"));
+        return;
+    }
+    assert_eq!(collect(table.iter_from_end().unwrap()), reversed(&all));
+    assert_eq!(
+        collect(table.iter_rev(b"\xff\x00").unwrap()),
+        reversed(&all)
+    );
+    // seeking an existing key in reverse includes it
+    assert_eq!(collect(table.iter_rev(b"a").unwrap()), reversed(&all[..2]));
+    assert_eq!(
+        collect(table.iter_rev(b"\x00").unwrap()),
+        reversed(&all[..1])
+    );
+    // seeking a missing key in reverse starts at the previous one by the encoded order
+    assert_eq!(
+        collect(table.iter_rev(b"a\x00\x00").unwrap()),
+        reversed(&all[..3])
+    );
+    assert_eq!(collect(table.iter_rev(b"aa").unwrap()), reversed(&all[..3]));
+    assert_eq!(collect(table.iter_rev(b"c").unwrap()), reversed(&all[..5]));
+    // seeking before the first key does not reach the previous table
+    assert_eq!(collect(table.iter_rev(b"").unwrap()), vec![]);
+}
+
+fn check_outstanding_write_schema<D: DatabaseTrait>(db: &mut D, reverse: bool) {
+    let write_schema = D::write_schema();
+    put::<D, Table>(&write_schema, &[(b"a", Some(b"1")), (b"b", Some(b"2"))]);
+    db.commit(write_schema).unwrap();
+
+    let write_schema = D::write_schema();
+    put::<D, Table>(
+        &write_schema,
+        &[(b"a", None), (b"b", Some(b"new")), (b"c", Some(b"3"))],
+    );
+
+    let before = rows(&[(b"a", b"1"), (b"b", b"2")]);
+    let table = db.view::<Table>().unwrap();
+    let mut iter = table.iter_from_start().unwrap();
+    let first = iter.next().unwrap().unwrap();
+    // writing more while iterating does not disturb the iterator
+    put::<D, Table>(&write_schema, &[(b"d", Some(b"4"))]);
+    assert_eq!((first.0.into_owned(), first.1.into_owned()), before[0]);
+    assert_eq!(collect(iter), before[1..]);
+    assert_eq!(collect(table.iter(b"").unwrap()), before);
+    if reverse {
+        assert_eq!(collect(table.iter_from_end().unwrap()), reversed(&before));
+        assert_eq!(collect(table.iter_rev(b"c").unwrap()), reversed(&before));
+    }
+    assert_eq!(table.get(b"a").unwrap().unwrap().as_ref(), b"1");
+    assert!(table.get(b"c").unwrap().is_none());
+    drop(table);
+
+    db.commit(write_schema).unwrap();
+    let table = db.view::<Table>().unwrap();
+    assert_eq!(
+        collect(table.iter_from_start().unwrap()),
+        rows(&[(b"b", b"new"), (b"c", b"3"), (b"d", b"4")])
+    );
+}
+
+fn check_merged_write_schemas<D: DatabaseTrait>(new_db: &mut impl FnMut() -> D) {
+    type Writes<'a> = [(&'a [u8], Option<&'a [u8]>)];
+    let first: (&Writes, &Writes) = (
+        &[(b"a", Some(b"1")), (b"b", Some(b"2")), (b"c", Some(b"3"))],
+        &[(b"x", Some(b"next"))],
+    );
+    // overwrites and deletes keys of the first writes
+    let second: (&Writes, &Writes) = (
+        &[(b"b", Some(b"new")), (b"c", None), (b"d", Some(b"4"))],
+        &[(b"x", None)],
+    );
+    let schema = |writes: &[(&Writes, &Writes)]| {
+        let write_schema = D::write_schema();
+        for (table, next_table) in writes {
+            put::<D, Table>(&write_schema, table);
+            put::<D, NextTable>(&write_schema, next_table);
+        }
+        write_schema
+    };
+    let check = |db: D| {
+        let table = db.view::<Table>().unwrap();
+        assert_eq!(
+            collect(table.iter_from_start().unwrap()),
+            rows(&[(b"a", b"1"), (b"b", b"new"), (b"d", b"4")])
+        );
+        let next_table = db.view::<NextTable>().unwrap();
+        assert!(next_table.iter_from_start().unwrap().next().is_none());
+    };
+
+    let mut db = new_db();
+    db.commit(schema(&[first, second])).unwrap();
+    check(db);
+
+    let mut db = new_db();
+    let mut merged = schema(&[first]);
+    merged.merge(schema(&[second]));
+    db.commit(merged).unwrap();
+    check(db);
+
+    let mut db = new_db();
+    db.commit_many(vec![schema(&[first]), schema(&[second])])
+        .unwrap();
+    check(db);
+
+    // merging an empty schema, or into one, changes nothing
+    let mut db = new_db();
+    let mut merged = D::write_schema();
+    merged.merge(schema(&[first, second]));
+    merged.merge(D::write_schema());
+    db.commit_many(vec![D::write_schema(), merged]).unwrap();
+    check(db);
+}
+
+fn change_key(version: u64, key: &[u8]) -> ChangeKey<u64, Box<[u8]>> {
+    ChangeKey::new(version, key.to_vec().into_boxed_slice())
+}
+
+fn check_change_table<D: DatabaseTrait>(db: &mut D, reverse: bool) {
+    let collect_changes = |iter: TableIter<ChangeTable>| -> Vec<_> {
+        iter.map(|item| {
+            let (k, v) = item.unwrap();
+            (k.into_owned(), v.into_owned())
+        })
+        .collect()
+    };
+
+    let write_schema = D::write_schema();
+    put::<D, Table>(&write_schema, &[(b"a", Some(b"table"))]);
+    for (version, key, value) in [
+        (256, b"a".as_slice(), b"256a".as_slice()),
+        (1, b"ab", b"1ab"),
+        (2, b"", b"2"),
+        (1, b"a", b"1a"),
+        (1, b"", b"1"),
+        (2, b"b", b"deleted"),
+    ] {
+        write_schema.write::<ChangeTable>((
+            Cow::Owned(change_key(version, key)),
+            Some(Cow::Borrowed(value)),
+        ));
+    }
+    db.commit(write_schema).unwrap();
+
+    let write_schema = D::write_schema();
+    write_schema.write::<ChangeTable>((Cow::Owned(change_key(2, b"b")), None));
+    db.commit(write_schema).unwrap();
+
+    let all: Vec<_> = [
+        (1, b"".as_slice(), b"1".as_slice()),
+        (1, b"a", b"1a"),
+        (1, b"ab", b"1ab"),
+        (2, b"", b"2"),
+        (256, b"a", b"256a"),
+    ]
+    .into_iter()
+    .map(|(version, key, value)| (change_key(version, key), value.to_vec()))
+    .collect();
+
+    let table = db.view::<ChangeTable>().unwrap();
+    assert_eq!(collect_changes(table.iter_from_start().unwrap()), all);
+    assert_eq!(
+        collect_changes(table.iter(&change_key(1, b"a")).unwrap()),
+        all[1..]
+    );
+    assert_eq!(
+        collect_changes(table.iter(&change_key(1, b"b")).unwrap()),
+        all[3..]
+    );
+    assert_eq!(
+        collect_changes(table.iter(&change_key(3, b"")).unwrap()),
+        all[4..]
+    );
+    assert_eq!(
+        collect_changes(table.iter(&change_key(257, b"")).unwrap()),
+        vec![]
+    );
+    if reverse {
+        assert_eq!(
+            collect_changes(table.iter_from_end().unwrap()),
+            reversed(&all)
+        );
+        assert_eq!(
+            collect_changes(table.iter_rev(&change_key(1, b"b")).unwrap()),
+            reversed(&all[..3])
+        );
+        assert_eq!(
+            collect_changes(table.iter_rev(&change_key(2, b"")).unwrap()),
+            reversed(&all[..4])
+        );
+        assert_eq!(
+            collect_changes(table.iter_rev(&change_key(0, b"a")).unwrap()),
+            vec![]
+        );
+    }
+    assert_eq!(
+        table.get(&change_key(1, b"ab")).unwrap().unwrap().as_ref(),
+        b"1ab"
+    );
+    assert!(table.get(&change_key(2, b"b")).unwrap().is_none());
+    assert!(table.get(&change_key(1, b"b")).unwrap().is_none());
+
+    // the other tables are not affected
+    let table = db.view::<Table>().unwrap();
+    assert_eq!(
+        collect(table.iter_from_start().unwrap()),
+        rows(&[(b"a", b"table")])
+    );
+}
+
+fn table_size<D: DatabaseTrait>(db: &D) -> u64 {
+    let sizes = db.approximate_sizes().unwrap();
+    sizes
+        .iter()
+        .find(|(name, _)| *name == Table::NAME)
+        .unwrap()
+        .1
+}
+
+fn check_sizes<D: DatabaseTrait>(db: &mut D, compaction: bool) {
+    if !compaction {
+        assert_unsupported(db.compact::<Table>());
+        assert_unsupported(db.approximate_sizes());
+        return;
+    }
+
+    db.compact::<Table>().unwrap();
+    let sizes = db.approximate_sizes().unwrap();
+    assert_eq!(
+        sizes,
+        TableName::ALL
+            .into_iter()
+            .map(|name| (name, 0))
+            .collect::<Vec<_>>()
+    );
+
+    let write_schema = D::write_schema();
+    put::<D, Table>(&write_schema, &[(b"a", Some(b"1")), (b"bc", Some(b"23"))]);
+    put::<D, NextTable>(&write_schema, &[(b"a", Some(b"next"))]);
+    db.commit(write_schema).unwrap();
+    assert_eq!(table_size(db), 6);
+
+    let write_schema = D::write_schema();
+    put::<D, Table>(&write_schema, &[(b"bc", None)]);
+    db.commit(write_schema).unwrap();
+    db.compact::<Table>().unwrap();
+    assert_eq!(table_size(db), 2);
+}
+
+// the backend must fail what it does not support as unsupported
+fn check_database<D: DatabaseTrait>(mut new_db: impl FnMut() -> D, supports: Supports) {
+    check_seek_and_iterate(&mut new_db(), supports.reverse);
+    check_outstanding_write_schema(&mut new_db(), supports.reverse);
+    check_merged_write_schemas(&mut new_db);
+    check_change_table(&mut new_db(), supports.reverse);
+    check_sizes(&mut new_db(), supports.compaction);
+}
+
+#[test]
+fn test_in_memory_database() {
+    check_database(InMemoryDatabase::empty, Supports::ALL);
+}
+
+#[test]
+fn test_rocksdb() {
+    let db_path = "__test_backend_conformance";
+
+    check_database(
+        || empty_rocksdb(db_path).unwrap(),
+        Supports {
+            reverse: true,
+            compaction: false,
+        },
+    );
+
+    if std::path::Path::new(db_path).exists() {
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+}
+
+#[test]
+fn test_in_memory_subkey_database() {
+    check_database(InMemorySubkeyDatabase::empty, Supports::ALL);
+}
+
+#[test]
+fn test_tiered_database() {
+    check_database(
+        || {
+            TieredDatabase::new(
+                InMemoryDatabase::empty(),
+                InMemoryDatabase::empty(),
+                [Table::NAME],
+            )
+        },
+        Supports::ALL,
+    );
+}
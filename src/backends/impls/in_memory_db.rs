@@ -1,11 +1,19 @@
 use super::super::{
+    compression::decode_value,
     serde::{Decode, Encode},
     table::TableSchema,
     write_schema::WriteSchemaNoSubkey,
-    DatabaseTrait, TableIter, TableRead,
+    DatabaseTrait, TableIter, TableName, TableRead,
+};
+use crate::errors::{DecodeError, Result};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Bound,
+    path::Path,
 };
-use crate::errors::Result;
-use std::{borrow::Cow, collections::BTreeMap};
 
 pub struct InMemoryDatabase(BTreeMap<(u32, Vec<u8>), Vec<u8>>);
 
@@ -24,7 +32,7 @@ impl<'b, T: TableSchema> TableRead<T> for InMemoryTable<'b> {
     fn get(&self, key: &T::Key) -> Result<Option<Cow<T::Value>>> {
         let key = (self.col, key.encode().into_owned());
         if let Some(v) = self.inner.0.get(&key) {
-            Ok(Some(<T::Value>::decode(v)?))
+            Ok(Some(decode_value::<T>(v)?))
         } else {
             Ok(None)
         }
@@ -35,7 +43,7 @@ impl<'b, T: TableSchema> TableRead<T> for InMemoryTable<'b> {
         let iter = range
             //.filter(|((col, _), _)| *col == self.col)
             .take_while(move |((col, _), _)| *col == self.col)
-            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, <T::Value>::decode(v)?)));
+            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, decode_value::<T>(v)?)));
         Ok(Box::new(iter))
     }
 
@@ -44,7 +52,30 @@ impl<'b, T: TableSchema> TableRead<T> for InMemoryTable<'b> {
         let iter = range
             //.filter(|((col, _), _)| *col == self.col)
             .take_while(move |((col, _), _)| *col == self.col)
-            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, <T::Value>::decode(v)?)));
+            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, decode_value::<T>(v)?)));
+        Ok(Box::new(iter))
+    }
+
+    fn iter_rev(&self, key: &T::Key) -> Result<TableIter<T>> {
+        let range = self.inner.0.range(..=(self.col, key.encode().into_owned()));
+        let iter = range
+            .rev()
+            .take_while(move |((col, _), _)| *col == self.col)
+            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, decode_value::<T>(v)?)));
+        Ok(Box::new(iter))
+    }
+
+    fn iter_from_end(&self) -> Result<TableIter<T>> {
+        // the rows of the next column bound the table, if any
+        let end = match self.col.checked_add(1) {
+            Some(next_col) => Bound::Excluded((next_col, Vec::new())),
+            None => Bound::Unbounded,
+        };
+        let range = self.inner.0.range((Bound::Unbounded, end));
+        let iter = range
+            .rev()
+            .take_while(move |((col, _), _)| *col == self.col)
+            .map(|((_, k), v)| Ok((<T::Key>::decode(k)?, decode_value::<T>(v)?)));
         Ok(Box::new(iter))
     }
 }
@@ -75,4 +106,66 @@ impl DatabaseTrait for InMemoryDatabase {
         }
         Ok(())
     }
+
+    // Each row is stored as `col | key length | key | value length | value`,
+    // with the column and the lengths as big-endian u32.
+    fn create_checkpoint(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::options().write(true).create_new(true).open(path)?);
+        for ((col, key), value) in self.0.iter() {
+            writer.write_all(&col.to_be_bytes())?;
+            for bytes in [key, value] {
+                writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+                writer.write_all(bytes)?;
+            }
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn open_checkpoint(path: &Path) -> Result<Self> {
+        fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            if input.len() < len {
+                return Err(DecodeError::IncorrectLength.into());
+            }
+            let (head, rest) = input.split_at(len);
+            *input = rest;
+            Ok(head)
+        }
+
+        fn take_u32(input: &mut &[u8]) -> Result<u32> {
+            Ok(u32::from_be_bytes(take(input, 4)?.try_into().unwrap()))
+        }
+
+        let content = std::fs::read(path)?;
+        let mut input = content.as_slice();
+        let mut map = BTreeMap::new();
+        while !input.is_empty() {
+            let col = take_u32(&mut input)?;
+            let key_len = take_u32(&mut input)? as usize;
+            let key = take(&mut input, key_len)?.to_vec();
+            let value_len = take_u32(&mut input)? as usize;
+            let value = take(&mut input, value_len)?.to_vec();
+            map.insert((col, key), value);
+        }
+
+        Ok(Self(map))
+    }
+
+    // the bytes of the keys and values, the rows being removed from the map on deletion
+    fn approximate_sizes(&self) -> Result<Vec<(TableName, u64)>> {
+        Ok(TableName::ALL
+            .into_iter()
+            .map(|name| {
+                let col: u32 = name.into();
+                let size = self
+                    .0
+                    .range((col, Vec::new())..)
+                    .take_while(|((c, _), _)| *c == col)
+                    .map(|((_, key), value)| (key.len() + value.len()) as u64)
+                    .sum();
+                (name, size)
+            })
+            .collect())
+    }
 }
@@ -0,0 +1,192 @@
+use super::super::{
+    compression::decode_value,
+    serde::{Decode, Encode, EncodeSubKey},
+    table::TableSchema,
+    write_schema::WriteSchemaWithSubkey,
+    DatabaseTrait, TableIter, TableName, TableRead,
+};
+use crate::errors::{DecodeError, Result};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::{Bound, RangeBounds},
+    path::Path,
+};
+
+type RowKey = (u32, Vec<u8>, Vec<u8>);
+
+/// An in-memory database storing the rows of the tables with [`TableSchema::SUPPORTS_SUBKEY`]
+/// under a key and a subkey, as the backends supporting subkeys do. The other tables are stored
+/// as in [`InMemoryDatabase`](super::InMemoryDatabase), with an empty subkey.
+pub struct InMemorySubkeyDatabase(BTreeMap<RowKey, Vec<u8>>);
+
+pub struct InMemorySubkeyTable<'a> {
+    inner: &'a InMemorySubkeyDatabase,
+    col: u32,
+}
+
+impl InMemorySubkeyDatabase {
+    pub fn empty() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<'a> InMemorySubkeyTable<'a> {
+    fn row_key<T: TableSchema>(&self, key: &T::Key) -> RowKey {
+        if T::SUPPORTS_SUBKEY {
+            let (key, subkey) = key.encode_subkey();
+            (self.col, key.into_owned(), subkey.into_owned())
+        } else {
+            (self.col, key.encode().into_owned(), Vec::new())
+        }
+    }
+
+    fn iter_range<T: TableSchema>(
+        &self,
+        range: impl RangeBounds<RowKey>,
+        rev: bool,
+    ) -> TableIter<T> {
+        let range = self.inner.0.range(range);
+        let rows: Box<dyn '_ + Iterator<Item = _>> = if rev {
+            Box::new(range.rev())
+        } else {
+            Box::new(range)
+        };
+        let iter = rows
+            .take_while(move |((col, _, _), _)| *col == self.col)
+            .map(|((_, k, subkey), v)| {
+                let key = if subkey.is_empty() {
+                    <T::Key>::decode(k)?
+                } else {
+                    Cow::Owned(<T::Key>::decode_owned([k.as_slice(), subkey].concat())?)
+                };
+                Ok((key, decode_value::<T>(v)?))
+            });
+        Box::new(iter)
+    }
+}
+
+impl<'b, T: TableSchema> TableRead<T> for InMemorySubkeyTable<'b> {
+    fn get(&self, key: &T::Key) -> Result<Option<Cow<T::Value>>> {
+        if let Some(v) = self.inner.0.get(&self.row_key::<T>(key)) {
+            Ok(Some(decode_value::<T>(v)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn iter(&self, key: &T::Key) -> Result<TableIter<T>> {
+        Ok(self.iter_range(self.row_key::<T>(key).., false))
+    }
+
+    fn iter_from_start(&self) -> Result<TableIter<T>> {
+        Ok(self.iter_range((self.col, Vec::new(), Vec::new()).., false))
+    }
+
+    fn iter_rev(&self, key: &T::Key) -> Result<TableIter<T>> {
+        Ok(self.iter_range(..=self.row_key::<T>(key), true))
+    }
+
+    fn iter_from_end(&self) -> Result<TableIter<T>> {
+        // the rows of the next column bound the table, if any
+        let end = match self.col.checked_add(1) {
+            Some(next_col) => Bound::Excluded((next_col, Vec::new(), Vec::new())),
+            None => Bound::Unbounded,
+        };
+        Ok(self.iter_range((Bound::Unbounded, end), true))
+    }
+}
+
+impl DatabaseTrait for InMemorySubkeyDatabase {
+    type TableID = u32;
+    type WriteSchema = WriteSchemaWithSubkey<Self::TableID>;
+
+    fn view<T: TableSchema>(&self) -> Result<impl '_ + TableRead<T>> {
+        Ok(InMemorySubkeyTable {
+            inner: self,
+            col: T::NAME.into(),
+        })
+    }
+
+    fn write_schema() -> Self::WriteSchema {
+        Self::WriteSchema::new()
+    }
+
+    fn commit(&mut self, changes: Self::WriteSchema) -> Result<()> {
+        for (col, key, subkey, value) in changes.drain() {
+            let k = (col, key, subkey.unwrap_or_default());
+            if let Some(v) = value {
+                self.0.insert(k, v)
+            } else {
+                self.0.remove(&k)
+            };
+        }
+        Ok(())
+    }
+
+    // Each row is stored as `col | key length | key | subkey length | subkey | value length |
+    // value`, with the column and the lengths as big-endian u32.
+    fn create_checkpoint(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::options().write(true).create_new(true).open(path)?);
+        for ((col, key, subkey), value) in self.0.iter() {
+            writer.write_all(&col.to_be_bytes())?;
+            for bytes in [key, subkey, value] {
+                writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+                writer.write_all(bytes)?;
+            }
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    fn open_checkpoint(path: &Path) -> Result<Self> {
+        fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            if input.len() < len {
+                return Err(DecodeError::IncorrectLength.into());
+            }
+            let (head, rest) = input.split_at(len);
+            *input = rest;
+            Ok(head)
+        }
+
+        fn take_field(input: &mut &[u8]) -> Result<Vec<u8>> {
+            let len = u32::from_be_bytes(take(input, 4)?.try_into().unwrap());
+            Ok(take(input, len as usize)?.to_vec())
+        }
+
+        let content = std::fs::read(path)?;
+        let mut input = content.as_slice();
+        let mut map = BTreeMap::new();
+        while !input.is_empty() {
+            let col = u32::from_be_bytes(take(&mut input, 4)?.try_into().unwrap());
+            let key = take_field(&mut input)?;
+            let subkey = take_field(&mut input)?;
+            let value = take_field(&mut input)?;
+            map.insert((col, key, subkey), value);
+        }
+
+        Ok(Self(map))
+    }
+    // the bytes of the keys, subkeys and values, the rows being removed from the map on
+    // deletion
+    fn approximate_sizes(&self) -> Result<Vec<(TableName, u64)>> {
+        Ok(TableName::ALL
+            .into_iter()
+            .map(|name| {
+                let col: u32 = name.into();
+                let size = self
+                    .0
+                    .range((col, Vec::new(), Vec::new())..)
+                    .take_while(|((c, _, _), _)| *c == col)
+                    .map(|((_, key, subkey), value)| {
+                        (key.len() + subkey.len() + value.len()) as u64
+                    })
+                    .sum();
+                (name, size)
+            })
+            .collect())
+    }
+}
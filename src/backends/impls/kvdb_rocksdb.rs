@@ -1,15 +1,16 @@
 use std::{
     borrow::{Borrow, Cow},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use super::super::{
+    compression::decode_value_owned,
     serde::{Decode, Encode},
     table::TableSchema,
     write_schema::WriteSchemaNoSubkey,
-    DatabaseTrait, TableIter, TableRead,
+    DatabaseTrait, TableIter, TableName, TableRead,
 };
-use crate::errors::{DatabaseError, Result};
+use crate::errors::{DatabaseError, DbResult, Result, StorageError};
 
 use kvdb::KeyValueDB;
 use kvdb_rocksdb::DatabaseConfig;
@@ -19,16 +20,19 @@ pub struct RocksDBColumn<'a> {
     inner: &'a kvdb_rocksdb::Database,
 }
 
-pub fn open_database(num_cols: u32, path: &str) -> Result<kvdb_rocksdb::Database> {
+// number of rows per transaction when copying a column into a checkpoint
+const CHECKPOINT_BATCH_SIZE: usize = 1 << 14;
+
+pub fn open_database(num_cols: u32, path: impl AsRef<Path>) -> Result<kvdb_rocksdb::Database> {
     let config = DatabaseConfig::with_columns(num_cols);
-    let db_path = PathBuf::from(path);
+    let db_path = PathBuf::from(path.as_ref());
     Ok(kvdb_rocksdb::Database::open(&config, db_path)?)
 }
 
 impl<'b, T: TableSchema> TableRead<T> for RocksDBColumn<'b> {
     fn get(&self, key: &T::Key) -> Result<Option<Cow<T::Value>>> {
         if let Some(v) = KeyValueDB::get(self.inner, self.col, key.encode().borrow())? {
-            let owned = <T::Value>::decode_owned(v)?;
+            let owned = decode_value_owned::<T>(v)?;
             Ok(Some(Cow::Owned(owned)))
         } else {
             Ok(None)
@@ -42,7 +46,7 @@ impl<'b, T: TableSchema> TableRead<T> for RocksDBColumn<'b> {
             .map(|kv| match kv {
                 Ok((k, v)) => Ok((
                     Cow::Owned(<T::Key>::decode_owned(k.to_vec())?),
-                    Cow::Owned(<T::Value>::decode_owned(v)?),
+                    Cow::Owned(decode_value_owned::<T>(v)?),
                 )),
                 Err(e) => Err(DatabaseError::IoError(e)),
             });
@@ -54,13 +58,100 @@ impl<'b, T: TableSchema> TableRead<T> for RocksDBColumn<'b> {
         let iter = self.inner.iter(self.col).map(|kv| match kv {
             Ok((k, v)) => Ok((
                 Cow::Owned(<T::Key>::decode_owned(k.into_vec())?),
-                Cow::Owned(<T::Value>::decode_owned(v)?),
+                Cow::Owned(decode_value_owned::<T>(v)?),
             )),
             Err(e) => Err(DatabaseError::IoError(e)),
         });
 
         Ok(Box::new(iter))
     }
+
+    fn iter_rev(&self, key: &T::Key) -> Result<TableIter<T>> {
+        // the keys not greater than `key` are those less than `key` followed by a zero byte
+        let mut bound = key.encode().into_owned();
+        bound.push(0);
+        Ok(self.iter_before(Some(bound)))
+    }
+
+    fn iter_from_end(&self) -> Result<TableIter<T>> {
+        Ok(self.iter_before(None))
+    }
+}
+
+// kvdb only iterates forward, so the reverse iteration finds each row with forward seeks, see
+// `seek_before`
+impl<'b> RocksDBColumn<'b> {
+    fn iter_before<T: TableSchema>(&self, bound: Option<Vec<u8>>) -> TableIter<'_, '_, T> {
+        let mut bound = Some(bound);
+        Box::new(std::iter::from_fn(move || {
+            let row = self.seek_before(bound.take()?.as_deref()).transpose()?;
+            let row = row.and_then(|(key, value)| {
+                let item = (
+                    Cow::Owned(<T::Key>::decode_owned(key.clone())?),
+                    Cow::Owned(decode_value_owned::<T>(value)?),
+                );
+                bound = Some(Some(key));
+                Ok(item)
+            });
+            Some(row)
+        }))
+    }
+
+    // The first key not less than `key`.
+    fn seek(&self, key: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        match self.inner.iter_from(self.col, key).next() {
+            Some(Ok((k, _))) => Ok(Some(k.to_vec())),
+            Some(Err(e)) => Err(DatabaseError::IoError(e)),
+            None => Ok(None),
+        }
+    }
+
+    // The last row whose key is less than `bound`, or the last row if `bound` is `None`.
+    //
+    // The key is found one byte at a time: the greatest next byte of a key less than `bound`
+    // is searched by bisection, each step seeking the first key after a candidate prefix. A row
+    // thus costs 8 seeks per byte of its key, and no more than a key of memory.
+    fn seek_before(&self, bound: Option<&[u8]>) -> DbResult<Option<(Vec<u8>, Vec<u8>)>> {
+        let below = |key: &[u8]| bound.map_or(true, |bound| key < bound);
+        // a key less than `bound` starts with `prefix`
+        let mut prefix = Vec::new();
+        if !matches!(self.seek(&prefix)?, Some(key) if below(&key)) {
+            return Ok(None);
+        }
+
+        loop {
+            let (mut low, mut high) = (0usize, 256usize);
+            let mut next_byte = None;
+            while low < high {
+                let middle = (low + high) / 2;
+                prefix.push(middle as u8);
+                let found = matches!(
+                    self.seek(&prefix)?,
+                    Some(key) if below(&key) && key.starts_with(&prefix[..prefix.len() - 1])
+                );
+                prefix.pop();
+                if found {
+                    next_byte = Some(middle as u8);
+                    low = middle + 1;
+                } else {
+                    high = middle;
+                }
+            }
+
+            match next_byte {
+                Some(byte) => prefix.push(byte),
+                // no key less than `bound` extends `prefix`, so `prefix` is the key
+                None => {
+                    let value = KeyValueDB::get(self.inner, self.col, &prefix)
+                        .map_err(DatabaseError::IoError)?
+                        .ok_or_else(|| {
+                            DatabaseError::IoError(std::io::Error::other("row removed during seek"))
+                        })?;
+                    return Ok(Some((prefix, value)));
+                }
+            }
+        }
+    }
 }
 
 impl DatabaseTrait for kvdb_rocksdb::Database {
@@ -90,4 +181,49 @@ impl DatabaseTrait for kvdb_rocksdb::Database {
 
         Ok(KeyValueDB::write(self, tx)?)
     }
+
+    // kvdb-rocksdb does not expose the underlying handle needed by the rocksdb checkpoint api,
+    // so the columns are copied. Unlike a hard-linked checkpoint, this writes every row again,
+    // in time and disk space O(database size). Holding `&self` excludes concurrent commits.
+    fn create_checkpoint(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+        }
+        std::fs::create_dir_all(path)?;
+
+        let num_cols = TableName::max_index() + 1;
+        let checkpoint = open_database(num_cols, path)?;
+        for col in 0..num_cols {
+            let mut tx = kvdb::DBTransaction::new();
+            for kv in self.iter(col) {
+                let (key, value) = kv?;
+                tx.put_vec(col, &key, value);
+
+                if tx.ops.len() >= CHECKPOINT_BATCH_SIZE {
+                    let full_tx = std::mem::replace(&mut tx, kvdb::DBTransaction::new());
+                    KeyValueDB::write(&checkpoint, full_tx)?;
+                }
+            }
+            KeyValueDB::write(&checkpoint, tx)?;
+        }
+
+        Ok(())
+    }
+
+    fn open_checkpoint(path: &Path) -> Result<Self> {
+        open_database(TableName::max_index() + 1, path)
+    }
+
+    // kvdb-rocksdb does not expose the underlying handle needed by `compact_range`. Doing
+    // nothing would let the callers believe the space was reclaimed, so the compaction fails
+    // and the columns are left to the background compaction of rocksdb.
+    fn compact<T: TableSchema>(&self) -> Result<()> {
+        Err(StorageError::Unsupported("compaction"))
+    }
+
+    // nor the size properties of the columns, and counting the bytes of every row would scan
+    // the whole database
+    fn approximate_sizes(&self) -> Result<Vec<(TableName, u64)>> {
+        Err(StorageError::Unsupported("table sizes"))
+    }
 }
@@ -1,5 +1,9 @@
 pub mod in_memory_db;
+pub mod in_memory_subkey_db;
 pub mod kvdb_rocksdb;
+pub mod tiered_db;
 
 pub use in_memory_db::{InMemoryDatabase, InMemoryTable};
+pub use in_memory_subkey_db::{InMemorySubkeyDatabase, InMemorySubkeyTable};
 pub use kvdb_rocksdb::RocksDBColumn;
+pub use tiered_db::{DemotionJournal, TieredDatabase, TieredTable};
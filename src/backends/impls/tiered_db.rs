@@ -0,0 +1,527 @@
+use std::{
+    borrow::{Borrow, Cow},
+    cmp::Ordering,
+    path::Path,
+};
+
+use itertools::{EitherOrBoth, Itertools};
+
+use super::super::{
+    serde::{Decode, Encode},
+    table::TableSchema,
+    write_schema::WriteSchemaNoSubkey,
+    DatabaseTrait, TableIter, TableName, TableRead, WriteSchemaTrait,
+};
+use crate::{
+    errors::{DecodeError, Result},
+    StorageError,
+};
+
+const TIERED_TABLES_FILE: &str = "TIERED";
+const HOT_CHECKPOINT_DIR: &str = "hot";
+const COLD_CHECKPOINT_DIR: &str = "cold";
+
+/// Records the keys of an unfinished [`TieredDatabase::demote`], indexed by the column of the table.
+#[derive(Clone, Copy)]
+pub struct DemotionJournal;
+
+impl TableSchema for DemotionJournal {
+    const NAME: TableName = TableName::DemotionJournal;
+    type Key = u64;
+    type Value = [u8];
+}
+
+/// A database with two tiers. All writes go to the hot tier, and rows of the
+/// tiered tables can be moved to the cold tier by [`TieredDatabase::demote`].
+///
+/// Reads of a tiered table look at the hot tier first, so they are correct
+/// while a row is being moved. Deletions of a tiered table apply to both tiers,
+/// which is not atomic across the tiers.
+pub struct TieredDatabase<Hot, Cold> {
+    hot: Hot,
+    cold: Cold,
+    tiered: Vec<u32>,
+}
+
+pub struct TieredTable<'a, T: TableSchema> {
+    hot: Box<dyn 'a + TableRead<T>>,
+    cold: Option<Box<dyn 'a + TableRead<T>>>,
+}
+
+impl<Hot, Cold> TieredDatabase<Hot, Cold>
+where
+    Hot: DatabaseTrait<TableID = u32, WriteSchema = WriteSchemaNoSubkey<u32>>,
+    Cold: DatabaseTrait<TableID = u32, WriteSchema = WriteSchemaNoSubkey<u32>>,
+{
+    pub fn new(hot: Hot, cold: Cold, tiered_tables: impl IntoIterator<Item = TableName>) -> Self {
+        Self {
+            hot,
+            cold,
+            tiered: tiered_tables.into_iter().map(u32::from).collect(),
+        }
+    }
+
+    fn is_tiered(&self, col: u32) -> bool {
+        self.tiered.contains(&col)
+    }
+
+    /// Moves the rows of table `T` accepted by `predicate` from the hot tier to the cold tier.
+    ///
+    /// The rows are written to the cold tier and read back before they are deleted from
+    /// the hot tier. A journal row makes the move idempotent: an interrupted demotion is
+    /// finished by the next `demote` or `recover_demotion` on the same table.
+    ///
+    /// # Returns
+    ///
+    /// The number of moved rows.
+    pub fn demote<T: TableSchema>(
+        &mut self,
+        mut predicate: impl FnMut(&T::Key, &T::Value) -> bool,
+    ) -> Result<usize> {
+        let col = u32::from(T::NAME);
+        if !self.is_tiered(col) {
+            return Err(StorageError::TableNotTiered);
+        }
+
+        self.recover_demotion::<T>()?;
+
+        let mut keys = vec![];
+        for item in self.hot.view::<T>()?.iter_from_start()? {
+            let (key, value) = item?;
+            if predicate(&*key, &*value) {
+                keys.push(key.into_owned());
+            }
+        }
+
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let journal = Hot::write_schema();
+        let encoded_keys = encode_keys::<T>(&keys);
+        journal.write::<DemotionJournal>((Cow::Owned(col as u64), Some(Cow::Owned(encoded_keys))));
+        self.hot.commit(journal)?;
+
+        self.move_to_cold::<T>(&keys)?;
+
+        Ok(keys.len())
+    }
+
+    /// Finishes an interrupted demotion of table `T`, if any.
+    pub fn recover_demotion<T: TableSchema>(&mut self) -> Result<()> {
+        let col = u32::from(T::NAME) as u64;
+        let keys = match self.hot.view::<DemotionJournal>()?.get(&col)? {
+            Some(encoded_keys) => decode_keys::<T>(&encoded_keys)?,
+            None => return Ok(()),
+        };
+
+        self.move_to_cold::<T>(&keys)
+    }
+
+    // A key missing from the hot tier counts as already moved:
+    // its row was deleted from both tiers after the journal was written.
+    fn move_to_cold<T: TableSchema>(&mut self, keys: &[<T::Key as ToOwned>::Owned]) -> Result<()> {
+        let hot_table = self.hot.view::<T>()?;
+
+        let cold_changes = Cold::write_schema();
+        for key in keys {
+            if let Some(value) = hot_table.get(key.borrow())? {
+                cold_changes.write::<T>((Cow::Borrowed(key.borrow()), Some(value)));
+            }
+        }
+        self.cold.commit(cold_changes)?;
+
+        let cold_table = self.cold.view::<T>()?;
+        let hot_changes = Hot::write_schema();
+        for key in keys {
+            let hot_value = hot_table.get(key.borrow())?;
+            let cold_value = cold_table.get(key.borrow())?;
+            match (hot_value, cold_value) {
+                (None, _) => continue,
+                (Some(hot_value), Some(cold_value))
+                    if <T::Value as Encode>::encode(&hot_value)
+                        == <T::Value as Encode>::encode(&cold_value) => {}
+                _ => return Err(StorageError::ConsistencyCheckFailure),
+            }
+            hot_changes.write::<T>((Cow::Borrowed(key.borrow()), None));
+        }
+        let col = u32::from(T::NAME) as u64;
+        hot_changes.write::<DemotionJournal>((Cow::Owned(col), None));
+
+        drop(hot_table);
+        drop(cold_table);
+        self.hot.commit(hot_changes)
+    }
+}
+
+impl<Hot, Cold> DatabaseTrait for TieredDatabase<Hot, Cold>
+where
+    Hot: DatabaseTrait<TableID = u32, WriteSchema = WriteSchemaNoSubkey<u32>>,
+    Cold: DatabaseTrait<TableID = u32, WriteSchema = WriteSchemaNoSubkey<u32>>,
+{
+    type TableID = u32;
+    type WriteSchema = WriteSchemaNoSubkey<Self::TableID>;
+
+    fn view<T: TableSchema>(&self) -> Result<impl '_ + TableRead<T>> {
+        let cold: Option<Box<dyn '_ + TableRead<T>>> = if self.is_tiered(T::NAME.into()) {
+            Some(Box::new(self.cold.view::<T>()?))
+        } else {
+            None
+        };
+
+        Ok(TieredTable {
+            hot: Box::new(self.hot.view::<T>()?),
+            cold,
+        })
+    }
+
+    fn write_schema() -> Self::WriteSchema {
+        Self::WriteSchema::new()
+    }
+
+    // Deletions of tiered tables are applied to the cold tier first,
+    // so an interruption never brings a deleted row back.
+    fn commit(&mut self, changes: Self::WriteSchema) -> Result<()> {
+        let ops = changes.drain();
+
+        let cold_deletions: Vec<_> = ops
+            .iter()
+            .filter(|(col, _, value)| value.is_none() && self.is_tiered(*col))
+            .map(|(col, key, _)| (*col, key.clone(), None))
+            .collect();
+        if !cold_deletions.is_empty() {
+            self.cold
+                .commit(WriteSchemaNoSubkey::from_ops(cold_deletions))?;
+        }
+
+        self.hot.commit(WriteSchemaNoSubkey::from_ops(ops))
+    }
+
+    fn create_checkpoint(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+        }
+        std::fs::create_dir_all(path)?;
+
+        let tiered: Vec<u8> = self
+            .tiered
+            .iter()
+            .flat_map(|col| col.to_be_bytes())
+            .collect();
+        std::fs::write(path.join(TIERED_TABLES_FILE), tiered)?;
+        self.hot.create_checkpoint(&path.join(HOT_CHECKPOINT_DIR))?;
+        self.cold.create_checkpoint(&path.join(COLD_CHECKPOINT_DIR))
+    }
+
+    fn open_checkpoint(path: &Path) -> Result<Self> {
+        let tiered = std::fs::read(path.join(TIERED_TABLES_FILE))?;
+        if tiered.len() % 4 != 0 {
+            return Err(DecodeError::IncorrectLength.into());
+        }
+
+        Ok(Self {
+            hot: Hot::open_checkpoint(&path.join(HOT_CHECKPOINT_DIR))?,
+            cold: Cold::open_checkpoint(&path.join(COLD_CHECKPOINT_DIR))?,
+            tiered: tiered
+                .chunks_exact(4)
+                .map(|col| u32::from_be_bytes(col.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    fn compact<T: TableSchema>(&self) -> Result<()> {
+        self.hot.compact::<T>()?;
+        if self.is_tiered(T::NAME.into()) {
+            self.cold.compact::<T>()?;
+        }
+        Ok(())
+    }
+
+    // the sum of both tiers, by the tables reported by either of them
+    fn approximate_sizes(&self) -> Result<Vec<(TableName, u64)>> {
+        let mut sizes = self.hot.approximate_sizes()?;
+        for (name, size) in self.cold.approximate_sizes()? {
+            match sizes.iter_mut().find(|(hot_name, _)| *hot_name == name) {
+                Some((_, hot_size)) => *hot_size += size,
+                None => sizes.push((name, size)),
+            }
+        }
+        Ok(sizes)
+    }
+}
+
+impl<'b, T: TableSchema> TableRead<T> for TieredTable<'b, T> {
+    fn get(&self, key: &T::Key) -> Result<Option<Cow<T::Value>>> {
+        if let Some(value) = self.hot.get(key)? {
+            return Ok(Some(value));
+        }
+
+        match &self.cold {
+            Some(cold) => cold.get(key),
+            None => Ok(None),
+        }
+    }
+
+    fn iter<'a>(&'a self, key: &T::Key) -> Result<TableIter<'a, '_, T>> {
+        let hot = self.hot.iter(key)?;
+        match &self.cold {
+            Some(cold) => Ok(merge_tiers(hot, cold.iter(key)?, false)),
+            None => Ok(hot),
+        }
+    }
+
+    fn iter_from_start(&self) -> Result<TableIter<T>> {
+        let hot = self.hot.iter_from_start()?;
+        match &self.cold {
+            Some(cold) => Ok(merge_tiers(hot, cold.iter_from_start()?, false)),
+            None => Ok(hot),
+        }
+    }
+
+    fn iter_rev<'a>(&'a self, key: &T::Key) -> Result<TableIter<'a, '_, T>> {
+        let hot = self.hot.iter_rev(key)?;
+        match &self.cold {
+            Some(cold) => Ok(merge_tiers(hot, cold.iter_rev(key)?, true)),
+            None => Ok(hot),
+        }
+    }
+
+    fn iter_from_end(&self) -> Result<TableIter<T>> {
+        let hot = self.hot.iter_from_end()?;
+        match &self.cold {
+            Some(cold) => Ok(merge_tiers(hot, cold.iter_from_end()?, true)),
+            None => Ok(hot),
+        }
+    }
+}
+
+// Both iterators are ordered by the encoded keys, descending if `rev`. The hot tier wins on
+// equal keys.
+fn merge_tiers<'a, 'b, T: TableSchema>(
+    hot: TableIter<'a, 'b, T>,
+    cold: TableIter<'a, 'b, T>,
+    rev: bool,
+) -> TableIter<'a, 'b, T> {
+    let merged = hot
+        .merge_join_by(cold, move |hot, cold| match (hot, cold) {
+            (Ok((hot_key, _)), Ok((cold_key, _))) => {
+                let ordering =
+                    <T::Key as Encode>::encode(hot_key).cmp(&<T::Key as Encode>::encode(cold_key));
+                if rev {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+            (Err(_), _) => Ordering::Less,
+            (_, Err(_)) => Ordering::Greater,
+        })
+        .map(|item| match item {
+            EitherOrBoth::Both(hot, _) | EitherOrBoth::Left(hot) => hot,
+            EitherOrBoth::Right(cold) => cold,
+        });
+
+    Box::new(merged)
+}
+
+fn encode_keys<T: TableSchema>(keys: &[<T::Key as ToOwned>::Owned]) -> Vec<u8> {
+    let mut output = vec![];
+    for key in keys {
+        let encoded = <T::Key as Encode>::encode(key.borrow());
+        output.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        output.extend_from_slice(&encoded);
+    }
+    output
+}
+
+fn decode_keys<T: TableSchema>(mut input: &[u8]) -> Result<Vec<<T::Key as ToOwned>::Owned>> {
+    let mut keys = vec![];
+    while !input.is_empty() {
+        if input.len() < 4 {
+            return Err(DecodeError::IncorrectLength.into());
+        }
+        let (len, rest) = input.split_at(4);
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        if rest.len() < len {
+            return Err(DecodeError::IncorrectLength.into());
+        }
+        let (encoded, rest) = rest.split_at(len);
+        keys.push(<T::Key as Decode>::decode(encoded)?.into_owned());
+        input = rest;
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::InMemoryDatabase;
+
+    #[derive(Clone, Copy)]
+    struct MockTable;
+    impl TableSchema for MockTable {
+        const NAME: TableName = TableName::MockTable;
+        type Key = [u8];
+        type Value = [u8];
+    }
+
+    type MockTieredDatabase = TieredDatabase<InMemoryDatabase, InMemoryDatabase>;
+
+    fn put(db: &mut MockTieredDatabase, rows: &[(u8, Option<u8>)]) {
+        let write_schema = MockTieredDatabase::write_schema();
+        for (key, value) in rows {
+            write_schema
+                .write::<MockTable>((Cow::Owned(vec![*key]), value.map(|v| Cow::Owned(vec![v]))));
+        }
+        db.commit(write_schema).unwrap();
+    }
+
+    fn rows(db: &MockTieredDatabase) -> Vec<(u8, u8)> {
+        let table = db.view::<MockTable>().unwrap();
+        let from_start: Vec<_> = table
+            .iter_from_start()
+            .unwrap()
+            .map(|item| {
+                let (k, v) = item.unwrap();
+                (k[0], v[0])
+            })
+            .collect();
+
+        for (key, value) in &from_start {
+            let value_by_get = table.get(&[*key]).unwrap().unwrap().into_owned();
+            assert_eq!(value_by_get, vec![*value]);
+        }
+        let from_2: Vec<_> = table
+            .iter(&[2])
+            .unwrap()
+            .map(|item| item.unwrap().0[0])
+            .collect();
+        let expected: Vec<_> = from_start
+            .iter()
+            .map(|(k, _)| *k)
+            .filter(|k| *k >= 2)
+            .collect();
+        assert_eq!(from_2, expected);
+
+        from_start
+    }
+
+    fn cold_keys(db: &MockTieredDatabase) -> Vec<u8> {
+        let cold = db.cold.view::<MockTable>().unwrap();
+        let keys = cold.iter_from_start().unwrap();
+        keys.map(|item| item.unwrap().0[0]).collect()
+    }
+
+    #[test]
+    fn test_demote() {
+        let mut db = MockTieredDatabase::new(
+            InMemoryDatabase::empty(),
+            InMemoryDatabase::empty(),
+            [TableName::MockTable],
+        );
+        put(
+            &mut db,
+            &[(0, Some(10)), (1, Some(11)), (2, Some(12)), (3, Some(13))],
+        );
+        let expected = vec![(0, 10), (1, 11), (2, 12), (3, 13)];
+
+        assert_eq!(db.demote::<MockTable>(|key, _| key[0] % 2 == 0).unwrap(), 2);
+        assert_eq!(cold_keys(&db), vec![0, 2]);
+        assert_eq!(rows(&db), expected);
+
+        // a demotion interrupted after writing the cold tier
+        let journal = MockTieredDatabase::write_schema();
+        let col = u32::from(TableName::MockTable) as u64;
+        let keys = encode_keys::<MockTable>(&[vec![1]]);
+        journal.write::<DemotionJournal>((Cow::Owned(col), Some(Cow::Owned(keys))));
+        db.hot.commit(journal).unwrap();
+        let cold_changes = InMemoryDatabase::write_schema();
+        cold_changes.write::<MockTable>((Cow::Owned(vec![1]), Some(Cow::Owned(vec![11]))));
+        db.cold.commit(cold_changes).unwrap();
+        assert_eq!(rows(&db), expected);
+
+        db.recover_demotion::<MockTable>().unwrap();
+        assert_eq!(cold_keys(&db), vec![0, 1, 2]);
+        assert!(db
+            .hot
+            .view::<DemotionJournal>()
+            .unwrap()
+            .get(&col)
+            .unwrap()
+            .is_none());
+        assert_eq!(rows(&db), expected);
+
+        // deletions and updates of demoted rows
+        put(&mut db, &[(0, None), (2, Some(22))]);
+        assert_eq!(rows(&db), vec![(1, 11), (2, 22), (3, 13)]);
+        assert_eq!(cold_keys(&db), vec![1, 2]);
+    }
+
+    fn journal_demotion(db: &mut MockTieredDatabase, keys: &[u8]) {
+        let journal = MockTieredDatabase::write_schema();
+        let col = u32::from(TableName::MockTable) as u64;
+        let keys: Vec<_> = keys.iter().map(|key| vec![*key]).collect();
+        let encoded_keys = encode_keys::<MockTable>(&keys);
+        journal.write::<DemotionJournal>((Cow::Owned(col), Some(Cow::Owned(encoded_keys))));
+        db.hot.commit(journal).unwrap();
+    }
+
+    fn has_journal(db: &MockTieredDatabase) -> bool {
+        let col = u32::from(TableName::MockTable) as u64;
+        let journal = db.hot.view::<DemotionJournal>().unwrap();
+        journal.get(&col).unwrap().is_some()
+    }
+
+    #[test]
+    fn test_recover_interrupted_demotion() {
+        let mut db = MockTieredDatabase::new(
+            InMemoryDatabase::empty(),
+            InMemoryDatabase::empty(),
+            [TableName::MockTable],
+        );
+        put(
+            &mut db,
+            &[(0, Some(10)), (1, Some(11)), (2, Some(12)), (3, Some(13))],
+        );
+        let mut expected = vec![(0, 10), (1, 11), (2, 12), (3, 13)];
+
+        // crashed right after committing the journal
+        journal_demotion(&mut db, &[0]);
+        db.recover_demotion::<MockTable>().unwrap();
+        assert!(!has_journal(&db));
+        assert_eq!(cold_keys(&db), vec![0]);
+        assert_eq!(rows(&db), expected);
+
+        // crashed after the cold tier and part of the hot tier were written
+        journal_demotion(&mut db, &[1, 2]);
+        let cold_changes = InMemoryDatabase::write_schema();
+        cold_changes.write::<MockTable>((Cow::Owned(vec![1]), Some(Cow::Owned(vec![11]))));
+        cold_changes.write::<MockTable>((Cow::Owned(vec![2]), Some(Cow::Owned(vec![12]))));
+        db.cold.commit(cold_changes).unwrap();
+        let hot_changes = InMemoryDatabase::write_schema();
+        hot_changes.write::<MockTable>((Cow::Owned(vec![1]), None));
+        db.hot.commit(hot_changes).unwrap();
+        assert_eq!(rows(&db), expected);
+
+        db.recover_demotion::<MockTable>().unwrap();
+        assert!(!has_journal(&db));
+        assert_eq!(cold_keys(&db), vec![0, 1, 2]);
+        assert_eq!(rows(&db), expected);
+
+        // a journaled row deleted before the recovery
+        journal_demotion(&mut db, &[3]);
+        put(&mut db, &[(3, None)]);
+        expected.pop();
+        db.recover_demotion::<MockTable>().unwrap();
+        assert!(!has_journal(&db));
+        assert_eq!(cold_keys(&db), vec![0, 1, 2]);
+        assert_eq!(rows(&db), expected);
+
+        // a later demotion is not blocked by the recovered journals
+        put(&mut db, &[(4, Some(14))]);
+        assert_eq!(db.demote::<MockTable>(|_, _| true).unwrap(), 1);
+        assert_eq!(cold_keys(&db), vec![0, 1, 2, 4]);
+        expected.push((4, 14));
+        assert_eq!(rows(&db), expected);
+    }
+}
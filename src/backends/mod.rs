@@ -1,15 +1,23 @@
+mod compression;
+#[cfg(test)]
+mod conformance_tests;
 pub mod impls;
 pub mod serde;
 mod table;
 mod table_name;
 mod write_schema;
 
+pub use compression::Compression;
 pub use impls::in_memory_db::InMemoryDatabase;
+pub use impls::in_memory_subkey_db::InMemorySubkeyDatabase;
+pub use impls::tiered_db::TieredDatabase;
 pub use table::{TableIter, TableKey, TableRead, TableReader, TableSchema, TableValue};
 pub use table_name::{TableName, VersionedKVName};
 pub use write_schema::WriteSchemaTrait;
 
-use crate::errors::Result;
+use std::path::Path;
+
+use crate::errors::{Result, StorageError};
 
 /// Trait defining the interface for a backend database, which provides multiple tables, each acting as a key-value store.
 pub trait DatabaseTrait: Sized + Send + Sync {
@@ -19,7 +27,7 @@ pub trait DatabaseTrait: Sized + Send + Sync {
 
     /// Type for collecting write operations.
     /// Each database can specify its own format to accommodate different key format extensions.
-    /// For example, MDBX supports subkeys.
+    /// For example, MDBX supports subkeys, as [`InMemorySubkeyDatabase`] does.
     type WriteSchema: WriteSchemaTrait;
 
     /// Returns a read-only view of a table.
@@ -42,6 +50,28 @@ pub trait DatabaseTrait: Sized + Send + Sync {
 
     /// Atomically commits multiple modifications to the database.
     ///
+    /// The readers returned by [`DatabaseTrait::view`] borrow the database, so none of them
+    /// outlives a commit: a reader never observes a commit in progress or after it, on any backend,
+    /// and readers created after the commit see all of it. Holders of readers, such as
+    /// `VersionedStore`, are therefore recreated after each commit.
+    ///
+    /// ```compile_fail
+    /// use cfx_storage2::backends::{DatabaseTrait, InMemoryDatabase, TableName, TableRead, TableSchema};
+    ///
+    /// #[derive(Clone, Copy)]
+    /// struct Table;
+    /// impl TableSchema for Table {
+    ///     const NAME: TableName = TableName::CommitAlias;
+    ///     type Key = [u8];
+    ///     type Value = [u8];
+    /// }
+    ///
+    /// let mut db = InMemoryDatabase::empty();
+    /// let reader = db.view::<Table>().unwrap();
+    /// db.commit(InMemoryDatabase::write_schema()).unwrap();
+    /// reader.get(b"key".as_slice()).unwrap();
+    /// ```
+    ///
     /// # Parameters
     ///
     /// * `changes`: The WriteSchema containing the modifications to be committed.
@@ -50,4 +80,78 @@ pub trait DatabaseTrait: Sized + Send + Sync {
     ///
     /// A `Result` indicating success or failure of the commit operation.
     fn commit(&mut self, changes: Self::WriteSchema) -> Result<()>;
+
+    /// Atomically commits several WriteSchemas as one, in order: where two of them write the
+    /// same key, the later one wins.
+    ///
+    /// # Parameters
+    ///
+    /// * `schemas`: The WriteSchemas to be committed, merged by [`WriteSchemaTrait::merge`].
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the commit operation.
+    fn commit_many(&mut self, schemas: Vec<Self::WriteSchema>) -> Result<()> {
+        let mut merged = Self::write_schema();
+        for schema in schemas {
+            merged.merge(schema);
+        }
+        self.commit(merged)
+    }
+
+    /// Writes a consistent copy of all tables to `path`, which must not exist yet. Fails with
+    /// [`StorageError::Unsupported`] by default, for the backends without checkpoints.
+    ///
+    /// The RocksDB backend copies every row, so a checkpoint costs O(database size).
+    ///
+    /// # Parameters
+    ///
+    /// * `path`: The location of the checkpoint.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the checkpoint.
+    fn create_checkpoint(&self, _path: &Path) -> Result<()> {
+        Err(StorageError::Unsupported("checkpoints"))
+    }
+
+    /// Opens a database from a checkpoint written by `create_checkpoint`. Fails with
+    /// [`StorageError::Unsupported`] by default, for the backends without checkpoints.
+    ///
+    /// # Parameters
+    ///
+    /// * `path`: The location of the checkpoint.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the database restored from the checkpoint.
+    fn open_checkpoint(_path: &Path) -> Result<Self> {
+        Err(StorageError::Unsupported("checkpoints"))
+    }
+
+    /// Reclaims the space of the rows of table `T` deleted or overwritten, where the backend
+    /// defers it, e.g. after pruning the history. Does nothing by default. Fails with
+    /// [`StorageError::Unsupported`] on a backend deferring it without a way to force it.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `T`: The schema of the table to be compacted.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the compaction.
+    fn compact<T: TableSchema>(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the approximate size in bytes of each table of [`TableName::ALL`]. Empty by
+    /// default, for the backends not reporting it. Fails with [`StorageError::Unsupported`]
+    /// on a backend which could only count the sizes by reading every row.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the tables with their sizes.
+    fn approximate_sizes(&self) -> Result<Vec<(TableName, u64)>> {
+        Ok(Vec::new())
+    }
 }
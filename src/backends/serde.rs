@@ -90,33 +90,97 @@ impl Encode for H256 {
 }
 
 impl Decode for H256 {
+    /// Borrows the input, so that decoding a key does not copy it.
     fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
-        if input.len() != H256::len_bytes() {
-            return Err(DecodeError::IncorrectLength);
-        }
+        use std::mem::{align_of, size_of};
+        const_assert_eq!(size_of::<H256>(), size_of::<[u8; 32]>());
+        const_assert_eq!(align_of::<H256>(), align_of::<[u8; 32]>());
 
-        Ok(Cow::Owned(H256::from_slice(input)))
+        let raw: &[u8; 32] = input.try_into().map_err(|_| DecodeError::IncorrectLength)?;
+        // `H256` is a `#[repr(C)]` wrapper of `[u8; 32]`
+        let hash = unsafe { &*(raw as *const [u8; 32] as *const H256) };
+        Ok(Cow::Borrowed(hash))
     }
 }
 
-impl Encode for u64 {
+/// Implements the fixed-length encoding of integers as big-endian bytes, which sort like the
+/// integers themselves.
+macro_rules! impl_order_preserving_encode {
+    ($($t:ty),+) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self) -> Cow<[u8]> {
+                    Cow::Owned(self.to_be_bytes().to_vec())
+                }
+            }
+
+            impl FixedLengthEncoded for $t {
+                const LENGTH: usize = std::mem::size_of::<$t>();
+            }
+
+            impl Decode for $t {
+                fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
+                    let raw = input.try_into().map_err(|_| DecodeError::IncorrectLength)?;
+                    Ok(Cow::Owned(<$t>::from_be_bytes(raw)))
+                }
+            }
+        )+
+    };
+}
+
+impl_order_preserving_encode!(u16, u32, u64, u128);
+
+/// Encodes a pair as its components back to back. As the first component has a fixed length,
+/// the encodings sort like the pairs if the encodings of both components sort like them.
+pub fn encode_pair<A, B>(first: &A, second: &B) -> Vec<u8>
+where
+    A: ?Sized + FixedLengthEncoded,
+    B: ?Sized + Encode,
+{
+    [first.encode().as_ref(), second.encode().as_ref()].concat()
+}
+
+/// Decodes a pair encoded by [`encode_pair`].
+pub fn decode_pair<A, B>(input: &[u8]) -> DecResult<(Cow<A>, Cow<B>)>
+where
+    A: ?Sized + FixedLengthEncoded + Decode,
+    B: ?Sized + Decode,
+{
+    if input.len() < A::LENGTH {
+        return Err(DecodeError::IncorrectLength);
+    }
+    let (first, second) = input.split_at(A::LENGTH);
+    Ok((A::decode(first)?, B::decode(second)?))
+}
+
+/// A pair with a fixed-length first component, encoded by [`encode_pair`]. The second component
+/// is the subkey.
+impl<A: Clone + FixedLengthEncoded, B: Clone + Encode> Encode for (A, B) {
     fn encode(&self) -> Cow<[u8]> {
-        Cow::Owned(self.to_be_bytes().to_vec())
+        Cow::Owned(encode_pair(&self.0, &self.1))
     }
 }
 
-impl FixedLengthEncoded for u64 {
-    const LENGTH: usize = std::mem::size_of::<u64>();
+impl<A: Clone + FixedLengthEncoded, B: Clone + FixedLengthEncoded> FixedLengthEncoded for (A, B) {
+    const LENGTH: usize = A::LENGTH + B::LENGTH;
 }
 
-impl Decode for u64 {
-    fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
-        const BYTES: usize = std::mem::size_of::<u64>();
-        if input.len() != (u64::BITS / 8) as usize {
-            return Err(DecodeError::IncorrectLength);
-        }
+impl<A: Clone + FixedLengthEncoded, B: Clone + Encode> EncodeSubKey for (A, B) {
+    const HAVE_SUBKEY: bool = true;
 
-        Ok(Cow::Owned(u64::from_be_bytes(input.try_into().unwrap())))
+    fn encode_subkey(&self) -> (Cow<[u8]>, Cow<[u8]>) {
+        (self.0.encode(), self.1.encode())
+    }
+}
+
+impl<A, B> Decode for (A, B)
+where
+    A: Clone + FixedLengthEncoded + Decode + ToOwned<Owned = A>,
+    B: Clone + Decode + ToOwned<Owned = B>,
+{
+    fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
+        let (first, second) = decode_pair::<A, B>(input)?;
+        Ok(Cow::Owned((first.into_owned(), second.into_owned())))
     }
 }
 
@@ -166,4 +230,114 @@ macro_rules! subkey_not_support {
     };
 }
 
-subkey_not_support!([u8], H256, u64, Box<[u8]>);
+subkey_not_support!([u8], H256, u16, u32, u64, u128, Box<[u8]>);
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn check_keep_order<T: Encode + Decode + Debug + Ord + ToOwned<Owned = T>>(a: T, b: T) {
+        assert_eq!(a.cmp(&b), a.encode().cmp(&b.encode()));
+        assert_eq!(T::decode(&a.encode()).unwrap().into_owned(), a);
+    }
+
+    fn h256() -> impl Strategy<Value = H256> {
+        any::<[u8; 32]>().prop_map(H256)
+    }
+
+    fn bytes() -> impl Strategy<Value = Box<[u8]>> {
+        prop::collection::vec(any::<u8>(), 0..8).prop_map(Vec::into_boxed_slice)
+    }
+
+    proptest! {
+        #[test]
+        fn test_serde_keep_order_u16(a in any::<u16>(), b in any::<u16>()) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_u32(a in any::<u32>(), b in any::<u32>()) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_u64(a in any::<u64>(), b in any::<u64>()) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_u128(a in any::<u128>(), b in any::<u128>()) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_h256(a in h256(), b in h256()) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_pair(
+            a in (any::<u64>(), any::<u16>()),
+            b in (any::<u64>(), any::<u16>()),
+        ) {
+            check_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_serde_keep_order_pair_with_bytes(a in (0..4u32, bytes()), b in (0..4u32, bytes())) {
+            check_keep_order(a, b)
+        }
+    }
+
+    #[test]
+    fn test_integer_layout() {
+        assert_eq!(0x0102u16.encode().as_ref(), [1, 2]);
+        assert_eq!(0x01020304u32.encode().as_ref(), [1, 2, 3, 4]);
+        assert_eq!(
+            0x0102030405060708u64.encode().as_ref(),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(1u128.encode().as_ref(), [[0; 15].as_slice(), &[1]].concat());
+        assert_eq!(
+            (1u64, 2u16).encode().as_ref(),
+            [0, 0, 0, 0, 0, 0, 0, 1, 0, 2]
+        );
+
+        assert_eq!(
+            u64::decode(&[0; 7]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+        assert_eq!(
+            u16::decode(&[0; 3]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+        assert_eq!(
+            <(u64, u16)>::decode(&[0; 9]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+    }
+    #[test]
+    fn test_h256_decode_borrows() {
+        let raw: Vec<u8> = (0..32).collect();
+        let decoded = H256::decode(&raw).unwrap();
+        assert!(matches!(decoded, Cow::Borrowed(_)));
+        assert_eq!(decoded.as_bytes(), raw.as_slice());
+        assert_eq!(
+            H256::decode_owned(raw.clone()).unwrap(),
+            H256::from_slice(&raw)
+        );
+
+        assert_eq!(
+            H256::decode(&raw[1..]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+        assert_eq!(
+            H256::decode(&[0; 33]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+    }
+}
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::fmt::Debug;
 use std::sync::Arc;
 
+use super::compression::Compression;
 use super::serde::{Decode, Encode, EncodeSubKey};
 use super::table_name::TableName;
 use crate::combine_traits;
@@ -23,6 +24,17 @@ pub trait TableRead<T: TableSchema> {
     fn iter<'a>(&'a self, key: &T::Key) -> Result<TableIter<'a, '_, T>>;
 
     fn iter_from_start(&self) -> Result<TableIter<T>>;
+
+    /// Iterates from the last key not greater than `key` down to the first key of the table.
+    ///
+    /// Fails with [`StorageError::Unsupported`](crate::errors::StorageError::Unsupported) on
+    /// the backends unable to iterate backward. RocksDB through kvdb only seeks forward, so each
+    /// row costs a few seeks per byte of its key.
+    fn iter_rev<'a>(&'a self, key: &T::Key) -> Result<TableIter<'a, '_, T>>;
+
+    /// Iterates from the last key of the table down to the first one. Fails as
+    /// [`TableRead::iter_rev`] does.
+    fn iter_from_end(&self) -> Result<TableIter<T>>;
 }
 
 combine_traits!(TableKey: 'static + EncodeSubKey + Decode + ToOwned + Ord + Eq + Send + Sync + Debug);
@@ -30,6 +42,13 @@ combine_traits!(TableValue: 'static + Encode + Decode + ToOwned  + Send + Sync +
 
 pub trait TableSchema: 'static + Copy + Send + Sync {
     const NAME: TableName;
+    /// Whether the backends supporting subkeys store the rows grouped by the first part of
+    /// [`EncodeSubKey::encode_subkey`], e.g. the version of a `ChangeKey`. The first part must
+    /// have a fixed length, so that the grouping keeps the order of the encoded keys.
+    const SUPPORTS_SUBKEY: bool = false;
+    /// How the values are compressed, if they are stored with a compression header at all. The
+    /// header records the compression of each value, so the setting can change while `Some`.
+    const VALUE_COMPRESSION: Option<Compression> = None;
     type Key: TableKey + ?Sized;
     type Value: TableValue + ?Sized;
 }
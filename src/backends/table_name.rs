@@ -5,6 +5,11 @@ pub enum TableName {
     HistoryChange(VersionedKVName),
     HistoryIndex(VersionedKVName),
     AuthNodeChange,
+    CommitAlias,
+    DemotionJournal,
+    AuthChangeRoot,
+    CommitMetadata,
+    LvmtMetadata,
     #[cfg(test)]
     MockTable,
 }
@@ -14,6 +19,7 @@ pub enum VersionedKVName {
     FlatKV,
     AmtNode,
     SlotAllocation,
+    FixedKV,
 }
 
 pub const fn change_history(versioned_kv: VersionedKVName) -> TableName {
@@ -28,8 +34,28 @@ use TableName::*;
 use VersionedKVName::*;
 
 impl TableName {
+    /// Every table, in the order of their indices.
+    pub const ALL: [TableName; 16] = [
+        CommitID,
+        HistoryNumber,
+        HistoryChange(FlatKV),
+        HistoryIndex(FlatKV),
+        HistoryChange(AmtNode),
+        HistoryIndex(AmtNode),
+        HistoryChange(SlotAllocation),
+        HistoryIndex(SlotAllocation),
+        AuthNodeChange,
+        CommitAlias,
+        DemotionJournal,
+        AuthChangeRoot,
+        CommitMetadata,
+        LvmtMetadata,
+        HistoryChange(FixedKV),
+        HistoryIndex(FixedKV),
+    ];
+
     pub const fn max_index() -> u32 {
-        9
+        16
     }
 }
 
@@ -45,6 +71,13 @@ impl From<TableName> for u32 {
             HistoryChange(SlotAllocation) => 7,
             HistoryIndex(SlotAllocation) => 8,
             AuthNodeChange => 9,
+            CommitAlias => 10,
+            DemotionJournal => 11,
+            AuthChangeRoot => 12,
+            CommitMetadata => 13,
+            LvmtMetadata => 14,
+            HistoryChange(FixedKV) => 15,
+            HistoryIndex(FixedKV) => 16,
             #[cfg(test)]
             MockTable => u32::MAX,
         }
@@ -63,6 +96,13 @@ impl From<TableName> for &'static str {
             HistoryChange(SlotAllocation) => "slot_alloc_change_history",
             HistoryIndex(SlotAllocation) => "slot_alloc_history_index",
             AuthNodeChange => "auth_node_change",
+            CommitAlias => "commit_alias",
+            DemotionJournal => "demotion_journal",
+            AuthChangeRoot => "auth_change_root",
+            CommitMetadata => "commit_metadata",
+            LvmtMetadata => "lvmt_metadata",
+            HistoryChange(FixedKV) => "fixed_kv_change_history",
+            HistoryIndex(FixedKV) => "fixed_kv_history_index",
             #[cfg(test)]
             MockTable => "mock_table",
         }
@@ -1,6 +1,8 @@
 mod no_sub_key;
+mod with_sub_key;
 
 pub use no_sub_key::WriteSchemaNoSubkey;
+pub use with_sub_key::{WriteSchemaSubkeyOp, WriteSchemaWithSubkey};
 
 use super::TableSchema;
 use auto_impl::auto_impl;
@@ -15,6 +17,19 @@ pub type TableWriteOp<'a, T> = (
 pub trait WriteSchemaTrait: Send + Sync {
     fn write<T: TableSchema>(&self, op: TableWriteOp<'_, T>);
     fn write_batch<'a, T: TableSchema>(&self, changes: impl Iterator<Item = TableWriteOp<'a, T>>);
+
+    /// Moves the writes of `other` after those of `self`, leaving `other` empty.
+    fn append(&self, other: &Self);
+
+    /// Adds the writes of `other` after those of `self`. Where both write the same key, the
+    /// write of `other` is committed last and wins.
+    #[auto_impl(keep_default_for(&))]
+    fn merge(&mut self, other: Self)
+    where
+        Self: Sized,
+    {
+        self.append(&other)
+    }
 }
 
 type A = Box<dyn WriteSchemaTrait>;
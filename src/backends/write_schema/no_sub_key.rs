@@ -1,4 +1,4 @@
-use super::super::{serde::Encode, TableName, TableSchema};
+use super::super::{compression::encode_value, serde::Encode, TableName, TableSchema};
 use super::{TableWriteOp, WriteSchemaTrait};
 use parking_lot::Mutex;
 
@@ -20,6 +20,12 @@ impl<Name> WriteSchemaNoSubkey<Name> {
         }
     }
 
+    pub fn from_ops(ops: Vec<WriteSchemaOp<Name>>) -> Self {
+        Self {
+            inner: Mutex::new(ops),
+        }
+    }
+
     pub fn drain(self) -> Vec<WriteSchemaOp<Name>> {
         let mut inner = self.inner.lock();
 
@@ -31,7 +37,7 @@ impl<Name: From<TableName>> WriteSchemaNoSubkey<Name> {
     fn write_inner<T: TableSchema>(inner: &mut Vec<WriteSchemaOp<Name>>, op: TableWriteOp<T>) {
         let (key, value) = op;
         let raw_key = <T::Key as Encode>::encode_cow(key).into_owned();
-        let raw_value = value.map(|v| <T::Value as Encode>::encode_cow(v).into_owned());
+        let raw_value = value.map(encode_value::<T>);
         inner.push((T::NAME.into(), raw_key, raw_value))
     }
 }
@@ -51,4 +57,12 @@ impl<Name: From<TableName> + Send + Sync> WriteSchemaTrait for WriteSchemaNoSubk
             Self::write_inner::<T>(&mut *inner, op)
         }
     }
+
+    fn append(&self, other: &Self) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        let mut ops = std::mem::take(&mut *other.inner.lock());
+        self.inner.lock().append(&mut ops);
+    }
 }
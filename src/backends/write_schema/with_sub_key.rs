@@ -0,0 +1,144 @@
+use super::super::{
+    compression::encode_value,
+    serde::{Encode, EncodeSubKey},
+    TableName, TableSchema,
+};
+use super::{TableWriteOp, WriteSchemaTrait};
+use parking_lot::Mutex;
+
+/// The table, the key, the subkey and the value of a write. The subkey is `None` for the tables
+/// not stored with subkeys, whose key is encoded as a whole.
+pub type WriteSchemaSubkeyOp<Name> = (Name, Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Collects writes for the backends storing subkeys. The key of a table with
+/// [`TableSchema::SUPPORTS_SUBKEY`] is split by [`EncodeSubKey::encode_subkey`].
+pub struct WriteSchemaWithSubkey<Name> {
+    inner: Mutex<Vec<WriteSchemaSubkeyOp<Name>>>,
+}
+
+impl<Name> Default for WriteSchemaWithSubkey<Name> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Name> WriteSchemaWithSubkey<Name> {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn from_ops(ops: Vec<WriteSchemaSubkeyOp<Name>>) -> Self {
+        Self {
+            inner: Mutex::new(ops),
+        }
+    }
+
+    pub fn drain(self) -> Vec<WriteSchemaSubkeyOp<Name>> {
+        let mut inner = self.inner.lock();
+
+        std::mem::take(&mut *inner)
+    }
+}
+
+impl<Name: From<TableName>> WriteSchemaWithSubkey<Name> {
+    fn write_inner<T: TableSchema>(
+        inner: &mut Vec<WriteSchemaSubkeyOp<Name>>,
+        op: TableWriteOp<T>,
+    ) {
+        let (key, value) = op;
+        let (raw_key, raw_subkey) = if T::SUPPORTS_SUBKEY {
+            let (key, subkey) = <T::Key as EncodeSubKey>::encode_subkey_cow(key);
+            (key.into_owned(), Some(subkey.into_owned()))
+        } else {
+            (<T::Key as Encode>::encode_cow(key).into_owned(), None)
+        };
+        let raw_value = value.map(encode_value::<T>);
+        inner.push((T::NAME.into(), raw_key, raw_subkey, raw_value))
+    }
+}
+
+impl<Name: From<TableName> + Send + Sync> WriteSchemaTrait for WriteSchemaWithSubkey<Name> {
+    fn write<T: TableSchema>(&self, op: TableWriteOp<'_, T>) {
+        let mut inner = self.inner.lock();
+        Self::write_inner::<T>(&mut *inner, op)
+    }
+
+    fn write_batch<'a, T: TableSchema>(
+        &self,
+        changes: impl IntoIterator<Item = TableWriteOp<'a, T>>,
+    ) {
+        let mut inner = self.inner.lock();
+        for op in changes {
+            Self::write_inner::<T>(&mut *inner, op)
+        }
+    }
+
+    fn append(&self, other: &Self) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        let mut ops = std::mem::take(&mut *other.inner.lock());
+        self.inner.lock().append(&mut ops);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::middlewares::ChangeKey;
+
+    #[derive(Clone, Copy)]
+    struct ChangeTable;
+    impl TableSchema for ChangeTable {
+        const NAME: TableName = TableName::AuthNodeChange;
+        const SUPPORTS_SUBKEY: bool = true;
+        type Key = ChangeKey<u64, Box<[u8]>>;
+        type Value = [u8];
+    }
+
+    #[derive(Clone, Copy)]
+    struct PlainTable;
+    impl TableSchema for PlainTable {
+        const NAME: TableName = TableName::CommitAlias;
+        type Key = [u8];
+        type Value = [u8];
+    }
+
+    #[test]
+    fn test_split_subkey() {
+        let key = ChangeKey::new(1u64, b"key".to_vec().into_boxed_slice());
+        let write_schema = WriteSchemaWithSubkey::<u32>::new();
+        write_schema
+            .write::<ChangeTable>((Cow::Borrowed(&key), Some(Cow::Borrowed(b"1".as_slice()))));
+        write_schema.write::<ChangeTable>((Cow::Owned(key.clone()), None));
+        write_schema.write::<PlainTable>((
+            Cow::Borrowed(b"key".as_slice()),
+            Some(Cow::Borrowed(b"2".as_slice())),
+        ));
+
+        let table: u32 = ChangeTable::NAME.into();
+        let version = 1u64.to_be_bytes().to_vec();
+        assert_eq!(
+            write_schema.drain(),
+            vec![
+                (
+                    table,
+                    version.clone(),
+                    Some(b"key".to_vec()),
+                    Some(b"1".to_vec())
+                ),
+                (table, version, Some(b"key".to_vec()), None),
+                (
+                    PlainTable::NAME.into(),
+                    b"key".to_vec(),
+                    None,
+                    Some(b"2".to_vec())
+                ),
+            ]
+        );
+    }
+}
@@ -1,7 +1,23 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Debug, Display},
+    hash::Hash,
+};
+
 use ark_serialize::SerializationError;
 use thiserror::Error;
 
-use crate::middlewares::{CommitID, PendingError};
+use crate::{
+    backends::{
+        serde::{Decode, Encode},
+        TableName, TableSchema,
+    },
+    middlewares::{CommitID, HistoryNumber, PendingError},
+};
+
+/// Commit id carried by [`StorageError::PendingError`], in its encoded form,
+/// so that matching on the error does not depend on the concrete commit id type.
+pub type EncodedCommitId = Box<[u8]>;
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -14,14 +30,171 @@ pub enum StorageError {
     #[error("commit id already in the historical part but try to add to pending")]
     CommitIdAlreadyExistsInHistory,
 
+    #[error("commit already added under another parent")]
+    ParentMismatch,
+
+    #[error("alias is already registered to another commit")]
+    AliasAlreadyRegistered,
+
+    #[error("empty keys are not supported")]
+    EmptyKey,
+
+    #[error("key of {0} bytes exceeds the maximum key length")]
+    KeyTooLong(usize),
+
+    #[error("slot allocation conflicts with the recorded allocation of the key")]
+    SlotAllocationConflict,
+
+    #[error("allocation scheme differs from the one the database is built with")]
+    AllocationSchemeMismatch,
+
     #[error("backend db fails consistency check")]
     ConsistencyCheckFailure,
 
+    #[error("height overflows the history number range")]
+    HeightOverflow,
+
+    #[error("history number {0} does not map to a height")]
+    InvalidHistoryNumber(HistoryNumber),
+
+    #[error("height {0} is not confirmed")]
+    HeightNotConfirmed(usize),
+
+    #[error("new commit would be at height {actual} instead of {expected}")]
+    HeightMismatch { expected: usize, actual: usize },
+
+    #[error("invalid backup: {0}")]
+    InvalidBackup(&'static str),
+
+    #[error("table is not tiered")]
+    TableNotTiered,
+
+    #[error("{0} not supported by the backend")]
+    Unsupported(&'static str),
+
     #[error("database error {0:?}")]
     DatabaseError(#[from] DatabaseError),
 
     #[error("pending error {0:?}")]
-    PendingError(#[from] PendingError<CommitID>),
+    PendingError(PendingError<EncodedCommitId>),
+
+    /// A [`StorageError::ConsistencyCheckFailure`] with the row found inconsistent, see
+    /// [`StorageError::with_context`]. Match through [`StorageError::root`].
+    #[error("{error} at {context}")]
+    WithContext {
+        error: Box<StorageError>,
+        context: Box<ErrorContext>,
+    },
+}
+
+/// Where an error happened, attached with [`StorageError::with_context`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub table: TableName,
+    /// The encoded key of the row.
+    pub key: Box<[u8]>,
+    /// The history number of the version read or written, if any.
+    pub version: Option<HistoryNumber>,
+    pub reason: Option<InconsistencyReason>,
+}
+
+impl ErrorContext {
+    pub fn new<T: TableSchema>(key: &T::Key, version: Option<HistoryNumber>) -> Self {
+        Self {
+            table: T::NAME,
+            key: key.encode().into(),
+            version,
+            reason: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: InconsistencyReason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "table {:?}, key 0x", self.table)?;
+        for byte in self.key.iter() {
+            write!(f, "{byte:02x}")?;
+        }
+        if let Some(version) = self.version {
+            write!(f, ", version {version}")?;
+        }
+        if let Some(reason) = self.reason {
+            write!(f, " ({reason})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a row is inconsistent with the rest of the database.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InconsistencyReason {
+    #[error("index record above the latest confirmed commit")]
+    AboveLatestConfirmed,
+    #[error("several index records at or below the earliest confirmed commit")]
+    SeveralPrunedVersions,
+    #[error("change row without an index record")]
+    UnreachableChange,
+    #[error("commit or height already confirmed")]
+    AlreadyConfirmed,
+    #[error("commit and history number tables disagree")]
+    MismatchedCommitId,
+}
+
+impl StorageError {
+    /// Attaches where the error happened. The contexts attached to an error are kept, the
+    /// innermost first.
+    ///
+    /// Only attached to [`StorageError::ConsistencyCheckFailure`], to tell which row is
+    /// inconsistent. The other errors are returned bare, so that matching them is unchanged.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        debug_assert!(
+            matches!(self.root(), Self::ConsistencyCheckFailure),
+            "context attached to {self:?}"
+        );
+        Self::WithContext {
+            error: Box::new(self),
+            context: Box::new(context),
+        }
+    }
+
+    /// The error without the contexts attached to it, to match on.
+    pub fn root(&self) -> &StorageError {
+        match self {
+            Self::WithContext { error, .. } => error.root(),
+            _ => self,
+        }
+    }
+
+    /// The innermost context attached to the error.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::WithContext { error, context } => error.context().or(Some(context)),
+            _ => None,
+        }
+    }
+
+    /// Returns the commit id carried by a pending error, decoded as a `CommitID`.
+    pub fn pending_commit_id_h256(&self) -> Option<CommitID> {
+        match self {
+            Self::PendingError(
+                PendingError::CommitIDNotFound(commit_id)
+                | PendingError::CommitIdAlreadyExists(commit_id)
+                | PendingError::CannotPrunePendingRoot(commit_id),
+            ) => CommitID::decode(commit_id).ok().map(Cow::into_owned),
+            _ => None,
+        }
+    }
+}
+
+impl<C: Encode + Debug + Eq + Hash> From<PendingError<C>> for StorageError {
+    fn from(value: PendingError<C>) -> Self {
+        Self::PendingError(value.map_commit_id(|commit_id| commit_id.encode().into()))
+    }
 }
 
 impl From<DecodeError> for StorageError {
@@ -77,9 +250,41 @@ impl PartialEq for StorageError {
             (VersionNotFound, VersionNotFound) => true,
             (CommitIDNotFound, CommitIDNotFound) => true,
             (CommitIdAlreadyExistsInHistory, CommitIdAlreadyExistsInHistory) => true,
+            (ParentMismatch, ParentMismatch) => true,
+            (AliasAlreadyRegistered, AliasAlreadyRegistered) => true,
+            (EmptyKey, EmptyKey) => true,
+            (KeyTooLong(a), KeyTooLong(b)) => a == b,
+            (SlotAllocationConflict, SlotAllocationConflict) => true,
+            (AllocationSchemeMismatch, AllocationSchemeMismatch) => true,
+            (HeightOverflow, HeightOverflow) => true,
+            (InvalidHistoryNumber(a), InvalidHistoryNumber(b)) => a == b,
+            (HeightNotConfirmed(a), HeightNotConfirmed(b)) => a == b,
+            (
+                HeightMismatch {
+                    expected: e1,
+                    actual: a1,
+                },
+                HeightMismatch {
+                    expected: e2,
+                    actual: a2,
+                },
+            ) => e1 == e2 && a1 == a2,
+            (InvalidBackup(a), InvalidBackup(b)) => a == b,
+            (TableNotTiered, TableNotTiered) => true,
+            (Unsupported(a), Unsupported(b)) => a == b,
             (ConsistencyCheckFailure, ConsistencyCheckFailure) => true,
             (DatabaseError(e1), DatabaseError(e2)) => e1 == e2,
             (PendingError(e1), PendingError(e2)) => e1 == e2,
+            (
+                WithContext {
+                    error: e1,
+                    context: c1,
+                },
+                WithContext {
+                    error: e2,
+                    context: c2,
+                },
+            ) => e1 == e2 && c1 == c2,
             _ => false,
         }
     }
@@ -1,7 +1,11 @@
 use crate::{
-    backends::{InMemoryDatabase, VersionedKVName},
+    backends::{DatabaseTrait, InMemoryDatabase, VersionedKVName},
     errors::Result,
-    middlewares::{table_schema::VersionedKeyValueSchema, VersionedStore, VersionedStoreCache},
+    middlewares::{
+        compact_history, confirmed_pending_to_history, table_schema::VersionedKeyValueSchema,
+        CommitAliasSchema, CommitID, CommitIDSchema, CommitMetadataSchema, ConfirmedPath,
+        HistoryNumberSchema, VersionedStore, VersionedStoreCache,
+    },
     traits::KeyValueStoreManager,
 };
 use ethereum_types::H256;
@@ -23,9 +27,35 @@ impl Storage {
     pub fn as_manager(&mut self) -> Result<VersionedStore<'_, '_, FlatKeyValue>> {
         VersionedStore::new(&self.backend, &mut self.cache)
     }
+
+    /// Confirms the pending commits up to the parent of `new_root_commit_id`, and returns them.
+    pub fn confirmed_pending_to_history(
+        &mut self,
+        new_root_commit_id: CommitID,
+    ) -> Result<ConfirmedPath> {
+        let write_schema = InMemoryDatabase::write_schema();
+        let confirmed_path = confirmed_pending_to_history(
+            &self.backend,
+            &mut self.cache,
+            new_root_commit_id,
+            &write_schema,
+        )?;
+        self.backend.commit(write_schema)?;
+        Ok(confirmed_path)
+    }
+
+    /// Compacts every table of the storage, e.g. after pruning the history.
+    pub fn compact_all(&self) -> Result<()> {
+        compact_history::<_, FlatKeyValue>(&self.backend)?;
+        self.backend.compact::<CommitIDSchema>()?;
+        self.backend.compact::<CommitMetadataSchema>()?;
+        self.backend.compact::<HistoryNumberSchema>()?;
+        self.backend.compact::<CommitAliasSchema>()
+    }
 }
 
 assert_impl_all!(VersionedStore<'_, '_, FlatKeyValue>: KeyValueStoreManager<Box<[u8]>, Box<[u8]>, H256>);
+assert_impl_all!(VersionedStore<'_, '_, FixedKeyValue>: KeyValueStoreManager<H256, Box<[u8]>, H256>);
 
 #[derive(Clone, Copy, Debug)]
 pub struct FlatKeyValue;
@@ -36,3 +66,152 @@ impl VersionedKeyValueSchema for FlatKeyValue {
     type Key = Box<[u8]>;
     type Value = Box<[u8]>;
 }
+
+/// A schema with 32-byte keys, e.g. the hashes of the accounts. Decoding an `H256` borrows the
+/// encoded bytes, so iterating the history does not allocate for each key.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedKeyValue;
+
+impl VersionedKeyValueSchema for FixedKeyValue {
+    const NAME: VersionedKVName = VersionedKVName::FixedKV;
+
+    type Key = H256;
+    type Value = Box<[u8]>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ethereum_types::H256;
+    use rand_chacha::rand_core::RngCore;
+
+    use super::FixedKeyValue;
+    use crate::{
+        backends::{DatabaseTrait, InMemoryDatabase},
+        middlewares::{
+            confirmed_pending_to_history, CommitID, VersionedStore, VersionedStoreCache,
+        },
+        test_utils::{gen_random_commit_id, get_rng_for_test},
+        traits::{KeyValueStoreManager, KeyValueStoreRead},
+    };
+
+    // the store reads the states of a model of the commits
+    #[test]
+    fn test_fixed_key_value() {
+        let mut rng = get_rng_for_test();
+        let keys: Vec<H256> = (0..32).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+        let mut db = InMemoryDatabase::empty();
+        let mut cache = VersionedStoreCache::<FixedKeyValue>::new_empty();
+        let mut states: Vec<(CommitID, BTreeMap<H256, Box<[u8]>>)> = Vec::new();
+        // the index in `states` of the first pending commit
+        let mut num_confirmed = 0;
+
+        for round in 0..64 {
+            let (parent, mut state) = match states.last() {
+                Some((commit, state)) => (Some(*commit), state.clone()),
+                None => (None, BTreeMap::new()),
+            };
+            let mut updates = Vec::new();
+            for _ in 0..(rng.next_u64() % 8 + 1) {
+                let key = keys[rng.next_u64() as usize % keys.len()];
+                if rng.next_u64() % 4 == 0 {
+                    state.remove(&key);
+                    updates.push((key, None));
+                } else {
+                    let value: Box<[u8]> = rng.next_u64().to_be_bytes().into();
+                    state.insert(key, value.clone());
+                    updates.push((key, Some(value)));
+                }
+            }
+            let commit = gen_random_commit_id(&mut rng);
+            let mut store = VersionedStore::new(&db, &mut cache).unwrap();
+            store.add_to_pending_part(parent, commit, updates).unwrap();
+            states.push((commit, state));
+
+            if round % 8 == 7 {
+                num_confirmed = states.len() - 1;
+                let write_schema = InMemoryDatabase::write_schema();
+                confirmed_pending_to_history(&db, &mut cache, commit, &write_schema).unwrap();
+                db.commit(write_schema).unwrap();
+            }
+
+            let store = VersionedStore::new(&db, &mut cache).unwrap();
+            for (index, (commit, state)) in states.iter().enumerate() {
+                let snapshot = store.get_versioned_store(commit).unwrap();
+                for key in &keys {
+                    let expected = state.get(key).cloned();
+                    assert_eq!(store.get_versioned_key(commit, key).unwrap(), expected);
+                    assert_eq!(snapshot.get(key).unwrap(), expected);
+                }
+                assert_eq!(store.is_pending(commit), index >= num_confirmed);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "count-allocations"))]
+mod allocations {
+    use std::collections::BTreeMap;
+
+    use ethereum_types::H256;
+
+    use super::{FixedKeyValue, FlatKeyValue};
+    use crate::{
+        backends::{DatabaseTrait, InMemoryDatabase, TableRead},
+        middlewares::{
+            confirm_maps_to_history,
+            table_schema::{HistoryIndicesTable, VersionedKeyValueSchema},
+        },
+        utils::allocations::allocations,
+    };
+
+    // the allocations of iterating the history index of `num_keys` keys
+    fn count_iteration_allocations<T: VersionedKeyValueSchema<Value = Box<[u8]>>>(
+        num_keys: u64,
+        key: impl Fn(u64) -> T::Key,
+    ) -> usize {
+        let mut db = InMemoryDatabase::empty();
+        let write_schema = InMemoryDatabase::write_schema();
+        let changes: BTreeMap<_, _> = (0..num_keys)
+            .map(|i| (key(i), Some(Box::from([0u8].as_slice()))))
+            .collect();
+        confirm_maps_to_history::<_, T>(&db, 0, vec![changes], &write_schema).unwrap();
+        db.commit(write_schema).unwrap();
+
+        let table = db.view::<HistoryIndicesTable<T>>().unwrap();
+        let before = allocations();
+        let mut num_entries = 0;
+        for item in table.iter_from_start().unwrap() {
+            std::hint::black_box(item.unwrap());
+            num_entries += 1;
+        }
+        let allocations = allocations() - before;
+        assert_eq!(num_entries, num_keys);
+        allocations
+    }
+
+    fn check_iteration_allocations(num_keys: u64) {
+        let fixed = count_iteration_allocations::<FixedKeyValue>(num_keys, H256::from_low_u64_be);
+        let bytes = count_iteration_allocations::<FlatKeyValue>(num_keys, |i| {
+            i.to_be_bytes().to_vec().into_boxed_slice()
+        });
+        // only the iterator itself, whatever the number of entries
+        assert!(fixed < 8);
+        assert!(bytes as u64 >= num_keys);
+    }
+
+    #[test]
+    fn test_iteration_allocations() {
+        check_iteration_allocations(1000);
+    }
+
+    // run with
+    // `cargo test --release --features count-allocations -- --ignored bench_iteration_allocations`
+    #[test]
+    #[ignore]
+    fn bench_iteration_allocations() {
+        check_iteration_allocations(1_000_000);
+    }
+}
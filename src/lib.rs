@@ -6,6 +6,9 @@ mod example;
 mod lvmt;
 mod macros;
 mod middlewares;
+mod open;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 pub mod traits;
 pub mod types;
 mod utils;
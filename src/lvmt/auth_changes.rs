@@ -1,17 +1,26 @@
-use std::collections::BTreeMap;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use blake2::Blake2s;
 use ethereum_types::H256;
 
 use crate::{
     backends::{serde::Encode, TableName, TableSchema},
+    errors::Result,
     lvmt::types::auth_changes::log2_ceil,
     middlewares::{ChangeKey, CommitID},
 };
 
 use super::types::{
     auth_changes::{MAX_NODE_SIZE, MAX_NODE_SIZE_LOG},
-    AmtId, AuthChangeKey, AuthChangeNode, CurvePointWithVersion, LvmtValue,
+    AmtId, AuthChangeKey, AuthChangeNode, AuthChangeProof, CurvePointWithVersion, LvmtValue,
 };
 use blake2::Digest;
 
@@ -19,11 +28,22 @@ use blake2::Digest;
 pub struct AuthChangeTable;
 impl TableSchema for AuthChangeTable {
     const NAME: TableName = TableName::AuthNodeChange;
+    const SUPPORTS_SUBKEY: bool = true;
 
     type Key = ChangeKey<CommitID, AuthChangeKey>;
     type Value = AuthChangeNode;
 }
 
+/// The root hash of the auth-change tree of each commit, written with the tree.
+#[derive(Clone, Copy)]
+pub struct AuthChangeRootTable;
+impl TableSchema for AuthChangeRootTable {
+    const NAME: TableName = TableName::AuthChangeRoot;
+
+    type Key = CommitID;
+    type Value = H256;
+}
+
 const KEY_VALUE_CHANGE_FLAG: u8 = 0;
 const AMT_CHANGE_FLAG: u8 = 1;
 
@@ -44,20 +64,146 @@ pub fn key_value_hash(key: &[u8], value: &LvmtValue) -> H256 {
     H256(hasher.finalize().into())
 }
 
-pub fn process_dump_items(mut hashes: Vec<H256>) -> BTreeMap<AuthChangeKey, AuthChangeNode> {
+/// Builds the auth-change tree of the leaf `hashes`, passing each node to `emit` once built
+/// instead of collecting them, and returns the root node.
+pub fn stream_dump_items<E>(
+    mut hashes: Vec<H256>,
+    mut emit: impl FnMut(AuthChangeKey, AuthChangeNode) -> std::result::Result<(), E>,
+) -> std::result::Result<AuthChangeNode, E> {
     hashes.sort_unstable();
 
-    let mut map = BTreeMap::new();
-    process_subtree(&hashes, AuthChangeKey::root(), &mut map);
-    map
+    let size = hashes.len();
+    build_tree(size, hashes.into_iter().map(Ok), &mut emit)
+}
+
+/// Limits the memory used to sort the leaves of the auth-change tree of large commits.
+///
+/// Only the sort is bounded. The nodes of the tree are still held by whoever receives them:
+/// `LvmtStore::commit` writes them to the write schema it returns, next to the changed keys and
+/// AMT nodes, so a commit holds O(changed keys) in memory either way. What the external sort
+/// saves is the vector of the 32-byte leaf hashes, sorted in memory otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalSortConfig {
+    /// Commits with more leaves than this are sorted externally.
+    pub threshold: usize,
+    /// Number of leaves sorted in memory and spilled to each run file.
+    pub run_size: usize,
+    /// Directory in which a temporary directory of run files is created.
+    pub temp_dir: PathBuf,
 }
 
-fn process_subtree(
-    items: &[H256],
+/// Builds the same tree as [`stream_dump_items`], holding `config.run_size` hashes while the
+/// runs are written, then a read buffer per run and a node per level of the tree while they are
+/// merged, whatever the number of hashes.
+///
+/// The hashes are spilled to sorted runs, which are merged into the tree builder, and the nodes
+/// are passed to `emit` as they are built, so `emit` decides what is kept of them. The run files
+/// are removed when this returns, whether it succeeds or not.
+pub fn process_dump_items_external(
+    hashes: impl Iterator<Item = H256>,
+    config: &ExternalSortConfig,
+    mut emit: impl FnMut(AuthChangeKey, AuthChangeNode) -> Result<()>,
+) -> Result<AuthChangeNode> {
+    let spill_dir = SpillDir::create(&config.temp_dir)?;
+    let run_size = config.run_size.max(1);
+
+    let mut run_paths = vec![];
+    let mut size = 0;
+    let mut buffer = Vec::with_capacity(run_size);
+    for hash in hashes {
+        buffer.push(hash);
+        if buffer.len() == run_size {
+            size += buffer.len();
+            run_paths.push(spill_dir.write_run(run_paths.len(), &mut buffer)?);
+        }
+    }
+    if !buffer.is_empty() {
+        size += buffer.len();
+        run_paths.push(spill_dir.write_run(run_paths.len(), &mut buffer)?);
+    }
+
+    build_tree(size, MergedRuns::open(&run_paths)?, &mut emit)
+}
+
+/// Proves that `leaf` is a leaf of the auth-change tree whose nodes are read by `get_node`,
+/// walking down from the root by the ticks of the nodes. Returns `None` if it is not a leaf, or
+/// a node on its path is missing.
+pub fn prove_leaf(
+    mut get_node: impl FnMut(&AuthChangeKey) -> Result<Option<AuthChangeNode>>,
+    leaf: H256,
+) -> Result<Option<AuthChangeProof>> {
+    let mut key = AuthChangeKey::root();
+    let mut path_proofs = vec![];
+    loop {
+        let Some(node) = get_node(&key)? else {
+            return Ok(None);
+        };
+        let Some(index) = node.route(&leaf) else {
+            return Ok(None);
+        };
+        let Some(proof) = node.prove(index) else {
+            return Ok(None);
+        };
+        path_proofs.push(proof);
+        if node.is_leaf() {
+            break;
+        }
+        key = key.child(index);
+    }
+
+    // from the leaf node up to the root
+    let mut proofs = path_proofs.into_iter().rev();
+    let proof = proofs.next().unwrap();
+    Ok(Some(proofs.fold(proof, AuthChangeProof::then)))
+}
+
+fn build_tree<E>(
+    size: usize,
+    sorted_hashes: impl Iterator<Item = std::result::Result<H256, E>>,
+    emit: &mut impl FnMut(AuthChangeKey, AuthChangeNode) -> std::result::Result<(), E>,
+) -> std::result::Result<AuthChangeNode, E> {
+    let mut leaves = SortedLeaves {
+        iter: sorted_hashes.peekable(),
+        last: None,
+    };
+    process_subtree(&mut leaves, size, AuthChangeKey::root(), emit)
+}
+
+// Sorted leaves, consumed from left to right while the tree is built.
+struct SortedLeaves<I: Iterator> {
+    iter: Peekable<I>,
+    last: Option<H256>,
+}
+
+impl<E, I: Iterator<Item = std::result::Result<H256, E>>> SortedLeaves<I> {
+    fn take(&mut self, n: usize) -> std::result::Result<Vec<H256>, E> {
+        let leaves: Vec<_> = self
+            .iter
+            .by_ref()
+            .take(n)
+            .collect::<std::result::Result<_, E>>()?;
+        assert_eq!(leaves.len(), n);
+        if let Some(last) = leaves.last() {
+            self.last = Some(*last);
+        }
+        Ok(leaves)
+    }
+
+    fn peek(&mut self) -> std::result::Result<Option<H256>, E> {
+        match self.iter.peek() {
+            None => Ok(None),
+            Some(Ok(next)) => Ok(Some(*next)),
+            Some(Err(_)) => Err(self.iter.next().unwrap().unwrap_err()),
+        }
+    }
+}
+
+fn process_subtree<E, I: Iterator<Item = std::result::Result<H256, E>>>(
+    leaves: &mut SortedLeaves<I>,
+    size: usize,
     key: AuthChangeKey,
-    btree: &mut BTreeMap<AuthChangeKey, AuthChangeNode>,
-) -> AuthChangeNode {
-    let size = items.len();
+    emit: &mut impl FnMut(AuthChangeKey, AuthChangeNode) -> std::result::Result<(), E>,
+) -> std::result::Result<AuthChangeNode, E> {
     let size_log = log2_ceil(size);
 
     let layer_size_log = if key.is_root() {
@@ -67,17 +213,17 @@ fn process_subtree(
             (size_log - 1) % MAX_NODE_SIZE_LOG + 1
         };
         if top_size_log == size_log {
-            let node = AuthChangeNode::from_leaves(items);
-            btree.insert(key, node.clone());
-            return node;
+            let node = AuthChangeNode::from_leaves(&leaves.take(size)?);
+            emit(key, node.clone())?;
+            return Ok(node);
         }
         top_size_log
     } else {
         assert!(size_log >= MAX_NODE_SIZE_LOG - 1);
-        if items.len() <= MAX_NODE_SIZE {
-            let node = AuthChangeNode::from_leaves(items);
-            btree.insert(key, node.clone());
-            return node;
+        if size <= MAX_NODE_SIZE {
+            let node = AuthChangeNode::from_leaves(&leaves.take(size)?);
+            emit(key, node.clone())?;
+            return Ok(node);
         }
         MAX_NODE_SIZE_LOG
     };
@@ -88,7 +234,7 @@ fn process_subtree(
     let max_subtree_size = 1usize << subtree_size_log;
     let min_subtree_size = 1usize << (subtree_size_log - 1);
 
-    let mut items = items;
+    let mut remaining = size;
     let mut processed_nodes = vec![];
     let mut ticks = vec![];
     let mut max_shared_prefix_len = 0;
@@ -96,30 +242,113 @@ fn process_subtree(
     for i in 0..num_subtree {
         let subtree_size = std::cmp::min(
             max_subtree_size,
-            items.len() - min_subtree_size * (num_subtree - i - 1),
+            remaining - min_subtree_size * (num_subtree - i - 1),
         );
         assert!(subtree_size >= min_subtree_size);
+        remaining -= subtree_size;
 
-        let subtree;
-        (subtree, items) = items.split_at(subtree_size);
+        let node = process_subtree(leaves, subtree_size, key.child(i), emit)?;
+        processed_nodes.push(node);
 
-        if !items.is_empty() {
-            ticks.push(items[0]);
+        if remaining > 0 {
+            let last = leaves.last.unwrap();
+            let next = leaves.peek()?.unwrap();
+            ticks.push(next);
 
-            let shared_prefix_len = shared_prefix_len(&subtree[subtree_size - 1].0, &items[0].0);
+            let shared_prefix_len = shared_prefix_len(&last.0, &next.0);
             if shared_prefix_len > max_shared_prefix_len {
                 max_shared_prefix_len = shared_prefix_len;
             }
         }
-
-        let node = process_subtree(subtree, key.child(i), btree);
-        processed_nodes.push(node);
     }
 
     let node = AuthChangeNode::from_nodes(&processed_nodes, ticks, max_shared_prefix_len);
 
-    btree.insert(key, node.clone());
-    node
+    emit(key, node.clone())?;
+    Ok(node)
+}
+
+static NUM_SPILL_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// A temporary directory of sorted runs, removed on drop.
+struct SpillDir {
+    path: PathBuf,
+}
+
+impl SpillDir {
+    fn create(temp_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(temp_dir)?;
+        let path = temp_dir.join(format!(
+            "auth-changes-{}-{}",
+            std::process::id(),
+            NUM_SPILL_DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+
+    // Sorts and writes `buffer` as the `index`-th run, leaving `buffer` empty.
+    fn write_run(&self, index: usize, buffer: &mut Vec<H256>) -> Result<PathBuf> {
+        buffer.sort_unstable();
+
+        let path = self.path.join(format!("{index}.run"));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for hash in buffer.drain(..) {
+            writer.write_all(&hash.0)?;
+        }
+        writer.flush()?;
+        Ok(path)
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// Merges sorted runs, holding the next hash of each run.
+struct MergedRuns {
+    readers: Vec<BufReader<File>>,
+    heads: BinaryHeap<Reverse<(H256, usize)>>,
+}
+
+impl MergedRuns {
+    fn open(run_paths: &[PathBuf]) -> Result<Self> {
+        let mut readers = vec![];
+        let mut heads = BinaryHeap::new();
+        for (index, path) in run_paths.iter().enumerate() {
+            let mut reader = BufReader::new(File::open(path)?);
+            if let Some(hash) = read_hash(&mut reader)? {
+                heads.push(Reverse((hash, index)));
+            }
+            readers.push(reader);
+        }
+        Ok(Self { readers, heads })
+    }
+}
+
+impl Iterator for MergedRuns {
+    type Item = Result<H256>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((hash, index)) = self.heads.pop()?;
+        match read_hash(&mut self.readers[index]) {
+            Ok(Some(next)) => self.heads.push(Reverse((next, index))),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e.into())),
+        }
+        Some(Ok(hash))
+    }
+}
+
+fn read_hash(reader: &mut impl Read) -> std::io::Result<Option<H256>> {
+    let mut hash = H256::zero();
+    match reader.read_exact(&mut hash.0) {
+        Ok(()) => Ok(Some(hash)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
 fn shared_prefix_len<T: PartialEq>(a: &[T], b: &[T]) -> usize {
@@ -128,7 +357,10 @@ fn shared_prefix_len<T: PartialEq>(a: &[T], b: &[T]) -> usize {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeSet;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        convert::Infallible,
+    };
 
     use crate::lvmt::types::test_utils::bytes32_strategy;
     use crate::utils::hash::blake2s_tuple;
@@ -140,6 +372,18 @@ mod tests {
     const MAX_TREE_SIZE: usize = 1 << MAX_NODE_SIZE_LOG;
     const MIN_TREE_SIZE: usize = MAX_TREE_SIZE / 2;
 
+    fn process_dump_items(hashes: Vec<H256>) -> BTreeMap<AuthChangeKey, AuthChangeNode> {
+        let mut map = BTreeMap::new();
+        let emit = |key, node| {
+            map.insert(key, node);
+            Ok::<_, Infallible>(())
+        };
+        match stream_dump_items(hashes, emit) {
+            Ok(_) => map,
+            Err(never) => match never {},
+        }
+    }
+
     fn leaf_node_sizes() -> impl Strategy<Value = Vec<usize>> {
         use std::iter::{once, repeat};
 
@@ -250,4 +494,80 @@ mod tests {
             prop_assert_eq!(leaf_nodes_map_actual, leaf_nodes_map_expected);
         }
     }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_prove_leaf(leaves in vec(bytes32_strategy(), 1..4096), other in bytes32_strategy()) {
+            let tree = process_dump_items(leaves.clone());
+            let root = tree[&AuthChangeKey::root()].hash();
+            let get_node = |key: &AuthChangeKey| Ok(tree.get(key).cloned());
+
+            for leaf in &leaves {
+                let proof = prove_leaf(get_node, *leaf).unwrap().unwrap();
+                prop_assert!(proof.verify(root, *leaf));
+            }
+            if !leaves.contains(&other) {
+                prop_assert!(prove_leaf(get_node, other).unwrap().is_none());
+            }
+        }
+
+        #[test]
+        fn test_external_sort(leaves in vec(bytes32_strategy(), 1..4096), run_size in 1usize..512) {
+            let temp_dir = PathBuf::from("__test_auth_changes_external_sort");
+            let config = ExternalSortConfig {
+                threshold: 0,
+                run_size,
+                temp_dir: temp_dir.clone(),
+            };
+
+            let mut external = BTreeMap::new();
+            let root = process_dump_items_external(leaves.iter().copied(), &config, |key, node| {
+                external.insert(key, node);
+                Ok(())
+            })
+            .unwrap();
+            prop_assert_eq!(&root, &external[&AuthChangeKey::root()]);
+            prop_assert_eq!(external, process_dump_items(leaves));
+
+            // the run files are removed
+            prop_assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+        }
+    }
+
+    // run with `cargo test --release --features count-allocations -- test_external_sort_memory`
+    #[cfg(feature = "count-allocations")]
+    #[test]
+    fn test_external_sort_memory() {
+        use crate::utils::{allocations::peak_bytes_of, hash::blake2s};
+
+        const NUM_LEAVES: usize = 1 << 18;
+        const RUN_SIZE: usize = 1 << 12;
+
+        let leaves = || (0..NUM_LEAVES as u64).map(|i| blake2s(&i.to_be_bytes()));
+        let config = ExternalSortConfig {
+            threshold: 0,
+            run_size: RUN_SIZE,
+            temp_dir: PathBuf::from("__test_auth_changes_external_sort_memory"),
+        };
+
+        // the nodes are dropped once emitted, so only the building itself is measured
+        let (in_memory, in_memory_peak) = peak_bytes_of(|| {
+            stream_dump_items(leaves().collect(), |_, _| Ok::<_, Infallible>(())).unwrap()
+        });
+        let (external, external_peak) = peak_bytes_of(|| {
+            process_dump_items_external(leaves(), &config, |_, _| Ok(())).unwrap()
+        });
+        assert_eq!(in_memory, external);
+
+        // the in-memory sort holds every hash, the external one the hashes of a run and a read
+        // buffer per run
+        let hashes_bytes = NUM_LEAVES * std::mem::size_of::<H256>();
+        assert!(in_memory_peak >= hashes_bytes);
+        assert!(
+            external_peak < hashes_bytes / 4,
+            "{external_peak} bytes held to sort {hashes_bytes} bytes of hashes"
+        );
+    }
 }
@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use blake2::{Blake2s256, Digest};
+use ethereum_types::H256;
+
+use super::{
+    auth_changes::{AuthChangeRootTable, AuthChangeTable},
+    table_schema::{AmtNodes, FlatKeyValue, LvmtMetadata, SlotAllocations},
+};
+use crate::{
+    backends::{serde::Encode, DatabaseTrait, TableName, TableRead, TableSchema},
+    errors::{DecodeError, Result},
+    middlewares::{
+        latest_confirmed,
+        table_schema::{HistoryChangeTable, HistoryIndicesTable},
+        CommitAliasSchema, CommitID, CommitIDSchema, CommitMetadataSchema, HistoryNumber,
+        HistoryNumberSchema,
+    },
+    StorageError,
+};
+
+pub(super) const BACKUP_DB_DIR: &str = "db";
+pub(super) const BACKUP_MANIFEST_FILE: &str = "MANIFEST";
+
+const MANIFEST_MAGIC: &[u8; 8] = b"LVMTBKUP";
+const MANIFEST_FORMAT_VERSION: u32 = 3;
+// the first version recording the state digest
+const STATE_DIGEST_FORMAT_VERSION: u32 = 2;
+// the first version where the state digest is optional, flagged by a byte before it
+const OPTIONAL_DIGEST_FORMAT_VERSION: u32 = 3;
+
+/// Describes a backup written by `LvmtStorage::backup`.
+///
+/// Only the historical part is backed up; the pending part lives in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    /// Number of tables of the backend, which changes whenever a table is added. A backup with
+    /// fewer tables than the current layout predates the last ones, which it reads as empty.
+    pub num_tables: u32,
+    /// The latest confirmed commit at the time of the backup.
+    pub latest_confirmed: Option<(HistoryNumber, CommitID)>,
+    /// The digest of every row of the backed up tables, to detect a checkpoint damaged when
+    /// copied. `None` for the manifests written before it was recorded, or without it.
+    ///
+    /// It is computed by [`state_digest`], a scan of the whole database, when the backup is
+    /// taken and again when it is opened.
+    pub state_digest: Option<H256>,
+}
+
+impl BackupManifest {
+    pub(super) fn from_db<D: DatabaseTrait>(db: &D, with_state_digest: bool) -> Result<Self> {
+        Self::from_db_with_tables(db, TableName::max_index() + 1, with_state_digest)
+    }
+
+    // describes `db` as a backup taken when the layout had `num_tables` tables
+    pub(super) fn from_db_with_tables<D: DatabaseTrait>(
+        db: &D,
+        num_tables: u32,
+        with_state_digest: bool,
+    ) -> Result<Self> {
+        let state_digest = if with_state_digest {
+            Some(state_digest(db, num_tables)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            format_version: MANIFEST_FORMAT_VERSION,
+            num_tables,
+            latest_confirmed: latest_confirmed(db)?,
+            state_digest,
+        })
+    }
+
+    /// Reads the manifest of the backup at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::decode(&std::fs::read(path.join(BACKUP_MANIFEST_FILE))?)
+    }
+
+    /// Checks that `db` is the database described by this manifest, e.g. that no file of the
+    /// checkpoint was lost or truncated when the backup was copied.
+    pub(super) fn validate<D: DatabaseTrait>(&self, db: &D) -> Result<()> {
+        if !(1..=MANIFEST_FORMAT_VERSION).contains(&self.format_version) {
+            return Err(StorageError::InvalidBackup("unsupported manifest version"));
+        }
+        // the tables added since the backup are opened as empty ones
+        if !(1..=TableName::max_index() + 1).contains(&self.num_tables) {
+            return Err(StorageError::InvalidBackup("mismatched number of tables"));
+        }
+        if latest_confirmed(db)? != self.latest_confirmed {
+            return Err(StorageError::InvalidBackup(
+                "mismatched latest confirmed commit",
+            ));
+        }
+        if let Some(digest) = self.state_digest {
+            if state_digest(db, self.num_tables)? != digest {
+                return Err(StorageError::InvalidBackup("mismatched state digest"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut output = MANIFEST_MAGIC.to_vec();
+        output.extend_from_slice(&self.format_version.to_be_bytes());
+        output.extend_from_slice(&self.num_tables.to_be_bytes());
+        if self.format_version >= OPTIONAL_DIGEST_FORMAT_VERSION {
+            output.push(self.state_digest.is_some() as u8);
+        }
+        if let Some(digest) = self.state_digest {
+            output.extend_from_slice(&digest.0);
+        }
+        if let Some((history_number, commit)) = self.latest_confirmed {
+            output.extend_from_slice(&history_number.to_be_bytes());
+            output.extend_from_slice(&commit.0);
+        }
+        output
+    }
+
+    fn decode(input: &[u8]) -> Result<Self> {
+        let body = input
+            .strip_prefix(MANIFEST_MAGIC.as_slice())
+            .ok_or(StorageError::InvalidBackup("not a backup manifest"))?;
+        if body.len() < 8 {
+            return Err(DecodeError::TooShortHeader.into());
+        }
+
+        let (header, rest) = body.split_at(8);
+        let format_version = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let num_tables = u32::from_be_bytes(header[4..].try_into().unwrap());
+        let (has_digest, rest) = if format_version >= OPTIONAL_DIGEST_FORMAT_VERSION {
+            match rest.split_first() {
+                Some((0, rest)) => (false, rest),
+                Some((1, rest)) => (true, rest),
+                Some(_) => return Err(DecodeError::Custom("invalid state digest flag").into()),
+                None => return Err(DecodeError::IncorrectLength.into()),
+            }
+        } else {
+            (format_version >= STATE_DIGEST_FORMAT_VERSION, rest)
+        };
+        let (state_digest, latest_confirmed) = if has_digest {
+            if rest.len() < 32 {
+                return Err(DecodeError::IncorrectLength.into());
+            }
+            let (digest, rest) = rest.split_at(32);
+            (Some(H256::from_slice(digest)), rest)
+        } else {
+            (None, rest)
+        };
+        let latest_confirmed = match latest_confirmed.len() {
+            0 => None,
+            40 => {
+                let (history_number, commit) = latest_confirmed.split_at(8);
+                Some((
+                    HistoryNumber::from_be_bytes(history_number.try_into().unwrap()),
+                    CommitID::from_slice(commit),
+                ))
+            }
+            _ => return Err(DecodeError::IncorrectLength.into()),
+        };
+
+        Ok(Self {
+            format_version,
+            num_tables,
+            latest_confirmed,
+            state_digest,
+        })
+    }
+}
+
+/// Hashes every row of the tables of an LVMT database, table by table in key order. Tables
+/// not used by LVMT, e.g. the journal of a tiered backend, are not included, nor the tables
+/// beyond the first `num_tables` of the layout, which did not exist in a backup taken before
+/// they were added.
+pub(super) fn state_digest<D: DatabaseTrait>(db: &D, num_tables: u32) -> Result<H256> {
+    let mut hasher = Blake2s256::new();
+    digest_table::<D, CommitIDSchema>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryNumberSchema>(db, num_tables, &mut hasher)?;
+    digest_table::<D, CommitAliasSchema>(db, num_tables, &mut hasher)?;
+    digest_table::<D, CommitMetadataSchema>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryChangeTable<FlatKeyValue>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryIndicesTable<FlatKeyValue>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryChangeTable<AmtNodes>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryIndicesTable<AmtNodes>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryChangeTable<SlotAllocations>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, HistoryIndicesTable<SlotAllocations>>(db, num_tables, &mut hasher)?;
+    digest_table::<D, AuthChangeTable>(db, num_tables, &mut hasher)?;
+    digest_table::<D, AuthChangeRootTable>(db, num_tables, &mut hasher)?;
+    digest_table::<D, LvmtMetadata>(db, num_tables, &mut hasher)?;
+    Ok(H256(hasher.finalize().into()))
+}
+
+// the number of rows of the table, then each row as its length-prefixed key and value
+fn digest_table<D: DatabaseTrait, T: TableSchema>(
+    db: &D,
+    num_tables: u32,
+    hasher: &mut Blake2s256,
+) -> Result<()> {
+    if u32::from(T::NAME) >= num_tables {
+        return Ok(());
+    }
+    let mut rows = Blake2s256::new();
+    let mut num_rows = 0u64;
+    for item in db.view::<T>()?.iter_from_start()? {
+        let (key, value) = item?;
+        for field in [key.encode(), value.encode()] {
+            rows.update((field.len() as u32).to_be_bytes());
+            rows.update(&field);
+        }
+        num_rows += 1;
+    }
+    hasher.update(num_rows.to_be_bytes());
+    hasher.update(rows.finalize());
+    Ok(())
+}
@@ -1,92 +1,289 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use crate::{
-    backends::DatabaseTrait,
+    backends::{DatabaseTrait, TableRead},
     errors::Result,
     middlewares::{
-        confirm_ids_to_history, confirm_maps_to_history, CommitID, KeyValueStoreBulks,
-        VersionedStore, VersionedStoreCache,
+        analyze_history, checked_height_to_history_number, compact_history, finalize_confirm,
+        prepare_confirm, CommitAliasSchema, CommitID, CommitIDSchema, CommitMetadataSchema,
+        ConfirmedPath, HistoryNumberSchema, HistoryStats, KeyValueStoreBulks, StorageMetrics,
+        VersionedStore, VersionedStoreCache, VersionedStoreReadOnly,
     },
+    traits::KeyValueStoreManager,
+    StorageError,
 };
 
 use super::{
-    auth_changes::AuthChangeTable,
-    storage::LvmtStore,
-    table_schema::{AmtNodes, FlatKeyValue, SlotAllocations},
+    auth_changes::{AuthChangeRootTable, AuthChangeTable, ExternalSortConfig},
+    backup::{BackupManifest, BACKUP_DB_DIR, BACKUP_MANIFEST_FILE},
+    state_view::{LvmtStateView, StateSelector},
+    storage::{AllocationScheme, LvmtStore, RootHashCache},
+    table_schema::{AmtNodes, FlatKeyValue, LvmtMetadata, SlotAllocations, ALLOCATION_SCHEME_KEY},
 };
 
+/// [`HistoryStats`] of the three versioned tables of an [`LvmtStorage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LvmtHistoryStats {
+    pub key_values: HistoryStats,
+    pub amt_nodes: HistoryStats,
+    pub slot_allocations: HistoryStats,
+}
+
 pub struct LvmtStorage<D: DatabaseTrait> {
     backend: D,
     key_value_cache: VersionedStoreCache<FlatKeyValue>,
     amt_node_cache: VersionedStoreCache<AmtNodes>,
     slot_alloc_cache: VersionedStoreCache<SlotAllocations>,
+    root_hash_cache: RootHashCache,
+    allocation_scheme: AllocationScheme,
+    external_sort: Option<ExternalSortConfig>,
 }
 
 impl<D: DatabaseTrait> LvmtStorage<D> {
     pub fn new(backend: D) -> Result<Self> {
+        let allocation_scheme = recorded_allocation_scheme(&backend)?.unwrap_or_default();
         Ok(Self {
             backend,
             key_value_cache: VersionedStoreCache::new_empty(),
             amt_node_cache: VersionedStoreCache::new_empty(),
             slot_alloc_cache: VersionedStoreCache::new_empty(),
+            root_hash_cache: RootHashCache::new(),
+            allocation_scheme,
+            external_sort: None,
         })
     }
 
-    pub fn as_manager(&mut self) -> Result<LvmtStore<'_, '_>> {
+    /// Opens a backup written by [`LvmtStorage::backup`].
+    ///
+    /// New commits can be added on top of the latest confirmed commit of the backup.
+    pub fn open_from_backup(path: &Path) -> Result<Self> {
+        let manifest = BackupManifest::read(path)?;
+        let backend = D::open_checkpoint(&path.join(BACKUP_DB_DIR))?;
+        manifest.validate(&backend)?;
+
+        // the next height equals the latest history number
+        let (parent_of_root, height_of_root) = match manifest.latest_confirmed {
+            Some((history_number, commit)) => (
+                Some(commit),
+                usize::try_from(history_number).map_err(|_| StorageError::HeightOverflow)?,
+            ),
+            None => (None, 0),
+        };
+        let allocation_scheme = recorded_allocation_scheme(&backend)?.unwrap_or_default();
+
+        Ok(Self {
+            backend,
+            key_value_cache: VersionedStoreCache::new(parent_of_root, height_of_root),
+            amt_node_cache: VersionedStoreCache::new(parent_of_root, height_of_root),
+            slot_alloc_cache: VersionedStoreCache::new(parent_of_root, height_of_root),
+            root_hash_cache: RootHashCache::new(),
+            allocation_scheme,
+            external_sort: None,
+        })
+    }
+
+    /// Commits the staged `write_schema` and backs up the historical part to `path`,
+    /// which must not exist yet.
+    ///
+    /// The manifest is written last, so an interrupted backup is rejected by `open_from_backup`.
+    /// With `with_state_digest`, it also records [`BackupManifest::state_digest`], which scans
+    /// the whole database here and again in `open_from_backup`. Without it, only the latest
+    /// confirmed commit is checked, which misses a checkpoint losing older rows when copied.
+    ///
+    /// The checkpoint of [`DatabaseTrait::create_checkpoint`] may itself copy every row, e.g. on
+    /// RocksDB, so the backup is O(database size) on such backends either way.
+    pub fn backup(
+        &mut self,
+        write_schema: <D as DatabaseTrait>::WriteSchema,
+        path: &Path,
+        with_state_digest: bool,
+    ) -> Result<BackupManifest> {
+        self.commit(write_schema)?;
+
+        let manifest = BackupManifest::from_db(&self.backend, with_state_digest)?;
+        std::fs::create_dir(path)?;
+        self.backend.create_checkpoint(&path.join(BACKUP_DB_DIR))?;
+        std::fs::write(path.join(BACKUP_MANIFEST_FILE), manifest.encode())?;
+
+        Ok(manifest)
+    }
+
+    /// Sets the order in which new keys are allocated.
+    ///
+    /// Every commit records the scheme in [`LvmtMetadata`], and opening the database loads it, so
+    /// a database built with another scheme fails with [`StorageError::AllocationSchemeMismatch`].
+    /// A database recording none, e.g. built before the scheme was recorded, takes the given one.
+    pub fn set_allocation_scheme(&mut self, allocation_scheme: AllocationScheme) -> Result<()> {
+        if allocation_scheme != self.allocation_scheme {
+            let has_commits = self.key_value_cache.get_last_added().is_some();
+            if has_commits || recorded_allocation_scheme(&self.backend)?.is_some() {
+                return Err(StorageError::AllocationSchemeMismatch);
+            }
+        }
+        self.allocation_scheme = allocation_scheme;
+        Ok(())
+    }
+
+    /// Sorts the leaves of the auth-change tree of large commits on disk, `None` to always sort in memory.
+    /// See [`ExternalSortConfig`] for the memory it saves.
+    pub fn set_external_sort(&mut self, external_sort: Option<ExternalSortConfig>) {
+        self.external_sort = external_sort;
+    }
+
+    /// Reports the events of each of the three stores to its own sink, see [`StorageMetrics`].
+    /// The same sink can be given for several stores.
+    pub fn set_metrics(
+        &mut self,
+        key_values: Arc<dyn StorageMetrics>,
+        amt_nodes: Arc<dyn StorageMetrics>,
+        slot_allocations: Arc<dyn StorageMetrics>,
+    ) {
+        self.key_value_cache.set_metrics(key_values);
+        self.amt_node_cache.set_metrics(amt_nodes);
+        self.slot_alloc_cache.set_metrics(slot_allocations);
+    }
+
+    pub fn as_manager(&mut self) -> Result<LvmtStore<'_, '_, D>> {
         let key_value_store = VersionedStore::new(&self.backend, &mut self.key_value_cache)?;
         let amt_node_store = VersionedStore::new(&self.backend, &mut self.amt_node_cache)?;
         let slot_alloc_store = VersionedStore::new(&self.backend, &mut self.slot_alloc_cache)?;
         let auth_changes =
             KeyValueStoreBulks::new(Arc::new(self.backend.view::<AuthChangeTable>()?));
+        let auth_change_roots = Arc::new(self.backend.view::<AuthChangeRootTable>()?);
 
         Ok(LvmtStore::new(
             key_value_store,
             amt_node_store,
             slot_alloc_store,
             auth_changes,
+            auth_change_roots,
+            &mut self.root_hash_cache,
+            self.allocation_scheme,
+            self.external_sort.clone(),
         ))
     }
 
+    /// Returns the snapshots of the three stores at the selected commit.
+    pub fn state_at(&self, selector: StateSelector) -> Result<LvmtStateView<'_>> {
+        let commit = match selector {
+            StateSelector::Commit(commit) => commit,
+            StateSelector::Height(height) => {
+                let history_number = checked_height_to_history_number(height)?;
+                let history_number_table = self.backend.view::<HistoryNumberSchema>()?;
+                match history_number_table.get(&history_number)? {
+                    Some(commit) => commit.into_owned(),
+                    None => return Err(StorageError::HeightNotConfirmed(height)),
+                }
+            }
+            StateSelector::Latest => self
+                .key_value_cache
+                .get_last_added()
+                .or_else(|| self.key_value_cache.get_parent_of_root())
+                .ok_or(StorageError::CommitIDNotFound)?,
+        };
+
+        LvmtStateView::new(
+            commit,
+            VersionedStoreReadOnly::new(&self.backend, &self.key_value_cache)?,
+            &VersionedStoreReadOnly::new(&self.backend, &self.amt_node_cache)?,
+            &VersionedStoreReadOnly::new(&self.backend, &self.slot_alloc_cache)?,
+            KeyValueStoreBulks::new(Arc::new(self.backend.view::<AuthChangeTable>()?)),
+        )
+    }
+
     pub fn commit(&mut self, write_schema: <D as DatabaseTrait>::WriteSchema) -> Result<()> {
         self.backend.commit(write_schema)
     }
 
+    /// Compacts every table of the storage, e.g. after pruning the history. Does nothing on a
+    /// backend without compaction, e.g. RocksDB through kvdb, which leaves it to the background
+    /// compaction of the database.
+    pub fn compact_all(&self) -> Result<()> {
+        match self.compact_tables() {
+            Err(StorageError::Unsupported(_)) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn compact_tables(&self) -> Result<()> {
+        compact_history::<D, FlatKeyValue>(&self.backend)?;
+        compact_history::<D, AmtNodes>(&self.backend)?;
+        compact_history::<D, SlotAllocations>(&self.backend)?;
+        self.backend.compact::<AuthChangeTable>()?;
+        self.backend.compact::<AuthChangeRootTable>()?;
+        self.backend.compact::<CommitIDSchema>()?;
+        self.backend.compact::<CommitMetadataSchema>()?;
+        self.backend.compact::<HistoryNumberSchema>()?;
+        self.backend.compact::<CommitAliasSchema>()
+    }
+
+    /// Collects the shape of the history of the three stores, see [`analyze_history`].
+    pub fn analyze_history(&self) -> Result<LvmtHistoryStats> {
+        Ok(LvmtHistoryStats {
+            key_values: analyze_history::<D, FlatKeyValue>(&self.backend)?,
+            amt_nodes: analyze_history::<D, AmtNodes>(&self.backend)?,
+            slot_allocations: analyze_history::<D, SlotAllocations>(&self.backend)?,
+        })
+    }
+
+    /// Confirms the pending commits up to the parent of `new_root_commit_id` in the three
+    /// stores, and returns them with the number of flat keys each of them changed.
+    ///
+    /// Each store goes through [`prepare_confirm`] and [`finalize_confirm`], so each reports the
+    /// confirmation to its metrics. The pending parts are only changed once the three stores are
+    /// written to `write_schema`.
     pub fn confirmed_pending_to_history(
         &mut self,
         new_root_commit_id: CommitID,
         write_schema: &D::WriteSchema,
-    ) -> Result<()> {
-        let key_value_confirmed_path = self.key_value_cache.change_root(new_root_commit_id)?;
-        let amt_node_confirmed_path = self.amt_node_cache.change_root(new_root_commit_id)?;
-        let slot_alloc_confirmed_path = self.slot_alloc_cache.change_root(new_root_commit_id)?;
-
-        assert!(key_value_confirmed_path.is_same_path(&amt_node_confirmed_path));
-        assert!(key_value_confirmed_path.is_same_path(&slot_alloc_confirmed_path));
-
-        let start_height = key_value_confirmed_path.start_height;
-        let commit_ids = &key_value_confirmed_path.commit_ids;
-
-        confirm_ids_to_history::<D>(&self.backend, start_height, commit_ids, write_schema)?;
-
-        confirm_maps_to_history::<D, FlatKeyValue>(
+    ) -> Result<ConfirmedPath> {
+        let key_value_ticket = prepare_confirm::<D, FlatKeyValue>(
             &self.backend,
-            start_height,
-            key_value_confirmed_path.key_value_maps,
+            &self.key_value_cache,
+            new_root_commit_id,
             write_schema,
         )?;
-        confirm_maps_to_history::<D, AmtNodes>(
+        let amt_node_ticket = prepare_confirm::<D, AmtNodes>(
             &self.backend,
-            start_height,
-            amt_node_confirmed_path.key_value_maps,
+            &self.amt_node_cache,
+            new_root_commit_id,
             write_schema,
         )?;
-        confirm_maps_to_history::<D, SlotAllocations>(
+        let slot_alloc_ticket = prepare_confirm::<D, SlotAllocations>(
             &self.backend,
-            start_height,
-            slot_alloc_confirmed_path.key_value_maps,
+            &self.slot_alloc_cache,
+            new_root_commit_id,
             write_schema,
         )?;
 
-        Ok(())
+        let key_value_confirmed_path =
+            finalize_confirm(&mut self.key_value_cache, key_value_ticket)?;
+        let amt_node_confirmed_path = finalize_confirm(&mut self.amt_node_cache, amt_node_ticket)?;
+        let slot_alloc_confirmed_path =
+            finalize_confirm(&mut self.slot_alloc_cache, slot_alloc_ticket)?;
+
+        for confirmed_path in [&amt_node_confirmed_path, &slot_alloc_confirmed_path] {
+            assert_eq!(
+                confirmed_path.start_height,
+                key_value_confirmed_path.start_height
+            );
+            assert_eq!(
+                confirmed_path.commit_ids,
+                key_value_confirmed_path.commit_ids
+            );
+        }
+
+        let key_value_cache = &self.key_value_cache;
+        self.root_hash_cache
+            .retain(|commit, _| key_value_cache.contains_commit_id(commit));
+
+        Ok(key_value_confirmed_path)
+    }
+}
+
+fn recorded_allocation_scheme<D: DatabaseTrait>(db: &D) -> Result<Option<AllocationScheme>> {
+    match db.view::<LvmtMetadata>()?.get(&ALLOCATION_SCHEME_KEY)? {
+        Some(encoded) => Ok(Some(AllocationScheme::decode(&encoded)?)),
+        None => Ok(None),
     }
 }
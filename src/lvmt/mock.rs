@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use amt::AmtParams;
+use ethereum_types::H256;
+
+use super::{
+    crypto::{CurveGroup, FrInt, G1Aff, VariableBaseMSM, G1, PE},
+    types::{
+        compute_amt_node_id, AllocatePosition, AmtId, AmtNodeId, CurvePointWithVersion,
+        KEY_SLOT_SIZE, SLOT_SIZE,
+    },
+};
+use crate::{
+    errors::Result,
+    middlewares::{CommitID, ConfirmedPath},
+    test_utils::MockVersionedStore,
+    traits::KeyValueStoreManager,
+    utils::hash::blake2s,
+};
+
+/// A reference model of [`LvmtStore`](super::storage::LvmtStore): the plain value of each key at
+/// each commit, with the slot and version of each key and the version of each AMT, from which
+/// the root commitment is recomputed by naive MSM.
+///
+/// Slots are allocated in the order of the keys of a commit, as with
+/// `AllocationScheme::ArrivalOrder` for changes passed in that order.
+pub struct MockLvmtStore {
+    key_values: MockVersionedStore<Box<[u8]>, Box<[u8]>>,
+    commits: HashMap<CommitID, MockCommit>,
+}
+
+#[derive(Clone, Default)]
+struct MockCommit {
+    num_changes: usize,
+    // the slot and version of every key written up to the commit, deleted ones included
+    slots: BTreeMap<Box<[u8]>, (AllocatePosition, u64)>,
+    // the number of slots allocated at each AMT node
+    allocated: BTreeMap<AmtNodeId, usize>,
+    // the version of each AMT, bumped by every commit changing a slot under it
+    amt_versions: BTreeMap<AmtId, u64>,
+}
+
+impl MockCommit {
+    fn allocate(&mut self, key: &[u8]) -> AllocatePosition {
+        let digest = blake2s(key);
+        let mut depth = 1;
+        loop {
+            let allocated = self
+                .allocated
+                .entry(compute_amt_node_id(digest, depth))
+                .or_default();
+            if *allocated < KEY_SLOT_SIZE {
+                *allocated += 1;
+                return AllocatePosition {
+                    depth: depth as u8,
+                    slot_index: (*allocated - 1) as u8,
+                };
+            }
+            depth += 1;
+        }
+    }
+
+    // Sums the version of each slot of the AMT times its basis.
+    fn commitment(&self, amt_id: &AmtId, pp: &AmtParams<PE>) -> G1Aff {
+        let key_slots = self.slots.iter().map(|(key, (position, version))| {
+            let (slot_amt_id, node_index, slot_index) = position.amt_info(key);
+            (slot_amt_id, node_index, slot_index as usize, *version)
+        });
+        let amt_slots = self.amt_versions.iter().filter_map(|(child, version)| {
+            let (parent, node_index) = child.parent_node()?.split();
+            Some((parent, node_index, SLOT_SIZE - 1, *version))
+        });
+
+        let mut basis = vec![];
+        let mut bigints = vec![];
+        for (_, node_index, slot_index, version) in key_slots
+            .chain(amt_slots)
+            .filter(|(slot_amt_id, ..)| slot_amt_id == amt_id)
+        {
+            basis.push(pp.get_basis_power_at(node_index as usize)[slot_index]);
+            bigints.push(FrInt::from(version));
+        }
+        G1::msm_bigint(&basis, &bigints).into_affine()
+    }
+}
+
+impl MockLvmtStore {
+    pub fn new() -> Self {
+        Self {
+            key_values: MockVersionedStore::new(),
+            commits: HashMap::new(),
+        }
+    }
+
+    pub fn commit(
+        &mut self,
+        old_commit: Option<CommitID>,
+        new_commit: CommitID,
+        changes: BTreeMap<Box<[u8]>, Option<Box<[u8]>>>,
+    ) -> Result<()> {
+        let keys: Vec<_> = changes.keys().cloned().collect();
+        self.key_values
+            .add_to_pending_part(old_commit, new_commit, changes)?;
+
+        let mut commit = match old_commit {
+            Some(old_commit) => self.commits[&old_commit].clone(),
+            None => MockCommit::default(),
+        };
+        commit.num_changes = keys.len();
+
+        // keys seen before keep their slot, then the new ones are allocated in order
+        let mut changed = Vec::with_capacity(keys.len());
+        let mut new_keys = vec![];
+        for key in keys {
+            match commit.slots.get_mut(&key) {
+                Some((position, version)) => {
+                    *version += 1;
+                    changed.push((key, *position));
+                }
+                None => new_keys.push(key),
+            }
+        }
+        for key in new_keys {
+            let position = commit.allocate(&key);
+            commit.slots.insert(key.clone(), (position, 1));
+            changed.push((key, position));
+        }
+
+        let mut changed_amts = BTreeSet::new();
+        for (key, position) in changed {
+            let (mut amt_id, _, _) = position.amt_info(&key);
+            changed_amts.insert(amt_id);
+            while amt_id.pop().is_some() {
+                changed_amts.insert(amt_id);
+            }
+        }
+        for amt_id in changed_amts {
+            *commit.amt_versions.entry(amt_id).or_default() += 1;
+        }
+
+        self.commits.insert(new_commit, commit);
+        Ok(())
+    }
+
+    /// Returns the value of `key` at `commit`, `None` if it does not exist or has been deleted.
+    pub fn get(&self, commit: &CommitID, key: &[u8]) -> Result<Option<Box<[u8]>>> {
+        self.key_values.get_versioned_key(commit, &key.into())
+    }
+
+    /// Computes the root commitment at `commit` and its hash, `None` if the commit was discarded
+    /// or never made.
+    pub fn root(&self, commit: &CommitID, pp: &AmtParams<PE>) -> Option<(G1Aff, H256)> {
+        let commitment = self.commits.get(commit)?.commitment(&AmtId::root(), pp);
+        let mut root = CurvePointWithVersion::default();
+        root.point += G1::from(commitment);
+        Some((commitment, root.point.hash()))
+    }
+
+    /// Returns the path `LvmtStorage::confirmed_pending_to_history` is expected to confirm.
+    pub fn confirmed_pending_to_history(
+        &mut self,
+        new_root_commit_id: CommitID,
+    ) -> Result<ConfirmedPath> {
+        let start_height = self.key_values.num_history();
+        let mut commit_ids = self.key_values.path_to_root(&new_root_commit_id)?;
+        self.key_values
+            .confirmed_pending_to_history(new_root_commit_id)?;
+        self.retain_live_commits();
+
+        // `new_root_commit_id` stays pending
+        commit_ids.remove(0);
+        commit_ids.reverse();
+        let num_keys_per_commit = commit_ids
+            .iter()
+            .map(|commit| self.commits[commit].num_changes)
+            .collect();
+        Ok(ConfirmedPath {
+            start_height,
+            commit_ids,
+            num_keys_per_commit,
+        })
+    }
+
+    pub fn discard(&mut self, commit: CommitID) -> Result<()> {
+        self.key_values.discard(commit)?;
+        self.retain_live_commits();
+        Ok(())
+    }
+
+    /// Returns the confirmed and pending commits, sorted.
+    pub fn commits(&self) -> Vec<CommitID> {
+        self.key_values.get_commit_ids().into_iter().collect()
+    }
+
+    /// Returns the pending commits, sorted.
+    pub fn pending(&self) -> Vec<CommitID> {
+        self.key_values.get_pending()
+    }
+
+    /// Returns the pending commits without children, sorted.
+    pub fn leaves(&self) -> Vec<CommitID> {
+        self.key_values.leaves()
+    }
+
+    pub fn parent_of_root(&self) -> Option<CommitID> {
+        self.key_values.get_parent_of_root()
+    }
+
+    fn retain_live_commits(&mut self) {
+        let live = self.key_values.get_commit_ids();
+        self.commits.retain(|commit, _| live.contains(commit));
+    }
+}
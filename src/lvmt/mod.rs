@@ -1,7 +1,11 @@
 mod amt_change_manager;
 mod auth_changes;
+mod backup;
 pub mod crypto;
 mod example;
+#[cfg(test)]
+mod mock;
+mod state_view;
 mod storage;
 pub mod table_schema;
 #[cfg(test)]
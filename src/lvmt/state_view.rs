@@ -0,0 +1,157 @@
+use ethereum_types::H256;
+
+use crate::{
+    errors::Result,
+    middlewares::{CommitID, KeyValueStoreBulks, SnapshotView, VersionedStoreReadOnly},
+    traits::{KeyValueStoreBulksTrait, KeyValueStoreRead},
+};
+
+use super::{
+    auth_changes::{key_value_hash, prove_leaf, AuthChangeTable},
+    table_schema::{AmtNodes, FlatKeyValue, SlotAllocations},
+    types::{AmtId, AuthChangeProof, LvmtValue},
+};
+
+/// Selects the commit read by `LvmtStorage::state_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateSelector {
+    Commit(CommitID),
+    /// A confirmed height.
+    Height(usize),
+    /// The last added pending commit if it is still pending, otherwise the latest confirmed commit.
+    Latest,
+}
+
+/// A page of [`LvmtStateView::iter_paged`], with the key the next page starts from.
+pub type StatePage = (Vec<(Box<[u8]>, Box<[u8]>)>, Option<Box<[u8]>>);
+
+/// A proof that the commit which last changed a key wrote its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LvmtProof {
+    /// The last commit changing the key, up to the commit of the view.
+    pub change_commit: CommitID,
+    /// What `change_commit` wrote, with the slot and version of the key.
+    pub value: LvmtValue,
+    /// The membership of the change in the auth-change tree of `change_commit`.
+    pub proof: AuthChangeProof,
+}
+
+impl LvmtProof {
+    /// Checks the proof of `key` against the auth-change root of `change_commit`, as returned
+    /// in its `CommitResult`.
+    pub fn verify(&self, auth_change_root: H256, key: &[u8]) -> bool {
+        self.proof
+            .verify(auth_change_root, key_value_hash(key, &self.value))
+    }
+}
+
+/// The key-value, AMT node and slot allocation snapshots of one commit.
+pub struct LvmtStateView<'a> {
+    commit: CommitID,
+    key_value: SnapshotView<'a, FlatKeyValue>,
+    amt_node: SnapshotView<'a, AmtNodes>,
+    slot_alloc: SnapshotView<'a, SlotAllocations>,
+    key_value_store: VersionedStoreReadOnly<'a, 'a, FlatKeyValue>,
+    auth_changes: KeyValueStoreBulks<'a, AuthChangeTable>,
+}
+
+impl<'a> LvmtStateView<'a> {
+    pub(super) fn new(
+        commit: CommitID,
+        key_value_store: VersionedStoreReadOnly<'a, 'a, FlatKeyValue>,
+        amt_node_store: &VersionedStoreReadOnly<'a, 'a, AmtNodes>,
+        slot_alloc_store: &VersionedStoreReadOnly<'a, 'a, SlotAllocations>,
+        auth_changes: KeyValueStoreBulks<'a, AuthChangeTable>,
+    ) -> Result<Self> {
+        Ok(Self {
+            commit,
+            key_value: key_value_store.get_versioned_store(&commit)?,
+            amt_node: amt_node_store.get_versioned_store(&commit)?,
+            slot_alloc: slot_alloc_store.get_versioned_store(&commit)?,
+            key_value_store,
+            auth_changes,
+        })
+    }
+
+    pub fn commit(&self) -> CommitID {
+        self.commit
+    }
+
+    /// Returns the value of `key`, `None` if it does not exist or has been deleted.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Box<[u8]>>> {
+        Ok(self
+            .key_value
+            .get(&key.into())?
+            .and_then(|lvmt_value| lvmt_value.value))
+    }
+
+    /// Returns the first key not less than `key` with its value. Deleted keys are skipped.
+    pub fn seek(&self, key: &[u8]) -> Result<Option<(Box<[u8]>, Box<[u8]>)>> {
+        let (mut page, _) = self.iter_paged(key, 1)?;
+        Ok(page.pop())
+    }
+
+    /// Returns up to `limit` keys from `start` on with their values, in the order of the keys,
+    /// and the key the next page starts from, `None` after the last key. Deleted keys are skipped.
+    pub fn iter_paged(&self, start: &[u8], limit: usize) -> Result<StatePage> {
+        let mut page = Vec::with_capacity(limit);
+        for item in self.key_value.iter_from(&start.into())? {
+            let (key, lvmt_value) = item?;
+            let Some(value) = lvmt_value.value else {
+                continue;
+            };
+            if page.len() == limit {
+                return Ok((page, Some(key)));
+            }
+            page.push((key, value));
+        }
+        Ok((page, None))
+    }
+
+    /// Returns the hash of the root AMT commitment, see `LvmtStore::root_hash`.
+    pub fn root_hash(&self) -> Result<H256> {
+        let root = self.amt_node.get(&AmtId::root())?.unwrap_or_default();
+        Ok(root.point.hash())
+    }
+
+    /// Proves what the last commit changing `key` wrote, `None` if `key` was never written, or
+    /// the auth-change tree of that commit is not stored.
+    pub fn prove(&self, key: &[u8]) -> Result<Option<LvmtProof>> {
+        let key: Box<[u8]> = key.into();
+        let mut last_change = None;
+        self.key_value_store.iter_historical_changes(
+            |commit, _, value| {
+                last_change = value.map(|value| (*commit, value.clone()));
+                false
+            },
+            &self.commit,
+            &key,
+        )?;
+        let Some((change_commit, value)) = last_change else {
+            return Ok(None);
+        };
+
+        let get_node = |node_key: &_| {
+            self.auth_changes
+                .get_versioned_key(&change_commit, node_key)
+        };
+        let proof = prove_leaf(get_node, key_value_hash(&key, &value))?;
+        Ok(proof.map(|proof| LvmtProof {
+            change_commit,
+            value,
+            proof,
+        }))
+    }
+
+    pub fn key_value_view(&self) -> &SnapshotView<'a, FlatKeyValue> {
+        &self.key_value
+    }
+
+    pub fn amt_node_view(&self) -> &SnapshotView<'a, AmtNodes> {
+        &self.amt_node
+    }
+
+    pub fn slot_alloc_view(&self) -> &SnapshotView<'a, SlotAllocations> {
+        &self.slot_alloc
+    }
+}
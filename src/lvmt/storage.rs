@@ -1,17 +1,27 @@
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    marker::PhantomData,
+};
 
 use amt::AmtParams;
+use ethereum_types::H256;
 
 use super::{
     amt_change_manager::AmtChangeManager,
-    auth_changes::{amt_change_hash, key_value_hash, process_dump_items, AuthChangeTable},
-    crypto::PE,
-    table_schema::{AmtNodes, FlatKeyValue, SlotAllocations},
-    types::{AllocatePosition, AmtNodeId},
+    auth_changes::{
+        amt_change_hash, key_value_hash, process_dump_items_external, stream_dump_items,
+        AuthChangeRootTable, AuthChangeTable, ExternalSortConfig,
+    },
+    crypto::{G1Aff, PE},
+    table_schema::{AmtNodes, FlatKeyValue, LvmtMetadata, SlotAllocations, ALLOCATION_SCHEME_KEY},
+    types::{
+        AllocatePosition, AmtId, AmtNodeId, AuthChangeKey, AuthChangeNode, CurvePointWithVersion,
+    },
 };
 use crate::{
-    backends::WriteSchemaTrait,
-    errors::Result,
+    backends::{DatabaseTrait, TableReader, WriteSchemaTrait},
+    errors::{DecodeError, Result},
     lvmt::types::{compute_amt_node_id, AllocationKeyInfo, KEY_SLOT_SIZE},
     middlewares::{table_schema::KeyValueSnapshotRead, CommitID},
     traits::KeyValueStoreBulksTrait,
@@ -21,40 +31,203 @@ use crate::{
     middlewares::{KeyValueStoreBulks, VersionedStore},
     traits::{KeyValueStoreManager, KeyValueStoreRead},
     utils::hash::blake2s,
+    StorageError,
 };
 
-pub struct LvmtStore<'cache, 'db> {
+/// The LVMT stores of a database of type `D`, whose write schema [`LvmtStore::commit`] returns.
+pub struct LvmtStore<'cache, 'db, D: DatabaseTrait> {
     key_value_store: VersionedStore<'cache, 'db, FlatKeyValue>,
     amt_node_store: VersionedStore<'cache, 'db, AmtNodes>,
     slot_alloc_store: VersionedStore<'cache, 'db, SlotAllocations>,
     auth_changes: KeyValueStoreBulks<'db, AuthChangeTable>,
+    auth_change_roots: TableReader<'db, AuthChangeRootTable>,
+    root_hashes: &'cache mut RootHashCache,
+    allocation_scheme: AllocationScheme,
+    external_sort: Option<ExternalSortConfig>,
+    _backend: PhantomData<D>,
+}
+
+/// What a commit of [`LvmtStore::commit`] computed, to be embedded in its block header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitResult {
+    /// The commitment of the root AMT at the new commit, whose hash is [`LvmtStore::root_hash`].
+    pub root_commitment: G1Aff,
+    /// The root hash of the auth-change tree of the commit, `None` if the commit changes nothing.
+    pub auth_change_root: Option<H256>,
+    /// The number of slots allocated to the keys new at the commit.
+    pub num_allocated_slots: usize,
+}
+
+/// What [`LvmtStore::simulate_commit`] computed for a commit that was not made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedCommit {
+    pub result: CommitResult,
+    /// The slots the commit would allocate to its new keys, in allocation order.
+    pub allocated_slots: Vec<(Box<[u8]>, AllocatePosition)>,
+}
+
+// The changes of a commit computed against its parent, before they are written.
+struct PreparedCommit {
+    key_value_changes: Vec<(Box<[u8]>, LvmtValue)>,
+    slot_alloc_changes: BTreeMap<AmtNodeId, AllocationKeyInfo>,
+    amt_changes: Vec<(AmtId, CurvePointWithVersion)>,
+    root_hash: H256,
+    result: CommitResult,
+    allocated_slots: Vec<(Box<[u8]>, AllocatePosition)>,
 }
 
+/// Root hashes of the pending commits, filled by [`LvmtStore::commit`].
+pub type RootHashCache = HashMap<CommitID, H256>;
+
 const ALLOC_START_VERSION: u64 = 1;
 
-impl<'cache, 'db> LvmtStore<'cache, 'db> {
+/// The maximum length of a key accepted by [`LvmtStore::commit`]. Keys are kept in the
+/// slot allocations and the auth changes, so an unbounded key would bloat every node of them.
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// Order in which a commit allocates slots to its new keys.
+///
+/// The AMT node of a key is derived from its digest, but the slots of a node are
+/// handed out in allocation order, so the order decides the slots of keys sharing a node.
+///
+/// Neither scheme is history-independent: a slot stays with the key first allocated it, so
+/// keys sharing a node get different slots when inserted by different commits, e.g. by a
+/// snapshot imported as one commit and by a replay of the original blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllocationScheme {
+    /// The order of the changes passed to [`LvmtStore::commit`].
+    /// Databases built with it must keep it to reproduce their commitments.
+    #[default]
+    ArrivalOrder,
+    /// Ascending order of `blake2s(key)`, then of the key, among the new keys of each commit,
+    /// so that the allocation does not depend on the order of the changes of a commit.
+    CommitDigestOrder,
+}
+
+impl AllocationScheme {
+    /// Encodes the scheme as recorded at [`ALLOCATION_SCHEME_KEY`] by [`LvmtStore::commit`].
+    pub fn encode(self) -> [u8; 1] {
+        match self {
+            AllocationScheme::ArrivalOrder => [0],
+            AllocationScheme::CommitDigestOrder => [1],
+        }
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self> {
+        match input {
+            [0] => Ok(AllocationScheme::ArrivalOrder),
+            [1] => Ok(AllocationScheme::CommitDigestOrder),
+            [_] => Err(DecodeError::Custom("unknown allocation scheme").into()),
+            _ => Err(DecodeError::IncorrectLength.into()),
+        }
+    }
+}
+
+impl<'cache, 'db, D: DatabaseTrait> LvmtStore<'cache, 'db, D> {
     pub fn new(
         key_value_store: VersionedStore<'cache, 'db, FlatKeyValue>,
         amt_node_store: VersionedStore<'cache, 'db, AmtNodes>,
         slot_alloc_store: VersionedStore<'cache, 'db, SlotAllocations>,
         auth_changes: KeyValueStoreBulks<'db, AuthChangeTable>,
+        auth_change_roots: TableReader<'db, AuthChangeRootTable>,
+        root_hashes: &'cache mut RootHashCache,
+        allocation_scheme: AllocationScheme,
+        external_sort: Option<ExternalSortConfig>,
     ) -> Self {
         Self {
             key_value_store,
             amt_node_store,
             slot_alloc_store,
             auth_changes,
+            auth_change_roots,
+            root_hashes,
+            allocation_scheme,
+            external_sort,
+            _backend: PhantomData,
         }
     }
 
+    /// Adds `new_commit` as a child of `old_commit` to the pending part, and returns what it
+    /// computed with the writes it makes to the database. The writes are committed by the
+    /// caller, e.g. merged with others by [`WriteSchemaTrait::merge`].
     pub fn commit(
         &mut self,
         old_commit: Option<CommitID>,
         new_commit: CommitID,
         changes: impl Iterator<Item = (Box<[u8]>, Option<Box<[u8]>>)>,
-        write_schema: &impl WriteSchemaTrait,
         pp: &AmtParams<PE>,
-    ) -> Result<()> {
+    ) -> Result<(CommitResult, D::WriteSchema)> {
+        // The auth-change nodes are written as they are built, not held until the end.
+        let write_schema = D::write_schema();
+        let auth_changes = &self.auth_changes;
+        let emit_auth_change = |key: AuthChangeKey, node: AuthChangeNode| {
+            let auth_change_bulk = std::iter::once((key, Some(node)));
+            auth_changes.commit(new_commit, auth_change_bulk, &write_schema)
+        };
+        let PreparedCommit {
+            key_value_changes,
+            slot_alloc_changes,
+            amt_changes,
+            root_hash,
+            result,
+            ..
+        } = self.prepare_commit(old_commit, changes, pp, emit_auth_change)?;
+
+        // Write to the pending part of db.
+        // TODO: Write to the history part is beyond the range of LvmtStore.
+        // TODO: LvmtStore.auth_changes includes all commits, even if they are removed but not confirmed,
+        //       so consider gc_commit elsewhere.
+        let amt_node_updates = amt_changes.into_iter().map(|(k, v)| (k, Some(v)));
+        self.amt_node_store
+            .add_to_pending_part(old_commit, new_commit, amt_node_updates)?;
+
+        let key_value_updates = key_value_changes.into_iter().map(|(k, v)| (k, Some(v)));
+        self.key_value_store
+            .add_to_pending_part(old_commit, new_commit, key_value_updates)?;
+
+        let slot_alloc_updates = slot_alloc_changes.into_iter().map(|(k, v)| (k, Some(v)));
+        self.slot_alloc_store
+            .add_to_pending_part(old_commit, new_commit, slot_alloc_updates)?;
+
+        if let Some(auth_change_root) = result.auth_change_root {
+            write_schema.write::<AuthChangeRootTable>((
+                Cow::Owned(new_commit),
+                Some(Cow::Owned(auth_change_root)),
+            ));
+        }
+        write_schema.write::<LvmtMetadata>((
+            Cow::Owned(ALLOCATION_SCHEME_KEY),
+            Some(Cow::Owned(self.allocation_scheme.encode().to_vec())),
+        ));
+
+        self.root_hashes.insert(new_commit, root_hash);
+
+        Ok((result, write_schema))
+    }
+
+    /// Computes what [`Self::commit`] would for a child of `old_commit` with `changes`, without
+    /// writing anything, e.g. to fill a block header before deciding to keep the block. A commit
+    /// with the same arguments then returns the same [`CommitResult`].
+    pub fn simulate_commit(
+        &self,
+        old_commit: Option<CommitID>,
+        changes: impl Iterator<Item = (Box<[u8]>, Option<Box<[u8]>>)>,
+        pp: &AmtParams<PE>,
+    ) -> Result<SimulatedCommit> {
+        let prepared = self.prepare_commit(old_commit, changes, pp, |_, _| Ok(()))?;
+        Ok(SimulatedCommit {
+            result: prepared.result,
+            allocated_slots: prepared.allocated_slots,
+        })
+    }
+
+    fn prepare_commit(
+        &self,
+        old_commit: Option<CommitID>,
+        changes: impl Iterator<Item = (Box<[u8]>, Option<Box<[u8]>>)>,
+        pp: &AmtParams<PE>,
+        emit_auth_change: impl FnMut(AuthChangeKey, AuthChangeNode) -> Result<()>,
+    ) -> Result<PreparedCommit> {
         let (amt_node_view, slot_alloc_view, key_value_view) = if let Some(old_commit) = old_commit
         {
             (
@@ -67,6 +240,7 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
         };
 
         let mut key_value_changes = vec![];
+        let mut new_keys = vec![];
         let mut allocations = AllocationCacheDb::new(&slot_alloc_view);
         let mut amt_change_manager = AmtChangeManager::default();
 
@@ -78,30 +252,52 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
             if !set_of_keys.insert(key.clone()) {
                 continue;
             }
+            check_key(&key)?;
 
-            let (allocation, version) = if let Some(old_value) = key_value_view.get(&key)? {
-                (old_value.allocation, old_value.version + 1)
+            if let Some(old_value) = key_value_view.get(&key)? {
+                key_value_changes.push((
+                    key,
+                    LvmtValue {
+                        allocation: old_value.allocation,
+                        version: old_value.version + 1,
+                        value,
+                    },
+                ));
             } else {
-                let allocation = allocate_version_slot(&key, &mut allocations)?;
-                (allocation, ALLOC_START_VERSION)
-            };
-
-            amt_change_manager.record_with_allocation(allocation, &key);
+                new_keys.push((key, value));
+            }
+        }
 
+        // Allocate slots for new keys
+        if self.allocation_scheme == AllocationScheme::CommitDigestOrder {
+            new_keys.sort_by_cached_key(|(key, _)| (blake2s(key), key.clone()));
+        }
+        let mut allocated_slots = Vec::with_capacity(new_keys.len());
+        for (key, value) in new_keys {
+            let allocation = allocate_version_slot(&key, &mut allocations)?;
+            allocated_slots.push((key.clone(), allocation));
             key_value_changes.push((
                 key,
                 LvmtValue {
                     allocation,
-                    version,
+                    version: ALLOC_START_VERSION,
                     value,
                 },
             ));
         }
 
+        for (key, value) in key_value_changes.iter() {
+            amt_change_manager.record_with_allocation(value.allocation, key);
+        }
+
         let amt_changes = amt_change_manager.compute_amt_changes(&amt_node_view, pp)?;
+        let root = match amt_changes.iter().find(|(amt_id, _)| amt_id.is_empty()) {
+            Some((_, root)) => root.clone(),
+            None => read_root(&amt_node_view)?,
+        };
 
         // Update auth changes
-        let auth_changes = {
+        let auth_change_root = {
             let auth_change_iter = amt_changes
                 .iter()
                 .filter(|&(amt_id, curve_point)| (amt_id.len() > 0))
@@ -110,42 +306,142 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
                 .iter()
                 .map(|(key, value)| key_value_hash(key, value));
 
-            let hashes = key_value_iter.chain(auth_change_iter).collect();
-            process_dump_items(hashes)
+            let num_leaves = key_value_changes.len()
+                + amt_changes
+                    .iter()
+                    .filter(|(amt_id, _)| amt_id.len() > 0)
+                    .count();
+            let hashes = key_value_iter.chain(auth_change_iter);
+            // a tree needs at least one leaf
+            match &self.external_sort {
+                _ if num_leaves == 0 => None,
+                Some(config) if num_leaves > config.threshold => {
+                    Some(process_dump_items_external(hashes, config, emit_auth_change)?.hash())
+                }
+                _ => Some(stream_dump_items(hashes.collect(), emit_auth_change)?.hash()),
+            }
         };
 
-        // Write to the pending part of db.
-        // TODO: Write to the history part is beyond the range of LvmtStore.
-        // TODO: LvmtStore.auth_changes includes all commits, even if they are removed but not confirmed,
-        //       so consider gc_commit elsewhere.
-        let amt_node_updates: BTreeMap<_, _> =
-            amt_changes.into_iter().map(|(k, v)| (k, Some(v))).collect();
-        self.amt_node_store
-            .add_to_pending_part(old_commit, new_commit, amt_node_updates)?;
+        Ok(PreparedCommit {
+            key_value_changes,
+            slot_alloc_changes: allocations.into_changes(),
+            amt_changes,
+            root_hash: root.point.hash(),
+            result: CommitResult {
+                root_commitment: root.point.affine().into_owned(),
+                auth_change_root,
+                num_allocated_slots: allocated_slots.len(),
+            },
+            allocated_slots,
+        })
+    }
 
-        let key_value_updates: BTreeMap<_, _> = key_value_changes
-            .into_iter()
-            .map(|(k, v)| (k, Some(v)))
-            .collect();
-        self.key_value_store
-            .add_to_pending_part(old_commit, new_commit, key_value_updates)?;
+    /// Like [`Self::commit`], but first checks that `new_commit` would be at `expected_height`,
+    /// see [`VersionedStore::check_height_of_new_commit`]. Nothing is written on a mismatch.
+    pub fn commit_checked(
+        &mut self,
+        old_commit: Option<CommitID>,
+        new_commit: CommitID,
+        changes: impl Iterator<Item = (Box<[u8]>, Option<Box<[u8]>>)>,
+        pp: &AmtParams<PE>,
+        expected_height: Option<usize>,
+    ) -> Result<(CommitResult, D::WriteSchema)> {
+        if let Some(expected_height) = expected_height {
+            self.key_value_store
+                .check_height_of_new_commit(old_commit, expected_height)?;
+        }
+        self.commit(old_commit, new_commit, changes, pp)
+    }
 
-        let slot_alloc_updates: BTreeMap<_, _> = allocations
-            .into_changes()
-            .into_iter()
-            .map(|(k, v)| (k, Some(v)))
-            .collect();
-        self.slot_alloc_store
-            .add_to_pending_part(old_commit, new_commit, slot_alloc_updates)?;
+    /// Returns the hash of the root AMT commitment at `commit`.
+    ///
+    /// The hashes of pending commits are kept since their commit, others are read from the store.
+    pub fn root_hash(&self, commit: &CommitID) -> Result<H256> {
+        if let Some(root_hash) = self.root_hashes.get(commit) {
+            return Ok(*root_hash);
+        }
+
+        Ok(
+            read_root(&self.amt_node_store.get_versioned_store(commit)?)?
+                .point
+                .hash(),
+        )
+    }
 
-        let auth_change_bulk = auth_changes.into_iter().map(|(k, v)| (k, Some(v)));
-        self.auth_changes
-            .commit(new_commit, auth_change_bulk, write_schema)?;
+    /// Returns the commitment of the root AMT at `commit`, whose hash is [`Self::root_hash`].
+    pub fn root_commitment(&self, commit: &CommitID) -> Result<G1Aff> {
+        Ok(
+            read_root(&self.amt_node_store.get_versioned_store(commit)?)?
+                .point
+                .affine()
+                .into_owned(),
+        )
+    }
+
+    /// Returns the value of `key` at `commit`, `None` if it does not exist or has been deleted.
+    pub fn get(&self, commit: &CommitID, key: &[u8]) -> Result<Option<Box<[u8]>>> {
+        Ok(self
+            .key_value_store
+            .get_versioned_key(commit, &key.into())?
+            .and_then(|lvmt_value| lvmt_value.value))
+    }
+
+    /// Returns the root hash of the auth-change tree of each of `commits`,
+    /// `None` for a commit without a tree in the committed state of the database.
+    ///
+    /// The roots are written with the auth changes, so they are kept after confirmation.
+    pub fn auth_change_roots(&self, commits: &[CommitID]) -> Result<Vec<Option<H256>>> {
+        commits
+            .iter()
+            .map(|commit| {
+                Ok(self
+                    .auth_change_roots
+                    .get(commit)?
+                    .map(|root| root.into_owned()))
+            })
+            .collect()
+    }
+
+    /// Returns the keys starting with `prefix` at `commit` with their values, in the order of the
+    /// keys. Deleted keys are skipped.
+    ///
+    /// Only the keys with the prefix are read, when called, so the iterator does not borrow the
+    /// store.
+    pub fn iter_key_prefix(
+        &self,
+        commit: CommitID,
+        prefix: &[u8],
+    ) -> Result<impl Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        let snapshot = self.key_value_store.get_versioned_store(&commit)?;
+        let end = prefix_end(prefix);
+        let mut entries = Vec::new();
+        for item in snapshot.iter_range(&Box::from(prefix), end.as_ref())? {
+            let (key, lvmt_value) = item?;
+            if let Some(value) = lvmt_value.value {
+                entries.push((key, value));
+            }
+        }
+        Ok(entries.into_iter())
+    }
+
+    /// Discards the pending commits forking from the path to `commit`, see [`KeyValueStoreManager::discard`].
+    pub fn discard(&mut self, commit: CommitID) -> Result<()> {
+        self.key_value_store.discard(commit)?;
+        self.amt_node_store.discard(commit)?;
+        self.slot_alloc_store.discard(commit)?;
+
+        let key_value_store = &self.key_value_store;
+        self.root_hashes
+            .retain(|commit, _| key_value_store.is_pending(commit));
 
         Ok(())
     }
 }
 
+fn read_root(amt_node_view: &KeyValueSnapshotRead<AmtNodes>) -> Result<CurvePointWithVersion> {
+    Ok(amt_node_view.get(&AmtId::root())?.unwrap_or_default())
+}
+
 struct AllocationCacheDb<'db> {
     db: &'db KeyValueSnapshotRead<'db, SlotAllocations>,
     cache: BTreeMap<AmtNodeId, AllocationKeyInfo>,
@@ -175,6 +471,30 @@ impl<'db> AllocationCacheDb<'db> {
     }
 }
 
+fn check_key(key: &[u8]) -> Result<()> {
+    if key.is_empty() {
+        return Err(StorageError::EmptyKey);
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(StorageError::KeyTooLong(key.len()));
+    }
+    Ok(())
+}
+
+/// Returns the least key after every key starting with `prefix`, `None` if there is none.
+fn prefix_end(prefix: &[u8]) -> Option<Box<[u8]>> {
+    let last = prefix.iter().rposition(|byte| *byte != u8::MAX)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end.into())
+}
+
+/// Allocates the next free slot to a new key, at the shallowest AMT node on the path of its
+/// digest with one left.
+///
+/// A node whose recorded allocations already name the key, at any of its slots, means the key
+/// holds a slot there while missing from the key-value store, so its allocation fails with
+/// [`StorageError::SlotAllocationConflict`] instead of binding it to a second slot.
 fn allocate_version_slot(
     key: &[u8],
     allocation_cache_db: &mut AllocationCacheDb,
@@ -185,26 +505,30 @@ fn allocate_version_slot(
     loop {
         let amt_node_id = compute_amt_node_id(key_digest, depth);
         let slot_alloc = allocation_cache_db.get(&amt_node_id)?;
-        let next_index = match slot_alloc {
-            None => 0,
-            Some(x) if (x.index as usize) < KEY_SLOT_SIZE - 1 => x.index + 1,
+        if matches!(&slot_alloc, Some(x) if x.holds_key(key)) {
+            return Err(StorageError::SlotAllocationConflict);
+        }
+        let alloc_info = match slot_alloc {
+            None => AllocationKeyInfo::new(0, key.into()),
+            Some(x) if (x.index as usize) < KEY_SLOT_SIZE - 1 => x.allocate_next(key.into()),
             _ => {
                 depth += 1;
                 continue;
             }
         };
 
-        allocation_cache_db.set(amt_node_id, AllocationKeyInfo::new(next_index, key.into()));
+        let slot_index = alloc_info.index;
+        allocation_cache_db.set(amt_node_id, alloc_info);
 
         return Ok(AllocatePosition {
             depth: depth as u8,
-            slot_index: next_index as u8,
+            slot_index,
         });
     }
 }
 
 #[cfg(test)]
-impl<'cache, 'db> LvmtStore<'cache, 'db> {
+impl<'cache, 'db, D: DatabaseTrait> LvmtStore<'cache, 'db, D> {
     pub fn get_key_value_store(&self) -> &VersionedStore<'cache, 'db, FlatKeyValue> {
         &self.key_value_store
     }
@@ -214,4 +538,119 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
     pub fn get_slot_alloc_store(&self) -> &VersionedStore<'cache, 'db, SlotAllocations> {
         &self.slot_alloc_store
     }
+    pub fn get_auth_changes(&self) -> &KeyValueStoreBulks<'db, AuthChangeTable> {
+        &self.auth_changes
+    }
+    pub fn is_root_hash_cached(&self, commit: &CommitID) -> bool {
+        self.root_hashes.contains_key(commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // allocations seeded directly, as a database left inconsistent would hold them
+    struct SeededAllocations(BTreeMap<AmtNodeId, AllocationKeyInfo>);
+
+    impl KeyValueStoreRead<AmtNodeId, AllocationKeyInfo> for SeededAllocations {
+        fn get(&self, key: &AmtNodeId) -> Result<Option<AllocationKeyInfo>> {
+            Ok(self.0.get(key).cloned())
+        }
+    }
+
+    // seeds the nodes on the path of `key` with the allocations recorded at their depths
+    fn seed(key: &[u8], entries: &[(usize, u8, &[u8])]) -> SeededAllocations {
+        SeededAllocations(
+            entries
+                .iter()
+                .map(|(depth, index, recorded)| {
+                    (
+                        compute_amt_node_id(blake2s(key), *depth),
+                        AllocationKeyInfo::new(*index, recorded.to_vec().into_boxed_slice()),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn position(depth: u8, slot_index: u8) -> AllocatePosition {
+        AllocatePosition { depth, slot_index }
+    }
+
+    #[test]
+    fn test_allocate_version_slot() {
+        let key = b"key".as_slice();
+        let fixture = seed(key, &[]);
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Ok(position(1, 0))
+        );
+        // allocating the same key twice in a commit is a conflict
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Err(StorageError::SlotAllocationConflict)
+        );
+
+        // another key of the node leaves the next slot
+        let fixture = seed(key, &[(1, 2, b"other")]);
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Ok(position(1, 3))
+        );
+
+        let full = KEY_SLOT_SIZE as u8 - 1;
+        let fixture = seed(key, &[(1, full, b"other")]);
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Ok(position(2, 0))
+        );
+    }
+
+    #[test]
+    fn test_allocate_version_slot_conflict() {
+        let key = b"key".as_slice();
+
+        // the key already holds the last slot of its node
+        let fixture = seed(key, &[(1, 2, key)]);
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Err(StorageError::SlotAllocationConflict)
+        );
+        assert!(allocations.into_changes().is_empty());
+
+        // or of a deeper node, once the first one is full
+        let full = KEY_SLOT_SIZE as u8 - 1;
+        let fixture = seed(key, &[(1, full, b"other"), (2, 0, key)]);
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Err(StorageError::SlotAllocationConflict)
+        );
+
+        // or a slot before the last one of its node
+        let node_id = compute_amt_node_id(blake2s(key), 1);
+        let recorded =
+            AllocationKeyInfo::new(0, key.into()).allocate_next(b"other".as_slice().into());
+        let fixture = SeededAllocations(BTreeMap::from([(node_id, recorded)]));
+        let mut allocations = AllocationCacheDb::new(&fixture);
+        assert_eq!(
+            allocate_version_slot(key, &mut allocations),
+            Err(StorageError::SlotAllocationConflict)
+        );
+    }
+
+    #[test]
+    fn test_check_key() {
+        assert_eq!(check_key(b""), Err(StorageError::EmptyKey));
+        assert!(check_key(&[0; MAX_KEY_LENGTH]).is_ok());
+        assert_eq!(
+            check_key(&[0; MAX_KEY_LENGTH + 1]),
+            Err(StorageError::KeyTooLong(MAX_KEY_LENGTH + 1))
+        );
+    }
 }
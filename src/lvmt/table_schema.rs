@@ -1,6 +1,9 @@
 use super::types::{AllocationKeyInfo, AmtId, AmtNodeId, CurvePointWithVersion, LvmtValue};
 use crate::define_key_value_schema;
-use crate::{backends::VersionedKVName, middlewares::table_schema::VersionedKeyValueSchema};
+use crate::{
+    backends::{TableName, TableSchema, VersionedKVName},
+    middlewares::table_schema::VersionedKeyValueSchema,
+};
 
 define_key_value_schema! {
     FlatKeyValue,
@@ -22,3 +25,16 @@ define_key_value_schema! {
     key: AmtNodeId,
     value: AllocationKeyInfo,
 }
+
+/// Settings an LVMT database is built with, which must not change over its lifetime.
+#[derive(Clone, Copy)]
+pub struct LvmtMetadata;
+impl TableSchema for LvmtMetadata {
+    const NAME: TableName = TableName::LvmtMetadata;
+
+    type Key = u64;
+    type Value = [u8];
+}
+
+/// The key of the `AllocationScheme` in [`LvmtMetadata`].
+pub const ALLOCATION_SCHEME_KEY: u64 = 0;
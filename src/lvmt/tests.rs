@@ -1,19 +1,35 @@
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use rand_chacha::ChaChaRng;
 
 use amt::{AmtParams, CreateMode};
 
 use crate::{
-    backends::{DatabaseTrait, InMemoryDatabase},
+    backends::{DatabaseTrait, InMemoryDatabase, TableName, TableRead, WriteSchemaTrait},
     errors::Result,
     lvmt::types::{LvmtValue, KEY_SLOT_SIZE},
-    middlewares::{empty_rocksdb, gen_random_commit_id, gen_updates, get_rng_for_test, CommitID},
+    middlewares::{table_schema::HistoryChangeTable, CommitID},
+    test_utils::{
+        empty_rocksdb, gen_novel_u64, gen_random_commit_id, gen_updates, get_rng_for_test,
+        select_vec_element,
+    },
     traits::{KeyValueStoreManager, KeyValueStoreRead},
 };
 
-use super::{crypto::PE, example::LvmtStorage, storage::LvmtStore};
+use super::{
+    backup::{state_digest, BackupManifest},
+    crypto::PE,
+    example::LvmtStorage,
+    mock::MockLvmtStore,
+    storage::LvmtStore,
+    table_schema::FlatKeyValue,
+};
 
 pub const TEST_LEVEL: usize = 16;
 
@@ -90,20 +106,24 @@ fn test_lvmt_store<D: DatabaseTrait>(backend: D, num_keys: usize) {
 
     // Get a manager for db
     let mut lvmt = db.as_manager().unwrap();
-    let write_schema = D::write_schema();
+    let mut write_schema = D::write_schema();
 
     // Perform non-forking commits
-    lvmt.commit(None, commit_1, changes_1, &write_schema, &AMT)
-        .unwrap();
+    let (_, writes) = lvmt.commit(None, commit_1, changes_1, &AMT).unwrap();
+    write_schema.merge(writes);
     lvmt.check_consistency(commit_1, &AMT).unwrap();
 
-    lvmt.commit(Some(commit_1), commit_2, changes_2, &write_schema, &AMT)
+    let (_, writes) = lvmt
+        .commit(Some(commit_1), commit_2, changes_2, &AMT)
         .unwrap();
+    write_schema.merge(writes);
     lvmt.check_consistency(commit_2, &AMT).unwrap();
 
     // Perform a forking commit
-    lvmt.commit(Some(commit_1), commit_2_1, changes_2_1, &write_schema, &AMT)
+    let (_, writes) = lvmt
+        .commit(Some(commit_1), commit_2_1, changes_2_1, &AMT)
         .unwrap();
+    write_schema.merge(writes);
     lvmt.check_consistency(commit_2_1, &AMT).unwrap();
 
     // Check the previous commit again after adding subsequent commits
@@ -118,11 +138,13 @@ fn test_lvmt_store<D: DatabaseTrait>(backend: D, num_keys: usize) {
 
     // Reinitialize the manager
     lvmt = db.as_manager().unwrap();
-    let write_schema = D::write_schema();
+    let mut write_schema = D::write_schema();
 
     // Commit again to verify success after persisting changes to the backend
-    lvmt.commit(Some(commit_2), commit_3, changes_3, &write_schema, &AMT)
+    let (_, writes) = lvmt
+        .commit(Some(commit_2), commit_3, changes_3, &AMT)
         .unwrap();
+    write_schema.merge(writes);
     lvmt.check_consistency(commit_3, &AMT).unwrap();
 
     // Check previous commits again after they are confirmed or removed
@@ -143,21 +165,624 @@ fn test_lvmt_store_rocksdb() {
     }
 }
 
+#[test]
+fn test_compact_all_rocksdb() {
+    let db_path = "__test_lvmt_compact_all";
+
+    // kvdb-rocksdb cannot compact a column, which is skipped
+    let db = LvmtStorage::new(empty_rocksdb(db_path).unwrap()).unwrap();
+    db.compact_all().unwrap();
+    drop(db);
+
+    if std::path::Path::new(db_path).exists() {
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+}
+
 #[test]
 fn test_lvmt_store_inmemory() {
     let backend = InMemoryDatabase::empty();
     test_lvmt_store::<InMemoryDatabase>(backend, 100000);
 }
 
-impl<'cache, 'db> LvmtStore<'cache, 'db> {
+fn test_lvmt_backup<D: DatabaseTrait>(backend: D, backup_path: &str, num_keys: usize) {
+    use crate::StorageError;
+
+    const NUM_COMMITS: usize = 6;
+
+    let mut rng = get_rng_for_test();
+    let backup_path = std::path::Path::new(backup_path);
+    if backup_path.exists() {
+        std::fs::remove_dir_all(backup_path).unwrap();
+    }
+
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..=NUM_COMMITS)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+    let mut all_keys = BTreeSet::new();
+    let mut gen_changes = |rng: &mut ChaChaRng| {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(rng, &previous_keys, num_keys, num_keys, &mut all_keys);
+        get_changes_from_updates(updates)
+    };
+
+    let db = Mutex::new(LvmtStorage::<D>::new(backend).unwrap());
+    let num_committed = AtomicUsize::new(0);
+
+    // a writer keeps committing a chain of commits, confirming the parent of each one, while
+    // the backup is taken between two of its commits
+    let manifest = std::thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            for i in 0..NUM_COMMITS {
+                let mut db = db.lock();
+                let mut lvmt = db.as_manager().unwrap();
+                let parent = i.checked_sub(1).map(|p| commits[p]);
+                let changes = gen_changes(&mut rng);
+                let (_, write_schema) = lvmt.commit(parent, commits[i], changes, &AMT).unwrap();
+                drop(lvmt);
+                db.confirmed_pending_to_history(commits[i], &write_schema)
+                    .unwrap();
+                db.commit(write_schema).unwrap();
+                drop(db);
+                num_committed.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        loop {
+            let writer_finished = writer.is_finished();
+            if num_committed.load(Ordering::SeqCst) >= 3 {
+                break;
+            }
+            assert!(!writer_finished, "the writer stopped early");
+            std::thread::yield_now();
+        }
+        db.lock()
+            .backup(D::write_schema(), backup_path, true)
+            .unwrap()
+    });
+    let mut db = db.into_inner();
+
+    // at least commits[1] was confirmed when the backup was taken
+    let (history_number, latest_confirmed) = manifest.latest_confirmed.unwrap();
+    let num_confirmed = history_number as usize;
+    assert!((2..NUM_COMMITS).contains(&num_confirmed));
+    assert_eq!(latest_confirmed, commits[num_confirmed - 1]);
+
+    // the backup reads consistently at the recorded height
+    let mut restored = LvmtStorage::<D>::open_from_backup(backup_path).unwrap();
+    let mut restored_lvmt = restored.as_manager().unwrap();
+    let lvmt = db.as_manager().unwrap();
+    for &commit in &commits[..num_confirmed] {
+        restored_lvmt.check_consistency(commit, &AMT).unwrap();
+
+        let expected: Vec<_> = lvmt
+            .get_key_value_store()
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .collect();
+        let actual: Vec<_> = restored_lvmt
+            .get_key_value_store()
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .collect();
+        assert_eq!(actual, expected);
+    }
+    for &commit in &commits[num_confirmed..NUM_COMMITS] {
+        restored_lvmt.check_consistency(commit, &AMT).unwrap_err();
+    }
+
+    // the backup accepts new commits on top of its latest confirmed commit
+    let changes = gen_changes(&mut rng);
+    restored_lvmt
+        .commit(Some(latest_confirmed), commits[NUM_COMMITS], changes, &AMT)
+        .unwrap();
+    restored_lvmt
+        .check_consistency(commits[NUM_COMMITS], &AMT)
+        .unwrap();
+    drop(restored_lvmt);
+    drop(restored);
+
+    // a backup taken before the last LVMT table was added, whose digest does not cover it,
+    // opens with it empty
+    let manifest_path = backup_path.join("MANIFEST");
+    let manifest_bytes = std::fs::read(&manifest_path).unwrap();
+    let old_num_tables = u32::from(TableName::LvmtMetadata);
+    let checkpoint = D::open_checkpoint(&backup_path.join("db")).unwrap();
+    let old_manifest =
+        BackupManifest::from_db_with_tables(&checkpoint, old_num_tables, true).unwrap();
+    assert_ne!(
+        old_manifest.state_digest,
+        Some(state_digest(&checkpoint, TableName::max_index() + 1).unwrap())
+    );
+    drop(checkpoint);
+    std::fs::write(&manifest_path, old_manifest.encode()).unwrap();
+    let mut restored = LvmtStorage::<D>::open_from_backup(backup_path).unwrap();
+    assert_eq!(
+        restored
+            .as_manager()
+            .unwrap()
+            .root_hash(&latest_confirmed)
+            .unwrap(),
+        lvmt.root_hash(&latest_confirmed).unwrap()
+    );
+    drop(restored);
+
+    // a backup with more tables than known is rejected
+    let manifest = BackupManifest {
+        num_tables: TableName::max_index() + 2,
+        ..BackupManifest::read(backup_path).unwrap()
+    };
+    std::fs::write(&manifest_path, manifest.encode()).unwrap();
+    assert!(matches!(
+        LvmtStorage::<D>::open_from_backup(backup_path),
+        Err(StorageError::InvalidBackup("mismatched number of tables"))
+    ));
+    std::fs::write(&manifest_path, manifest_bytes).unwrap();
+
+    // a checkpoint missing a row, as a torn copy would, is rejected
+    tear_checkpoint::<D>(backup_path);
+    assert!(matches!(
+        LvmtStorage::<D>::open_from_backup(backup_path),
+        Err(StorageError::InvalidBackup("mismatched state digest"))
+    ));
+
+    // without the state digest, only the latest confirmed commit is checked
+    let manifest = BackupManifest {
+        state_digest: None,
+        ..BackupManifest::read(backup_path).unwrap()
+    };
+    std::fs::write(&manifest_path, manifest.encode()).unwrap();
+    assert_eq!(BackupManifest::read(backup_path).unwrap(), manifest);
+    LvmtStorage::<D>::open_from_backup(backup_path).unwrap();
+
+    // a backup without manifest is rejected
+    std::fs::remove_file(backup_path.join("MANIFEST")).unwrap();
+    assert!(LvmtStorage::<D>::open_from_backup(backup_path).is_err());
+
+    std::fs::remove_dir_all(backup_path).unwrap();
+}
+
+// deletes a row of the checkpoint of the backup at `backup_path`
+fn tear_checkpoint<D: DatabaseTrait>(backup_path: &std::path::Path) {
+    type Table = HistoryChangeTable<FlatKeyValue>;
+
+    let db_path = backup_path.join("db");
+    let torn_path = backup_path.join("torn");
+    let mut checkpoint = D::open_checkpoint(&db_path).unwrap();
+    let table = checkpoint.view::<Table>().unwrap();
+    let key = table
+        .iter_from_start()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .0
+        .into_owned();
+    drop(table);
+    let write_schema = D::write_schema();
+    write_schema.write::<Table>((Cow::Owned(key), None));
+    checkpoint.commit(write_schema).unwrap();
+
+    // the in-memory backend does not write back to its checkpoint
+    checkpoint.create_checkpoint(&torn_path).unwrap();
+    drop(checkpoint);
+    if db_path.is_dir() {
+        std::fs::remove_dir_all(&db_path).unwrap();
+    } else {
+        std::fs::remove_file(&db_path).unwrap();
+    }
+    std::fs::rename(&torn_path, &db_path).unwrap();
+}
+
+#[test]
+fn test_lvmt_backup_rocksdb() {
+    let db_path = "__test_lvmt_backup_source";
+
+    let backend = empty_rocksdb(db_path).unwrap();
+    test_lvmt_backup::<kvdb_rocksdb::Database>(backend, "__test_lvmt_backup_rocksdb", 1000);
+
+    if std::path::Path::new(db_path).exists() {
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+}
+
+#[test]
+fn test_lvmt_backup_inmemory() {
+    let backend = InMemoryDatabase::empty();
+    test_lvmt_backup::<InMemoryDatabase>(backend, "__test_lvmt_backup_inmemory", 1000);
+}
+
+#[test]
+fn test_commit_digest_order_allocation() {
+    use super::storage::AllocationScheme;
+
+    const NUM_KEYS: usize = 10000;
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..2)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let mut all_keys = BTreeSet::new();
+    let updates: Vec<Vec<_>> = (0..2)
+        .map(|_| {
+            let previous_keys = all_keys.clone();
+            let updates = gen_updates(&mut rng, &previous_keys, NUM_KEYS, NUM_KEYS, &mut all_keys);
+            get_changes_from_updates(updates).collect()
+        })
+        .collect();
+
+    // the same commits, with their changes in opposite orders
+    let mut dbs: Vec<_> = [false, true]
+        .into_iter()
+        .map(|reversed| {
+            let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+            db.set_allocation_scheme(AllocationScheme::CommitDigestOrder)
+                .unwrap();
+
+            let mut lvmt = db.as_manager().unwrap();
+            let mut write_schema = InMemoryDatabase::write_schema();
+            for (i, changes) in updates.iter().enumerate() {
+                let parent = i.checked_sub(1).map(|p| commits[p]);
+                let mut changes = changes.clone();
+                if reversed {
+                    changes.reverse();
+                }
+                let (_, writes) = lvmt
+                    .commit(parent, commits[i], changes.into_iter(), &AMT)
+                    .unwrap();
+                write_schema.merge(writes);
+            }
+            drop(lvmt);
+            db.commit(write_schema).unwrap();
+            db
+        })
+        .collect();
+
+    let (db_forward, db_reversed) = dbs.split_at_mut(1);
+    let mut lvmt_forward = db_forward[0].as_manager().unwrap();
+    let mut lvmt_reversed = db_reversed[0].as_manager().unwrap();
+    for &commit in &commits {
+        lvmt_forward.check_consistency(commit, &AMT).unwrap();
+        lvmt_reversed.check_consistency(commit, &AMT).unwrap();
+
+        let forward: Vec<_> = lvmt_forward
+            .get_key_value_store()
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .collect();
+        let reversed: Vec<_> = lvmt_reversed
+            .get_key_value_store()
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter()
+            .unwrap()
+            .collect();
+        assert_eq!(forward, reversed);
+    }
+}
+
+#[test]
+fn test_commit_digest_order_allocation_collision() {
+    use super::{storage::AllocationScheme, types::compute_amt_node_id};
+    use crate::utils::hash::blake2s;
+
+    // two keys sharing their AMT node at depth 1, found by the birthday bound
+    let mut seen = HashMap::new();
+    let (first, second) = (0u64..)
+        .find_map(|i| {
+            let key = u64_to_boxed_u8(i);
+            let node = compute_amt_node_id(blake2s(&key), 1);
+            seen.insert(node, key.clone()).map(|other| (other, key))
+        })
+        .unwrap();
+    let (smaller, larger) = if blake2s(&first) < blake2s(&second) {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    let value: Box<[u8]> = [1u8].into();
+
+    for scheme in [
+        AllocationScheme::ArrivalOrder,
+        AllocationScheme::CommitDigestOrder,
+    ] {
+        let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+        db.set_allocation_scheme(scheme).unwrap();
+        let mut lvmt = db.as_manager().unwrap();
+
+        let simulated: Vec<_> = [[&smaller, &larger], [&larger, &smaller]]
+            .into_iter()
+            .map(|keys| {
+                let changes = keys.map(|key| (key.clone(), Some(value.clone())));
+                let mut simulated = lvmt
+                    .simulate_commit(None, changes.into_iter(), &AMT)
+                    .unwrap();
+                simulated.allocated_slots.sort_by(|a, b| a.0.cmp(&b.0));
+                simulated
+            })
+            .collect();
+
+        for simulated in &simulated {
+            let depths: Vec<_> = simulated
+                .allocated_slots
+                .iter()
+                .map(|(_, allocation)| allocation.depth)
+                .collect();
+            assert_eq!(depths, vec![1, 1]);
+        }
+        let slot_of_smaller = |simulated: &super::storage::SimulatedCommit| {
+            simulated
+                .allocated_slots
+                .iter()
+                .find(|(key, _)| *key == smaller)
+                .unwrap()
+                .1
+                .slot_index
+        };
+        match scheme {
+            AllocationScheme::ArrivalOrder => {
+                assert_eq!(slot_of_smaller(&simulated[0]), 0);
+                assert_eq!(slot_of_smaller(&simulated[1]), 1);
+                assert_ne!(simulated[0].result, simulated[1].result);
+            }
+            AllocationScheme::CommitDigestOrder => {
+                assert_eq!(slot_of_smaller(&simulated[0]), 0);
+                assert_eq!(simulated[0], simulated[1]);
+
+                // not history-independent: a slot stays with the key of an earlier commit
+                let commit = gen_random_commit_id(&mut get_rng_for_test());
+                let changes = [(larger.clone(), Some(value.clone()))];
+                lvmt.commit(None, commit, changes.into_iter(), &AMT)
+                    .unwrap();
+                let changes = [(smaller.clone(), Some(value.clone()))];
+                let simulated = lvmt
+                    .simulate_commit(Some(commit), changes.into_iter(), &AMT)
+                    .unwrap();
+                assert_eq!(slot_of_smaller(&simulated), 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_external_sort_threshold() {
+    use super::auth_changes::ExternalSortConfig;
+    use std::path::PathBuf;
+
+    const THRESHOLD: usize = 1000;
+
+    let temp_dir = PathBuf::from("__test_lvmt_external_sort_threshold");
+    if temp_dir.exists() {
+        std::fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..2)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    // a commit with fewer leaves than the threshold, then one with more
+    let mut all_keys = BTreeSet::new();
+    let updates: Vec<Vec<_>> = [THRESHOLD / 4, THRESHOLD * 4]
+        .into_iter()
+        .map(|num_keys| {
+            let previous_keys = all_keys.clone();
+            let updates = gen_updates(&mut rng, &previous_keys, num_keys, 0, &mut all_keys);
+            get_changes_from_updates(updates).collect()
+        })
+        .collect();
+
+    let config = ExternalSortConfig {
+        threshold: THRESHOLD,
+        run_size: THRESHOLD / 3,
+        temp_dir: temp_dir.clone(),
+    };
+    let outputs: Vec<Vec<_>> = [None, Some(config)]
+        .into_iter()
+        .map(|external_sort| {
+            let is_external = external_sort.is_some();
+            let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+            db.set_external_sort(external_sort);
+            let mut lvmt = db.as_manager().unwrap();
+
+            let mut outputs = vec![];
+            for (i, changes) in updates.iter().enumerate() {
+                let parent = i.checked_sub(1).map(|p| commits[p]);
+                let (result, write_schema) = lvmt
+                    .commit(parent, commits[i], changes.clone().into_iter(), &AMT)
+                    .unwrap();
+                outputs.push((result, write_schema.drain()));
+
+                // the spill directory is only created above the threshold
+                assert_eq!(temp_dir.exists(), is_external && i == 1);
+            }
+            outputs
+        })
+        .collect();
+    assert_eq!(outputs[0], outputs[1]);
+
+    // the run files are removed
+    assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&temp_dir).unwrap();
+}
+
+#[test]
+fn test_allocation_scheme_recorded() {
+    use super::storage::AllocationScheme;
+    use crate::{
+        backends::{impls::kvdb_rocksdb::open_database, TableName},
+        StorageError,
+    };
+
+    let db_path = "__test_allocation_scheme_recorded";
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..2)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+    let changes = |i: u64| vec![(u64_to_boxed_u8(i), Some(u64_to_boxed_u8(i)))].into_iter();
+
+    let mut db = LvmtStorage::new(empty_rocksdb(db_path).unwrap()).unwrap();
+    db.set_allocation_scheme(AllocationScheme::CommitDigestOrder)
+        .unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let (_, write_schema) = lvmt.commit(None, commits[0], changes(0), &AMT).unwrap();
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+
+    // a scheme cannot change under the commits made with another
+    assert_eq!(
+        db.set_allocation_scheme(AllocationScheme::ArrivalOrder),
+        Err(StorageError::AllocationSchemeMismatch)
+    );
+    drop(db);
+
+    // the reopened database loads the recorded scheme
+    let backend = open_database(TableName::max_index() + 1, db_path).unwrap();
+    let mut db = LvmtStorage::new(backend).unwrap();
+    assert_eq!(
+        db.set_allocation_scheme(AllocationScheme::ArrivalOrder),
+        Err(StorageError::AllocationSchemeMismatch)
+    );
+    db.set_allocation_scheme(AllocationScheme::CommitDigestOrder)
+        .unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let (_, write_schema) = lvmt
+        .commit(Some(commits[0]), commits[1], changes(1), &AMT)
+        .unwrap();
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+    drop(db);
+
+    std::fs::remove_dir_all(db_path).unwrap();
+}
+
+#[test]
+fn test_state_at() {
+    use super::state_view::StateSelector;
+    use crate::StorageError;
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    // the state after each commit of the chain commits[0] <- commits[1] <- commits[2]
+    let mut all_keys = BTreeSet::new();
+    let mut state = BTreeMap::new();
+    let mut states = vec![];
+    let mut root_hashes = vec![];
+    let mut auth_change_roots = HashMap::new();
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    for (i, commit) in commits.iter().enumerate() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 100, 100, &mut all_keys);
+        state.extend(updates.clone());
+        states.push(state.clone());
+
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let changes = get_changes_from_updates(updates);
+        let (result, writes) = lvmt.commit(parent, *commit, changes, &AMT).unwrap();
+        write_schema.merge(writes);
+        root_hashes.push(lvmt.root_hash(commit).unwrap());
+        auth_change_roots.insert(*commit, result.auth_change_root.unwrap());
+    }
+    let novel_key = u64_to_boxed_u8(gen_novel_u64(&mut rng, &all_keys));
+    drop(lvmt);
+    db.confirmed_pending_to_history(commits[0], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+
+    let check_view = |view: &super::state_view::LvmtStateView, index: usize| {
+        assert_eq!(view.commit(), commits[index]);
+        assert_eq!(view.root_hash().unwrap(), root_hashes[index]);
+        for (key, value) in &states[index] {
+            let key = u64_to_boxed_u8(*key);
+            assert_eq!(view.get(&key).unwrap(), value.map(u64_to_boxed_u8));
+
+            // deleted keys are proven by the change deleting them
+            let proof = view.prove(&key).unwrap().unwrap();
+            assert_eq!(proof.value.value, value.map(u64_to_boxed_u8));
+            assert!(proof.verify(auth_change_roots[&proof.change_commit], &key));
+            assert!(!proof.verify(auth_change_roots[&proof.change_commit], &novel_key));
+        }
+        assert_eq!(view.prove(&novel_key).unwrap(), None);
+
+        // the live keys in the order of their encodings, read in pages
+        let expected: BTreeMap<_, _> = states[index]
+            .iter()
+            .filter_map(|(key, value)| Some((u64_to_boxed_u8(*key), u64_to_boxed_u8((*value)?))))
+            .collect();
+        let mut paged = vec![];
+        let mut start = Some(Box::<[u8]>::from([]));
+        while let Some(from) = start {
+            let (page, next) = view.iter_paged(&from, 7).unwrap();
+            assert!(page.len() == 7 || next.is_none());
+            paged.extend(page);
+            start = next;
+        }
+        assert_eq!(paged, expected.clone().into_iter().collect::<Vec<_>>());
+
+        for (key, value) in expected.iter().take(10) {
+            let entry = Some((key.clone(), value.clone()));
+            assert_eq!(view.seek(key).unwrap(), entry);
+
+            // a key with a trailing zero byte is the least key after `key`
+            let after: Box<[u8]> = key.iter().copied().chain([0]).collect();
+            let next = expected.range(after.clone()..).next();
+            let next = next.map(|(key, value)| (key.clone(), value.clone()));
+            assert_eq!(view.seek(&after).unwrap(), next);
+        }
+    };
+
+    check_view(&db.state_at(StateSelector::Height(0)).unwrap(), 0);
+    check_view(&db.state_at(StateSelector::Commit(commits[1])).unwrap(), 1);
+    check_view(&db.state_at(StateSelector::Latest).unwrap(), 2);
+
+    // commits[1] is still pending at height 1
+    assert_eq!(
+        db.state_at(StateSelector::Height(1)).err(),
+        Some(StorageError::HeightNotConfirmed(1))
+    );
+    assert_eq!(
+        db.state_at(StateSelector::Height(5)).err(),
+        Some(StorageError::HeightNotConfirmed(5))
+    );
+
+    // without pending commits, the latest confirmed commit is selected
+    let write_schema = InMemoryDatabase::write_schema();
+    db.confirmed_pending_to_history(commits[2], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+    check_view(&db.state_at(StateSelector::Latest).unwrap(), 2);
+    check_view(&db.state_at(StateSelector::Height(1)).unwrap(), 1);
+}
+
+impl<'cache, 'db, D: DatabaseTrait> LvmtStore<'cache, 'db, D> {
     pub fn check_consistency(&mut self, commit: CommitID, pp: &AmtParams<PE>) -> Result<()> {
         use std::collections::BTreeSet;
 
         use ark_ec::CurveGroup;
 
-        use crate::lvmt::{
-            crypto::{FrInt, VariableBaseMSM, G1},
-            types::SLOT_SIZE,
+        use crate::{
+            lvmt::{
+                crypto::{FrInt, VariableBaseMSM, G1},
+                types::{AuthChangeKey, SLOT_SIZE},
+            },
+            traits::KeyValueStoreBulksTrait,
         };
 
         let amt_node_view = self.get_amt_node_store().get_versioned_store(&commit)?;
@@ -169,7 +794,7 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
         // The expection of the children of the root Amt is due to the design that the root Amt does not allocate slots.
         for (amt_id, curve_point_with_version) in amt_node_view.iter()? {
             if amt_id.len() > 1 {
-                let amt_node_id = amt_id;
+                let amt_node_id = amt_id.parent_node().unwrap();
                 let alloc_key_info = slot_alloc_view.get(&amt_node_id)?.unwrap();
                 assert_eq!(alloc_key_info.index as usize, KEY_SLOT_SIZE - 1);
             }
@@ -183,8 +808,7 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
 
         // Each Amt node with allocated slots should be in an Amt tree
         for (amt_node_id, alloc_key_info) in slot_alloc_view.iter()? {
-            let mut parent_amt_id = amt_node_id;
-            parent_amt_id.pop().unwrap();
+            let (parent_amt_id, _) = amt_node_id.split();
 
             amt_node_view.get(&parent_amt_id)?.unwrap();
 
@@ -219,8 +843,7 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
         // Gather allocated slots for keys, in another way
         let mut slot_allocs = BTreeMap::new();
         for (amt_node_id, alloc_key_info) in slot_alloc_view.iter()? {
-            let mut parent_amt_id = amt_node_id;
-            let node_index = parent_amt_id.pop().unwrap();
+            let (parent_amt_id, node_index) = amt_node_id.split();
 
             match alloc_key_info {
                 crate::types::ValueEntry::Value(alloc_key_info) => {
@@ -306,6 +929,676 @@ impl<'cache, 'db> LvmtStore<'cache, 'db> {
 
             assert_eq!(commitment, stored_commitment, "Inconsitent commitments");
         }
+
+        // The stored root of the auth-change tree is the hash of its root node
+        let auth_change_root = self.auth_change_roots(&[commit])?[0];
+        let root_node = self
+            .get_auth_changes()
+            .get_versioned_key(&commit, &AuthChangeKey::root())?;
+        assert_eq!(
+            auth_change_root,
+            root_node.map(|node| node.hash()),
+            "Inconsistent auth change root"
+        );
         Ok(())
     }
 }
+
+#[test]
+fn test_root_hash_cache() {
+    use super::types::AmtId;
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..4)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+    let (base, tips) = (commits[0], &commits[1..]);
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+
+    // base <- tips[0], base <- tips[1], base <- tips[2]
+    let mut all_keys = BTreeSet::new();
+    let updates = gen_updates(&mut rng, &BTreeSet::new(), 100, 0, &mut all_keys);
+    let (_, writes) = lvmt
+        .commit(None, base, get_changes_from_updates(updates), &AMT)
+        .unwrap();
+    write_schema.merge(writes);
+    let previous_keys = all_keys.clone();
+    for &tip in tips {
+        let updates = gen_updates(&mut rng, &previous_keys, 100, 100, &mut all_keys);
+        let (_, writes) = lvmt
+            .commit(Some(base), tip, get_changes_from_updates(updates), &AMT)
+            .unwrap();
+        write_schema.merge(writes);
+    }
+
+    let recompute_root_hash = |lvmt: &LvmtStore<InMemoryDatabase>, commit: &CommitID| {
+        lvmt.get_amt_node_store()
+            .get_versioned_store(commit)
+            .unwrap()
+            .get(&AmtId::root())
+            .unwrap()
+            .unwrap()
+            .point
+            .hash()
+    };
+    let mut root_hashes = HashSet::new();
+    for commit in &commits {
+        assert!(lvmt.is_root_hash_cached(commit));
+        let root_hash = lvmt.root_hash(commit).unwrap();
+        assert_eq!(root_hash, recompute_root_hash(&lvmt, commit));
+        root_hashes.insert(root_hash);
+    }
+    assert_eq!(root_hashes.len(), commits.len());
+
+    // discarding keeps tips[0] and removes its siblings
+    lvmt.discard(tips[0]).unwrap();
+    assert!(lvmt.is_root_hash_cached(&tips[0]));
+    for tip in &tips[1..] {
+        assert!(!lvmt.is_root_hash_cached(tip));
+        assert!(lvmt.root_hash(tip).is_err());
+    }
+
+    // confirmed commits are read from the store
+    let expected = lvmt.root_hash(&base).unwrap();
+    drop(lvmt);
+    db.confirmed_pending_to_history(base, &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+    let lvmt = db.as_manager().unwrap();
+    assert!(!lvmt.is_root_hash_cached(&base));
+    assert_eq!(lvmt.root_hash(&base).unwrap(), expected);
+    assert!(lvmt.is_root_hash_cached(&tips[0]));
+}
+
+#[test]
+fn test_commit_result() {
+    use super::types::AmtId;
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..2)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    let mut all_keys = BTreeSet::new();
+    let mut results = Vec::new();
+    for (i, commit) in commits.iter().enumerate() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 100, 100, &mut all_keys);
+        let num_new_keys = updates
+            .keys()
+            .filter(|key| !previous_keys.contains(*key))
+            .count();
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let (result, writes) = lvmt
+            .commit(parent, *commit, get_changes_from_updates(updates), &AMT)
+            .unwrap();
+        write_schema.merge(writes);
+        assert_eq!(result.num_allocated_slots, num_new_keys);
+        results.push(result);
+    }
+    assert_ne!(results[0].root_commitment, results[1].root_commitment);
+    assert_ne!(results[0].auth_change_root, results[1].auth_change_root);
+
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    for (commit, result) in commits.iter().zip(&results) {
+        // `check_consistency` checks the stored root against its MSM
+        lvmt.check_consistency(*commit, &AMT).unwrap();
+        let stored_root = lvmt
+            .get_amt_node_store()
+            .get_versioned_store(commit)
+            .unwrap()
+            .get(&AmtId::root())
+            .unwrap()
+            .unwrap()
+            .point;
+        assert_eq!(*stored_root.affine(), result.root_commitment);
+        assert_eq!(lvmt.root_hash(commit).unwrap(), stored_root.hash());
+        assert_eq!(
+            lvmt.auth_change_roots(&[*commit]).unwrap(),
+            vec![result.auth_change_root]
+        );
+        assert!(result.auth_change_root.is_some());
+    }
+}
+
+#[test]
+fn test_empty_commit() {
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+    let no_changes = || std::iter::empty::<(Box<[u8]>, Option<Box<[u8]>>)>();
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+
+    // an empty first commit, a commit with a key, and an empty child of it
+    let mut results = Vec::new();
+    for (i, commit) in commits.iter().enumerate() {
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let (result, writes) = if i == 1 {
+            let changes = vec![(u64_to_boxed_u8(1), Some(u64_to_boxed_u8(1)))];
+            lvmt.commit(parent, *commit, changes.into_iter(), &AMT)
+        } else {
+            lvmt.commit(parent, *commit, no_changes(), &AMT)
+        }
+        .unwrap();
+        write_schema.merge(writes);
+        results.push(result);
+    }
+    assert_eq!(results[0].auth_change_root, None);
+    assert_eq!(results[0].num_allocated_slots, 0);
+    assert!(results[1].auth_change_root.is_some());
+    assert_eq!(results[2].auth_change_root, None);
+    assert_eq!(results[2].root_commitment, results[1].root_commitment);
+
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let roots: Vec<_> = results
+        .iter()
+        .map(|result| result.auth_change_root)
+        .collect();
+    assert_eq!(lvmt.auth_change_roots(&commits).unwrap(), roots);
+    for commit in &commits {
+        lvmt.check_consistency(*commit, &AMT).unwrap();
+    }
+}
+
+#[test]
+fn test_commit_invalid_keys() {
+    use super::storage::MAX_KEY_LENGTH;
+    use crate::StorageError;
+
+    let mut rng = get_rng_for_test();
+    let commit = gen_random_commit_id(&mut rng);
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut commit_changes = |changes: Vec<(Box<[u8]>, Option<Box<[u8]>>)>| {
+        lvmt.commit(None, commit, changes.into_iter(), &AMT).err()
+    };
+
+    let value = Some(u64_to_boxed_u8(1));
+    assert_eq!(
+        commit_changes(vec![
+            (u64_to_boxed_u8(1), value.clone()),
+            (Box::new([]), value.clone())
+        ]),
+        Some(StorageError::EmptyKey)
+    );
+    let long_key = vec![1u8; MAX_KEY_LENGTH + 1].into_boxed_slice();
+    assert_eq!(
+        commit_changes(vec![(long_key, None)]),
+        Some(StorageError::KeyTooLong(MAX_KEY_LENGTH + 1))
+    );
+    // the rejected commits leave nothing behind
+    let max_key = vec![1u8; MAX_KEY_LENGTH].into_boxed_slice();
+    assert_eq!(commit_changes(vec![(max_key, value)]), None);
+}
+
+#[test]
+fn test_simulate_commit() {
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..2)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    let mut all_keys = BTreeSet::new();
+    for (i, commit) in commits.iter().enumerate() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 100, 100, &mut all_keys);
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+
+        let simulated = lvmt
+            .simulate_commit(parent, get_changes_from_updates(updates.clone()), &AMT)
+            .unwrap();
+        assert!(!lvmt.get_key_value_store().is_pending(commit));
+        assert!(!lvmt.get_amt_node_store().is_pending(commit));
+        assert!(!lvmt.is_root_hash_cached(commit));
+
+        let (result, writes) = lvmt
+            .commit(parent, *commit, get_changes_from_updates(updates), &AMT)
+            .unwrap();
+        write_schema.merge(writes);
+        assert_eq!(simulated.result, result);
+        assert_eq!(simulated.allocated_slots.len(), result.num_allocated_slots);
+
+        let key_value_view = lvmt
+            .get_key_value_store()
+            .get_versioned_store(commit)
+            .unwrap();
+        for (key, allocation) in &simulated.allocated_slots {
+            let value = key_value_view.get(key).unwrap().unwrap();
+            assert_eq!(value.allocation, *allocation);
+        }
+    }
+}
+
+#[test]
+fn test_auth_change_roots() {
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+    let novel_commit = gen_novel_commit_id(&mut rng, &mut previous_commits);
+
+    // commits[0] <- commits[1] <- commits[2]
+    let mut all_keys = BTreeSet::new();
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    for (i, commit) in commits.iter().enumerate() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 100, 100, &mut all_keys);
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let (_, writes) = lvmt
+            .commit(parent, *commit, get_changes_from_updates(updates), &AMT)
+            .unwrap();
+        write_schema.merge(writes);
+    }
+
+    // the roots are written to the database with the auth changes
+    assert_eq!(lvmt.auth_change_roots(&commits).unwrap(), vec![None; 3]);
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+
+    let mut lvmt = db.as_manager().unwrap();
+    let roots = lvmt.auth_change_roots(&commits).unwrap();
+    assert!(roots.iter().all(Option::is_some));
+    for &commit in &commits {
+        lvmt.check_consistency(commit, &AMT).unwrap();
+    }
+
+    // commits[0] is confirmed, the others stay pending
+    drop(lvmt);
+    let write_schema = InMemoryDatabase::write_schema();
+    db.confirmed_pending_to_history(commits[1], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+
+    let mut lvmt = db.as_manager().unwrap();
+    let mut queried = commits.clone();
+    queried.insert(1, novel_commit);
+    let mut expected = roots.clone();
+    expected.insert(1, None);
+    assert_eq!(lvmt.auth_change_roots(&queried).unwrap(), expected);
+    for &commit in &commits {
+        lvmt.check_consistency(commit, &AMT).unwrap();
+    }
+}
+
+#[test]
+fn test_confirm_metrics() {
+    use crate::middlewares::StorageMetrics;
+    use std::{sync::Arc, time::Duration};
+
+    // the height and the number of keys of each reported confirmation
+    #[derive(Default)]
+    struct ConfirmRecorder(Mutex<Vec<(usize, usize)>>);
+
+    impl StorageMetrics for ConfirmRecorder {
+        fn on_confirm(&self, height: usize, keys: usize, _duration: Duration) {
+            self.0.lock().push((height, keys));
+        }
+    }
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let recorders: [Arc<ConfirmRecorder>; 3] = Default::default();
+    db.set_metrics(
+        recorders[0].clone(),
+        recorders[1].clone(),
+        recorders[2].clone(),
+    );
+
+    // commits[0] <- commits[1] <- commits[2]
+    let mut all_keys = BTreeSet::new();
+    let mut num_keys = Vec::new();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    for (i, commit) in commits.iter().enumerate() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 10, 10, &mut all_keys);
+        num_keys.push(updates.len());
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let (_, writes) = lvmt
+            .commit(parent, *commit, get_changes_from_updates(updates), &AMT)
+            .unwrap();
+        write_schema.merge(writes);
+    }
+    drop(lvmt);
+    db.commit(write_schema).unwrap();
+
+    // confirming the pending root again confirms nothing and reports nothing
+    for new_root in [commits[1], commits[1], commits[2]] {
+        let write_schema = InMemoryDatabase::write_schema();
+        db.confirmed_pending_to_history(new_root, &write_schema)
+            .unwrap();
+        db.commit(write_schema).unwrap();
+    }
+
+    let reported: Vec<_> = recorders.iter().map(|r| r.0.lock().clone()).collect();
+    assert_eq!(reported[0], vec![(0, num_keys[0]), (1, num_keys[1])]);
+    for reported in &reported[1..] {
+        let heights: Vec<_> = reported.iter().map(|(height, _)| *height).collect();
+        assert_eq!(heights, vec![0, 1]);
+        assert!(reported.iter().all(|(_, keys)| *keys > 0));
+    }
+}
+
+#[test]
+fn test_commit_checked() {
+    use crate::StorageError;
+
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    let mut all_keys = BTreeSet::new();
+    let mut gen_changes = |rng: &mut ChaChaRng| {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(rng, &previous_keys, 10, 10, &mut all_keys);
+        get_changes_from_updates(updates)
+    };
+
+    // the first commit is at height 0
+    let changes = gen_changes(&mut rng);
+    assert_eq!(
+        lvmt.commit_checked(None, commits[0], changes, &AMT, Some(1)),
+        Err(StorageError::HeightMismatch {
+            expected: 1,
+            actual: 0
+        })
+    );
+    assert!(!lvmt.get_key_value_store().is_pending(&commits[0]));
+
+    for (height, commit) in commits.iter().enumerate() {
+        let parent = height.checked_sub(1).map(|p| commits[p]);
+        let changes = gen_changes(&mut rng);
+        let (_, writes) = lvmt
+            .commit_checked(parent, *commit, changes, &AMT, Some(height))
+            .unwrap();
+        write_schema.merge(writes);
+    }
+
+    // a parent deeper than intended
+    let novel_commit = gen_novel_commit_id(&mut rng, &mut previous_commits);
+    let changes = gen_changes(&mut rng);
+    assert_eq!(
+        lvmt.commit_checked(Some(commits[2]), novel_commit, changes, &AMT, Some(2)),
+        Err(StorageError::HeightMismatch {
+            expected: 2,
+            actual: 3
+        })
+    );
+    assert!(!lvmt.get_amt_node_store().is_pending(&novel_commit));
+    assert!(!lvmt.get_slot_alloc_store().is_pending(&novel_commit));
+}
+
+#[test]
+fn test_iter_key_prefix() {
+    let mut rng = get_rng_for_test();
+    let mut previous_commits = HashSet::new();
+    let commits: Vec<_> = (0..3)
+        .map(|_| gen_novel_commit_id(&mut rng, &mut previous_commits))
+        .collect();
+
+    let boxed = |bytes: &str| -> Box<[u8]> { bytes.as_bytes().into() };
+    // nested prefixes, with keys around them
+    let changes: [Vec<(&str, Option<&str>)>; 3] = [
+        vec![
+            ("a", Some("1")),
+            ("ab", Some("2")),
+            ("abc", Some("3")),
+            ("ac", Some("4")),
+            ("b", Some("5")),
+            ("\x00", Some("6")),
+        ],
+        vec![("ab", None), ("abd", Some("7")), ("ac", Some("8"))],
+        vec![("abc", None), ("ab", Some("9"))],
+    ];
+
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut lvmt = db.as_manager().unwrap();
+    let mut write_schema = InMemoryDatabase::write_schema();
+    for (i, commit) in commits.iter().enumerate() {
+        let parent = i.checked_sub(1).map(|p| commits[p]);
+        let changes = changes[i]
+            .iter()
+            .map(|(key, value)| (boxed(key), value.map(boxed)));
+        let (_, writes) = lvmt.commit(parent, *commit, changes, &AMT).unwrap();
+        write_schema.merge(writes);
+    }
+    drop(lvmt);
+    // commits[0] is read from the history, the others from the pending part
+    db.confirmed_pending_to_history(commits[1], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+
+    let expected = |rows: &[(&str, &str)]| -> Vec<(Box<[u8]>, Box<[u8]>)> {
+        rows.iter().map(|(k, v)| (boxed(k), boxed(v))).collect()
+    };
+    let check = |db: &mut LvmtStorage<InMemoryDatabase>,
+                 commit: CommitID,
+                 prefix: &str,
+                 rows: &[(&str, &str)]| {
+        let lvmt = db.as_manager().unwrap();
+        let entries: Vec<_> = lvmt
+            .iter_key_prefix(commit, prefix.as_bytes())
+            .unwrap()
+            .collect();
+        assert_eq!(entries, expected(rows));
+    };
+
+    check(
+        &mut db,
+        commits[0],
+        "a",
+        &[("a", "1"), ("ab", "2"), ("abc", "3"), ("ac", "4")],
+    );
+    check(&mut db, commits[0], "ab", &[("ab", "2"), ("abc", "3")]);
+    check(&mut db, commits[0], "abc", &[("abc", "3")]);
+    check(&mut db, commits[0], "abcd", &[]);
+    check(&mut db, commits[0], "c", &[]);
+    check(
+        &mut db,
+        commits[0],
+        "",
+        &[
+            ("\x00", "6"),
+            ("a", "1"),
+            ("ab", "2"),
+            ("abc", "3"),
+            ("ac", "4"),
+            ("b", "5"),
+        ],
+    );
+
+    // a deletion in the pending part masks the history
+    let at_1: &[(&str, &str)] = &[("a", "1"), ("abc", "3"), ("abd", "7"), ("ac", "8")];
+    check(&mut db, commits[1], "a", at_1);
+    check(&mut db, commits[1], "ab", &at_1[1..3]);
+    let at_2: &[(&str, &str)] = &[("ab", "9"), ("abd", "7")];
+    check(&mut db, commits[2], "ab", at_2);
+
+    // and so does a deletion in the history
+    let write_schema = InMemoryDatabase::write_schema();
+    db.confirmed_pending_to_history(commits[2], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+    check(&mut db, commits[1], "a", at_1);
+    check(&mut db, commits[2], "ab", at_2);
+
+    let lvmt = db.as_manager().unwrap();
+    assert!(lvmt
+        .iter_key_prefix(gen_random_commit_id(&mut rng), b"a")
+        .is_err());
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operation {
+    Commit,
+    ForkingCommit,
+    Get,
+    Confirm,
+    Discard,
+}
+
+// Applies random operations to an `LvmtStorage` and a `MockLvmtStore` and compares them after
+// each one, as `test_versioned_store` does for a `VersionedStore`
+fn test_lvmt_store_against_mock(num_operations: usize) {
+    let operations = [
+        Operation::Commit,
+        Operation::Commit,
+        Operation::Commit,
+        Operation::ForkingCommit,
+        Operation::Get,
+        Operation::Get,
+        Operation::Confirm,
+        Operation::Discard,
+    ];
+
+    let mut rng = get_rng_for_test();
+    let mut db = LvmtStorage::new(InMemoryDatabase::empty()).unwrap();
+    let mut mock = MockLvmtStore::new();
+    let mut previous_commits = HashSet::new();
+    let mut all_keys = BTreeSet::new();
+    let mut discarded = BTreeSet::new();
+
+    for _ in 0..num_operations {
+        let live_commits = mock.commits();
+
+        // the commit to check against its MSM after the operation
+        let touched = match select_vec_element(&mut rng, &operations) {
+            operation @ (Operation::Commit | Operation::ForkingCommit) => {
+                let parents = match operation {
+                    Operation::Commit => mock.leaves(),
+                    _ => mock.pending(),
+                };
+                let parent = if parents.is_empty() {
+                    mock.parent_of_root()
+                } else {
+                    Some(select_vec_element(&mut rng, &parents))
+                };
+                let commit = gen_novel_commit_id(&mut rng, &mut previous_commits);
+                let previous_keys = all_keys.clone();
+                let updates = gen_updates(&mut rng, &previous_keys, 10, 10, &mut all_keys);
+                let changes: BTreeMap<_, _> = get_changes_from_updates(updates).collect();
+
+                let mut lvmt = db.as_manager().unwrap();
+                let (result, write_schema) = lvmt
+                    .commit(parent, commit, changes.clone().into_iter(), &AMT)
+                    .unwrap();
+                drop(lvmt);
+                db.commit(write_schema).unwrap();
+
+                mock.commit(parent, commit, changes).unwrap();
+                let (root_commitment, _) = mock.root(&commit, &AMT).unwrap();
+                assert_eq!(result.root_commitment, root_commitment);
+                Some(commit)
+            }
+            Operation::Get => {
+                // discarded and unknown commits are not found by both
+                let mut commits: Vec<_> = live_commits.iter().chain(&discarded).copied().collect();
+                commits.push(gen_random_commit_id(&mut rng));
+                let commit = select_vec_element(&mut rng, &commits);
+
+                let mut keys = vec![gen_novel_u64(&mut rng, &all_keys)];
+                if !all_keys.is_empty() {
+                    let all_keys: Vec<_> = all_keys.iter().copied().collect();
+                    keys.extend((0..10).map(|_| select_vec_element(&mut rng, &all_keys)));
+                }
+
+                let lvmt = db.as_manager().unwrap();
+                for key in keys {
+                    let key = u64_to_boxed_u8(key);
+                    match mock.get(&commit, &key) {
+                        Ok(value) => assert_eq!(lvmt.get(&commit, &key).unwrap(), value),
+                        Err(_) => assert!(lvmt.get(&commit, &key).is_err()),
+                    }
+                }
+                live_commits.contains(&commit).then_some(commit)
+            }
+            Operation::Confirm => {
+                let pending = mock.pending();
+                if pending.is_empty() {
+                    continue;
+                }
+                let new_root = select_vec_element(&mut rng, &pending);
+
+                let write_schema = InMemoryDatabase::write_schema();
+                let path = db
+                    .confirmed_pending_to_history(new_root, &write_schema)
+                    .unwrap();
+                db.commit(write_schema).unwrap();
+
+                assert_eq!(path, mock.confirmed_pending_to_history(new_root).unwrap());
+                Some(new_root)
+            }
+            Operation::Discard => {
+                if live_commits.is_empty() {
+                    continue;
+                }
+                // discarding a confirmed commit does nothing
+                let commit = select_vec_element(&mut rng, &live_commits);
+                db.as_manager().unwrap().discard(commit).unwrap();
+                mock.discard(commit).unwrap();
+                Some(commit)
+            }
+        };
+
+        let remaining_commits = mock.commits();
+        discarded.extend(
+            live_commits
+                .into_iter()
+                .filter(|commit| remaining_commits.binary_search(commit).is_err()),
+        );
+
+        let mut lvmt = db.as_manager().unwrap();
+        if let Some(commit) = touched {
+            lvmt.check_consistency(commit, &AMT).unwrap();
+        }
+        for commit in &remaining_commits {
+            let (root_commitment, root_hash) = mock.root(commit, &AMT).unwrap();
+            assert_eq!(lvmt.root_commitment(commit).unwrap(), root_commitment);
+            assert_eq!(lvmt.root_hash(commit).unwrap(), root_hash);
+        }
+        for commit in &discarded {
+            assert!(lvmt.root_commitment(commit).is_err());
+            assert!(lvmt.root_hash(commit).is_err());
+        }
+    }
+}
+
+#[test]
+fn test_lvmt_store_random_operations() {
+    test_lvmt_store_against_mock(300);
+}
@@ -14,9 +14,7 @@ pub struct AllocatePosition {
 impl AllocatePosition {
     pub fn amt_info(&self, key: &[u8]) -> (AmtId, u16, u8) {
         let digest = blake2s(key);
-        let mut amt_node_id = compute_amt_node_id(digest, self.depth as usize);
-        let node_index = amt_node_id.pop().unwrap();
-        let amt_id = amt_node_id;
+        let (amt_id, node_index) = compute_amt_node_id(digest, self.depth as usize).split();
         (amt_id, node_index, self.slot_index)
     }
 }
@@ -2,21 +2,69 @@ use crate::backends::serde::{Decode, Encode};
 use crate::errors::{DecResult, DecodeError};
 use std::borrow::Cow;
 
+// set in the index byte of the encodings listing the keys before the last slot
+const EARLIER_KEYS_FLAG: u8 = 0x80;
+
+/// The slots allocated in an AMT node: the index of the last one, with the key it was allocated
+/// to and those of the slots before.
+///
+/// The rows written before the earlier keys were recorded name the key of the last slot only, so
+/// `earlier_keys` may miss the first slots, and grows from there as the node is allocated.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct AllocationKeyInfo {
     pub(in crate::lvmt) index: u8,
     key: Box<[u8]>,
+    /// The keys of the slots right before `index`, the last one of slot `index - 1`.
+    earlier_keys: Vec<Box<[u8]>>,
 }
 
 impl AllocationKeyInfo {
     pub fn new(index: u8, key: Box<[u8]>) -> Self {
-        Self { index, key }
+        Self {
+            index,
+            key,
+            earlier_keys: vec![],
+        }
+    }
+
+    /// Allocates the next slot of the node to `key`.
+    pub fn allocate_next(mut self, key: Box<[u8]>) -> Self {
+        let last_key = std::mem::replace(&mut self.key, key);
+        self.earlier_keys.push(last_key);
+        self.index += 1;
+        self
+    }
+
+    /// The key allocated the last slot of the node.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Whether a known slot of the node is allocated to `key`.
+    pub fn holds_key(&self, key: &[u8]) -> bool {
+        self.key() == key || self.earlier_keys.iter().any(|k| k.as_ref() == key)
     }
 }
 
+/// Encoded as the index and the last key, as before the earlier keys were recorded, if there
+/// are none. Otherwise the index is flagged and followed by the number of earlier keys, each
+/// with its length in two big-endian bytes, and then the last key.
 impl Encode for AllocationKeyInfo {
     fn encode(&self) -> Cow<[u8]> {
-        let mut raw = vec![self.index];
+        if self.earlier_keys.is_empty() {
+            let mut raw = vec![self.index];
+            raw.extend(self.key.as_ref());
+            return Cow::Owned(raw);
+        }
+
+        let mut raw = vec![
+            self.index | EARLIER_KEYS_FLAG,
+            self.earlier_keys.len() as u8,
+        ];
+        for key in &self.earlier_keys {
+            raw.extend((key.len() as u16).to_be_bytes());
+            raw.extend(key.as_ref());
+        }
         raw.extend(self.key.as_ref());
         Cow::Owned(raw)
     }
@@ -24,12 +72,27 @@ impl Encode for AllocationKeyInfo {
 
 impl Decode for AllocationKeyInfo {
     fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
-        if input.is_empty() {
-            return Err(DecodeError::IncorrectLength);
+        let (&index, mut rest) = input.split_first().ok_or(DecodeError::IncorrectLength)?;
+        if index & EARLIER_KEYS_FLAG == 0 {
+            return Ok(Cow::Owned(Self::new(index, rest.into())));
+        }
+
+        let (&num_earlier_keys, remaining) =
+            rest.split_first().ok_or(DecodeError::IncorrectLength)?;
+        rest = remaining;
+        let mut earlier_keys = Vec::with_capacity(num_earlier_keys as usize);
+        for _ in 0..num_earlier_keys {
+            let len = rest.get(..2).ok_or(DecodeError::IncorrectLength)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let key = rest.get(2..2 + len).ok_or(DecodeError::IncorrectLength)?;
+            earlier_keys.push(key.into());
+            rest = &rest[2 + len..];
         }
-        let index = input[0];
-        let key = input[1..].to_vec().into_boxed_slice();
-        Ok(Cow::Owned(Self { index, key }))
+        Ok(Cow::Owned(Self {
+            index: index & !EARLIER_KEYS_FLAG,
+            key: rest.into(),
+            earlier_keys,
+        }))
     }
 }
 
@@ -51,9 +114,15 @@ mod tests {
 
         fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
             (0..KEY_SLOT_SIZE, vec(0u8..=255, 0..128))
-                .prop_map(|(depth, key)| Self {
-                    index: depth as u8,
-                    key: key.into_boxed_slice(),
+                .prop_flat_map(|(index, key)| {
+                    vec(vec(0u8..=255, 0..128), 0..=index).prop_map(move |earlier_keys| Self {
+                        index: index as u8,
+                        key: key.clone().into_boxed_slice(),
+                        earlier_keys: earlier_keys
+                            .into_iter()
+                            .map(Vec::into_boxed_slice)
+                            .collect(),
+                    })
                 })
                 .boxed()
         }
@@ -67,4 +136,30 @@ mod tests {
             test_utils::test_serde(data)
         }
     }
+
+    #[test]
+    fn test_encoding() {
+        // without earlier keys, as written before they were recorded
+        let info = AllocationKeyInfo::new(2, b"key".as_slice().into());
+        assert_eq!(info.encode().as_ref(), b"\x02key");
+        assert_eq!(
+            AllocationKeyInfo::decode(b"\x02key").unwrap().as_ref(),
+            &info
+        );
+
+        let info = info.allocate_next(b"next".as_slice().into());
+        assert_eq!(info.encode().as_ref(), b"\x83\x01\x00\x03keynext");
+        assert_eq!(info.index, 3);
+        assert_eq!(info.key(), b"next");
+        assert!(info.holds_key(b"key") && !info.holds_key(b"other"));
+
+        assert_eq!(
+            AllocationKeyInfo::decode(b""),
+            Err(DecodeError::IncorrectLength)
+        );
+        assert_eq!(
+            AllocationKeyInfo::decode(b"\x83\x01\x00\x04key"),
+            Err(DecodeError::IncorrectLength)
+        );
+    }
 }
@@ -58,30 +58,118 @@ impl AuthChangeNode {
         if self.hashes.len() == 1 {
             return self.hashes[0];
         }
-        let height = log2_ceil(self.hashes.len());
-        let pairs = self.hashes.len() - (1 << (height - 1));
-
-        let mut hashes: Vec<_> = self
-            .hashes
-            .chunks_exact(2)
-            .take(pairs)
-            .map(|x| blake2s_tuple(&x[0], &x[1]))
-            .collect();
-        hashes.extend(self.hashes[pairs * 2..].iter().cloned());
 
+        let mut hashes = self.first_level();
         while hashes.len() > 1 {
-            hashes = hashes
-                .chunks_exact(2)
-                .map(|x| blake2s_tuple(&x[0], &x[1]))
-                .collect();
+            hashes = next_level(&hashes);
         }
 
         hashes[0]
     }
 
+    /// Proves that the hash at `index` is a member of the node, `None` if there is no hash at
+    /// `index` or its slot is not available.
+    ///
+    /// For an inner node, the hash at `index` is the hash of its child, so the proof of a leaf
+    /// of the child is extended by [`AuthChangeProof::then`] to a proof against this node.
+    pub fn prove(&self, index: usize) -> Option<AuthChangeProof> {
+        if index >= self.hashes.len() || self.avail_bitmap & (1 << index) == 0 {
+            return None;
+        }
+
+        let mut proof = AuthChangeProof::default();
+        if self.hashes.len() == 1 {
+            return Some(proof);
+        }
+
+        // the first level pairs the leading hashes only, see `first_level`
+        let pairs = self.num_first_level_pairs();
+        let mut index = if index < pairs * 2 {
+            proof.push_sibling(&self.hashes, index);
+            index / 2
+        } else {
+            index - pairs
+        };
+
+        let mut hashes = self.first_level();
+        while hashes.len() > 1 {
+            proof.push_sibling(&hashes, index);
+            index /= 2;
+            hashes = next_level(&hashes);
+        }
+
+        Some(proof)
+    }
+
+    /// Returns the index of `hash` in a leaf node, `None` if it is not a member. For an inner
+    /// node, returns the index of the child whose subtree would hold `hash`, by its ticks.
+    pub fn route(&self, hash: &H256) -> Option<usize> {
+        match &self.ticks {
+            None => self.hashes.iter().position(|leaf| leaf == hash),
+            Some(ticks) => Some(
+                ticks
+                    .iter()
+                    .take_while(|tick| hash.0[..tick.len()] >= tick[..])
+                    .count(),
+            ),
+        }
+    }
+
     pub fn is_leaf(&self) -> bool {
         self.ticks.is_none()
     }
+
+    fn num_first_level_pairs(&self) -> usize {
+        let height = log2_ceil(self.hashes.len());
+        self.hashes.len() - (1 << (height - 1))
+    }
+
+    // Pairs as many leading hashes as needed to leave a power of two hashes, the rest is kept.
+    fn first_level(&self) -> Vec<H256> {
+        let pairs = self.num_first_level_pairs();
+        let mut hashes = next_level(&self.hashes[..pairs * 2]);
+        hashes.extend(self.hashes[pairs * 2..].iter().cloned());
+        hashes
+    }
+}
+
+fn next_level(hashes: &[H256]) -> Vec<H256> {
+    hashes
+        .chunks_exact(2)
+        .map(|x| blake2s_tuple(&x[0], &x[1]))
+        .collect()
+}
+
+/// A proof that a hash is a member of an [`AuthChangeNode`], or of a path of nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthChangeProof {
+    // from the bottom up, with whether the sibling is on the left
+    siblings: Vec<(H256, bool)>,
+}
+
+impl AuthChangeProof {
+    /// Checks that `leaf` is a member of the node, or path of nodes, hashed to `root`.
+    pub fn verify(&self, root: H256, leaf: H256) -> bool {
+        let computed = self.siblings.iter().fold(leaf, |hash, (sibling, is_left)| {
+            if *is_left {
+                blake2s_tuple(sibling, &hash)
+            } else {
+                blake2s_tuple(&hash, sibling)
+            }
+        });
+        computed == root
+    }
+
+    /// Extends a proof against a node to a proof against its parent, given the proof of the
+    /// hash of the node in the parent.
+    pub fn then(mut self, parent: AuthChangeProof) -> Self {
+        self.siblings.extend(parent.siblings);
+        self
+    }
+
+    fn push_sibling(&mut self, hashes: &[H256], index: usize) {
+        self.siblings.push((hashes[index ^ 1], index % 2 == 1));
+    }
 }
 
 impl Encode for AuthChangeNode {
@@ -195,6 +283,11 @@ mod tests {
     }
 
     fn inner_node_strategy() -> impl Strategy<Value = AuthChangeNode> {
+        two_level_strategy().prop_map(|(_, node)| node)
+    }
+
+    // an inner node with its leaf nodes
+    fn two_level_strategy() -> impl Strategy<Value = (Vec<AuthChangeNode>, AuthChangeNode)> {
         vec(4usize..=8, 2..=8).prop_flat_map(|size_list| {
             let num_leaves: usize = size_list.iter().sum();
             let num_nodes = size_list.len();
@@ -216,7 +309,8 @@ mod tests {
                         leaf_nodes.push(AuthChangeNode::from_leaves(head));
                     }
 
-                    AuthChangeNode::from_nodes(&leaf_nodes, ticks, shared_prefix_len)
+                    let node = AuthChangeNode::from_nodes(&leaf_nodes, ticks, shared_prefix_len);
+                    (leaf_nodes, node)
                 })
         })
     }
@@ -262,6 +356,44 @@ mod tests {
             prop_assert!(ticks[1..].iter().all(|x| x.len() == prefix_len));
         }
 
+        #[test]
+        fn test_prove_leaves(l in leaves_strategy(1..=MAX_NODE_SIZE), other in bytes32_strategy()) {
+            let node = AuthChangeNode::from_leaves(&l);
+            let root = node.hash();
+            for (index, leaf) in l.iter().enumerate() {
+                let proof = node.prove(index).unwrap();
+                prop_assert!(proof.verify(root, *leaf));
+                prop_assert!(!proof.verify(root, other) || other == *leaf);
+            }
+            prop_assert!(node.prove(l.len()).is_none());
+        }
+
+        #[test]
+        fn test_prove_two_levels((leaf_nodes, node) in two_level_strategy()) {
+            let root = node.hash();
+            for (child_index, leaf_node) in leaf_nodes.iter().enumerate() {
+                let child_proof = node.prove(child_index).unwrap();
+                prop_assert!(child_proof.verify(root, leaf_node.hash()));
+                for (index, leaf) in leaf_node.hashes.iter().enumerate() {
+                    let proof = leaf_node.prove(index).unwrap().then(child_proof.clone());
+                    prop_assert!(proof.verify(root, *leaf));
+                    prop_assert!(!proof.verify(leaf_node.hash(), *leaf));
+                }
+            }
+        }
+
+        #[test]
+        fn test_prove_unavailable(data in any_with::<AuthChangeNode>(false)) {
+            for index in 0..MAX_NODE_SIZE {
+                let available = index < data.hashes.len() && data.avail_bitmap & (1 << index) != 0;
+                let proof = data.prove(index);
+                prop_assert_eq!(proof.is_some(), available);
+                if let Some(proof) = proof {
+                    prop_assert!(proof.verify(data.hash(), data.hashes[index]));
+                }
+            }
+        }
+
         #[test]
         fn test_leaves_length_1(l in leaves_strategy(1)) {
             let actual_hash = AuthChangeNode::from_leaves(&l[..]).hash();
@@ -5,13 +5,13 @@ mod lvmt_value;
 mod node_id;
 
 pub use allocation::{AllocatePosition, AllocationKeyInfo, KEY_SLOT_SIZE, SLOT_SIZE};
-pub use auth_changes::{AuthChangeKey, AuthChangeNode};
+pub use auth_changes::{AuthChangeKey, AuthChangeNode, AuthChangeProof};
 pub use curve_point::{batch_normalize, CurvePointWithVersion};
 pub use lvmt_value::LvmtValue;
 pub use node_id::{compute_amt_node_id, AmtId, AmtNodeId};
 
 use crate::subkey_not_support;
-subkey_not_support!(AmtId, AuthChangeKey);
+subkey_not_support!(AmtId, AmtNodeId, AuthChangeKey);
 
 #[cfg(test)]
 pub mod test_utils {
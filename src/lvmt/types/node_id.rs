@@ -23,7 +23,50 @@ impl DerefMut for AmtId {
     }
 }
 
-pub type AmtNodeId = AmtId;
+impl AmtId {
+    /// The id of the root AMT, which holds the commitment of the whole state.
+    pub fn root() -> Self {
+        AmtId(ArrayVec::new())
+    }
+
+    /// Returns the node of the parent AMT whose last slot commits to this AMT, `None` for the root AMT.
+    pub fn parent_node(&self) -> Option<AmtNodeId> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(AmtNodeId(*self))
+        }
+    }
+}
+
+/// The id of a node in an AMT: the id of the AMT followed by the index of the node.
+///
+/// It is a distinct type so that the keys of the slot allocation table cannot be mixed up
+/// with the keys of the AMT node table. The id of the child AMT committed to by the node
+/// is obtained with `AmtId::from`.
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AmtNodeId(AmtId);
+
+impl AmtNodeId {
+    pub fn new(amt_id: AmtId, node_index: u16) -> Self {
+        let mut node_id = amt_id;
+        node_id.push(node_index);
+        AmtNodeId(node_id)
+    }
+
+    /// Returns the id of the AMT containing the node and the index of the node.
+    pub fn split(self) -> (AmtId, u16) {
+        let mut amt_id = self.0;
+        let node_index = amt_id.pop().unwrap();
+        (amt_id, node_index)
+    }
+}
+
+impl From<AmtNodeId> for AmtId {
+    fn from(node_id: AmtNodeId) -> Self {
+        node_id.0
+    }
+}
 
 pub fn compute_amt_node_id(digest: H256, depth: usize) -> AmtNodeId {
     let length = depth + 1;
@@ -31,7 +74,7 @@ pub fn compute_amt_node_id(digest: H256, depth: usize) -> AmtNodeId {
     for i in 0..length {
         data[i] = u16::from_be_bytes(digest[i * 2..(i + 1) * 2].try_into().unwrap());
     }
-    AmtId(ArrayVec::from_array_len(data, length))
+    AmtNodeId(AmtId(ArrayVec::from_array_len(data, length)))
 }
 
 impl Encode for AmtId {
@@ -56,6 +99,22 @@ impl Decode for AmtId {
     }
 }
 
+impl Encode for AmtNodeId {
+    fn encode(&self) -> Cow<[u8]> {
+        self.0.encode()
+    }
+}
+
+impl Decode for AmtNodeId {
+    fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
+        let amt_id = AmtId::decode(input)?.into_owned();
+        match amt_id.parent_node() {
+            Some(node_id) => Ok(Cow::Owned(node_id)),
+            None => Err(DecodeError::IncorrectLength),
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::lvmt::types::test_utils;
@@ -76,6 +135,17 @@ pub mod tests {
         }
     }
 
+    impl Arbitrary for AmtNodeId {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+            vec(0u16..u16::MAX, 1..=16)
+                .prop_map(|x| AmtNodeId(AmtId(x.as_slice().try_into().unwrap())))
+                .boxed()
+        }
+    }
+
     proptest! {
         #![proptest_config(ProptestConfig::with_cases(10_000))]
 
@@ -90,6 +160,23 @@ pub mod tests {
             test_utils::test_serde_keep_order(a, b)
         }
 
+        #[test]
+        fn test_node_id_serde(data in any::<AmtNodeId>()) {
+            test_utils::test_serde(data)
+        }
+
+        #[test]
+        fn test_node_id_serde_keep_order((a, b) in (any::<AmtNodeId>(), any::<AmtNodeId>())) {
+            test_utils::test_serde_keep_order(a, b)
+        }
+
+        #[test]
+        fn test_node_id_split((amt_id, node_index) in (any::<AmtId>(), any::<u16>()).prop_filter("AMT too deep", |(amt_id, _)| amt_id.len() < 16)) {
+            let node_id = AmtNodeId::new(amt_id, node_index);
+            prop_assert_eq!(node_id.split(), (amt_id, node_index));
+            prop_assert_eq!(AmtId::from(node_id).parent_node(), Some(node_id));
+        }
+
         #[test]
         fn test_amt_id_decode_error(data in vec(0u8..=255, 1..32).prop_filter("Length must be odd", |v| v.len() % 2 != 0)) {
             let result = AmtId::decode(&data);
@@ -104,7 +191,7 @@ pub mod tests {
 
             let node_id = compute_amt_node_id(digest, depth);
 
-            prop_assert_eq!(node_id.len(), depth + 1);
+            prop_assert_eq!(AmtId::from(node_id).len(), depth + 1);
 
             prop_assert_eq!(node_id.encode(), &digest[0..2*(depth + 1)]);
         }
@@ -1,13 +1,36 @@
+use std::borrow::Cow;
+
 use ethereum_types::H256;
 
-use crate::backends::{TableName, TableSchema};
+use crate::{
+    backends::{
+        serde::{Decode, Encode},
+        DatabaseTrait, TableName, TableRead, TableSchema,
+    },
+    errors::{DecResult, DecodeError, ErrorContext, InconsistencyReason, Result},
+    StorageError,
+};
 
 pub type CommitID = H256;
+/// The number of a confirmed commit in the tables, its height plus one, see
+/// [`height_to_history_number`]. Stored as 8 big-endian bytes in [`HistoryNumberSchema`] and
+/// [`CommitIDSchema`], and as [`encode_history_number_rev`] after the key in the history indices.
+///
+/// Heights are `usize` and history numbers `u64`, so passing one for the other does not compile
+/// and [`height_to_history_number`] and [`history_number_to_height`] convert between them.
 pub type HistoryNumber = u64;
 
+/// Encodes a history number so that the encodings sort in the reverse order of the numbers:
+/// the bitwise complement of the number, in big-endian. This layout is stable.
 pub fn encode_history_number_rev(input: u64) -> [u8; 8] {
     (!input).to_be_bytes()
 }
+
+/// Decodes the output of [`encode_history_number_rev`].
+///
+/// # Panics
+///
+/// Panics if `input` is not 8 bytes long.
 pub fn decode_history_number_rev(input: &[u8]) -> u64 {
     !u64::from_be_bytes(input.try_into().unwrap())
 }
@@ -30,13 +53,258 @@ impl TableSchema for HistoryNumberSchema {
     type Value = CommitID;
 }
 
-/// Converts a `height` to a `history_number`.
+/// Maps an external identifier of a block (alias) to its `CommitID`.
+#[derive(Clone, Copy)]
+pub struct CommitAliasSchema;
+
+impl TableSchema for CommitAliasSchema {
+    const NAME: TableName = TableName::CommitAlias;
+    type Key = H256;
+    type Value = CommitID;
+}
+
+/// What the caller recorded about a confirmed commit, see
+/// [`confirm_ids_with_metadata`](super::confirm_ids_with_metadata).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitMetadata {
+    /// When the commit was confirmed, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Any other bytes, e.g. the chain the block of the commit comes from.
+    pub extra: Box<[u8]>,
+}
+
+// the timestamp in big-endian, then the extra bytes
+impl Encode for CommitMetadata {
+    fn encode(&self) -> Cow<[u8]> {
+        Cow::Owned([&self.timestamp_ms.to_be_bytes()[..], &self.extra[..]].concat())
+    }
+}
+
+impl Decode for CommitMetadata {
+    fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
+        const BYTES: usize = std::mem::size_of::<u64>();
+        if input.len() < BYTES {
+            return Err(DecodeError::IncorrectLength);
+        }
+        let (timestamp_ms, extra) = input.split_at(BYTES);
+        Ok(Cow::Owned(CommitMetadata {
+            timestamp_ms: u64::from_be_bytes(timestamp_ms.try_into().unwrap()),
+            extra: extra.into(),
+        }))
+    }
+}
+
+/// Maps the history number of a confirmed commit to its [`CommitMetadata`], if it was confirmed
+/// with some. Databases created before this table have none.
+#[derive(Clone, Copy)]
+pub struct CommitMetadataSchema;
+
+impl TableSchema for CommitMetadataSchema {
+    const NAME: TableName = TableName::CommitMetadata;
+    type Key = HistoryNumber;
+    type Value = CommitMetadata;
+}
+
+/// Converts a `height` to a `history_number`: the commit at height 0 has history number 1, so
+/// that 0 stays below every confirmed commit. This mapping is stable.
+///
+/// For heights known to be confirmed. See [`checked_height_to_history_number`] for the others.
 pub fn height_to_history_number(height: usize) -> HistoryNumber {
     height as u64 + 1
 }
 
-/// Converts a `history_number` back to a `height`.
-#[cfg(test)]
+/// Converts a `height` to a `history_number`, failing with [`StorageError::HeightOverflow`] if
+/// it does not fit.
+pub fn checked_height_to_history_number(height: usize) -> Result<HistoryNumber> {
+    HistoryNumber::try_from(height)
+        .ok()
+        .and_then(|height| height.checked_add(1))
+        .ok_or(StorageError::HeightOverflow)
+}
+
+/// Converts a `history_number` back to a `height`, the inverse of [`height_to_history_number`].
+///
+/// For history numbers read from the tables. See [`checked_history_number_to_height`] for the
+/// others.
 pub fn history_number_to_height(history_number: HistoryNumber) -> usize {
     history_number as usize - 1
 }
+
+/// Converts a `history_number` back to a `height`, failing with
+/// [`StorageError::InvalidHistoryNumber`] for 0 and the numbers whose height does not fit.
+pub fn checked_history_number_to_height(history_number: HistoryNumber) -> Result<usize> {
+    history_number
+        .checked_sub(1)
+        .and_then(|height| usize::try_from(height).ok())
+        .ok_or(StorageError::InvalidHistoryNumber(history_number))
+}
+
+/// Returns the history number and the id of the latest confirmed commit of `db`, `None` if
+/// nothing is confirmed.
+///
+/// The last row of [`HistoryNumberSchema`] is checked against [`CommitIDSchema`], and an empty
+/// [`HistoryNumberSchema`] against an empty [`CommitIDSchema`]. A mismatch fails with
+/// [`StorageError::ConsistencyCheckFailure`]. The last row is read with
+/// [`TableRead::iter_from_end`].
+pub fn latest_confirmed<D: DatabaseTrait>(db: &D) -> Result<Option<(HistoryNumber, CommitID)>> {
+    let commit_id_table = db.view::<CommitIDSchema>()?;
+    let history_number_table = db.view::<HistoryNumberSchema>()?;
+    let Some(item) = history_number_table.iter_from_end()?.next() else {
+        if commit_id_table.iter_from_start()?.next().is_some() {
+            return Err(StorageError::ConsistencyCheckFailure);
+        }
+        return Ok(None);
+    };
+    let (history_number, commit) = item?;
+    let (history_number, commit) = (history_number.into_owned(), commit.into_owned());
+
+    if commit_id_table.get(&commit)?.as_deref() != Some(&history_number) {
+        return Err(StorageError::ConsistencyCheckFailure.with_context(
+            ErrorContext::new::<HistoryNumberSchema>(&history_number, Some(history_number))
+                .with_reason(InconsistencyReason::MismatchedCommitId),
+        ));
+    }
+    Ok(Some((history_number, commit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{
+        backends::{InMemoryDatabase, WriteSchemaTrait},
+        test_utils::empty_rocksdb,
+    };
+
+    #[test]
+    fn test_boundaries() {
+        assert_eq!(checked_height_to_history_number(0), Ok(1));
+        assert_eq!(checked_history_number_to_height(1), Ok(0));
+        assert_eq!(
+            checked_history_number_to_height(0),
+            Err(StorageError::InvalidHistoryNumber(0))
+        );
+
+        let max_height = usize::try_from(HistoryNumber::MAX - 1).unwrap_or(usize::MAX);
+        let max_history_number = max_height as HistoryNumber + 1;
+        assert_eq!(
+            checked_height_to_history_number(max_height),
+            Ok(max_history_number)
+        );
+        assert_eq!(
+            checked_history_number_to_height(max_history_number),
+            Ok(max_height)
+        );
+        if max_height < usize::MAX {
+            assert_eq!(
+                checked_height_to_history_number(max_height + 1),
+                Err(StorageError::HeightOverflow)
+            );
+        }
+        assert_eq!(
+            decode_history_number_rev(&encode_history_number_rev(HistoryNumber::MAX)),
+            HistoryNumber::MAX
+        );
+        assert_eq!(encode_history_number_rev(0), [0xff; 8]);
+        assert_eq!(encode_history_number_rev(HistoryNumber::MAX), [0; 8]);
+    }
+
+    #[test]
+    fn test_commit_metadata_encoding() {
+        for metadata in [
+            CommitMetadata {
+                timestamp_ms: 0,
+                extra: Box::new([]),
+            },
+            CommitMetadata {
+                timestamp_ms: u64::MAX - 1,
+                extra: b"chain 1".to_vec().into(),
+            },
+        ] {
+            let encoded = metadata.encode();
+            assert_eq!(encoded.len(), 8 + metadata.extra.len());
+            assert_eq!(
+                CommitMetadata::decode(&encoded).unwrap().as_ref(),
+                &metadata
+            );
+        }
+        assert_eq!(
+            CommitMetadata::decode(&[0; 7]),
+            Err(DecodeError::IncorrectLength)
+        );
+    }
+
+    fn check_latest_confirmed<D: DatabaseTrait>(mut db: D) {
+        assert_eq!(latest_confirmed(&db), Ok(None));
+
+        // the confirmed commits from history number 3 on, the first ones being pruned
+        let commit = |history_number: HistoryNumber| H256::from_low_u64_be(history_number);
+        let mut last = 2;
+        for next_last in [3, 4, 5, 8, 9, 100, 1000] {
+            let write_schema = D::write_schema();
+            for history_number in last + 1..=next_last {
+                write_schema.write::<HistoryNumberSchema>((
+                    Cow::Owned(history_number),
+                    Some(Cow::Owned(commit(history_number))),
+                ));
+                write_schema.write::<CommitIDSchema>((
+                    Cow::Owned(commit(history_number)),
+                    Some(Cow::Owned(history_number)),
+                ));
+            }
+            db.commit(write_schema).unwrap();
+            last = next_last;
+            assert_eq!(latest_confirmed(&db), Ok(Some((last, commit(last)))));
+        }
+    }
+
+    #[test]
+    fn test_latest_confirmed() {
+        check_latest_confirmed(InMemoryDatabase::empty());
+    }
+
+    #[test]
+    fn test_latest_confirmed_rocksdb() {
+        let db_path = "__test_latest_confirmed";
+        check_latest_confirmed(empty_rocksdb(db_path).unwrap());
+
+        if std::path::Path::new(db_path).exists() {
+            std::fs::remove_dir_all(db_path).unwrap();
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(height in any::<usize>(), history_number in any::<u64>()) {
+            if let Ok(converted) = checked_height_to_history_number(height) {
+                prop_assert_eq!(converted, height_to_history_number(height));
+                prop_assert_eq!(checked_history_number_to_height(converted), Ok(height));
+            }
+            if let Ok(converted) = checked_history_number_to_height(history_number) {
+                prop_assert_eq!(converted, history_number_to_height(history_number));
+                prop_assert_eq!(checked_height_to_history_number(converted), Ok(history_number));
+            }
+            prop_assert_eq!(
+                decode_history_number_rev(&encode_history_number_rev(history_number)),
+                history_number
+            );
+        }
+
+        #[test]
+        fn test_ordering(a in any::<usize>(), b in any::<usize>()) {
+            let (Ok(history_a), Ok(history_b)) = (
+                checked_height_to_history_number(a),
+                checked_height_to_history_number(b),
+            ) else {
+                return Ok(());
+            };
+            // a higher height has a higher history number, whose reversed encoding sorts first
+            prop_assert_eq!(history_a.cmp(&history_b), a.cmp(&b));
+            prop_assert_eq!(
+                encode_history_number_rev(history_a).cmp(&encode_history_number_rev(history_b)),
+                b.cmp(&a)
+            );
+        }
+    }
+}
@@ -2,19 +2,30 @@ use std::borrow::Cow;
 
 use crate::{
     backends::{
-        serde::{Decode, Encode, EncodeSubKey, FixedLengthEncoded},
-        TableReader, TableSchema, WriteSchemaTrait,
+        serde::{decode_pair, encode_pair, Decode, Encode, EncodeSubKey, FixedLengthEncoded},
+        TableIter, TableReader, TableSchema, WriteSchemaTrait,
     },
     errors::{DecResult, Result},
     traits::KeyValueStoreBulksTrait,
 };
 
-#[cfg(test)]
-use crate::backends::TableIter;
-
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct ChangeKey<C: Copy, K: Clone>(C, K);
 
+impl<C: Copy, K: Clone> ChangeKey<C, K> {
+    pub(crate) fn new(version: C, key: K) -> Self {
+        Self(version, key)
+    }
+
+    pub fn version(&self) -> C {
+        self.0
+    }
+
+    pub fn key(&self) -> &K {
+        &self.1
+    }
+}
+
 pub struct KeyValueStoreBulks<'db, T: TableSchema>(TableReader<'db, T>);
 
 impl<'db, T: TableSchema> KeyValueStoreBulks<'db, T> {
@@ -22,7 +33,6 @@ impl<'db, T: TableSchema> KeyValueStoreBulks<'db, T> {
         Self(db)
     }
 
-    #[cfg(test)]
     pub fn iter_from_start(&self) -> Result<TableIter<T>> {
         self.0.iter_from_start()
     }
@@ -37,7 +47,7 @@ impl<'db, T: TableSchema> Clone for KeyValueStoreBulks<'db, T> {
 impl<'a, K, V, C, T> KeyValueStoreBulksTrait<K, V, C> for KeyValueStoreBulks<'a, T>
 where
     T: TableSchema<Key = ChangeKey<C, K>, Value = V>,
-    C: Copy,
+    C: Copy + PartialEq,
     K: Clone,
     V: Clone,
 {
@@ -58,6 +68,34 @@ where
         Ok(loaded.map(|x| x.into_owned()))
     }
 
+    fn contains_versioned_key(&self, commit: &C, key: &K) -> Result<bool> {
+        Ok(self.0.get(&ChangeKey(*commit, key.clone()))?.is_some())
+    }
+
+    fn get_bulk(&self, commit: &C) -> Result<Vec<(K, V)>> {
+        // the versions are encoded first, so the rows of a version are contiguous
+        let mut bulk = Vec::new();
+        for item in self.0.iter_from_start()? {
+            let (k, v) = item?;
+            let ChangeKey(version, key) = k.into_owned();
+            if version == *commit {
+                bulk.push((key, v.into_owned()));
+            } else if !bulk.is_empty() {
+                break;
+            }
+        }
+        Ok(bulk)
+    }
+
+    fn delete_bulk(&self, commit: &C, write_schema: &impl WriteSchemaTrait) -> Result<()> {
+        let table_op = self
+            .get_bulk(commit)?
+            .into_iter()
+            .map(|(k, _)| (Cow::Owned(ChangeKey(*commit, k)), None));
+        write_schema.write_batch::<T>(table_op);
+        Ok(())
+    }
+
     fn gc_commit(
         &self,
         changes: impl Iterator<Item = (C, K, Option<V>)>,
@@ -76,10 +114,7 @@ where
     K: Clone + Encode,
 {
     fn encode(&self) -> Cow<[u8]> {
-        let encoded_commit = self.0.encode();
-        let encoded_key = self.1.encode();
-
-        Cow::Owned([encoded_commit.as_ref(), encoded_key.as_ref()].concat())
+        Cow::Owned(encode_pair(&self.0, &self.1))
     }
 }
 
@@ -104,8 +139,7 @@ where
     K: Clone + Decode + ToOwned<Owned = K>,
 {
     fn decode(input: &[u8]) -> DecResult<Cow<Self>> {
-        let (raw_commit, raw_key) = input.split_at(C::LENGTH);
-        let (commit, key) = (C::decode(raw_commit)?, K::decode(raw_key)?);
+        let (commit, key) = decode_pair::<C, K>(input)?;
         Ok(Cow::Owned(ChangeKey(commit.into_owned(), key.into_owned())))
     }
 }
@@ -3,15 +3,21 @@ mod key_value_store_bulks;
 mod versioned_flat_key_value;
 
 pub use commit_id_schema::{
-    decode_history_number_rev, encode_history_number_rev, CommitID, CommitIDSchema, HistoryNumber,
+    checked_height_to_history_number, checked_history_number_to_height, decode_history_number_rev,
+    encode_history_number_rev, height_to_history_number, history_number_to_height,
+    latest_confirmed, CommitAliasSchema, CommitID, CommitIDSchema, CommitMetadata,
+    CommitMetadataSchema, HistoryNumber, HistoryNumberSchema,
 };
 pub use key_value_store_bulks::{ChangeKey, KeyValueStoreBulks};
 pub use versioned_flat_key_value::{
-    confirm_ids_to_history, confirm_maps_to_history, table_schema, PendingError, VersionedStore,
-    VersionedStoreCache,
-};
-
-#[cfg(test)]
-pub use versioned_flat_key_value::{
-    empty_rocksdb, gen_random_commit_id, gen_updates, get_rng_for_test,
+    analyze_history, compact_history, confirm_ids_to_history, confirm_ids_with_metadata,
+    confirm_maps_to_history, confirm_maps_to_history_with_stats, confirmed_pending_to_history,
+    estimate_reclaimable, export_snapshot, finalize_confirm, import_snapshot, prepare_confirm,
+    prune_history_before, restore_pending_part, rollback_history_to, table_schema,
+    AddOrSkipOutcome, AddOutcome, ConfirmTicket, ConfirmationCursor, ConfirmedPath,
+    ConfirmedPathInfo, DiffEntry, DiffIter, GetSource, HistoryStats, KeyStatus, MemoryStats,
+    NoopMetrics, PendingBatch, PendingError, PolicyEstimate, PrefixCounts, PrefixStats,
+    PrefixStatsConfig, ReclaimEstimate, RetentionPolicy, SnapshotIter, SnapshotManifest,
+    SnapshotView, StorageMetrics, VersionedStore, VersionedStoreCache, VersionedStoreReadOnly,
+    RECLAIM_TOP_KEYS,
 };
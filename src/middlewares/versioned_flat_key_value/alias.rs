@@ -0,0 +1,192 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+};
+
+use ethereum_types::H256;
+
+use super::{
+    diff::DiffIter, key_status::KeyStatus, manager_impl::SnapshotView,
+    table_schema::VersionedKeyValueSchema, VersionedStore,
+};
+use crate::{
+    backends::{DatabaseTrait, TableRead, WriteSchemaTrait},
+    errors::Result,
+    middlewares::{
+        commit_id_schema::{CommitAliasSchema, CommitMetadata},
+        CommitID,
+    },
+    traits::{IsCompleted, KeyValueStoreManager, NeedNext},
+    StorageError,
+};
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
+    /// Registers `alias` as another name of `commit`, which can be pending or historical.
+    ///
+    /// The alias of a pending commit is kept in the pending part: it is written by the
+    /// confirmation of the commit, and dropped when the commit is discarded or pruned. The alias
+    /// of a historical commit is written to `write_schema`, and again by the next confirmation.
+    /// Registering the same pair twice is a no-op.
+    pub fn register_alias(
+        &mut self,
+        alias: H256,
+        commit: CommitID,
+        write_schema: &impl WriteSchemaTrait,
+    ) -> Result<()> {
+        let pending = self.pending_part.contains_commit_id(&commit);
+        if !pending && self.tables.commit_id_table.get(&commit)?.is_none() {
+            return Err(StorageError::CommitIDNotFound);
+        }
+
+        match self.resolve(&alias)? {
+            Some(registered) if registered != commit => {
+                return Err(StorageError::AliasAlreadyRegistered)
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+
+        if !pending {
+            write_schema.write::<CommitAliasSchema>((Cow::Owned(alias), Some(Cow::Owned(commit))));
+        }
+        self.pending_part.stage_alias(alias, commit);
+        Ok(())
+    }
+
+    /// Returns the commit registered under `alias`, pending or historical.
+    pub fn resolve(&self, alias: &H256) -> Result<Option<CommitID>> {
+        if let Some(commit) = self.pending_part.get_staged_alias(alias) {
+            return Ok(Some(commit));
+        }
+        Ok(self.alias_table.get(alias)?.map(Cow::into_owned))
+    }
+
+    pub fn get_versioned_store_by_alias(&self, alias: &H256) -> Result<SnapshotView<'db, T>> {
+        let commit = self.resolve_existing(alias)?;
+        self.get_versioned_store(&commit)
+    }
+
+    pub fn get_versioned_key_by_alias(
+        &self,
+        alias: &H256,
+        key: &T::Key,
+    ) -> Result<Option<T::Value>> {
+        let commit = self.resolve_existing(alias)?;
+        self.get_versioned_key(&commit, key)
+    }
+
+    pub fn contains_versioned_key_by_alias(&self, alias: &H256, key: &T::Key) -> Result<bool> {
+        let commit = self.resolve_existing(alias)?;
+        self.contains_versioned_key(&commit, key)
+    }
+
+    /// Like [`Self::get_versioned_key_multi_commits`]. Fails with
+    /// [`StorageError::CommitIDNotFound`] if any of `aliases` is not registered.
+    pub fn get_versioned_key_multi_commits_by_alias(
+        &self,
+        aliases: &[H256],
+        key: &T::Key,
+    ) -> Result<Vec<Option<T::Value>>> {
+        let commits = aliases
+            .iter()
+            .map(|alias| self.resolve_existing(alias))
+            .collect::<Result<Vec<_>>>()?;
+        self.get_versioned_key_multi_commits(&commits, key)
+    }
+
+    pub fn iter_historical_changes_by_alias(
+        &self,
+        accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
+        alias: &H256,
+        key: &T::Key,
+    ) -> Result<IsCompleted> {
+        let commit = self.resolve_existing(alias)?;
+        self.iter_historical_changes(accept, &commit, key)
+    }
+
+    pub fn iter_historical_changes_bounded_by_alias(
+        &self,
+        accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
+        alias: &H256,
+        key: &T::Key,
+        max_results: usize,
+        skip: usize,
+    ) -> Result<IsCompleted> {
+        let commit = self.resolve_existing(alias)?;
+        self.iter_historical_changes_bounded(accept, &commit, key, max_results, skip)
+    }
+
+    pub fn iter_all_keys_with_status_by_alias(
+        &self,
+        alias: &H256,
+        start_after: Option<&T::Key>,
+        limit: usize,
+    ) -> Result<(Vec<KeyStatus<T::Key>>, Option<T::Key>)> {
+        let commit = self.resolve_existing(alias)?;
+        self.iter_all_keys_with_status(&commit, start_after, limit)
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn diff_by_alias(
+        &self,
+        from: &H256,
+        to: &H256,
+    ) -> Result<BTreeMap<T::Key, (Option<T::Value>, Option<T::Value>)>> {
+        let (from, to) = (self.resolve_existing(from)?, self.resolve_existing(to)?);
+        self.diff(&from, &to)
+    }
+
+    pub fn diff_iter_by_alias(&self, from: &H256, to: &H256) -> Result<DiffIter<'db, T>> {
+        let (from, to) = (self.resolve_existing(from)?, self.resolve_existing(to)?);
+        self.diff_iter(&from, &to)
+    }
+
+    pub fn children_of_by_alias(&self, alias: &H256) -> Result<Vec<CommitID>> {
+        let commit = self.resolve_existing(alias)?;
+        self.children_of(&commit)
+    }
+
+    pub fn path_to_root_by_alias(&self, alias: &H256) -> Result<Vec<CommitID>> {
+        let commit = self.resolve_existing(alias)?;
+        self.path_to_root(&commit)
+    }
+
+    pub fn is_pending_by_alias(&self, alias: &H256) -> Result<bool> {
+        let commit = self.resolve_existing(alias)?;
+        Ok(self.is_pending(&commit))
+    }
+
+    pub fn get_height_by_alias(&self, alias: &H256) -> Result<Option<usize>> {
+        let commit = self.resolve_existing(alias)?;
+        self.get_height_by_commit_id(&commit)
+    }
+
+    pub fn get_commit_metadata_by_alias(&self, alias: &H256) -> Result<Option<CommitMetadata>> {
+        let commit = self.resolve_existing(alias)?;
+        self.get_commit_metadata(&commit)
+    }
+
+    fn resolve_existing(&self, alias: &H256) -> Result<CommitID> {
+        self.resolve(alias)?.ok_or(StorageError::CommitIDNotFound)
+    }
+}
+
+/// Deletes the aliases of `removed_commits`, e.g. of the confirmed commits removed from the
+/// history.
+pub(super) fn delete_aliases<D: DatabaseTrait>(
+    db: &D,
+    removed_commits: &HashSet<CommitID>,
+    write_schema: &D::WriteSchema,
+) -> Result<()> {
+    if removed_commits.is_empty() {
+        return Ok(());
+    }
+
+    for item in db.view::<CommitAliasSchema>()?.iter_from_start()? {
+        let (alias, commit) = item?;
+        if removed_commits.contains(&*commit) {
+            write_schema.write::<CommitAliasSchema>((alias, None));
+        }
+    }
+    Ok(())
+}
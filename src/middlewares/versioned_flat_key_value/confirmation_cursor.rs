@@ -0,0 +1,102 @@
+use std::{borrow::Cow, marker::PhantomData, sync::Arc, time::Instant};
+
+use super::{
+    finalize_confirm,
+    table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
+    write_ids, write_maps, ConfirmTicket, ConfirmedPath, VersionedStoreCache,
+};
+use crate::{
+    backends::{DatabaseTrait, TableReader},
+    errors::Result,
+    middlewares::{
+        CommitAliasSchema, CommitID, CommitIDSchema, HistoryNumberSchema, KeyValueStoreBulks,
+    },
+};
+
+/// Confirms pending commits to the history with the tables opened once, for callers confirming
+/// one commit at a time. The writes are the same as with [`confirmed_pending_to_history`].
+///
+/// The cursor borrows the database, so it is dropped before the write schema is committed and
+/// never reads the tables as they were before a commit. The commits confirmed through the same
+/// cursor therefore go to the same write schema.
+///
+/// [`confirmed_pending_to_history`]: super::confirmed_pending_to_history
+pub struct ConfirmationCursor<'db, D: DatabaseTrait, T: VersionedKeyValueSchema> {
+    commit_id_table: TableReader<'db, CommitIDSchema>,
+    history_number_table: TableReader<'db, HistoryNumberSchema>,
+    history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
+    change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+    _db: PhantomData<&'db D>,
+}
+
+impl<'db, D: DatabaseTrait, T: VersionedKeyValueSchema> ConfirmationCursor<'db, D, T> {
+    pub fn new(db: &'db D) -> Result<Self> {
+        Ok(Self {
+            commit_id_table: Arc::new(db.view::<CommitIDSchema>()?),
+            history_number_table: Arc::new(db.view::<HistoryNumberSchema>()?),
+            history_index_table: Arc::new(db.view::<HistoryIndicesTable<T>>()?),
+            change_history_table: KeyValueStoreBulks::new(Arc::new(
+                db.view::<HistoryChangeTable<T>>()?,
+            )),
+            _db: PhantomData,
+        })
+    }
+
+    /// Confirms the pending commits up to the parent of `new_root_commit_id`, usually the
+    /// pending root alone, and makes `new_root_commit_id` the pending root. Returns the confirmed
+    /// commits.
+    pub fn confirm_next(
+        &self,
+        pending_part: &mut VersionedStoreCache<T>,
+        new_root_commit_id: CommitID,
+        write_schema: &D::WriteSchema,
+    ) -> Result<ConfirmedPath> {
+        let ticket = self.prepare(pending_part, new_root_commit_id, write_schema)?;
+        finalize_confirm::<T>(pending_part, ticket)
+    }
+
+    /// Like [`prepare_confirm`](super::prepare_confirm), with the tables of the cursor.
+    pub fn prepare(
+        &self,
+        pending_part: &VersionedStoreCache<T>,
+        new_root_commit_id: CommitID,
+        write_schema: &D::WriteSchema,
+    ) -> Result<ConfirmTicket> {
+        let start = Instant::now();
+        let confirmed_path = pending_part.get_confirmed_path(new_root_commit_id)?;
+        let num_keys_per_commit = confirmed_path
+            .key_value_maps
+            .iter()
+            .map(|m| m.len())
+            .collect();
+
+        write_ids::<D>(
+            &self.commit_id_table,
+            &self.history_number_table,
+            confirmed_path.start_height,
+            &confirmed_path.commit_ids,
+            write_schema,
+        )?;
+        for (alias, commit_id) in pending_part.get_aliases_to_confirm(&confirmed_path.commit_ids) {
+            write_schema
+                .write::<CommitAliasSchema>((Cow::Owned(alias), Some(Cow::Owned(commit_id))));
+        }
+
+        write_maps::<D, T>(
+            &self.history_index_table,
+            &self.change_history_table,
+            confirmed_path.start_height,
+            confirmed_path.key_value_maps,
+            write_schema,
+            None,
+        )?;
+
+        Ok(ConfirmTicket {
+            new_root_commit_id,
+            start_height: confirmed_path.start_height,
+            commit_ids: confirmed_path.commit_ids,
+            num_keys_per_commit,
+            duration: start.elapsed(),
+        })
+    }
+}
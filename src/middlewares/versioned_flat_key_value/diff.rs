@@ -0,0 +1,132 @@
+use std::{
+    cmp::Ordering,
+    collections::{btree_set, BTreeSet},
+};
+
+use super::{manager_impl::SnapshotIter, table_schema::VersionedKeyValueSchema, SnapshotView};
+use crate::{backends::serde::Encode, errors::Result, traits::KeyValueStoreRead};
+
+/// A key whose value differs between two commits, with its values at the first and at the
+/// second of them.
+pub type DiffEntry<T> = (
+    <T as VersionedKeyValueSchema>::Key,
+    (
+        Option<<T as VersionedKeyValueSchema>::Value>,
+        Option<<T as VersionedKeyValueSchema>::Value>,
+    ),
+);
+
+/// Iterator over the keys whose value differs between two commits, in the order of the keys,
+/// see [`VersionedStore::diff_iter`](super::VersionedStore::diff_iter).
+pub struct DiffIter<'db, T: VersionedKeyValueSchema>(DiffSource<'db, T>);
+
+enum DiffSource<'db, T: VersionedKeyValueSchema> {
+    // the keys modified between the two commits, read at both
+    Keys {
+        keys: btree_set::IntoIter<T::Key>,
+        from: SnapshotView<'db, T>,
+        to: SnapshotView<'db, T>,
+    },
+    // both snapshots walked in full, side by side
+    Snapshots {
+        from: SnapshotIter<'db, T>,
+        to: SnapshotIter<'db, T>,
+        // the next entries of `from` and `to`, read ahead to be compared
+        from_head: Option<(T::Key, T::Value)>,
+        to_head: Option<(T::Key, T::Value)>,
+    },
+}
+
+impl<'db, T: VersionedKeyValueSchema> DiffIter<'db, T> {
+    pub(super) fn from_keys(
+        keys: BTreeSet<T::Key>,
+        from: SnapshotView<'db, T>,
+        to: SnapshotView<'db, T>,
+    ) -> Self {
+        Self(DiffSource::Keys {
+            keys: keys.into_iter(),
+            from,
+            to,
+        })
+    }
+
+    pub(super) fn from_snapshots(
+        from: &SnapshotView<'db, T>,
+        to: &SnapshotView<'db, T>,
+    ) -> Result<Self> {
+        Ok(Self(DiffSource::Snapshots {
+            from: from.iter_all()?,
+            to: to.iter_all()?,
+            from_head: None,
+            to_head: None,
+        }))
+    }
+
+    fn next_entry(&mut self) -> Result<Option<DiffEntry<T>>> {
+        match &mut self.0 {
+            DiffSource::Keys { keys, from, to } => {
+                for key in keys {
+                    let (old, new) = (from.get(&key)?, to.get(&key)?);
+                    if !same_value::<T>(&old, &new) {
+                        return Ok(Some((key, (old, new))));
+                    }
+                }
+                Ok(None)
+            }
+            DiffSource::Snapshots {
+                from,
+                to,
+                from_head,
+                to_head,
+            } => loop {
+                if from_head.is_none() {
+                    *from_head = from.next().transpose()?;
+                }
+                if to_head.is_none() {
+                    *to_head = to.next().transpose()?;
+                }
+
+                let order = match (&from_head, &to_head) {
+                    (None, None) => return Ok(None),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some((from_key, _)), Some((to_key, _))) => from_key.cmp(to_key),
+                };
+                match order {
+                    Ordering::Less => {
+                        let (key, old) = from_head.take().unwrap();
+                        return Ok(Some((key, (Some(old), None))));
+                    }
+                    Ordering::Greater => {
+                        let (key, new) = to_head.take().unwrap();
+                        return Ok(Some((key, (None, Some(new)))));
+                    }
+                    Ordering::Equal => {
+                        let (key, old) = from_head.take().unwrap();
+                        let (_, new) = to_head.take().unwrap();
+                        if old.encode() != new.encode() {
+                            return Ok(Some((key, (Some(old), Some(new)))));
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl<'db, T: VersionedKeyValueSchema> Iterator for DiffIter<'db, T> {
+    type Item = Result<DiffEntry<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+// values are compared by their encodings, as `T::Value` need not implement `PartialEq`
+fn same_value<T: VersionedKeyValueSchema>(a: &Option<T::Value>, b: &Option<T::Value>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.encode() == b.encode(),
+        (None, None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use super::{
+    table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
+    HistoryIndexKey,
+};
+use crate::{
+    backends::{serde::Encode, DatabaseTrait, TableRead},
+    errors::Result,
+};
+
+/// Shape of the history of a versioned table, see [`analyze_history`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryStats {
+    pub num_keys: u64,
+    /// Versions of all the keys, one index record each.
+    pub index_records: u64,
+    /// Versions with a value, one change record each. The other versions are deletions.
+    pub change_records: u64,
+    /// Encoded bytes of the values of the change records, before compression.
+    pub value_bytes: u64,
+    /// Number of keys by their number of versions.
+    pub versions_per_key: BTreeMap<u64, u64>,
+}
+
+impl HistoryStats {
+    /// Returns the versions without a change record, 0 if the tables are inconsistent or partly
+    /// pruned and the change records outnumber the index records.
+    pub fn deletions(&self) -> u64 {
+        self.index_records.saturating_sub(self.change_records)
+    }
+
+    pub fn average_value_bytes(&self) -> Option<f64> {
+        (self.change_records > 0).then(|| self.value_bytes as f64 / self.change_records as f64)
+    }
+
+    /// Returns the number of versions such that `percent`% of the keys have at most as many,
+    /// by nearest rank. `None` if there is no key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percent` is above 100.
+    pub fn versions_percentile(&self, percent: u8) -> Option<u64> {
+        assert!(percent <= 100, "percentile above 100");
+        let rank = (self.num_keys * percent as u64).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (versions, keys) in &self.versions_per_key {
+            seen += keys;
+            if seen >= rank {
+                return Some(*versions);
+            }
+        }
+        None
+    }
+}
+
+/// Collects the [`HistoryStats`] of `T`, without writing.
+///
+/// The history index and the change table are each scanned once. The index is sorted by key, so
+/// the versions of a key are counted in a single run and only the current key is kept.
+pub fn analyze_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+) -> Result<HistoryStats> {
+    let mut stats = HistoryStats::default();
+
+    let mut current: Option<(T::Key, u64)> = None;
+    for item in db.view::<HistoryIndicesTable<T>>()?.iter_from_start()? {
+        let (k_with_history_number, _) = item?;
+        let HistoryIndexKey(key, _) = k_with_history_number.as_ref();
+        stats.index_records += 1;
+
+        match &mut current {
+            Some((current_key, versions)) if current_key == key => *versions += 1,
+            _ => {
+                if let Some((_, versions)) = current.replace((key.clone(), 1)) {
+                    *stats.versions_per_key.entry(versions).or_default() += 1;
+                }
+                stats.num_keys += 1;
+            }
+        }
+    }
+    if let Some((_, versions)) = current {
+        *stats.versions_per_key.entry(versions).or_default() += 1;
+    }
+
+    for item in db.view::<HistoryChangeTable<T>>()?.iter_from_start()? {
+        let (_, value) = item?;
+        stats.change_records += 1;
+        stats.value_bytes += value.encode().len() as u64;
+    }
+
+    Ok(stats)
+}
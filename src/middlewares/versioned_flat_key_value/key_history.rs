@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use super::{table_schema::VersionedKeyValueSchema, HistoryIndexKey, VersionedStore};
+use crate::{
+    backends::TableRead, errors::Result, middlewares::HistoryNumber,
+    traits::KeyValueStoreBulksTrait,
+};
+
+/// Result of a targeted check on the history of one key.
+///
+/// The check does not stop at the first problem, so the counts always describe the whole
+/// index chain of the key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyHistoryReport {
+    pub records_examined: usize,
+    pub versions_verified: usize,
+    /// The verified versions with a row in the change table, the others being deletions.
+    pub changes_found: usize,
+    pub first_inconsistency: Option<KeyHistoryInconsistency>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyHistoryInconsistency {
+    /// An index record refers to a history number that has not been confirmed.
+    DanglingIndexRecord(HistoryNumber),
+    /// The change table holds a value at this history number, but no index record refers to it.
+    OrphanChange(HistoryNumber),
+}
+
+impl KeyHistoryReport {
+    pub fn is_consistent(&self) -> bool {
+        self.first_inconsistency.is_none()
+    }
+
+    fn record(&mut self, inconsistency: KeyHistoryInconsistency) {
+        self.first_inconsistency.get_or_insert(inconsistency);
+    }
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
+    /// Cross-checks the history index records of `key` against the history numbers and the
+    /// change table, from the newest record down, so the reported inconsistency is the newest one.
+    ///
+    /// Each record costs two point lookups. Deletions are stored as absent rows in the change
+    /// table, so a record without a change row is legal. Change rows without a record are only
+    /// found by [`Self::verify_key_history_with_orphans`].
+    pub fn verify_key_history(&self, key: &T::Key) -> Result<KeyHistoryReport> {
+        let mut report = KeyHistoryReport::default();
+        self.check_index_records(key, &mut report)?;
+        Ok(report)
+    }
+
+    /// Like [`Self::verify_key_history`], but also reports the change rows of `key` that no
+    /// index record refers to, after the inconsistencies of the records.
+    ///
+    /// The change table is ordered by history number first, so the rows of `key` are found by
+    /// scanning the whole table: the cost is O(size of the change table), not of the key.
+    pub fn verify_key_history_with_orphans(&self, key: &T::Key) -> Result<KeyHistoryReport> {
+        let mut report = KeyHistoryReport::default();
+        let indexed = self.check_index_records(key, &mut report)?;
+
+        // the change table is walked forward, as not every backend iterates in reverse
+        let mut orphans = vec![];
+        for item in self.tables.change_history_table.iter_from_start()? {
+            let (change_key, _) = item?;
+            if change_key.key() == key && !indexed.contains(&change_key.version()) {
+                orphans.push(change_key.version());
+            }
+        }
+        for version in orphans.into_iter().rev() {
+            report.record(KeyHistoryInconsistency::OrphanChange(version));
+        }
+
+        Ok(report)
+    }
+
+    // Checks the index records of `key`, and returns their history numbers.
+    fn check_index_records(
+        &self,
+        key: &T::Key,
+        report: &mut KeyHistoryReport,
+    ) -> Result<HashSet<HistoryNumber>> {
+        let mut indexed = HashSet::new();
+        let range_query_key = HistoryIndexKey(key.clone(), HistoryNumber::MAX);
+        for item in self.tables.history_index_table.iter(&range_query_key)? {
+            let (index_key, _) = item?;
+            let HistoryIndexKey(k, history_number) = index_key.as_ref();
+            if k != key {
+                break;
+            }
+            let history_number = *history_number;
+            indexed.insert(history_number);
+
+            report.records_examined += 1;
+            if self
+                .tables
+                .history_number_table
+                .get(&history_number)?
+                .is_none()
+            {
+                report.record(KeyHistoryInconsistency::DanglingIndexRecord(history_number));
+                continue;
+            }
+            report.versions_verified += 1;
+            if self
+                .tables
+                .change_history_table
+                .contains_versioned_key(&history_number, key)?
+            {
+                report.changes_found += 1;
+            }
+        }
+        Ok(indexed)
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use super::{table_schema::VersionedKeyValueSchema, HistoryIndexKey, PendingError, VersionedStore};
+use crate::{
+    backends::TableRead,
+    errors::Result,
+    middlewares::{commit_id_schema::history_number_to_height, CommitID, HistoryNumber},
+    traits::KeyValueStoreBulksTrait,
+    types::ValueEntry,
+};
+
+/// Status of a key at some commit, reported by [`VersionedStore::iter_all_keys_with_status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStatus<K> {
+    pub key: K,
+    /// Whether the key has a value, i.e. its latest version is not a deletion.
+    pub live: bool,
+    pub last_modified_height: usize,
+    /// Number of versions up to the commit, deletions included.
+    pub version_count: u64,
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
+    /// Lists every key modified up to `commit`, deleted keys included, in key order.
+    ///
+    /// Returns at most `limit` keys greater than `start_after`, and the cursor to pass
+    /// as `start_after` for the next page, which is `None` after the last page.
+    ///
+    /// The history index is scanned once per page. Only the liveness of keys
+    /// without pending changes is read from the change table, since deletions
+    /// are stored there as absent rows.
+    pub fn iter_all_keys_with_status(
+        &self,
+        commit: &CommitID,
+        start_after: Option<&T::Key>,
+        limit: usize,
+    ) -> Result<(Vec<KeyStatus<T::Key>>, Option<T::Key>)> {
+        if limit == 0 {
+            return Ok((Vec::new(), start_after.cloned()));
+        }
+
+        let (history_number, pending_statuses) =
+            match self.pending_part.get_path_key_statuses(*commit) {
+                Ok(pending_statuses) => {
+                    let history_number = match self.pending_part.get_parent_of_root() {
+                        Some(parent_of_root) => {
+                            Some(self.get_history_number_by_commit_id(parent_of_root)?)
+                        }
+                        None => None,
+                    };
+                    (history_number, pending_statuses)
+                }
+                Err(PendingError::CommitIDNotFound(_)) => (
+                    Some(self.get_history_number_by_commit_id(*commit)?),
+                    BTreeMap::new(),
+                ),
+                Err(other_err) => return Err(other_err.into()),
+            };
+
+        let after_start = |key: &T::Key| start_after.map_or(true, |start| key > start);
+
+        // (latest version, number of versions) of the smallest `limit + 1` keys.
+        // A key dropped from the map never comes back, as the largest key kept only decreases.
+        let mut history_statuses: BTreeMap<T::Key, (HistoryNumber, u64)> = BTreeMap::new();
+        if let Some(history_number) = history_number {
+            for item in self.tables.history_index_table.iter_from_start()? {
+                let (k_with_history_number, _) = item?;
+                let HistoryIndexKey(key, version) = k_with_history_number.as_ref();
+                if *version > history_number || !after_start(key) {
+                    continue;
+                }
+
+                let status = history_statuses.entry(key.clone()).or_insert((*version, 0));
+                status.0 = status.0.max(*version);
+                status.1 += 1;
+
+                if history_statuses.len() > limit + 1 {
+                    history_statuses.pop_last();
+                }
+            }
+        }
+
+        let mut statuses = BTreeMap::new();
+        for (key, (value, height, count)) in pending_statuses {
+            if !after_start(&key) {
+                continue;
+            }
+
+            let history_count = history_statuses
+                .remove(&key)
+                .map_or(0, |(_, history_count)| history_count);
+            statuses.insert(
+                key.clone(),
+                KeyStatus {
+                    key,
+                    live: matches!(value, ValueEntry::Value(_)),
+                    last_modified_height: height,
+                    version_count: history_count + count,
+                },
+            );
+        }
+        for (key, (version, version_count)) in history_statuses {
+            let live = self
+                .tables
+                .change_history_table
+                .get_versioned_key(&version, &key)?
+                .is_some();
+            statuses.insert(
+                key.clone(),
+                KeyStatus {
+                    key,
+                    live,
+                    last_modified_height: history_number_to_height(version),
+                    version_count,
+                },
+            );
+        }
+
+        let mut statuses: Vec<_> = statuses.into_values().take(limit + 1).collect();
+        let cursor = if statuses.len() > limit {
+            statuses.truncate(limit);
+            statuses.last().map(|status| status.key.clone())
+        } else {
+            None
+        };
+
+        Ok((statuses, cursor))
+    }
+}
@@ -1,4 +1,10 @@
-use std::{borrow::Borrow, collections::BTreeMap};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::{btree_map, BTreeMap},
+    iter::Peekable,
+    ops::Bound,
+};
 
 use crate::{
     backends::TableReader,
@@ -7,14 +13,15 @@ use crate::{
     traits::{
         IsCompleted, KeyValueStoreBulksTrait, KeyValueStoreManager, KeyValueStoreRead, NeedNext,
     },
-    types::ValueEntry,
+    types::{KeyLookup, ValueEntry},
     StorageError,
 };
 
 use super::{
-    get_versioned_key,
+    get_versioned_entries, get_versioned_entry, get_versioned_key,
+    metrics::GetSource,
     table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
-    HistoryIndexKey, PendingError, VersionedStore,
+    HistoryIndexKey, PendingError, VersionedStore, VersionedStoreReadOnly,
 };
 
 pub struct SnapshotView<'db, T: VersionedKeyValueSchema> {
@@ -22,69 +29,17 @@ pub struct SnapshotView<'db, T: VersionedKeyValueSchema> {
     history: Option<SnapshotHistorical<'db, T>>,
 }
 
-#[cfg(test)]
 const MIN_HISTORY_NUMBER_MINUS_ONE: u64 = 0;
 
 impl<'db, T: VersionedKeyValueSchema> SnapshotView<'db, T> {
+    // the latest version in the history of each key from `start` on, all keys for `None`
     #[cfg(test)]
-    fn iter_history(&self) -> Result<BTreeMap<T::Key, ValueEntry<T::Value>>> {
+    fn iter_history_from(
+        &self,
+        start: Option<&T::Key>,
+    ) -> Result<BTreeMap<T::Key, ValueEntry<T::Value>>> {
         if let Some(ref history) = self.history {
-            let (key_with_history_number, _) =
-                match history.history_index_table.iter_from_start()?.next() {
-                    Some(item) => item.unwrap(),
-                    None => return Ok(BTreeMap::new()),
-                };
-
-            let HistoryIndexKey(mut key, mut history_number) =
-                key_with_history_number.as_ref().clone();
-
-            let mut history_map = BTreeMap::new();
-
-            loop {
-                let found_version_number = if history_number <= history.history_number {
-                    history_number
-                } else {
-                    let range_query_key = HistoryIndexKey(key.clone(), history.history_number);
-                    match history.history_index_table.iter(&range_query_key)?.next() {
-                        None => break,
-                        Some(Err(e)) => return Err(e.into()),
-                        Some(Ok((next_key_with_history_number, _)))
-                            if next_key_with_history_number.as_ref().0 != key =>
-                        {
-                            HistoryIndexKey(key, history_number) =
-                                next_key_with_history_number.as_ref().clone();
-                            continue;
-                        }
-                        Some(Ok((k, indices))) => {
-                            let HistoryIndexKey(_, found_history_number) = k.as_ref();
-                            indices.as_ref().last(*found_history_number)
-                        }
-                    }
-                };
-
-                let value = history
-                    .change_history_table
-                    .get_versioned_key(&found_version_number, &key)?;
-
-                history_map.insert(key.clone(), ValueEntry::from_option(value));
-
-                let next_range_query_key =
-                    HistoryIndexKey(key.clone(), MIN_HISTORY_NUMBER_MINUS_ONE);
-                match history
-                    .history_index_table
-                    .iter(&next_range_query_key)?
-                    .next()
-                {
-                    None => break,
-                    Some(Err(e)) => return Err(e.into()),
-                    Some(Ok((next_key_with_history_number, _))) => {
-                        HistoryIndexKey(key, history_number) =
-                            next_key_with_history_number.as_ref().clone();
-                    }
-                }
-            }
-
-            Ok(history_map)
+            history.iter_from(start)?.collect()
         } else {
             Ok(BTreeMap::new())
         }
@@ -92,7 +47,7 @@ impl<'db, T: VersionedKeyValueSchema> SnapshotView<'db, T> {
 
     #[cfg(test)]
     pub fn iter(&self) -> Result<impl Iterator<Item = (T::Key, ValueEntry<T::Value>)>> {
-        let mut map = self.iter_history()?;
+        let mut map = self.iter_history_from(None)?;
 
         if let Some(ref pending_map) = self.pending_updates {
             for (k, v) in pending_map {
@@ -104,12 +59,273 @@ impl<'db, T: VersionedKeyValueSchema> SnapshotView<'db, T> {
     }
 }
 
+impl<'db, T: VersionedKeyValueSchema> SnapshotView<'db, T> {
+    /// Reads `key` like [`KeyValueStoreRead::get`], but reports a deletion in the pending
+    /// part or in the history as [`KeyLookup::Tombstone`] and a key without any version
+    /// as [`KeyLookup::Unknown`].
+    pub fn get_entry(&self, key: &T::Key) -> Result<KeyLookup<T::Value>> {
+        if let Some(entry) = self.pending_updates.as_ref().and_then(|u| u.get(key)) {
+            return Ok(entry.into());
+        }
+
+        if let Some(history) = &self.history {
+            get_versioned_entry(
+                history.history_number,
+                key,
+                &history.history_index_table,
+                &history.change_history_table,
+            )
+        } else {
+            Ok(KeyLookup::Unknown)
+        }
+    }
+
+    /// Reads `keys` at once, returning their values in the order of `keys`.
+    ///
+    /// Unlike calling [`get`](KeyValueStoreRead::get) for each key, the history index is walked
+    /// in key order, reusing one cursor across nearby keys instead of seeking for every key.
+    pub fn multi_get(&self, keys: &[T::Key]) -> Result<Vec<Option<T::Value>>> {
+        let mut values: Vec<Option<Option<T::Value>>> = keys
+            .iter()
+            .map(|key| {
+                self.pending_updates
+                    .as_ref()
+                    .and_then(|u| u.get(key))
+                    .map(|entry| entry.to_option())
+            })
+            .collect();
+
+        if let Some(history) = &self.history {
+            let (history_positions, history_keys): (Vec<_>, Vec<_>) = keys
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| values[*i].is_none())
+                .map(|(i, key)| (i, key.clone()))
+                .unzip();
+            let entries = get_versioned_entries(
+                history.history_number,
+                &history_keys,
+                &history.history_index_table,
+                &history.change_history_table,
+            )?;
+            for (i, entry) in history_positions.into_iter().zip(entries) {
+                values[i] = Some(entry.into_option());
+            }
+        }
+
+        Ok(values.into_iter().map(Option::flatten).collect())
+    }
+
+    /// Returns all the keys of the snapshot with their values, in the order of the keys.
+    /// Deleted keys are skipped.
+    ///
+    /// The history is read while iterating, one key at a time, so the iterator holds readers
+    /// of the database: it must be dropped before the database commits.
+    pub fn iter_all(&self) -> Result<SnapshotIter<'db, T>> {
+        self.iter_all_inner(None)
+    }
+
+    /// Like [`Self::iter_all`], from the first key not less than `key`. Only the history from
+    /// `key` on is read, so stopping early reads only the keys visited.
+    pub fn iter_from(&self, key: &T::Key) -> Result<SnapshotIter<'db, T>> {
+        self.iter_all_inner(Some(key), None)
+    }
+
+    /// Like [`Self::iter_from`], stopping before `end` if given. Only the pending keys before
+    /// `end` are copied.
+    pub fn iter_range(&self, start: &T::Key, end: Option<&T::Key>) -> Result<SnapshotIter<'db, T>> {
+        self.iter_all_inner(Some(start), end)
+    }
+
+    fn iter_all_inner(
+        &self,
+        start: Option<&T::Key>,
+        end: Option<&T::Key>,
+    ) -> Result<SnapshotIter<'db, T>> {
+        let history = match &self.history {
+            Some(history) => Some(history.iter_from(start)?),
+            None => None,
+        };
+        let pending = match (&self.pending_updates, start, end) {
+            // `range` panics on a start after the end
+            (Some(_), Some(start), Some(end)) if start > end => BTreeMap::new(),
+            (Some(pending_map), _, _) => {
+                let range = (
+                    start.map_or(Bound::Unbounded, Bound::Included),
+                    end.map_or(Bound::Unbounded, Bound::Excluded),
+                );
+                pending_map
+                    .range::<T::Key, _>(range)
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            }
+            (None, _, _) => BTreeMap::new(),
+        };
+        Ok(SnapshotIter {
+            pending: pending.into_iter().peekable(),
+            history,
+            history_head: None,
+            end: end.cloned(),
+        })
+    }
+}
+
 pub struct SnapshotHistorical<'db, T: VersionedKeyValueSchema> {
     history_number: HistoryNumber,
     history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
     change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
 }
 
+impl<'db, T: VersionedKeyValueSchema> SnapshotHistorical<'db, T> {
+    fn iter_from(&self, start: Option<&T::Key>) -> Result<HistoryIter<'db, T>> {
+        HistoryIter::new(
+            self.history_number,
+            self.history_index_table.clone(),
+            self.change_history_table.clone(),
+            start,
+        )
+    }
+}
+
+// Walks the history index key by key, reading the latest version of each key at or below
+// `history_number`. Only the index record following the last visited key is kept.
+pub(super) struct HistoryIter<'db, T: VersionedKeyValueSchema> {
+    history_number: HistoryNumber,
+    history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
+    change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+    // the first index record of the next key to visit
+    next: Option<HistoryIndexKey<T::Key>>,
+}
+
+impl<'db, T: VersionedKeyValueSchema> HistoryIter<'db, T> {
+    pub(super) fn new(
+        history_number: HistoryNumber,
+        history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
+        change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+        start: Option<&T::Key>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            history_number,
+            history_index_table,
+            change_history_table,
+            next: None,
+        };
+        iter.next = match start {
+            // the latest history number is encoded first
+            Some(start) => iter.seek(&HistoryIndexKey(start.clone(), HistoryNumber::MAX))?,
+            None => match iter.history_index_table.iter_from_start()?.next() {
+                Some(item) => Some(item?.0.into_owned()),
+                None => None,
+            },
+        };
+        Ok(iter)
+    }
+
+    fn seek(&self, index_key: &HistoryIndexKey<T::Key>) -> Result<Option<HistoryIndexKey<T::Key>>> {
+        match self.history_index_table.iter(index_key)?.next() {
+            Some(item) => Ok(Some(item?.0.into_owned())),
+            None => Ok(None),
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(T::Key, ValueEntry<T::Value>)>> {
+        while let Some(HistoryIndexKey(key, history_number)) = self.next.take() {
+            let found_version_number = if history_number <= self.history_number {
+                history_number
+            } else {
+                let range_query_key = HistoryIndexKey(key.clone(), self.history_number);
+                let Some(item) = self.history_index_table.iter(&range_query_key)?.next() else {
+                    return Ok(None);
+                };
+                let (k, indices) = item?;
+                let HistoryIndexKey(found_key, found_history_number) = k.as_ref();
+                if *found_key != key {
+                    // no version of `key` at or below `history_number`
+                    self.next = Some(k.into_owned());
+                    continue;
+                }
+                indices.as_ref().last(*found_history_number)
+            };
+
+            let value = self
+                .change_history_table
+                .get_versioned_key(&found_version_number, &key)?;
+
+            self.next = self.seek(&HistoryIndexKey(key.clone(), MIN_HISTORY_NUMBER_MINUS_ONE))?;
+            return Ok(Some((key, ValueEntry::from_option(value))));
+        }
+        Ok(None)
+    }
+}
+
+impl<'db, T: VersionedKeyValueSchema> Iterator for HistoryIter<'db, T> {
+    type Item = Result<(T::Key, ValueEntry<T::Value>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// Iterator over the keys of a snapshot and their values, in the order of the keys,
+/// see [`SnapshotView::iter_all`]. Deleted keys are skipped.
+pub struct SnapshotIter<'db, T: VersionedKeyValueSchema> {
+    pending: Peekable<btree_map::IntoIter<T::Key, ValueEntry<T::Value>>>,
+    history: Option<HistoryIter<'db, T>>,
+    // the next entry of `history`, read ahead to be compared with the next pending entry
+    history_head: Option<(T::Key, ValueEntry<T::Value>)>,
+    // the key to stop before, the pending entries are already cut at it
+    end: Option<T::Key>,
+}
+
+impl<'db, T: VersionedKeyValueSchema> SnapshotIter<'db, T> {
+    fn next_entry(&mut self) -> Result<Option<(T::Key, ValueEntry<T::Value>)>> {
+        if self.history_head.is_none() {
+            if let Some(history) = self.history.as_mut() {
+                self.history_head = history.next_entry()?;
+            }
+        }
+
+        let order = match (self.pending.peek(), &self.history_head) {
+            (None, None) => return Ok(None),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((pending_key, _)), Some((history_key, _))) => pending_key.cmp(history_key),
+        };
+        let entry = match order {
+            Ordering::Less => self.pending.next(),
+            Ordering::Equal => {
+                // the pending part overrides the history
+                self.history_head = None;
+                self.pending.next()
+            }
+            Ordering::Greater => self.history_head.take(),
+        };
+        match entry {
+            // the pending entries are all visited, as they are before `end`
+            Some((key, _)) if self.end.as_ref().is_some_and(|end| &key >= end) => {
+                self.history = None;
+                Ok(None)
+            }
+            entry => Ok(entry),
+        }
+    }
+}
+
+impl<'db, T: VersionedKeyValueSchema> Iterator for SnapshotIter<'db, T> {
+    type Item = Result<(T::Key, T::Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_entry() {
+                Ok(Some((key, ValueEntry::Value(value)))) => return Some(Ok((key, value))),
+                Ok(Some((_, ValueEntry::Deleted))) => continue,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
 impl<'db, T: VersionedKeyValueSchema> KeyValueStoreRead<T::Key, T::Value> for SnapshotView<'db, T> {
     fn get(&self, key: &T::Key) -> Result<Option<T::Value>> {
         if let Some(opt_v) = self.pending_updates.as_ref().and_then(|u| u.get(key)) {
@@ -146,124 +362,249 @@ impl<'cache, 'db, T: VersionedKeyValueSchema> KeyValueStoreManager<T::Key, T::Va
 {
     type Store = SnapshotView<'db, T>;
     fn get_versioned_store(&self, commit: &CommitID) -> Result<Self::Store> {
+        self.as_read_only().get_versioned_store(commit)
+    }
+
+    fn iter_historical_changes(
+        &self,
+        accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
+        commit_id: &CommitID,
+        key: &T::Key,
+    ) -> Result<IsCompleted> {
+        self.as_read_only()
+            .iter_historical_changes(accept, commit_id, key)
+    }
+
+    fn iter_historical_changes_bounded(
+        &self,
+        accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
+        commit_id: &CommitID,
+        key: &T::Key,
+        max_results: usize,
+        skip: usize,
+    ) -> Result<IsCompleted> {
+        self.as_read_only().iter_historical_changes_bounded(
+            accept,
+            commit_id,
+            key,
+            max_results,
+            skip,
+        )
+    }
+
+    fn discard(&mut self, commit: CommitID) -> Result<()> {
+        if self.tables.commit_id_table.get(&commit)?.is_some() {
+            return Ok(());
+        }
+
+        Ok(self.pending_part.discard(commit)?)
+    }
+
+    fn get_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<Option<T::Value>> {
+        self.as_read_only().get_versioned_key(commit, key)
+    }
+
+    fn contains_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<bool> {
+        self.as_read_only().contains_versioned_key(commit, key)
+    }
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStoreReadOnly<'cache, 'db, T> {
+    /// See [`KeyValueStoreManager::get_versioned_store`].
+    pub fn get_versioned_store(&self, commit: &CommitID) -> Result<SnapshotView<'db, T>> {
         let pending_res = self.pending_part.get_versioned_store(*commit);
         match pending_res {
-            Ok(pending_map) => {
-                let history = if let Some(history_commit) = self.pending_part.get_parent_of_root() {
-                    Some(SnapshotHistorical {
-                        history_number: self.get_history_number_by_commit_id(history_commit)?,
-                        history_index_table: self.history_index_table.clone(),
-                        change_history_table: self.change_history_table.clone(),
-                    })
-                } else {
-                    None
-                };
-                Ok(SnapshotView {
-                    pending_updates: Some(pending_map),
-                    history,
-                })
-            }
+            Ok(pending_map) => Ok(SnapshotView {
+                pending_updates: Some(pending_map),
+                history: self.get_latest_historical()?,
+            }),
             Err(PendingError::CommitIDNotFound(target_commit_id)) => {
                 assert_eq!(target_commit_id, *commit);
                 let history = SnapshotHistorical {
                     history_number: self.get_history_number_by_commit_id(*commit)?,
-                    history_index_table: self.history_index_table.clone(),
-                    change_history_table: self.change_history_table.clone(),
+                    history_index_table: self.tables.history_index_table.clone(),
+                    change_history_table: self.tables.change_history_table.clone(),
                 };
                 Ok(SnapshotView {
                     pending_updates: None,
                     history: Some(history),
                 })
             }
-            Err(other_err) => Err(StorageError::PendingError(other_err)),
+            Err(other_err) => Err(other_err.into()),
         }
     }
 
-    fn iter_historical_changes(
+    /// See [`KeyValueStoreManager::iter_historical_changes`].
+    pub fn iter_historical_changes(
+        &self,
+        accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
+        commit_id: &CommitID,
+        key: &T::Key,
+    ) -> Result<IsCompleted> {
+        self.iter_historical_changes_bounded(accept, commit_id, key, usize::MAX, 0)
+    }
+
+    /// See [`KeyValueStoreManager::iter_historical_changes_bounded`].
+    pub fn iter_historical_changes_bounded(
         &self,
         mut accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
         commit_id: &CommitID,
         key: &T::Key,
+        max_results: usize,
+        skip: usize,
     ) -> Result<IsCompleted> {
-        let pending_res = self
-            .pending_part
-            .iter_historical_changes(&mut accept, commit_id, key);
-        match pending_res {
-            Ok(false) => Ok(false),
-            Ok(true) => {
-                if let Some(history_commit) = self.pending_part.get_parent_of_root() {
-                    self.iter_historical_changes_history_part(&mut accept, &history_commit, key)
-                } else {
-                    Ok(true)
+        let mut skip = skip;
+        let mut remaining = max_results;
+        let pending_res = self.pending_part.iter_historical_changes(
+            |commit, key, value| {
+                if skip > 0 {
+                    skip -= 1;
+                    return true;
                 }
-            }
+                if remaining == 0 {
+                    return false;
+                }
+                remaining -= 1;
+                accept(commit, key, value)
+            },
+            commit_id,
+            key,
+        );
+        let history_commit = match pending_res {
+            Ok(false) => return Ok(false),
+            Ok(true) => match self.pending_part.get_parent_of_root() {
+                Some(history_commit) => history_commit,
+                None => return Ok(true),
+            },
             Err(PendingError::CommitIDNotFound(target_commit)) => {
                 assert_eq!(target_commit, *commit_id);
-                self.iter_historical_changes_history_part(&mut accept, &target_commit, key)
+                target_commit
             }
-            Err(other_err) => Err(StorageError::PendingError(other_err)),
-        }
-    }
-
-    fn discard(&mut self, commit: CommitID) -> Result<()> {
-        if self.commit_id_table.get(&commit)?.is_some() {
-            return Ok(());
-        }
-
-        Ok(self.pending_part.discard(commit)?)
+            Err(other_err) => return Err(other_err.into()),
+        };
+        self.iter_historical_changes_history_part(
+            &mut accept,
+            &history_commit,
+            key,
+            remaining,
+            skip,
+        )
     }
 
-    fn get_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<Option<T::Value>> {
+    /// See [`KeyValueStoreManager::get_versioned_key`].
+    pub fn get_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<Option<T::Value>> {
         // let pending_res = self.pending_part.get_versioned_key_with_checkout(commit, key); // this will checkout_current
         let pending_res = self.pending_part.get_versioned_key(commit, key);
+        let metrics = self.pending_part.metrics();
         let history_commit = match pending_res {
             Ok(Some(value)) => {
+                metrics.on_get(GetSource::Pending);
                 return Ok(value.into_option());
             }
             Ok(None) => {
-                if let Some(commit) = self.pending_part.get_parent_of_root() {
-                    commit
-                } else {
-                    return Ok(None);
-                }
+                metrics.on_get(GetSource::History);
+                return self.get_latest_confirmed(key);
             }
             Err(PendingError::CommitIDNotFound(target_commit)) => {
                 assert_eq!(target_commit, *commit);
                 target_commit
             }
             Err(other_err) => {
-                return Err(StorageError::PendingError(other_err));
+                return Err(other_err.into());
             }
         };
 
         let history_number = self.get_history_number_by_commit_id(history_commit)?;
+        metrics.on_get(GetSource::History);
         self.get_historical_part(history_number, key)
     }
+
+    /// See [`KeyValueStoreManager::contains_versioned_key`].
+    pub fn contains_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<bool> {
+        Ok(self.get_versioned_key(commit, key)?.is_some())
+    }
 }
 
-// Helper methods used in trait implementations
 impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
+    /// Returns the snapshot at the latest confirmed commit, see [`VersionedStore::get_parent_of_root`].
+    /// `None` if the history is empty.
+    pub fn latest_confirmed_snapshot(&self) -> Result<Option<SnapshotView<'db, T>>> {
+        self.as_read_only().latest_confirmed_snapshot()
+    }
+
+    /// Reads `key` at the latest confirmed commit, `None` if the history is empty.
+    pub fn get_latest_confirmed(&self, key: &T::Key) -> Result<Option<T::Value>> {
+        self.as_read_only().get_latest_confirmed(key)
+    }
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStoreReadOnly<'cache, 'db, T> {
+    /// Returns the snapshot at the latest confirmed commit, see [`Self::get_parent_of_root`].
+    /// `None` if the history is empty.
+    pub fn latest_confirmed_snapshot(&self) -> Result<Option<SnapshotView<'db, T>>> {
+        Ok(self.get_latest_historical()?.map(|history| SnapshotView {
+            pending_updates: None,
+            history: Some(history),
+        }))
+    }
+
+    /// Reads `key` at the latest confirmed commit, `None` if the history is empty.
+    pub fn get_latest_confirmed(&self, key: &T::Key) -> Result<Option<T::Value>> {
+        match self.get_parent_of_root_history_number()? {
+            Some(history_number) => self.get_historical_part(history_number, key),
+            None => Ok(None),
+        }
+    }
+}
+
+// Helper methods used in trait implementations
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStoreReadOnly<'cache, 'db, T> {
+    fn get_latest_historical(&self) -> Result<Option<SnapshotHistorical<'db, T>>> {
+        Ok(self
+            .get_parent_of_root_history_number()?
+            .map(|history_number| SnapshotHistorical {
+                history_number,
+                history_index_table: self.tables.history_index_table.clone(),
+                change_history_table: self.tables.change_history_table.clone(),
+            }))
+    }
+
     fn iter_historical_changes_history_part(
         &self,
         mut accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
         commit_id: &CommitID,
         key: &T::Key,
+        mut remaining: usize,
+        mut skip: usize,
     ) -> Result<IsCompleted> {
         let query_number = self.get_history_number_by_commit_id(*commit_id)?;
 
         let range_query_key = HistoryIndexKey(key.clone(), query_number);
-        for item in self.history_index_table.iter(&range_query_key)? {
+        for item in self.tables.history_index_table.iter(&range_query_key)? {
             let (k_with_history_number, indices) = item?;
             let HistoryIndexKey(k, history_number) = k_with_history_number.as_ref();
             if k != key {
                 break;
             }
+            // each index row is one change, so skipped changes are not read
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            if remaining == 0 {
+                return Ok(false);
+            }
+            remaining -= 1;
 
             let found_version_number = indices.as_ref().last(*history_number);
             let found_value = self
+                .tables
                 .change_history_table
                 .get_versioned_key(&found_version_number, key)?;
-            let found_commit_id = self.history_number_table.get(&found_version_number)?;
+            let found_commit_id = self
+                .tables
+                .history_number_table
+                .get(&found_version_number)?;
             if let Some(found_commit_id) = found_commit_id {
                 let need_next = accept(found_commit_id.borrow(), key, found_value.as_ref());
                 if !need_next {
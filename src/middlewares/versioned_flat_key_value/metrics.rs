@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// Where [`get_versioned_key`](crate::traits::KeyValueStoreManager::get_versioned_key) found
+/// the value of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GetSource {
+    /// Modified by a pending commit on the path of the query.
+    Pending,
+    /// Read from the history, including for a pending commit not modifying the key.
+    History,
+}
+
+/// Receives the events of a versioned store, to be exported as metrics. Set on the pending part
+/// with `VersionedMap::set_metrics`, so that it outlives the stores borrowing it.
+///
+/// Every method does nothing by default. They are called on the hot paths, so an
+/// implementation should only update counters.
+pub trait StorageMetrics: Send + Sync {
+    /// The current map of the pending part moved to another commit, rolling back or applying
+    /// the modifications of `steps` pending commits.
+    fn on_checkout(&self, _steps: usize) {}
+
+    /// The commits up to `height` were confirmed, writing the changes of `keys` keys. `duration`
+    /// covers writing them to the write schema, not committing it.
+    fn on_confirm(&self, _height: usize, _keys: usize, _duration: Duration) {}
+
+    /// A key was read at a commit.
+    fn on_get(&self, _source: GetSource) {}
+}
+
+/// The default [`StorageMetrics`], ignoring every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl StorageMetrics for NoopMetrics {}
@@ -1,31 +1,64 @@
+mod alias;
+mod confirmation_cursor;
+mod diff;
+mod history_stats;
+mod key_history;
+mod key_status;
 mod manager_impl;
+mod metrics;
+mod pending_batch;
 mod pending_part;
+mod prefix_stats;
+mod reclaim;
 mod serde;
+mod snapshot;
 pub mod table_schema;
 #[cfg(test)]
 mod tests;
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Read;
 use std::sync::Arc;
+use std::time::Duration;
 
-pub use pending_part::PendingError;
-
-#[cfg(test)]
-pub use tests::{empty_rocksdb, gen_random_commit_id, gen_updates, get_rng_for_test};
+pub use confirmation_cursor::ConfirmationCursor;
+pub use diff::{DiffEntry, DiffIter};
+pub use history_stats::{analyze_history, HistoryStats};
+pub use key_history::{KeyHistoryInconsistency, KeyHistoryReport};
+pub use key_status::KeyStatus;
+pub use manager_impl::{SnapshotIter, SnapshotView};
+pub use metrics::{GetSource, NoopMetrics, StorageMetrics};
+pub use pending_batch::PendingBatch;
+pub use pending_part::{pending_schema::ConfirmedPathInfo, MemoryStats, PendingError};
+pub use prefix_stats::{PrefixCounts, PrefixStats, PrefixStatsConfig};
+pub use reclaim::{
+    estimate_reclaimable, PolicyEstimate, ReclaimEstimate, RetentionPolicy, RECLAIM_TOP_KEYS,
+};
+pub use snapshot::{export_snapshot, import_snapshot, SnapshotManifest};
 
+use self::alias::delete_aliases;
 use self::pending_part::pending_schema::PendingKeyValueConfig;
+use self::prefix_stats::PrefixStatsCollector;
 use self::table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema};
 use pending_part::VersionedMap;
 
-use super::commit_id_schema::HistoryNumberSchema;
+use super::commit_id_schema::{
+    CommitAliasSchema, CommitMetadata, CommitMetadataSchema, HistoryNumberSchema,
+};
 use super::ChangeKey;
 use super::CommitIDSchema;
-use crate::backends::{DatabaseTrait, TableRead, TableReader, WriteSchemaTrait};
-use crate::errors::Result;
-use crate::middlewares::commit_id_schema::height_to_history_number;
+use crate::backends::serde::Encode;
+use crate::backends::{DatabaseTrait, TableIter, TableRead, TableReader, WriteSchemaTrait};
+use crate::errors::{ErrorContext, InconsistencyReason, Result};
+use crate::middlewares::commit_id_schema::{
+    checked_height_to_history_number, checked_history_number_to_height, height_to_history_number,
+    history_number_to_height, latest_confirmed,
+};
 use crate::middlewares::{CommitID, HistoryNumber, KeyValueStoreBulks};
 use crate::traits::KeyValueStoreBulksTrait;
+use crate::types::KeyLookup;
 use crate::StorageError;
 
 pub type VersionedStoreCache<Schema> = VersionedMap<PendingKeyValueConfig<Schema, CommitID>>;
@@ -43,12 +76,38 @@ impl HistoryIndices {
     }
 }
 
+/// Outcome of [`VersionedStore::add_to_pending_part`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddOutcome {
+    /// The commit to pass to [`confirmed_pending_to_history`] to keep the pending part
+    /// within `max_unconfirmed_heights`. Siblings of its ancestors are discarded by the confirmation.
+    pub confirm_to_restore_limit: Option<CommitID>,
+}
+
+/// Outcome of [`VersionedStore::add_to_pending_part_or_skip`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOrSkipOutcome {
+    /// Added as the pending root.
+    AddedAsRoot(AddOutcome),
+    /// Added under a pending commit.
+    AddedAsChild(AddOutcome),
+    /// Already confirmed at `height`, under the same parent.
+    AlreadyInHistory { height: usize },
+    /// Already pending, under the same parent.
+    AlreadyPending,
+}
+
+/// Reads and extends a versioned table. The history is read through readers borrowing the
+/// database, so a `VersionedStore` is recreated after each `DatabaseTrait::commit` to see it.
 pub struct VersionedStore<'cache, 'db, T: VersionedKeyValueSchema> {
     pending_part: &'cache mut VersionedMap<PendingKeyValueConfig<T, CommitID>>,
-    history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
-    commit_id_table: TableReader<'db, CommitIDSchema>,
-    history_number_table: TableReader<'db, HistoryNumberSchema>,
-    change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+    tables: HistoryTables<'db, T>,
+    alias_table: TableReader<'db, CommitAliasSchema>,
+    metadata_table: TableReader<'db, CommitMetadataSchema>,
+    // history number of the parent of the pending root, read at construction. The pending
+    // root only changes by confirmation, which needs the pending part borrowed by the store.
+    // `None` if there is no history or the confirmation of the parent is not committed yet.
+    parent_of_root_history_number: Option<HistoryNumber>,
 }
 
 impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
@@ -56,39 +115,536 @@ impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
         db: &'db D,
         pending_part: &'cache mut VersionedMap<PendingKeyValueConfig<T, CommitID>>,
     ) -> Result<Self> {
-        let history_index_table = Arc::new(db.view::<HistoryIndicesTable<T>>()?);
-        let commit_id_table = Arc::new(db.view::<CommitIDSchema>()?);
-        let history_number_table = Arc::new(db.view::<HistoryNumberSchema>()?);
-        let change_history_table =
-            KeyValueStoreBulks::new(Arc::new(db.view::<HistoryChangeTable<T>>()?));
+        let VersionedStoreReadOnly {
+            pending_part: _,
+            tables,
+            parent_of_root_history_number,
+        } = VersionedStoreReadOnly::new(db, pending_part)?;
+        let alias_table = Arc::new(db.view::<CommitAliasSchema>()?);
+        let metadata_table = Arc::new(db.view::<CommitMetadataSchema>()?);
 
         let versioned_store = VersionedStore {
             pending_part,
-            history_index_table,
-            commit_id_table,
-            history_number_table,
-            change_history_table,
+            tables: tables.into_owned(),
+            alias_table,
+            metadata_table,
+            parent_of_root_history_number,
         };
 
         Ok(versioned_store)
     }
 
+    /// Reads through the pending part borrowed shared, as a [`VersionedStoreReadOnly`] would.
+    pub fn as_read_only(&self) -> VersionedStoreReadOnly<'_, 'db, T> {
+        VersionedStoreReadOnly {
+            pending_part: self.pending_part,
+            tables: Cow::Borrowed(&self.tables),
+            parent_of_root_history_number: self.parent_of_root_history_number,
+        }
+    }
+
+    /// Adds `commit` under `parent_commit` to the pending part. `updates` can be any sequence of
+    /// changes, e.g. a map or a vec: if it changes a key more than once, the last change wins.
     pub fn add_to_pending_part(
         &mut self,
         parent_commit: Option<CommitID>,
         commit: CommitID,
-        updates: BTreeMap<T::Key, Option<T::Value>>,
-    ) -> Result<()> {
-        if self.commit_id_table.get(&commit)?.is_some() {
+        updates: impl IntoIterator<Item = (T::Key, Option<T::Value>)>,
+    ) -> Result<AddOutcome> {
+        if self.tables.commit_id_table.get(&commit)?.is_some() {
             return Err(StorageError::CommitIdAlreadyExistsInHistory);
         }
 
-        Ok(self.pending_part.add_node(updates, commit, parent_commit)?)
+        self.pending_part.add_node(updates, commit, parent_commit)?;
+
+        Ok(AddOutcome {
+            confirm_to_restore_limit: self.pending_part.get_pending_root_to_confirm(commit)?,
+        })
+    }
+
+    /// Like [`Self::add_to_pending_part`], but skips a commit already confirmed or pending, e.g.
+    /// when replaying the blocks of a journal after a restart. Nothing is added, and
+    /// [`StorageError::ParentMismatch`] is returned, if the known commit has another parent.
+    /// The parent of a commit confirmed at the first retained height is not checked.
+    pub fn add_to_pending_part_or_skip(
+        &mut self,
+        parent_commit: Option<CommitID>,
+        commit: CommitID,
+        updates: impl IntoIterator<Item = (T::Key, Option<T::Value>)>,
+    ) -> Result<AddOrSkipOutcome> {
+        if let Some(history_number) = self.tables.commit_id_table.get(&commit)? {
+            let height = history_number_to_height(history_number.into_owned());
+            let recorded_parent = match height.checked_sub(1) {
+                Some(parent_height) => self.get_commit_id_by_height(parent_height)?,
+                None => None,
+            };
+            // the parent is no longer recorded once pruned
+            let parent_pruned = height > 0 && recorded_parent.is_none();
+            if !parent_pruned && recorded_parent != parent_commit {
+                return Err(StorageError::ParentMismatch);
+            }
+            return Ok(AddOrSkipOutcome::AlreadyInHistory { height });
+        }
+
+        if self.pending_part.contains_commit_id(&commit) {
+            let recorded_parent = match self.pending_part.path_to_root(&commit)?.get(1) {
+                Some(parent) => Some(*parent),
+                None => self.pending_part.get_parent_of_root(),
+            };
+            if recorded_parent != parent_commit {
+                return Err(StorageError::ParentMismatch);
+            }
+            return Ok(AddOrSkipOutcome::AlreadyPending);
+        }
+
+        let is_root = match parent_commit {
+            Some(parent) => !self.pending_part.contains_commit_id(&parent),
+            None => true,
+        };
+        let outcome = self.add_to_pending_part(parent_commit, commit, updates)?;
+        Ok(if is_root {
+            AddOrSkipOutcome::AddedAsRoot(outcome)
+        } else {
+            AddOrSkipOutcome::AddedAsChild(outcome)
+        })
+    }
+
+    /// Like [`Self::add_to_pending_part`], but first checks that the new commit would be at
+    /// `expected_height`, e.g. the block number known to the caller. Nothing is added on a mismatch.
+    pub fn add_to_pending_part_checked(
+        &mut self,
+        parent_commit: Option<CommitID>,
+        commit: CommitID,
+        updates: impl IntoIterator<Item = (T::Key, Option<T::Value>)>,
+        expected_height: Option<usize>,
+    ) -> Result<AddOutcome> {
+        if let Some(expected_height) = expected_height {
+            self.check_height_of_new_commit(parent_commit, expected_height)?;
+        }
+        self.add_to_pending_part(parent_commit, commit, updates)
+    }
+
+    /// Returns [`StorageError::HeightMismatch`] unless a commit added under `parent_commit`
+    /// would be at `expected_height`. The pending root is at the height following the latest
+    /// confirmed commit.
+    pub fn check_height_of_new_commit(
+        &self,
+        parent_commit: Option<CommitID>,
+        expected_height: usize,
+    ) -> Result<()> {
+        let actual = self.pending_part.get_height_of_new_node(parent_commit)?;
+        if actual != expected_height {
+            return Err(StorageError::HeightMismatch {
+                expected: expected_height,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes the pending `commit` and all its descendants, returning the removed commits,
+    /// `commit` first. The pending root cannot be removed: it is confirmed or kept.
+    pub fn prune_subtree(&mut self, commit: CommitID) -> Result<Vec<CommitID>> {
+        Ok(self.pending_part.prune_subtree(commit)?)
+    }
+
+    /// Returns the children of the pending `commit`, in no particular order.
+    pub fn children_of(&self, commit: &CommitID) -> Result<Vec<CommitID>> {
+        Ok(self.pending_part.children_of(commit)?)
+    }
+
+    /// Returns the pending commits without children, the tips of the pending forks, in no
+    /// particular order. Empty if the pending part is.
+    pub fn leaves(&self) -> Vec<CommitID> {
+        self.pending_part.leaves()
+    }
+
+    /// Returns the pending `commit` and its ancestors up to the pending root, `commit` first.
+    /// The height of a pending commit is given by [`Self::get_height_by_commit_id`].
+    pub fn path_to_root(&self, commit: &CommitID) -> Result<Vec<CommitID>> {
+        Ok(self.pending_part.path_to_root(commit)?)
+    }
+
+    /// Whether `commit` is in the pending part, i.e. added but neither confirmed nor discarded.
+    pub fn is_pending(&self, commit: &CommitID) -> bool {
+        self.pending_part.contains_commit_id(commit)
+    }
+
+    /// Returns the keys whose value differs between `from` and `to`, with their values at `from`
+    /// and at `to`. See [`Self::diff_iter`] to read a large difference without keeping it.
+    #[allow(clippy::type_complexity)]
+    pub fn diff(
+        &self,
+        from: &CommitID,
+        to: &CommitID,
+    ) -> Result<BTreeMap<T::Key, (Option<T::Value>, Option<T::Value>)>> {
+        self.diff_iter(from, to)?.collect()
+    }
+
+    /// Iterates over the keys whose value differs between `from` and `to`, in the order of the
+    /// keys, with their values at `from` and at `to`. Each commit is pending, on any branch, or
+    /// confirmed.
+    ///
+    /// Between two pending commits, or a pending commit and the parent of the pending root, only
+    /// the keys modified on the way from one to the other are read. Otherwise both snapshots are
+    /// walked in full: deletions are not kept in the change history, so the keys deleted between
+    /// two heights cannot be listed from the changes of these heights.
+    pub fn diff_iter(&self, from: &CommitID, to: &CommitID) -> Result<DiffIter<'db, T>> {
+        let from_view = self.as_read_only().get_versioned_store(from)?;
+        let to_view = self.as_read_only().get_versioned_store(to)?;
+
+        let parent_of_root = self.get_parent_of_root();
+        let keys = match (self.is_pending(from), self.is_pending(to)) {
+            _ if from == to => Some(BTreeSet::new()),
+            (true, true) => Some(self.pending_part.get_diff_keys(*from, *to)?),
+            (true, false) if Some(*to) == parent_of_root => Some(self.path_keys(*from)?),
+            (false, true) if Some(*from) == parent_of_root => Some(self.path_keys(*to)?),
+            _ => None,
+        };
+        match keys {
+            Some(keys) => Ok(DiffIter::from_keys(keys, from_view, to_view)),
+            None => DiffIter::from_snapshots(&from_view, &to_view),
+        }
+    }
+
+    // the keys modified from the pending root to the pending `commit`
+    fn path_keys(&self, commit: CommitID) -> Result<BTreeSet<T::Key>> {
+        Ok(self
+            .pending_part
+            .get_path_key_statuses(commit)?
+            .into_keys()
+            .collect())
+    }
+
+    /// Returns the value of `key` at each of `commits`, pending or historical, in order, as
+    /// [`get_versioned_key`](crate::traits::KeyValueStoreManager::get_versioned_key) would. The pending part is walked once for
+    /// the pending commits, and the versions of the key in the history are read once for all.
+    pub fn get_versioned_key_multi_commits(
+        &self,
+        commits: &[CommitID],
+        key: &T::Key,
+    ) -> Result<Vec<Option<T::Value>>> {
+        let (pending_positions, pending_commits): (Vec<_>, Vec<_>) = commits
+            .iter()
+            .enumerate()
+            .filter(|(_, commit)| self.is_pending(commit))
+            .map(|(i, commit)| (i, *commit))
+            .unzip();
+        let pending_values = self
+            .pending_part
+            .get_versioned_key_multi_commits(&pending_commits, key)?;
+
+        let mut values = vec![None; commits.len()];
+        let mut pending_values = pending_positions.into_iter().zip(pending_values).peekable();
+        // positions to read from the history, with their history numbers
+        let mut history_queries = Vec::new();
+        for (i, commit) in commits.iter().enumerate() {
+            if pending_values.peek().map(|(position, _)| *position) == Some(i) {
+                match pending_values.next().unwrap().1 {
+                    Some(value) => values[i] = value.into_option(),
+                    // not modified in the pending part, read at the latest confirmed commit
+                    None => {
+                        if let Some(history_number) = self.get_parent_of_root_history_number()? {
+                            history_queries.push((i, history_number));
+                        }
+                    }
+                }
+            } else {
+                history_queries.push((i, self.get_history_number_by_commit_id(*commit)?));
+            }
+        }
+
+        let history_numbers: Vec<_> = history_queries.iter().map(|(_, n)| *n).collect();
+        let history_values = get_versioned_key_at_history_numbers(
+            &history_numbers,
+            key,
+            &self.tables.history_index_table,
+            &self.tables.change_history_table,
+        )?;
+        for ((i, _), value) in history_queries.into_iter().zip(history_values) {
+            values[i] = value;
+        }
+        Ok(values)
+    }
+
+    /// Returns the height of `commit`, pending or confirmed, `None` if the commit is unknown.
+    pub fn get_height_by_commit_id(&self, commit: &CommitID) -> Result<Option<usize>> {
+        if self.is_pending(commit) {
+            return Ok(Some(self.pending_part.get_height_by_commit_id(*commit)?));
+        }
+
+        match self.get_history_number_by_commit_id(*commit) {
+            Ok(history_number) => Ok(Some(history_number_to_height(history_number))),
+            Err(StorageError::CommitIDNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the metadata `commit` was confirmed with by [`confirm_ids_with_metadata`].
+    ///
+    /// `None` if the commit was confirmed without, e.g. before the table existed, and for the
+    /// pending and unknown commits.
+    pub fn get_commit_metadata(&self, commit: &CommitID) -> Result<Option<CommitMetadata>> {
+        if self.is_pending(commit) {
+            return Ok(None);
+        }
+
+        match self.get_history_number_by_commit_id(*commit) {
+            Ok(history_number) => Ok(self
+                .metadata_table
+                .get(&history_number)?
+                .map(Cow::into_owned)),
+            Err(StorageError::CommitIDNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the confirmed commit at `height`.
+    ///
+    /// `None` for the heights of the pending part, which may hold several commits at one
+    /// height until one of them is confirmed, and for the heights beyond the tip.
+    pub fn get_commit_id_by_height(&self, height: usize) -> Result<Option<CommitID>> {
+        let Ok(history_number) = checked_height_to_history_number(height) else {
+            return Ok(None);
+        };
+        Ok(self
+            .tables
+            .history_number_table
+            .get(&history_number)?
+            .map(Cow::into_owned))
+    }
+
+    /// Returns the value of `key` at the confirmed commit at `height`, as
+    /// [`get_versioned_key`](crate::traits::KeyValueStoreManager::get_versioned_key) would at
+    /// that commit.
+    ///
+    /// Fails with [`StorageError::HeightNotConfirmed`] for the heights of the pending part, whose
+    /// commit is not decided yet, and for the heights beyond the tip.
+    pub fn get_key_at_height(&self, height: usize, key: &T::Key) -> Result<Option<T::Value>> {
+        let history_number = checked_height_to_history_number(height)
+            .map_err(|_| StorageError::HeightNotConfirmed(height))?;
+        if self
+            .tables
+            .history_number_table
+            .get(&history_number)?
+            .is_none()
+        {
+            return Err(StorageError::HeightNotConfirmed(height));
+        }
+        self.get_historical_part(history_number, key)
+    }
+
+    /// Checks the history index and change tables against each other and against the confirmed
+    /// commits. Fails with [`StorageError::ConsistencyCheckFailure`], with the context of the
+    /// first inconsistent row, on
+    /// - an index record above the latest confirmed commit,
+    /// - several index records of a key at or below the earliest confirmed commit, of which
+    ///   [`prune_history_before`] keeps one,
+    /// - a change row without an index record, which no read reaches.
+    ///
+    /// An index record without a change row is a deletion. `sample` bounds the number of keys of
+    /// the index and of rows of the change table checked, `None` to check them all.
+    pub fn check_history_integrity(&self, sample: Option<usize>) -> Result<()> {
+        let limit = sample.unwrap_or(usize::MAX);
+        let earliest = match self.tables.history_number_table.iter_from_start()?.next() {
+            Some(item) => Some(item?.0.into_owned()),
+            None => None,
+        };
+        let (Some(latest), Some(earliest)) = (self.get_parent_of_root_history_number()?, earliest)
+        else {
+            let is_empty = self
+                .tables
+                .history_index_table
+                .iter_from_start()?
+                .next()
+                .is_none()
+                && self
+                    .tables
+                    .change_history_table
+                    .iter_from_start()?
+                    .next()
+                    .is_none();
+            return if is_empty {
+                Ok(())
+            } else {
+                Err(StorageError::ConsistencyCheckFailure)
+            };
+        };
+
+        // the key of the previous index record, and whether it has a record at or below the
+        // earliest confirmed commit
+        let mut current: Option<(T::Key, bool)> = None;
+        let mut num_keys = 0;
+        for item in self.tables.history_index_table.iter_from_start()? {
+            let (k_with_history_number, _) = item?;
+            let index_key = k_with_history_number.as_ref();
+            let HistoryIndexKey(key, history_number) = index_key;
+            if !matches!(&current, Some((current_key, _)) if current_key == key) {
+                if num_keys == limit {
+                    break;
+                }
+                num_keys += 1;
+                current = Some((key.clone(), false));
+            }
+
+            let inconsistent = |reason| {
+                StorageError::ConsistencyCheckFailure.with_context(
+                    ErrorContext::new::<HistoryIndicesTable<T>>(index_key, Some(*history_number))
+                        .with_reason(reason),
+                )
+            };
+            if *history_number > latest {
+                return Err(inconsistent(InconsistencyReason::AboveLatestConfirmed));
+            }
+            if *history_number <= earliest {
+                let pruned = &mut current.as_mut().unwrap().1;
+                if *pruned {
+                    return Err(inconsistent(InconsistencyReason::SeveralPrunedVersions));
+                }
+                *pruned = true;
+            }
+        }
+
+        for item in self
+            .tables
+            .change_history_table
+            .iter_from_start()?
+            .take(limit)
+        {
+            let (change_key, _) = item?;
+            let index_key = HistoryIndexKey(change_key.key().clone(), change_key.version());
+            if self.tables.history_index_table.get(&index_key)?.is_none() {
+                return Err(StorageError::ConsistencyCheckFailure.with_context(
+                    ErrorContext::new::<HistoryChangeTable<T>>(
+                        &change_key,
+                        Some(change_key.version()),
+                    )
+                    .with_reason(InconsistencyReason::UnreachableChange),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the latest confirmed commit, i.e. the parent of the pending root, `None` if the history is empty.
+    pub fn get_parent_of_root(&self) -> Option<CommitID> {
+        self.pending_part.get_parent_of_root()
+    }
+
+    // the history number of `get_parent_of_root`
+    fn get_parent_of_root_history_number(&self) -> Result<Option<HistoryNumber>> {
+        self.as_read_only().get_parent_of_root_history_number()
+    }
+
+    fn get_history_number_by_commit_id(&self, commit: CommitID) -> Result<HistoryNumber> {
+        self.as_read_only().get_history_number_by_commit_id(commit)
+    }
+
+    fn get_historical_part(
+        &self,
+        query_version_number: HistoryNumber,
+        key: &T::Key,
+    ) -> Result<Option<T::Value>> {
+        self.as_read_only()
+            .get_historical_part(query_version_number, key)
+    }
+}
+
+/// The readers of the history tables, shared by [`VersionedStore`] and the
+/// [`VersionedStoreReadOnly`] it lends.
+struct HistoryTables<'db, T: VersionedKeyValueSchema> {
+    history_index_table: TableReader<'db, HistoryIndicesTable<T>>,
+    commit_id_table: TableReader<'db, CommitIDSchema>,
+    history_number_table: TableReader<'db, HistoryNumberSchema>,
+    change_history_table: KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+}
+
+impl<'db, T: VersionedKeyValueSchema> HistoryTables<'db, T> {
+    fn new<D: DatabaseTrait>(db: &'db D) -> Result<Self> {
+        Ok(HistoryTables {
+            history_index_table: Arc::new(db.view::<HistoryIndicesTable<T>>()?),
+            commit_id_table: Arc::new(db.view::<CommitIDSchema>()?),
+            history_number_table: Arc::new(db.view::<HistoryNumberSchema>()?),
+            change_history_table: KeyValueStoreBulks::new(Arc::new(
+                db.view::<HistoryChangeTable<T>>()?,
+            )),
+        })
+    }
+}
+
+impl<'db, T: VersionedKeyValueSchema> Clone for HistoryTables<'db, T> {
+    fn clone(&self) -> Self {
+        HistoryTables {
+            history_index_table: self.history_index_table.clone(),
+            commit_id_table: self.commit_id_table.clone(),
+            history_number_table: self.history_number_table.clone(),
+            change_history_table: self.change_history_table.clone(),
+        }
+    }
+}
+
+/// Reads a versioned table like [`VersionedStore`], but borrows the pending part shared, so
+/// that several readers, e.g. on different threads, can read it at once. Adding, discarding and
+/// confirming commits changes the pending part, so they are only offered by [`VersionedStore`].
+///
+/// As for [`VersionedStore`], the history is read through readers borrowing the database.
+pub struct VersionedStoreReadOnly<'cache, 'db, T: VersionedKeyValueSchema> {
+    pending_part: &'cache VersionedMap<PendingKeyValueConfig<T, CommitID>>,
+    // borrowed from the store when lent by `VersionedStore::as_read_only`
+    tables: Cow<'cache, HistoryTables<'db, T>>,
+    // as in `VersionedStore`, the pending root cannot change while the pending part is borrowed
+    parent_of_root_history_number: Option<HistoryNumber>,
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStoreReadOnly<'cache, 'db, T> {
+    pub fn new<D: DatabaseTrait>(
+        db: &'db D,
+        pending_part: &'cache VersionedMap<PendingKeyValueConfig<T, CommitID>>,
+    ) -> Result<Self> {
+        let tables = HistoryTables::new(db)?;
+
+        let parent_of_root_history_number = match pending_part.get_parent_of_root() {
+            Some(parent_of_root) => tables
+                .commit_id_table
+                .get(&parent_of_root)?
+                .map(|history_number| history_number.into_owned()),
+            None => None,
+        };
+
+        Ok(VersionedStoreReadOnly {
+            pending_part,
+            tables: Cow::Owned(tables),
+            parent_of_root_history_number,
+        })
+    }
+
+    /// Returns the latest confirmed commit, i.e. the parent of the pending root, `None` if the history is empty.
+    pub fn get_parent_of_root(&self) -> Option<CommitID> {
+        self.pending_part.get_parent_of_root()
+    }
+
+    // the history number of `get_parent_of_root`
+    fn get_parent_of_root_history_number(&self) -> Result<Option<HistoryNumber>> {
+        if let Some(history_number) = self.parent_of_root_history_number {
+            return Ok(Some(history_number));
+        }
+
+        self.get_parent_of_root()
+            .map(|commit| self.get_history_number_by_commit_id(commit))
+            .transpose()
     }
 
     fn get_history_number_by_commit_id(&self, commit: CommitID) -> Result<HistoryNumber> {
-        if let Some(value) = self.commit_id_table.get(&commit)? {
-            Ok(value.into_owned())
+        if let Some(history_number) = self.pending_part.get_cached_history_number(&commit) {
+            return Ok(history_number);
+        }
+
+        if let Some(value) = self.tables.commit_id_table.get(&commit)? {
+            let history_number = value.into_owned();
+            self.pending_part
+                .cache_history_number(commit, history_number);
+            Ok(history_number)
         } else {
             Err(StorageError::CommitIDNotFound)
         }
@@ -102,8 +658,8 @@ impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
         get_versioned_key(
             query_version_number,
             key,
-            &self.history_index_table,
-            &self.change_history_table,
+            &self.tables.history_index_table,
+            &self.tables.change_history_table,
         )
     }
 }
@@ -114,17 +670,33 @@ fn get_versioned_key<'db, T: VersionedKeyValueSchema>(
     history_index_table: &TableReader<'db, HistoryIndicesTable<T>>,
     change_history_table: &KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
 ) -> Result<Option<T::Value>> {
-    let range_query_key = HistoryIndexKey(key.clone(), query_version_number);
+    Ok(get_versioned_entry(
+        query_version_number,
+        key,
+        history_index_table,
+        change_history_table,
+    )?
+    .into_option())
+}
 
+/// Like [`get_versioned_key`], but tells a deleted key (an index record without a
+/// change row) from a key never written up to `query_version_number`.
+fn get_versioned_entry<'db, T: VersionedKeyValueSchema>(
+    query_version_number: HistoryNumber,
+    key: &T::Key,
+    history_index_table: &TableReader<'db, HistoryIndicesTable<T>>,
+    change_history_table: &KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+) -> Result<KeyLookup<T::Value>> {
+    let range_query_key = HistoryIndexKey(key.clone(), query_version_number);
     let found_version_number = match history_index_table.iter(&range_query_key)?.next() {
         None => {
-            return Ok(None);
+            return Ok(KeyLookup::Unknown);
         }
         Some(Err(e)) => {
             return Err(e.into());
         }
         Some(Ok((k, _))) if &k.as_ref().0 != key => {
-            return Ok(None);
+            return Ok(KeyLookup::Unknown);
         }
         Some(Ok((k, indices))) => {
             let HistoryIndexKey(_, history_number) = k.as_ref();
@@ -133,47 +705,293 @@ fn get_versioned_key<'db, T: VersionedKeyValueSchema>(
         }
     };
 
-    change_history_table.get_versioned_key(&found_version_number, key)
+    let value = change_history_table.get_versioned_key(&found_version_number, key)?;
+    Ok(match value {
+        Some(value) => KeyLookup::Value(value),
+        None => KeyLookup::Tombstone,
+    })
+}
+
+/// [`get_versioned_key`] at each of `query_version_numbers`, in order. The index records of the
+/// key are read with one cursor, from the latest queried version down.
+fn get_versioned_key_at_history_numbers<'db, T: VersionedKeyValueSchema>(
+    query_version_numbers: &[HistoryNumber],
+    key: &T::Key,
+    history_index_table: &TableReader<'db, HistoryIndicesTable<T>>,
+    change_history_table: &KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+) -> Result<Vec<Option<T::Value>>> {
+    let mut values = vec![None; query_version_numbers.len()];
+    let Some(max_version_number) = query_version_numbers.iter().max() else {
+        return Ok(values);
+    };
+
+    let mut order: Vec<_> = (0..query_version_numbers.len()).collect();
+    order.sort_by_key(|i| Reverse(query_version_numbers[*i]));
+
+    let mut iter = history_index_table.iter(&HistoryIndexKey(key.clone(), *max_version_number))?;
+    let mut record = next_index_record::<T>(&mut iter)?;
+    // the last version read from the change table, with its value
+    let mut last_read: Option<(HistoryNumber, Option<T::Value>)> = None;
+    for i in order {
+        let query_version_number = query_version_numbers[i];
+        let found_version_number = loop {
+            match &record {
+                Some((_, found_key, version)) if found_key == key => {
+                    if *version <= query_version_number {
+                        break Some(*version);
+                    }
+                    record = next_index_record::<T>(&mut iter)?;
+                }
+                // no version of the key at or below the queried version
+                _ => break None,
+            }
+        };
+        let Some(found_version_number) = found_version_number else {
+            continue;
+        };
+
+        if !matches!(&last_read, Some((version, _)) if *version == found_version_number) {
+            let value = change_history_table.get_versioned_key(&found_version_number, key)?;
+            last_read = Some((found_version_number, value));
+        }
+        values[i] = last_read.as_ref().unwrap().1.clone();
+    }
+    Ok(values)
+}
+
+// Number of index records the cursor of `get_versioned_entries` steps over to reach the next key
+// before seeking again.
+const MAX_SKIPPED_INDEX_RECORDS: usize = 16;
+
+/// Batched [`get_versioned_entry`], returned in the order of `keys`. The keys are visited in the
+/// order of the index table, with one cursor advanced from key to key while the next one is
+/// close, and the change rows are then read in the order of their table.
+fn get_versioned_entries<'db, T: VersionedKeyValueSchema>(
+    query_version_number: HistoryNumber,
+    keys: &[T::Key],
+    history_index_table: &TableReader<'db, HistoryIndicesTable<T>>,
+    change_history_table: &KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+) -> Result<Vec<KeyLookup<T::Value>>> {
+    let mut order: Vec<(Vec<u8>, usize)> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let range_query_key = HistoryIndexKey(key.clone(), query_version_number);
+            (range_query_key.encode().into_owned(), i)
+        })
+        .collect();
+    order.sort();
+
+    let mut cursor: Option<TableIter<HistoryIndicesTable<T>>> = None;
+    let mut record: Option<(Vec<u8>, T::Key, HistoryNumber)> = None;
+    let mut found_versions = Vec::new();
+    for (target, i) in order {
+        let key = &keys[i];
+        let mut reached = false;
+        if let Some(iter) = cursor.as_mut() {
+            for _ in 0..=MAX_SKIPPED_INDEX_RECORDS {
+                match &record {
+                    Some((encoded, _, _)) if encoded < &target => {}
+                    // a record at or after the target, or the end of the table
+                    _ => {
+                        reached = true;
+                        break;
+                    }
+                }
+                record = next_index_record::<T>(iter)?;
+            }
+        }
+        if !reached {
+            let mut iter =
+                history_index_table.iter(&HistoryIndexKey(key.clone(), query_version_number))?;
+            record = next_index_record::<T>(&mut iter)?;
+            cursor = Some(iter);
+        }
+
+        if let Some((_, found_key, version)) = &record {
+            if found_key == key {
+                found_versions.push((*version, i));
+            }
+        }
+    }
+
+    let mut entries: Vec<_> = keys.iter().map(|_| KeyLookup::Unknown).collect();
+    found_versions.sort();
+    for (version, i) in found_versions {
+        entries[i] = match change_history_table.get_versioned_key(&version, &keys[i])? {
+            Some(value) => KeyLookup::Value(value),
+            None => KeyLookup::Tombstone,
+        };
+    }
+    Ok(entries)
+}
+
+// Reads the next index record as its encoded key, its key and the version it points to.
+fn next_index_record<T: VersionedKeyValueSchema>(
+    iter: &mut TableIter<HistoryIndicesTable<T>>,
+) -> Result<Option<(Vec<u8>, T::Key, HistoryNumber)>> {
+    match iter.next() {
+        None => Ok(None),
+        Some(Err(e)) => Err(e.into()),
+        Some(Ok((k, indices))) => {
+            let encoded = k.as_ref().encode().into_owned();
+            let HistoryIndexKey(key, history_number) = k.into_owned();
+            Ok(Some((encoded, key, indices.as_ref().last(history_number))))
+        }
+    }
 }
 
+/// Confirms the pending commits up to the parent of `new_root_commit_id` at once: the pending
+/// part is changed before `write_schema` is committed, so a failed commit loses the confirmed
+/// commits. See [`prepare_confirm`] to change the pending part only once the commit succeeds.
+///
+/// Returns the confirmed commits, empty if `new_root_commit_id` is already the pending root.
 pub fn confirmed_pending_to_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(
     db: &D,
     pending_part: &mut VersionedMap<PendingKeyValueConfig<T, CommitID>>,
     new_root_commit_id: CommitID,
     write_schema: &D::WriteSchema,
-) -> Result<()> {
-    let confirmed_path = pending_part.change_root(new_root_commit_id)?;
+) -> Result<ConfirmedPath> {
+    let ticket = prepare_confirm::<D, T>(db, pending_part, new_root_commit_id, write_schema)?;
+    finalize_confirm::<T>(pending_part, ticket)
+}
+
+/// The commits moved from the pending part to the history by a confirmation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmedPath {
+    /// The height of the first confirmed commit, i.e. of the previous pending root.
+    pub start_height: usize,
+    /// The confirmed commits, from `start_height` up to the parent of the new pending root.
+    pub commit_ids: Vec<CommitID>,
+    /// The number of keys changed by each of `commit_ids`.
+    pub num_keys_per_commit: Vec<usize>,
+}
+
+/// A confirmation written by [`prepare_confirm`], to be applied to the pending part by
+/// [`finalize_confirm`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmTicket {
+    new_root_commit_id: CommitID,
+    start_height: usize,
+    commit_ids: Vec<CommitID>,
+    num_keys_per_commit: Vec<usize>,
+    // reported to the metrics of the pending part once finalized
+    duration: Duration,
+}
+
+/// Writes the pending commits up to the parent of `new_root_commit_id` to the history in
+/// `write_schema`, with their aliases, leaving the pending part unchanged.
+///
+/// Once `write_schema` is committed, the returned ticket is passed to [`finalize_confirm`]. If the
+/// commit fails, the ticket is dropped: the pending part still holds the commits, and the
+/// confirmation can be prepared again.
+pub fn prepare_confirm<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    pending_part: &VersionedMap<PendingKeyValueConfig<T, CommitID>>,
+    new_root_commit_id: CommitID,
+    write_schema: &D::WriteSchema,
+) -> Result<ConfirmTicket> {
+    ConfirmationCursor::<D, T>::new(db)?.prepare(pending_part, new_root_commit_id, write_schema)
+}
 
-    confirm_ids_to_history::<D>(
+/// Removes the commits written by [`prepare_confirm`] from the pending part, once their write
+/// schema is committed. Fails with [`StorageError::ConsistencyCheckFailure`], leaving the pending
+/// part unchanged, if the pending part no longer holds the same path to confirm.
+pub fn finalize_confirm<T: VersionedKeyValueSchema>(
+    pending_part: &mut VersionedMap<PendingKeyValueConfig<T, CommitID>>,
+    ticket: ConfirmTicket,
+) -> Result<ConfirmedPath> {
+    let (start_height, commit_ids) =
+        pending_part.get_confirmed_commit_ids(ticket.new_root_commit_id)?;
+    if start_height != ticket.start_height || commit_ids != ticket.commit_ids {
+        return Err(StorageError::ConsistencyCheckFailure);
+    }
+
+    pending_part.change_root_to_commit_ids(ticket.new_root_commit_id)?;
+    if !ticket.commit_ids.is_empty() {
+        pending_part.metrics().on_confirm(
+            ticket.start_height + ticket.commit_ids.len() - 1,
+            ticket.num_keys_per_commit.iter().sum(),
+            ticket.duration,
+        );
+    }
+    Ok(ConfirmedPath {
+        start_height: ticket.start_height,
+        commit_ids: ticket.commit_ids,
+        num_keys_per_commit: ticket.num_keys_per_commit,
+    })
+}
+
+pub fn confirm_maps_to_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    to_confirm_start_height: usize,
+    to_confirm_maps: Vec<BTreeMap<T::Key, impl Into<Option<T::Value>>>>,
+    write_schema: &D::WriteSchema,
+) -> Result<()> {
+    confirm_maps::<D, T>(
         db,
-        confirmed_path.start_height,
-        &confirmed_path.commit_ids,
+        to_confirm_start_height,
+        to_confirm_maps,
         write_schema,
-    )?;
+        None,
+    )
+}
 
-    confirm_maps_to_history::<D, T>(
+/// Like [`confirm_maps_to_history`], and also returns the changes of the confirmed heights
+/// grouped by key prefix. Reads the history once for each confirmed key.
+pub fn confirm_maps_to_history_with_stats<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    to_confirm_start_height: usize,
+    to_confirm_maps: Vec<BTreeMap<T::Key, impl Into<Option<T::Value>>>>,
+    write_schema: &D::WriteSchema,
+    config: &PrefixStatsConfig,
+) -> Result<PrefixStats> {
+    let mut collector = PrefixStatsCollector::new(config);
+    confirm_maps::<D, T>(
         db,
-        confirmed_path.start_height,
-        confirmed_path.key_value_maps,
+        to_confirm_start_height,
+        to_confirm_maps,
         write_schema,
+        Some(&mut collector),
     )?;
-
-    Ok(())
+    Ok(collector.finish())
 }
 
-pub fn confirm_maps_to_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+fn confirm_maps<D: DatabaseTrait, T: VersionedKeyValueSchema>(
     db: &D,
     to_confirm_start_height: usize,
     to_confirm_maps: Vec<BTreeMap<T::Key, impl Into<Option<T::Value>>>>,
     write_schema: &D::WriteSchema,
+    stats: Option<&mut PrefixStatsCollector<T>>,
 ) -> Result<()> {
-    let history_index_table = db.view::<HistoryIndicesTable<T>>()?;
+    let history_index_table: TableReader<HistoryIndicesTable<T>> =
+        Arc::new(db.view::<HistoryIndicesTable<T>>()?);
     let change_history_table =
         KeyValueStoreBulks::new(Arc::new(db.view::<HistoryChangeTable<T>>()?));
 
+    write_maps::<D, T>(
+        &history_index_table,
+        &change_history_table,
+        to_confirm_start_height,
+        to_confirm_maps,
+        write_schema,
+        stats,
+    )
+}
+
+fn write_maps<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    history_index_table: &TableReader<HistoryIndicesTable<T>>,
+    change_history_table: &KeyValueStoreBulks<HistoryChangeTable<T>>,
+    to_confirm_start_height: usize,
+    to_confirm_maps: Vec<BTreeMap<T::Key, impl Into<Option<T::Value>>>>,
+    write_schema: &D::WriteSchema,
+    mut stats: Option<&mut PrefixStatsCollector<T>>,
+) -> Result<()> {
     for (delta_height, updates) in to_confirm_maps.into_iter().enumerate() {
-        let height = to_confirm_start_height + delta_height;
-        let history_number = height_to_history_number(height);
+        let height = to_confirm_start_height
+            .checked_add(delta_height)
+            .ok_or(StorageError::HeightOverflow)?;
+        let history_number = checked_height_to_history_number(height)?;
 
         let history_indices_table_op = updates.keys().map(|key| {
             (
@@ -183,11 +1001,19 @@ pub fn confirm_maps_to_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(
         });
         write_schema.write_batch::<HistoryIndicesTable<T>>(history_indices_table_op);
 
-        change_history_table.commit(
-            history_number,
-            updates.into_iter().map(|(key, value)| (key, value.into())),
-            &write_schema,
-        )?;
+        let updates = updates.into_iter().map(|(key, value)| (key, value.into()));
+        if let Some(stats) = stats.as_mut() {
+            let updates: Vec<_> = updates.collect();
+            stats.record(
+                history_number,
+                &updates,
+                history_index_table,
+                change_history_table,
+            )?;
+            change_history_table.commit(history_number, updates.into_iter(), &write_schema)?;
+        } else {
+            change_history_table.commit(history_number, updates, &write_schema)?;
+        }
     }
 
     Ok(())
@@ -199,17 +1025,67 @@ pub fn confirm_ids_to_history<D: DatabaseTrait>(
     to_confirm_ids: &[CommitID],
     write_schema: &D::WriteSchema,
 ) -> Result<()> {
-    let commit_id_table = db.view::<CommitIDSchema>()?;
-    let history_number_table = db.view::<HistoryNumberSchema>()?;
+    write_ids::<D>(
+        &db.view::<CommitIDSchema>()?,
+        &db.view::<HistoryNumberSchema>()?,
+        to_confirm_start_height,
+        to_confirm_ids,
+        write_schema,
+    )
+}
 
+/// Like [`confirm_ids_to_history`], and records `to_confirm_metadata[i]` for
+/// `to_confirm_ids[i]`, read back by [`VersionedStore::get_commit_metadata`].
+///
+/// # Panics
+///
+/// Panics if the two slices differ in length.
+pub fn confirm_ids_with_metadata<D: DatabaseTrait>(
+    db: &D,
+    to_confirm_start_height: usize,
+    to_confirm_ids: &[CommitID],
+    to_confirm_metadata: &[CommitMetadata],
+    write_schema: &D::WriteSchema,
+) -> Result<()> {
+    assert_eq!(to_confirm_ids.len(), to_confirm_metadata.len());
+    confirm_ids_to_history::<D>(db, to_confirm_start_height, to_confirm_ids, write_schema)?;
+
+    for (delta_height, metadata) in to_confirm_metadata.iter().enumerate() {
+        // the heights were checked by `confirm_ids_to_history`
+        let history_number = height_to_history_number(to_confirm_start_height + delta_height);
+        write_schema.write::<CommitMetadataSchema>((
+            Cow::Owned(history_number),
+            Some(Cow::Borrowed(metadata)),
+        ));
+    }
+
+    Ok(())
+}
+
+fn write_ids<D: DatabaseTrait>(
+    commit_id_table: &impl TableRead<CommitIDSchema>,
+    history_number_table: &impl TableRead<HistoryNumberSchema>,
+    to_confirm_start_height: usize,
+    to_confirm_ids: &[CommitID],
+    write_schema: &D::WriteSchema,
+) -> Result<()> {
     for (delta_height, confirmed_commit_id) in to_confirm_ids.iter().enumerate() {
-        let height = to_confirm_start_height + delta_height;
-        let history_number = height_to_history_number(height);
+        let height = to_confirm_start_height
+            .checked_add(delta_height)
+            .ok_or(StorageError::HeightOverflow)?;
+        let history_number = checked_height_to_history_number(height)?;
 
-        if commit_id_table.get(confirmed_commit_id)?.is_some()
-            || history_number_table.get(&history_number)?.is_some()
-        {
-            return Err(StorageError::ConsistencyCheckFailure);
+        if commit_id_table.get(confirmed_commit_id)?.is_some() {
+            return Err(StorageError::ConsistencyCheckFailure.with_context(
+                ErrorContext::new::<CommitIDSchema>(confirmed_commit_id, Some(history_number))
+                    .with_reason(InconsistencyReason::AlreadyConfirmed),
+            ));
+        }
+        if history_number_table.get(&history_number)?.is_some() {
+            return Err(StorageError::ConsistencyCheckFailure.with_context(
+                ErrorContext::new::<HistoryNumberSchema>(&history_number, Some(history_number))
+                    .with_reason(InconsistencyReason::AlreadyConfirmed),
+            ));
         }
 
         let commit_id_table_op = (
@@ -227,3 +1103,160 @@ pub fn confirm_ids_to_history<D: DatabaseTrait>(
 
     Ok(())
 }
+
+/// Rebuilds the pending part written by [`VersionedMap::dump`] on top of the latest confirmed
+/// commit of `db`, e.g. after a restart. Fails with [`StorageError::InvalidBackup`] if the dump
+/// does not follow it, e.g. because commits were confirmed after the dump.
+pub fn restore_pending_part<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    reader: impl Read,
+) -> Result<VersionedStoreCache<T>> {
+    let (parent_of_root, height_of_root) = match latest_confirmed(db)? {
+        Some((history_number, commit)) => (
+            Some(commit),
+            checked_history_number_to_height(history_number)?
+                .checked_add(1)
+                .ok_or(StorageError::HeightOverflow)?,
+        ),
+        None => (None, 0),
+    };
+    VersionedMap::restore(reader, parent_of_root, height_of_root)
+}
+
+/// Removes the history only needed to read the commits below `cutoff_height`, the records
+/// [`RetentionPolicy::PruneBeforeHeight`] estimates as reclaimable.
+///
+/// For each key, the versions above the cutoff and the latest version at or below it are kept,
+/// so reads at the commits from `cutoff_height` on are unchanged. The commits below the cutoff
+/// are forgotten with their aliases, and dropped from the history numbers cached in
+/// `pending_part`: reading them fails with [`StorageError::CommitIDNotFound`].
+pub fn prune_history_before<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    pending_part: &mut VersionedStoreCache<T>,
+    cutoff_height: usize,
+    write_schema: &D::WriteSchema,
+) -> Result<()> {
+    let cutoff_history_number = checked_height_to_history_number(cutoff_height)?;
+    let history_number_table = db.view::<HistoryNumberSchema>()?;
+    if history_number_table.get(&cutoff_history_number)?.is_none() {
+        return Err(StorageError::HeightNotConfirmed(cutoff_height));
+    }
+
+    let history_index_table = db.view::<HistoryIndicesTable<T>>()?;
+    // the key of the previous index record, and whether a version at or below the cutoff is kept
+    let mut current: Option<(T::Key, bool)> = None;
+    for item in history_index_table.iter_from_start()? {
+        let (k_with_history_number, _) = item?;
+        let HistoryIndexKey(key, history_number) = k_with_history_number.into_owned();
+
+        if !matches!(&current, Some((current_key, _)) if *current_key == key) {
+            current = Some((key.clone(), false));
+        }
+        if history_number > cutoff_history_number {
+            continue;
+        }
+        let kept = &mut current.as_mut().unwrap().1;
+        if !*kept {
+            // the version read at the cutoff
+            *kept = true;
+            continue;
+        }
+
+        write_schema.write::<HistoryChangeTable<T>>((
+            Cow::Owned(ChangeKey::new(history_number, key.clone())),
+            None,
+        ));
+        write_schema.write::<HistoryIndicesTable<T>>((
+            Cow::Owned(HistoryIndexKey(key, history_number)),
+            None,
+        ));
+    }
+
+    let mut removed_commits = HashSet::new();
+    for item in history_number_table.iter_from_start()? {
+        let (history_number, commit_id) = item?;
+        if *history_number >= cutoff_history_number {
+            break;
+        }
+        let commit_id = commit_id.into_owned();
+        write_schema.write::<CommitIDSchema>((Cow::Owned(commit_id), None));
+        write_schema.write::<CommitMetadataSchema>((history_number.clone(), None));
+        write_schema.write::<HistoryNumberSchema>((history_number, None));
+        removed_commits.insert(commit_id);
+    }
+    pending_part.uncache_history_numbers_below(cutoff_history_number);
+    pending_part.unstage_aliases_of(&removed_commits);
+
+    delete_aliases(db, &removed_commits, write_schema)
+}
+
+/// Compacts the history index and change tables of `T`, e.g. once the history pruned by
+/// [`prune_history_before`] is committed. See [`DatabaseTrait::compact`].
+pub fn compact_history<D: DatabaseTrait, T: VersionedKeyValueSchema>(db: &D) -> Result<()> {
+    db.compact::<HistoryIndicesTable<T>>()?;
+    db.compact::<HistoryChangeTable<T>>()
+}
+
+/// Removes the confirmed commits above `target_commit` with their changes and empties
+/// `pending_part`, so that the next pending root is a child of `target_commit`. A branch
+/// confirmed by mistake can then be replaced by another one, e.g. after a deep reorg.
+///
+/// The pending commits are dropped, and so are the aliases of the removed commits. Fails with
+/// [`StorageError::CommitIDNotFound`] if `target_commit` is not confirmed.
+pub fn rollback_history_to<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &mut D,
+    pending_part: &mut VersionedStoreCache<T>,
+    target_commit: CommitID,
+) -> Result<()> {
+    let target_history_number = db
+        .view::<CommitIDSchema>()?
+        .get(&target_commit)?
+        .ok_or(StorageError::CommitIDNotFound)?
+        .into_owned();
+
+    let write_schema = D::write_schema();
+    {
+        // The change table cannot drive this: a deletion has an index record but no change
+        // record. The versions of a key are indexed from the latest one, so only the versions
+        // above the target are read, and the older ones of each key are passed by one seek.
+        let history_index_table = db.view::<HistoryIndicesTable<T>>()?;
+        let mut iter = history_index_table.iter_from_start()?;
+        while let Some(item) = iter.next() {
+            let (k_with_history_number, _) = item?;
+            let HistoryIndexKey(key, history_number) = k_with_history_number.into_owned();
+            if history_number <= target_history_number {
+                // no history number is 0, so this seeks the first version of the next key
+                iter = history_index_table.iter(&HistoryIndexKey(key, 0))?;
+                continue;
+            }
+
+            write_schema.write::<HistoryChangeTable<T>>((
+                Cow::Owned(ChangeKey::new(history_number, key.clone())),
+                None,
+            ));
+            write_schema.write::<HistoryIndicesTable<T>>((
+                Cow::Owned(HistoryIndexKey(key, history_number)),
+                None,
+            ));
+        }
+
+        let history_number_table = db.view::<HistoryNumberSchema>()?;
+        let mut removed_commits = HashSet::new();
+        for item in history_number_table.iter(&(target_history_number + 1))? {
+            let (history_number, commit_id) = item?;
+            let commit_id = commit_id.into_owned();
+            write_schema.write::<CommitIDSchema>((Cow::Owned(commit_id), None));
+            write_schema.write::<CommitMetadataSchema>((history_number.clone(), None));
+            write_schema.write::<HistoryNumberSchema>((history_number, None));
+            removed_commits.insert(commit_id);
+        }
+        delete_aliases(&*db, &removed_commits, &write_schema)?;
+    }
+    db.commit(write_schema)?;
+
+    pending_part.reset_root(
+        Some(target_commit),
+        history_number_to_height(target_history_number) + 1,
+    );
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use super::{table_schema::VersionedKeyValueSchema, AddOutcome, VersionedStore};
+use crate::{backends::TableRead, errors::Result, middlewares::CommitID, StorageError};
+
+/// Pending commits staged by [`VersionedStore::begin_pending_batch`].
+///
+/// Nothing reaches the pending part until [`PendingBatch::commit`], which adds either all the
+/// staged commits or none of them. Dropping the batch is the same as [`PendingBatch::abort`].
+pub struct PendingBatch<'a, 'cache, 'db, T: VersionedKeyValueSchema> {
+    store: &'a mut VersionedStore<'cache, 'db, T>,
+    #[allow(clippy::type_complexity)]
+    nodes: Vec<(
+        CommitID,
+        Option<CommitID>,
+        BTreeMap<T::Key, Option<T::Value>>,
+    )>,
+}
+
+impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
+    pub fn begin_pending_batch(&mut self) -> PendingBatch<'_, 'cache, 'db, T> {
+        PendingBatch {
+            store: self,
+            nodes: Vec::new(),
+        }
+    }
+}
+
+impl<'a, 'cache, 'db, T: VersionedKeyValueSchema> PendingBatch<'a, 'cache, 'db, T> {
+    /// Stages a commit, as [`VersionedStore::add_to_pending_part`] would add it. The parent can
+    /// be a pending commit or one staged earlier in the batch.
+    pub fn add(
+        &mut self,
+        parent_commit: Option<CommitID>,
+        commit: CommitID,
+        updates: BTreeMap<T::Key, Option<T::Value>>,
+    ) {
+        self.nodes.push((commit, parent_commit, updates));
+    }
+
+    /// Adds the staged commits in order. On error, the pending part is left as it was.
+    ///
+    /// Returns the outcome of each staged commit, in order, once the whole batch is added.
+    pub fn commit(self) -> Result<Vec<AddOutcome>> {
+        for (commit, _, _) in &self.nodes {
+            if self.store.tables.commit_id_table.get(commit)?.is_some() {
+                return Err(StorageError::CommitIdAlreadyExistsInHistory);
+            }
+        }
+
+        let commits: Vec<_> = self.nodes.iter().map(|(commit, _, _)| *commit).collect();
+        self.store.pending_part.add_nodes(self.nodes)?;
+
+        commits
+            .into_iter()
+            .map(|commit| {
+                Ok(AddOutcome {
+                    confirm_to_restore_limit: self
+                        .store
+                        .pending_part
+                        .get_pending_root_to_confirm(commit)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops the staged commits.
+    pub fn abort(self) {}
+}
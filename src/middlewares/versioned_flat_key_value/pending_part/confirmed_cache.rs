@@ -0,0 +1,55 @@
+use std::collections::VecDeque;
+
+use crate::middlewares::HistoryNumber;
+
+/// Capacity of the cache of history numbers kept by `VersionedMap`.
+pub const CONFIRMED_CACHE_CAPACITY: usize = 64;
+
+/// History numbers of recently read confirmed commits.
+///
+/// Reads concentrate on a few recent commits, so a small cache evicting the
+/// oldest insertion avoids most history number lookups in the database.
+pub struct ConfirmedCache<C> {
+    entries: VecDeque<(C, HistoryNumber)>,
+    capacity: usize,
+}
+
+impl<C: Eq + Copy> ConfirmedCache<C> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn get(&self, commit_id: &C) -> Option<HistoryNumber> {
+        self.entries
+            .iter()
+            .find(|(cached, _)| cached == commit_id)
+            .map(|(_, history_number)| *history_number)
+    }
+
+    pub fn insert(&mut self, commit_id: C, history_number: HistoryNumber) {
+        if self.capacity == 0 || self.get(&commit_id).is_some() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((commit_id, history_number));
+    }
+
+    /// Drops the commits whose history number is below `history_number`.
+    pub fn remove_below(&mut self, history_number: HistoryNumber) {
+        self.entries.retain(|(_, cached)| *cached >= history_number);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
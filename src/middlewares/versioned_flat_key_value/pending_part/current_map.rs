@@ -10,6 +10,15 @@ pub(super) struct CurrentMap<S: PendingKeyValueSchema> {
     commit_id: S::CommitId,
 }
 
+impl<S: PendingKeyValueSchema> Clone for CurrentMap<S> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            commit_id: self.commit_id,
+        }
+    }
+}
+
 impl<S: PendingKeyValueSchema> Deref for CurrentMap<S> {
     type Target = BTreeMap<S::Key, ApplyRecord<S>>;
 
@@ -53,8 +62,10 @@ impl<S: PendingKeyValueSchema> CurrentMap<S> {
         }
     }
 
+    /// Keeps the map after the pending root moves to an ancestor of its commit, dropping the
+    /// records of the confirmed commits so that their keys are read from the history.
     pub fn update_rerooted(&mut self, tree: &Tree<S>) {
         self.map
-            .retain(|_, ApplyRecord { commit_id, .. }| tree.contains_commit_id(commit_id));
+            .retain(|_, ApplyRecord { commit_id, .. }| tree.is_in_tree(commit_id));
     }
 }
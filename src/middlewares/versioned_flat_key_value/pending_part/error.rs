@@ -12,4 +12,33 @@ pub enum PendingError<CommitId: Debug + Eq + Hash> {
     CommitIdAlreadyExists(CommitId),
     #[error("non_root node should have parent")]
     NonRootNodeShouldHaveParent,
+    #[error("pending depth {depth} exceeds the limit {limit}")]
+    MaxDepthExceeded { depth: usize, limit: usize },
+    #[error("pending height overflows")]
+    HeightOverflow,
+    #[error("the pending root cannot be pruned")]
+    CannotPrunePendingRoot(CommitId),
+}
+
+impl<CommitId: Debug + Eq + Hash> PendingError<CommitId> {
+    pub fn map_commit_id<C: Debug + Eq + Hash>(
+        self,
+        f: impl FnOnce(CommitId) -> C,
+    ) -> PendingError<C> {
+        match self {
+            Self::CommitIDNotFound(commit_id) => PendingError::CommitIDNotFound(f(commit_id)),
+            Self::MultipleRootsNotAllowed => PendingError::MultipleRootsNotAllowed,
+            Self::CommitIdAlreadyExists(commit_id) => {
+                PendingError::CommitIdAlreadyExists(f(commit_id))
+            }
+            Self::NonRootNodeShouldHaveParent => PendingError::NonRootNodeShouldHaveParent,
+            Self::MaxDepthExceeded { depth, limit } => {
+                PendingError::MaxDepthExceeded { depth, limit }
+            }
+            Self::HeightOverflow => PendingError::HeightOverflow,
+            Self::CannotPrunePendingRoot(commit_id) => {
+                PendingError::CannotPrunePendingRoot(f(commit_id))
+            }
+        }
+    }
 }
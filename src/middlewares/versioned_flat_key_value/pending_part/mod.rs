@@ -1,3 +1,4 @@
+mod confirmed_cache;
 mod current_map;
 pub mod error;
 pub mod pending_schema;
@@ -5,4 +6,4 @@ mod tree;
 pub mod versioned_map;
 
 pub use error::PendingError;
-pub use versioned_map::VersionedMap;
+pub use versioned_map::{MemoryStats, VersionedMap};
@@ -1,5 +1,6 @@
 use std::{collections::BTreeMap, fmt::Debug, hash::Hash, marker::PhantomData};
 
+use crate::backends::serde::Encode;
 use crate::middlewares::versioned_flat_key_value::table_schema::VersionedKeyValueSchema;
 use crate::types::ValueEntry;
 
@@ -9,6 +10,12 @@ pub trait PendingKeyValueSchema {
     type Key: Eq + Hash + Clone + Ord;
     type CommitId: Debug + Eq + Hash + Copy;
     type Value: Clone;
+
+    /// Whether writing `new` over `old` is dropped from a pending commit, see
+    /// [`VersionedKeyValueSchema::DEDUP_IDENTICAL_WRITES`].
+    fn is_identical_write(_old: &ValueEntry<Self::Value>, _new: &ValueEntry<Self::Value>) -> bool {
+        false
+    }
 }
 
 type Key<S> = <S as PendingKeyValueSchema>::Key;
@@ -20,15 +27,38 @@ pub struct RecoverRecord<S: PendingKeyValueSchema> {
     pub last_commit_id: Option<S::CommitId>,
 }
 
+impl<S: PendingKeyValueSchema> Clone for RecoverRecord<S> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            last_commit_id: self.last_commit_id,
+        }
+    }
+}
+
 pub struct ApplyRecord<S: PendingKeyValueSchema> {
     pub value: ValueEntry<S::Value>,
     pub commit_id: S::CommitId,
 }
 
-/// `commit_ids` and `key_value_maps` should be ordered from the smallest height to the largest height.
+impl<S: PendingKeyValueSchema> Clone for ApplyRecord<S> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            commit_id: self.commit_id,
+        }
+    }
+}
+
+/// The commits confirmed by `VersionedMap::change_root`, with their changes. `commit_ids` and
+/// `key_value_maps` are ordered from the smallest height to the largest height.
 pub struct ConfirmedPathInfo<S: PendingKeyValueSchema> {
+    /// The height of the first commit, i.e. of the previous pending root.
     pub start_height: usize,
+    /// The commits from `start_height` up to the parent of the new pending root, empty if the
+    /// pending root is unchanged.
     pub commit_ids: Vec<S::CommitId>,
+    /// The changes of each of `commit_ids` to its parent.
     pub key_value_maps: Vec<KeyValueMap<S>>,
 }
 
@@ -61,4 +91,13 @@ where
     type Key = T::Key;
     type CommitId = CId;
     type Value = T::Value;
+
+    fn is_identical_write(old: &ValueEntry<Self::Value>, new: &ValueEntry<Self::Value>) -> bool {
+        T::DEDUP_IDENTICAL_WRITES
+            && match (old, new) {
+                (ValueEntry::Value(old), ValueEntry::Value(new)) => old.encode() == new.encode(),
+                (ValueEntry::Deleted, ValueEntry::Deleted) => true,
+                _ => false,
+            }
+    }
 }
@@ -1,18 +1,19 @@
 use crate::middlewares::{
     versioned_flat_key_value::pending_part::pending_schema::{
-        PendingKeyValueSchema, RecoverMap, Result as PendResult,
+        PendingKeyValueSchema, Result as PendResult,
     },
     PendingError,
 };
 
-use super::{node::TreeNode, Tree};
+use super::{arena::Modification, node::TreeNode, Tree};
 
 // methods to support VersionedMap::add_node()
 impl<S: PendingKeyValueSchema> Tree<S> {
     pub fn add_root(
         &mut self,
         commit_id: S::CommitId,
-        modifications: RecoverMap<S>,
+        modifications: impl IntoIterator<Item = Modification<S>>,
+        keep: impl FnMut(&Modification<S>) -> bool,
     ) -> PendResult<(), S> {
         // return error if there is root
         if self.has_root() {
@@ -21,6 +22,7 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         // PendingError::CommitIdAlreadyExists(_) cannot happend because no root <=> no node
 
         // new root
+        let modifications = self.arena.push(modifications, keep);
         let root = TreeNode::new_root(commit_id, modifications, self.height_of_root);
 
         // add root to tree
@@ -34,24 +36,27 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         &mut self,
         commit_id: S::CommitId,
         parent_commit_id: S::CommitId,
-        modifications: RecoverMap<S>,
+        modifications: impl IntoIterator<Item = Modification<S>>,
+        keep: impl FnMut(&Modification<S>) -> bool,
     ) -> PendResult<(), S> {
         // return error if parent_commit_id does not exist
         let parent_slab_index = self.get_slab_index_by_commit_id(parent_commit_id)?;
 
-        // return error if commit_id exists
-        if self.contains_commit_id(&commit_id) {
+        // return error if commit_id exists, even unaddressable
+        if self.is_in_tree(&commit_id) {
             return Err(PendingError::CommitIdAlreadyExists(commit_id));
         }
 
-        // new node
+        // return error if the new node is too deep
         let parent_height = self.get_node_by_slab_index(parent_slab_index).get_height();
-        let node = TreeNode::new_non_root_node(
-            commit_id,
-            parent_slab_index,
-            parent_height + 1,
-            modifications,
-        );
+        let height = parent_height
+            .checked_add(1)
+            .ok_or(PendingError::HeightOverflow)?;
+        self.check_depth(height)?;
+
+        // new node
+        let modifications = self.arena.push(modifications, keep);
+        let node = TreeNode::new_non_root_node(commit_id, parent_slab_index, height, modifications);
 
         // add node to tree
         let slab_index = self.nodes.insert(node);
@@ -60,4 +65,19 @@ impl<S: PendingKeyValueSchema> Tree<S> {
 
         Ok(())
     }
+
+    // undoes `add_root` or `add_non_root_node` of a node that has no children yet
+    pub fn remove_leaf(&mut self, commit_id: S::CommitId) -> PendResult<(), S> {
+        let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        let node = self.get_node_by_slab_index(slab_index);
+        assert!(node.get_children().is_empty());
+
+        if let Some(parent_slab_index) = node.get_parent() {
+            self.get_node_mut_by_slab_index(parent_slab_index)
+                .remove_child(&slab_index);
+        }
+        self.detach_node(slab_index);
+
+        Ok(())
+    }
 }
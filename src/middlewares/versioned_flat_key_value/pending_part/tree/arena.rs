@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use crate::middlewares::versioned_flat_key_value::pending_part::pending_schema::{
+    PendingKeyValueSchema, RecoverRecord,
+};
+
+pub(super) type Modification<S> = (<S as PendingKeyValueSchema>::Key, RecoverRecord<S>);
+
+// The modifications of all the nodes of a tree, back to back in one buffer, so that adding a
+// node does not allocate a map of its own. A node holds the range of its modifications, sorted
+// by key. The ranges of removed nodes are garbage until the next `compact`.
+pub(super) struct ModificationArena<S: PendingKeyValueSchema> {
+    entries: Vec<Modification<S>>,
+    num_garbage: usize,
+}
+
+impl<S: PendingKeyValueSchema> ModificationArena<S> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            num_garbage: 0,
+        }
+    }
+
+    // Appends the modifications of a node, sorted by key, and returns their range. Of several
+    // modifications of the same key only the last one counts, and it is dropped unless `keep`
+    // accepts it.
+    pub fn push(
+        &mut self,
+        modifications: impl IntoIterator<Item = Modification<S>>,
+        mut keep: impl FnMut(&Modification<S>) -> bool,
+    ) -> Range<usize> {
+        let start = self.entries.len();
+        self.entries.extend(modifications);
+        let added = &mut self.entries[start..];
+        // sorting allocates, so the modifications of a map, already sorted, are not sorted
+        // again; the sort is stable, so the last modification of a key stays the last one
+        if !added.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            added.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+
+        let mut end = start;
+        for i in start..self.entries.len() {
+            let is_last = i + 1 == self.entries.len() || self.entries[i + 1].0 != self.entries[i].0;
+            if is_last && keep(&self.entries[i]) {
+                self.entries.swap(end, i);
+                end += 1;
+            }
+        }
+        self.entries.truncate(end);
+        start..end
+    }
+
+    pub fn get(&self, range: &Range<usize>) -> &[Modification<S>] {
+        &self.entries[range.clone()]
+    }
+
+    pub fn get_mut(&mut self, range: &Range<usize>) -> &mut [Modification<S>] {
+        &mut self.entries[range.clone()]
+    }
+
+    pub fn find(&self, range: &Range<usize>, key: &S::Key) -> Option<&RecoverRecord<S>> {
+        let modifications = self.get(range);
+        modifications
+            .binary_search_by(|(k, _)| k.cmp(key))
+            .ok()
+            .map(|i| &modifications[i].1)
+    }
+
+    pub fn release(&mut self, range: &Range<usize>) {
+        self.num_garbage += range.len();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(test)]
+    pub fn num_garbage(&self) -> usize {
+        self.num_garbage
+    }
+
+    // Moves the modifications of the live nodes to a new buffer once they are less than half
+    // of the current one, and updates their ranges.
+    pub fn compact<'a>(&mut self, live_ranges: impl Iterator<Item = &'a mut Range<usize>>) {
+        if self.num_garbage * 2 <= self.entries.len() {
+            return;
+        }
+        self.reclaim(live_ranges);
+    }
+
+    // Moves the modifications of the live nodes to a new buffer, however few are garbage.
+    pub fn reclaim<'a>(&mut self, live_ranges: impl Iterator<Item = &'a mut Range<usize>>) {
+        if self.num_garbage == 0 {
+            return;
+        }
+
+        let mut live_ranges: Vec<_> = live_ranges.collect();
+        // an empty range sorts before a range starting at the same position
+        live_ranges.sort_by_key(|range| (range.start, range.end));
+
+        let num_live = self.entries.len() - self.num_garbage;
+        let mut entries = Vec::with_capacity(num_live);
+        let mut old_entries = std::mem::take(&mut self.entries).into_iter();
+        let mut position = 0;
+        for range in live_ranges {
+            let start = entries.len();
+            entries.extend(
+                old_entries
+                    .by_ref()
+                    .skip(range.start - position)
+                    .take(range.len()),
+            );
+            position = range.end;
+            *range = start..entries.len();
+        }
+        assert_eq!(entries.len(), num_live);
+
+        self.entries = entries;
+        self.num_garbage = 0;
+    }
+}
+
+#[cfg(all(test, feature = "count-allocations"))]
+mod tests {
+    use super::*;
+    use crate::{
+        backends::VersionedKVName,
+        middlewares::versioned_flat_key_value::{
+            pending_part::pending_schema::{PendingKeyValueConfig, RecoverMap},
+            table_schema::VersionedKeyValueSchema,
+        },
+        types::ValueEntry,
+        utils::allocations::allocations,
+    };
+
+    #[derive(Clone, Copy)]
+    struct TestSchema;
+
+    impl VersionedKeyValueSchema for TestSchema {
+        const NAME: VersionedKVName = VersionedKVName::FlatKV;
+        type Key = u64;
+        type Value = u64;
+    }
+
+    type TestPendingConfig = PendingKeyValueConfig<TestSchema, u64>;
+
+    // run with `cargo test --release --features count-allocations -- test_arena_allocations`
+    #[test]
+    fn test_arena_allocations() {
+        const NUM_COMMITS: u64 = 5000;
+        const NUM_KEYS: u64 = 1024;
+        const KEYS_PER_COMMIT: u64 = 64;
+
+        // a chain of commits, each writing keys of a fixed set in order, as a map yields them
+        let commits: Vec<Vec<Modification<TestPendingConfig>>> = (0..NUM_COMMITS)
+            .map(|commit_id| {
+                (0..KEYS_PER_COMMIT)
+                    .map(|i| {
+                        let key = (commit_id * 7 + i * (NUM_KEYS / KEYS_PER_COMMIT)) % NUM_KEYS;
+                        let record = RecoverRecord {
+                            value: ValueEntry::Value(commit_id),
+                            last_commit_id: commit_id.checked_sub(1),
+                        };
+                        (key, record)
+                    })
+                    .collect::<RecoverMap<TestPendingConfig>>()
+                    .into_iter()
+                    .collect()
+            })
+            .collect();
+
+        // a map per commit, as the modifications were held before the arena
+        let before = allocations();
+        let maps: Vec<RecoverMap<TestPendingConfig>> = commits
+            .iter()
+            .map(|modifications| modifications.iter().cloned().collect())
+            .collect();
+        let map_allocations = allocations() - before;
+        drop(maps);
+
+        let before = allocations();
+        let mut arena = ModificationArena::<TestPendingConfig>::new();
+        let ranges: Vec<_> = commits
+            .iter()
+            .map(|modifications| arena.push(modifications.iter().cloned(), |_| true))
+            .collect();
+        let arena_allocations = allocations() - before;
+
+        assert_eq!(arena.len(), (NUM_COMMITS * KEYS_PER_COMMIT) as usize);
+        for (range, modifications) in ranges.iter().zip(&commits) {
+            assert_eq!(arena.get(range).len(), modifications.len());
+        }
+        assert!(
+            arena_allocations * 10 <= map_allocations,
+            "{arena_allocations} allocations in the arena, {map_allocations} in maps"
+        );
+    }
+}
@@ -1,54 +1,96 @@
 use std::collections::VecDeque;
 
 use crate::middlewares::versioned_flat_key_value::pending_part::pending_schema::{
-    ConfirmedPathInfo, KeyValueMap, PendingKeyValueSchema, Result as PendResult,
+    CommitIdVec, ConfirmedPathInfo, KeyValueMap, PendingKeyValueSchema, Result as PendResult,
 };
 
 use super::{SlabIndex, Tree};
 
 // methods to support VersionedMap::change_root()
 impl<S: PendingKeyValueSchema> Tree<S> {
-    pub fn change_root(&mut self, commit_id: S::CommitId) -> PendResult<ConfirmedPathInfo<S>, S> {
+    // returns the height of the old root and the commits from it up to the parent of the new root
+    pub fn change_root(
+        &mut self,
+        commit_id: S::CommitId,
+    ) -> PendResult<(usize, CommitIdVec<S>), S> {
         let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
 
         // old_root..=new_root's parent
-        let to_commit = self.find_path(slab_index);
+        let to_commit = self.find_path_commit_ids(slab_index);
 
         if let Some(last) = to_commit.last() {
-            for (ancester, _) in to_commit.iter() {
-                self.discard(*ancester)?;
+            // the ancestors may be unaddressable
+            let ancesters: Vec<_> = to_commit
+                .iter()
+                .map(|ancester| self.index_map[ancester])
+                .collect();
+            for ancester in ancesters.iter() {
+                self.discard_siblings(*ancester);
             }
-            self.discard(commit_id)?;
+            self.discard_siblings(slab_index);
 
-            for (ancester, _) in to_commit.iter() {
-                self.detach_node(self.get_slab_index_by_commit_id(*ancester).unwrap())
+            for ancester in ancesters {
+                self.detach_node(ancester)
             }
 
             // set new_root as root
             let new_root = self.get_node_mut_by_slab_index(slab_index);
             new_root.set_as_root();
             self.height_of_root = new_root.get_height();
-            self.parent_of_root = Some(last.0);
+            self.parent_of_root = Some(*last);
+            self.compact_arena();
         }
 
         // height of old_root
         let start_height_to_commit = self.height_of_root - to_commit.len();
-        let (to_commit_ids, to_commit_maps) = to_commit.into_iter().unzip();
+        Ok((start_height_to_commit, to_commit))
+    }
+
+    // the path `change_root(commit_id)` would confirm, without changing the tree
+    pub fn get_confirmed_path(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<ConfirmedPathInfo<S>, S> {
+        let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        let (commit_ids, key_value_maps) = self.find_path(slab_index).into_iter().unzip();
 
         Ok(ConfirmedPathInfo {
-            start_height: start_height_to_commit,
-            commit_ids: to_commit_ids,
-            key_value_maps: to_commit_maps,
+            start_height: self.height_of_root,
+            commit_ids,
+            key_value_maps,
         })
     }
 
+    // as `get_confirmed_path`, without the changes of the commits
+    pub fn get_confirmed_commit_ids(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<(usize, CommitIdVec<S>), S> {
+        let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        Ok((self.height_of_root, self.find_path_commit_ids(slab_index)))
+    }
+
+    // excluding target
+    fn find_path_commit_ids(&self, target_slab_index: SlabIndex) -> CommitIdVec<S> {
+        let mut target_node = self.get_node_by_slab_index(target_slab_index);
+        let mut path = VecDeque::new();
+        while let Some(parent_slab_index) = target_node.get_parent() {
+            target_node = self.get_node_by_slab_index(parent_slab_index);
+            path.push_front(target_node.get_commit_id());
+        }
+        path.into()
+    }
+
     // excluding target
     fn find_path(&self, target_slab_index: SlabIndex) -> Vec<(S::CommitId, KeyValueMap<S>)> {
         let mut target_node = self.get_node_by_slab_index(target_slab_index);
         let mut path = VecDeque::new();
         while let Some(parent_slab_index) = target_node.get_parent() {
             target_node = self.get_node_by_slab_index(parent_slab_index);
-            path.push_front((target_node.get_commit_id(), target_node.get_updates()));
+            path.push_front((
+                target_node.get_commit_id(),
+                target_node.get_updates(&self.arena),
+            ));
         }
         path.into()
     }
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::middlewares::versioned_flat_key_value::pending_part::{
     current_map::CurrentMap,
@@ -9,43 +9,117 @@ use super::Tree;
 
 // methods to support VersionedMap::checkout_current()
 impl<S: PendingKeyValueSchema> Tree<S> {
+    // checks out one of `currents` at `target_commit_id`, returning its index and the number of
+    // nodes rolled back or applied. The map closest to the target is moved, unless building one
+    // from the root walks fewer nodes. While fewer than `capacity` maps are kept, the moved map
+    // is a copy, so that the others stay checked out, unless building one from the root walks
+    // fewer nodes than the copied entries and the moves.
     pub fn checkout_current(
         &self,
         target_commit_id: S::CommitId,
-        maybe_current: &mut Option<CurrentMap<S>>,
-    ) -> PendResult<(), S> {
-        if let Some(current) = maybe_current.as_mut() {
-            self.switch_current_head(target_commit_id, current)?;
-        } else {
-            *maybe_current = Some(self.make_current(target_commit_id)?);
+        currents: &mut Vec<CurrentMap<S>>,
+        capacity: usize,
+    ) -> PendResult<(usize, usize), S> {
+        let steps_from_root =
+            self.get_height_by_commit_id(target_commit_id)? - self.get_height_of_root() + 1;
+
+        let mut closest = None;
+        for (index, current) in currents.iter().enumerate() {
+            let (lca, steps) = self.find_lca(current.get_commit_id(), target_commit_id)?;
+            if closest.map_or(true, |(_, _, closest_steps)| steps < closest_steps) {
+                closest = Some((index, lca, steps));
+            }
         }
 
-        assert_eq!(
-            maybe_current.as_ref().unwrap().get_commit_id(),
-            target_commit_id
-        );
+        let has_room = currents.len() < capacity;
+        let (index, steps) = match closest {
+            Some((index, _, 0)) => (index, 0),
+            Some((index, lca, steps))
+                if steps < steps_from_root
+                    && (!has_room || steps + currents[index].len() < steps_from_root) =>
+            {
+                let index = if has_room {
+                    currents.push(currents[index].clone());
+                    currents.len() - 1
+                } else {
+                    index
+                };
+                self.switch_current_head(target_commit_id, lca, &mut currents[index]);
+                (index, steps)
+            }
+            _ => {
+                let (current, steps) = self.make_current(target_commit_id)?;
+                let index = match closest {
+                    Some((index, _, _)) if !has_room => {
+                        currents[index] = current;
+                        index
+                    }
+                    _ => {
+                        currents.push(current);
+                        currents.len() - 1
+                    }
+                };
+                (index, steps)
+            }
+        };
+
+        assert_eq!(currents[index].get_commit_id(), target_commit_id);
+
+        Ok((index, steps))
+    }
+
+    // the closest common ancestor of the two commits, with the number of nodes
+    // `collect_rollback_and_apply_ops` walks to it
+    fn find_lca(
+        &self,
+        current_commit_id: S::CommitId,
+        target_commit_id: S::CommitId,
+    ) -> PendResult<(S::CommitId, usize), S> {
+        let mut current_node = self.get_node_by_commit_id(current_commit_id)?;
+        let mut target_node = self.get_node_by_commit_id(target_commit_id)?;
+        let mut steps = 0;
+
+        while current_node.get_height() > target_node.get_height() {
+            current_node = self.get_parent_node(current_node).unwrap();
+            steps += 1;
+        }
+
+        while target_node.get_height() > current_node.get_height() {
+            target_node = self.get_parent_node(target_node).unwrap();
+            steps += 1;
+        }
+
+        while current_node.get_commit_id() != target_node.get_commit_id() {
+            current_node = self.get_parent_node(current_node).unwrap();
+            target_node = self.get_parent_node(target_node).unwrap();
+            steps += 2;
+        }
 
-        Ok(())
+        Ok((current_node.get_commit_id(), steps))
     }
 
     fn switch_current_head(
         &self,
         target_commit_id: S::CommitId,
+        lca_commit_id: S::CommitId,
         current: &mut CurrentMap<S>,
-    ) -> PendResult<(), S> {
-        let (rollbacks, applys) =
-            self.collect_rollback_and_apply_ops(current.get_commit_id(), target_commit_id)?;
+    ) {
+        let (rollbacks, applys) = self.collect_rollback_and_apply_ops(
+            current.get_commit_id(),
+            target_commit_id,
+            lca_commit_id,
+        );
         current.rollback(rollbacks);
         current.apply(applys);
         current.set_commit_id(target_commit_id);
-        Ok(())
     }
 
-    fn make_current(&self, target_commit_id: S::CommitId) -> PendResult<CurrentMap<S>, S> {
+    fn make_current(&self, target_commit_id: S::CommitId) -> PendResult<(CurrentMap<S>, usize), S> {
         let applys = self.get_apply_map_from_root_included(target_commit_id)?;
+        let steps = self.get_height_by_commit_id(target_commit_id)? - self.get_height_of_root() + 1;
         let mut new_current = CurrentMap::<S>::new(target_commit_id);
         new_current.apply(applys);
-        Ok(new_current)
+        Ok((new_current, steps))
     }
 
     #[cfg(test)]
@@ -62,41 +136,47 @@ impl<S: PendingKeyValueSchema> Tree<S> {
     ) -> PendResult<ApplyMap<S>, S> {
         let mut target_node = self.get_node_by_commit_id(target_commit_id)?;
         let mut commits_rev = BTreeMap::new();
-        target_node.export_commit_data::<false>(&mut commits_rev);
+        target_node.export_commit_data::<false>(&self.arena, &mut commits_rev);
         while let Some(parent_slab_index) = target_node.get_parent() {
             target_node = self.get_node_by_slab_index(parent_slab_index);
-            target_node.export_commit_data::<false>(&mut commits_rev);
+            target_node.export_commit_data::<false>(&self.arena, &mut commits_rev);
         }
         Ok(commits_rev)
     }
 
+    // the keys modified on the paths from the closest common ancestor of the two commits to
+    // each of them, i.e. the keys whose value may differ between them
+    pub fn get_diff_keys(
+        &self,
+        current_commit_id: S::CommitId,
+        target_commit_id: S::CommitId,
+    ) -> PendResult<BTreeSet<S::Key>, S> {
+        let (lca, _) = self.find_lca(current_commit_id, target_commit_id)?;
+        let (rollbacks, applys) =
+            self.collect_rollback_and_apply_ops(current_commit_id, target_commit_id, lca);
+        Ok(rollbacks.into_keys().chain(applys.into_keys()).collect())
+    }
+
     // correctness based on single root
+    // `lca_commit_id` is the closest common ancestor of the two commits, see `find_lca`
     #[allow(clippy::type_complexity)]
     fn collect_rollback_and_apply_ops(
         &self,
         current_commit_id: S::CommitId,
         target_commit_id: S::CommitId,
-    ) -> PendResult<(BTreeMap<S::Key, Option<ApplyRecord<S>>>, ApplyMap<S>), S> {
-        let mut current_node = self.get_node_by_commit_id(current_commit_id)?;
-        let mut target_node = self.get_node_by_commit_id(target_commit_id)?;
+        lca_commit_id: S::CommitId,
+    ) -> (BTreeMap<S::Key, Option<ApplyRecord<S>>>, ApplyMap<S>) {
+        let mut current_node = self.get_node_by_commit_id(current_commit_id).unwrap();
         let mut rollbacks = BTreeMap::new();
-        let mut commits_rev = BTreeMap::new();
-
-        while current_node.get_height() > target_node.get_height() {
-            current_node.export_rollback_data::<true>(&mut rollbacks);
+        while current_node.get_commit_id() != lca_commit_id {
+            current_node.export_rollback_data::<true>(&self.arena, &mut rollbacks);
             current_node = self.get_parent_node(current_node).unwrap();
         }
 
-        while target_node.get_height() > current_node.get_height() {
-            target_node.export_commit_data::<false>(&mut commits_rev);
-            target_node = self.get_parent_node(target_node).unwrap();
-        }
-
-        while current_node.get_commit_id() != target_node.get_commit_id() {
-            current_node.export_rollback_data::<true>(&mut rollbacks);
-            current_node = self.get_parent_node(current_node).unwrap();
-
-            target_node.export_commit_data::<false>(&mut commits_rev);
+        let mut target_node = self.get_node_by_commit_id(target_commit_id).unwrap();
+        let mut commits_rev = BTreeMap::new();
+        while target_node.get_commit_id() != lca_commit_id {
+            target_node.export_commit_data::<false>(&self.arena, &mut commits_rev);
             target_node = self.get_parent_node(target_node).unwrap();
         }
 
@@ -104,8 +184,7 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         for (key, old_commit_id) in rollbacks.into_iter() {
             let actual_old_commit_id = if let Some(ref old_commit_id) = old_commit_id {
                 // check rollbacks' old_commit_id because TreeNodes are deleted in a lazy way with respect to TreeNodes.modifications
-                self.contains_commit_id(old_commit_id)
-                    .then_some(old_commit_id)
+                self.is_in_tree(old_commit_id).then_some(old_commit_id)
             } else {
                 None
             };
@@ -127,6 +206,6 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         // rollbacks or commits_rev may be empty,
         // they contain current and target (if they are not lca), respectively,
         // but they do not contain lca
-        Ok((rollbacks_with_value, commits_rev))
+        (rollbacks_with_value, commits_rev)
     }
 }
@@ -1,17 +1,42 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
 use crate::{
     middlewares::versioned_flat_key_value::pending_part::pending_schema::{
-        PendingKeyValueSchema, RecoverRecord, Result as PendResult,
+        KeyValueMap, PendingKeyValueSchema, RecoverRecord, Result as PendResult,
     },
+    middlewares::versioned_flat_key_value::pending_part::PendingError,
     traits::{IsCompleted, NeedNext},
     types::ValueEntry,
 };
 
-use super::Tree;
+use super::{SlabIndex, Tree};
 
 // Internal Tree methods
 // supporting helper methods in VersionedMap for
 // implementing `KeyValueStoreManager` for `VersionedStore`.
 impl<S: PendingKeyValueSchema> Tree<S> {
+    // for each key modified from the root to `commit_id`:
+    // (latest value, height of the latest modification, number of modifications)
+    #[allow(clippy::type_complexity)]
+    pub fn get_path_key_statuses(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<BTreeMap<S::Key, (ValueEntry<S::Value>, usize, u64)>, S> {
+        let mut statuses = BTreeMap::new();
+        let mut node_option = Some(self.get_node_by_commit_id(commit_id)?);
+        while let Some(node) = node_option {
+            for (key, value) in node.get_updates(&self.arena) {
+                let status = statuses.entry(key).or_insert((value, node.get_height(), 0));
+                status.2 += 1;
+            }
+            node_option = self.get_parent_node(node);
+        }
+        Ok(statuses)
+    }
+
     pub fn iter_historical_changes(
         &self,
         mut accept: impl FnMut(&S::CommitId, &S::Key, Option<&S::Value>) -> NeedNext,
@@ -24,7 +49,7 @@ impl<S: PendingKeyValueSchema> Tree<S> {
             if let Some(RecoverRecord {
                 value,
                 last_commit_id,
-            }) = node.get_recover_record(key)
+            }) = node.get_recover_record(&self.arena, key)
             {
                 let need_next = accept(&node.get_commit_id(), key, value.as_opt_ref());
                 if !need_next {
@@ -37,14 +62,13 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         }
 
         while let Some(old_cid) = old_commit_id {
-            if !self.contains_commit_id(&old_cid) {
+            let Some(node) = self.get_node_in_tree(&old_cid) else {
                 break;
-            }
-            let node = self.get_node_by_commit_id(old_cid).unwrap();
+            };
             let RecoverRecord {
                 value,
                 last_commit_id,
-            } = node.get_recover_record(key).unwrap();
+            } = node.get_recover_record(&self.arena, key).unwrap();
             let need_next = accept(&node.get_commit_id(), key, value.as_opt_ref());
             if !need_next {
                 return Ok(false);
@@ -62,7 +86,7 @@ impl<S: PendingKeyValueSchema> Tree<S> {
     ) -> PendResult<Option<ValueEntry<S::Value>>, S> {
         let mut node_option = Some(self.get_node_by_commit_id(*commit_id)?);
         while let Some(node) = node_option {
-            if let Some(value) = node.get_modified_value(key) {
+            if let Some(value) = node.get_modified_value(&self.arena, key) {
                 return Ok(Some(value));
             }
             node_option = self.get_parent_node(node);
@@ -70,8 +94,144 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         Ok(None)
     }
 
+    // `get_versioned_key` at each of `commit_ids`. The deepest commits are walked up first and
+    // the answer of each visited node is kept, so no node is visited twice.
+    pub fn get_versioned_key_multi_commits(
+        &self,
+        commit_ids: &[S::CommitId],
+        key: &S::Key,
+    ) -> PendResult<Vec<Option<ValueEntry<S::Value>>>, S> {
+        let slab_indices = commit_ids
+            .iter()
+            .map(|commit_id| self.get_slab_index_by_commit_id(*commit_id))
+            .collect::<PendResult<Vec<_>, S>>()?;
+        let mut order = slab_indices.clone();
+        order.sort_by_key(|slab_index| {
+            Reverse(self.get_node_by_slab_index(*slab_index).get_height())
+        });
+
+        let mut answers: HashMap<SlabIndex, Option<ValueEntry<S::Value>>> = HashMap::new();
+        for start in order {
+            let mut path = Vec::new();
+            let mut node_option = Some(start);
+            let answer = loop {
+                let Some(slab_index) = node_option else {
+                    break None;
+                };
+                if let Some(answer) = answers.get(&slab_index) {
+                    break answer.clone();
+                }
+                path.push(slab_index);
+                let node = self.get_node_by_slab_index(slab_index);
+                if let Some(value) = node.get_modified_value(&self.arena, key) {
+                    break Some(value);
+                }
+                node_option = node.get_parent();
+            };
+            for slab_index in path {
+                answers.insert(slab_index, answer.clone());
+            }
+        }
+
+        Ok(slab_indices
+            .iter()
+            .map(|slab_index| answers[slab_index].clone())
+            .collect())
+    }
+
+    // in no particular order
+    pub fn get_children(&self, commit_id: S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        let node = self.get_node_by_commit_id(commit_id)?;
+        Ok(node
+            .get_children()
+            .iter()
+            .map(|idx| self.get_node_by_slab_index(*idx))
+            .filter(|child| child.is_addressable())
+            .map(|child| child.get_commit_id())
+            .collect())
+    }
+
+    // every node with its parent, updates and whether it is addressable, each after its parent
+    #[allow(clippy::type_complexity)]
+    pub fn get_nodes_in_order(
+        &self,
+    ) -> Vec<(S::CommitId, Option<S::CommitId>, KeyValueMap<S>, bool)> {
+        let Some((root_slab_index, _)) = self
+            .nodes
+            .iter()
+            .find(|(_, node)| node.get_parent().is_none())
+        else {
+            return Vec::new();
+        };
+        self.bfs_subtree(root_slab_index)
+            .into_iter()
+            .map(|slab_index| {
+                let node = self.get_node_by_slab_index(slab_index);
+                let parent = self
+                    .get_parent_node(node)
+                    .map(|parent| parent.get_commit_id());
+                (
+                    node.get_commit_id(),
+                    parent,
+                    node.get_updates(&self.arena),
+                    node.is_addressable(),
+                )
+            })
+            .collect()
+    }
+
+    // the nodes without children, in no particular order
+    pub fn get_leaves(&self) -> Vec<S::CommitId> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.get_children().is_empty() && node.is_addressable())
+            .map(|(_, node)| node.get_commit_id())
+            .collect()
+    }
+
+    // `commit_id` and its addressable ancestors up to the pending root, `commit_id` first
+    pub fn get_path_to_root(&self, commit_id: S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        let mut node = self.get_node_by_commit_id(commit_id)?;
+        let mut path = vec![commit_id];
+        while let Some(parent) = self.get_parent_node(node) {
+            if parent.is_addressable() {
+                path.push(parent.get_commit_id());
+            }
+            node = parent;
+        }
+        Ok(path)
+    }
+
+    // removes `commit_id` and its descendants, returning the removed commits, `commit_id` first
+    pub fn prune_subtree(&mut self, commit_id: S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        let Some(parent_slab_index) = self.get_node_by_slab_index(slab_index).get_parent() else {
+            return Err(PendingError::CannotPrunePendingRoot(commit_id));
+        };
+        self.get_node_mut_by_slab_index(parent_slab_index)
+            .remove_child(&slab_index);
+
+        let to_remove = self.bfs_subtree(slab_index);
+        let removed = to_remove
+            .iter()
+            .map(|idx| self.get_node_by_slab_index(*idx).get_commit_id())
+            .collect();
+        for idx in to_remove {
+            self.detach_node(idx);
+        }
+        self.compact_arena();
+
+        Ok(removed)
+    }
+
     pub fn discard(&mut self, commit_id: S::CommitId) -> PendResult<(), S> {
         let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        self.discard_siblings(slab_index);
+        Ok(())
+    }
+
+    // removes the siblings of a node with their descendants
+    pub(super) fn discard_siblings(&mut self, slab_index: SlabIndex) {
         if let Some(parent_of_discard) = self.get_node_by_slab_index(slab_index).get_parent() {
             let parent_node = self.get_node_by_slab_index(parent_of_discard);
             let mut to_remove = Vec::new();
@@ -86,8 +246,7 @@ impl<S: PendingKeyValueSchema> Tree<S> {
 
             let parent_node = self.get_node_mut_by_slab_index(parent_of_discard);
             parent_node.remove_child_except(&slab_index);
+            self.compact_arena();
         } // else // root is already the unique child of its parent, so do nothing
-
-        Ok(())
     }
 }
@@ -0,0 +1,149 @@
+use std::collections::{btree_map::Entry, HashSet};
+use std::mem::size_of;
+
+use crate::middlewares::versioned_flat_key_value::pending_part::{
+    pending_schema::{PendingKeyValueSchema, RecoverMap, Result as PendResult},
+    versioned_map::MemoryStats,
+};
+
+use super::{arena::Modification, node::TreeNode, SlabIndex, Tree};
+
+// methods to support VersionedMap::mark_unaddressable(), VersionedMap::compact_branch() and
+// VersionedMap::memory_usage()
+impl<S: PendingKeyValueSchema> Tree<S> {
+    pub fn mark_unaddressable(&mut self, commit_id: S::CommitId) -> PendResult<(), S> {
+        let slab_index = self.get_slab_index_by_commit_id(commit_id)?;
+        self.get_node_mut_by_slab_index(slab_index)
+            .set_unaddressable();
+        Ok(())
+    }
+
+    // merges each unaddressable node with a single child on the path from the root to `tip`
+    // into the closest node below it that is not merged, and removes it from the tree
+    pub fn compact_branch(&mut self, tip: S::CommitId) -> PendResult<(), S> {
+        let mut target = self.get_slab_index_by_commit_id(tip)?;
+        // the nodes to merge into each target, from the closest to the target up
+        let mut groups = Vec::new();
+        let mut merged = Vec::new();
+        let mut node = target;
+        while let Some(parent) = self.get_node_by_slab_index(node).get_parent() {
+            let parent_node = self.get_node_by_slab_index(parent);
+            if !parent_node.is_addressable() && parent_node.get_children().len() == 1 {
+                merged.push(parent);
+            } else {
+                if !merged.is_empty() {
+                    groups.push((target, std::mem::take(&mut merged)));
+                }
+                target = parent;
+            }
+            node = parent;
+        }
+        if !merged.is_empty() {
+            groups.push((target, merged));
+        }
+
+        for (target, merged) in groups {
+            self.merge_into(target, &merged);
+            self.remove_merged(target, &merged);
+        }
+        self.arena.reclaim(
+            self.nodes
+                .iter_mut()
+                .map(|(_, node)| node.get_modification_range_mut()),
+        );
+
+        Ok(())
+    }
+
+    // moves the modifications of `merged`, ancestors of `target` with a single child ordered
+    // from the closest to `target` up, into `target`, keeping the latest one of each key
+    fn merge_into(&mut self, target: SlabIndex, merged: &[SlabIndex]) {
+        let target_node = self.get_node_by_slab_index(target);
+        let target_commit_id = target_node.get_commit_id();
+        let mut modifications: RecoverMap<S> = target_node
+            .get_modifications(&self.arena)
+            .iter()
+            .cloned()
+            .collect();
+
+        let mut merged_commit_ids = HashSet::new();
+        for slab_index in merged {
+            let node = self.get_node_by_slab_index(*slab_index);
+            merged_commit_ids.insert(node.get_commit_id());
+            for (key, record) in node.get_modifications(&self.arena) {
+                match modifications.entry(key.clone()) {
+                    // the later modification now follows the one before `node`
+                    Entry::Occupied(mut later) => {
+                        later.get_mut().last_commit_id = record.last_commit_id;
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(record.clone());
+                    }
+                }
+            }
+        }
+
+        // the descendants of `target` find the merged modifications in `target`
+        for slab_index in self.bfs_subtree(target).into_iter().skip(1) {
+            let range = self
+                .get_node_by_slab_index(slab_index)
+                .get_modification_range()
+                .clone();
+            for (_, record) in self.arena.get_mut(&range) {
+                if record
+                    .last_commit_id
+                    .map_or(false, |commit_id| merged_commit_ids.contains(&commit_id))
+                {
+                    record.last_commit_id = Some(target_commit_id);
+                }
+            }
+        }
+
+        self.arena
+            .release(self.nodes[target].get_modification_range());
+        let range = self.arena.push(modifications, |_| true);
+        *self
+            .get_node_mut_by_slab_index(target)
+            .get_modification_range_mut() = range;
+    }
+
+    // detaches `merged`, as passed to `merge_into`, and moves `target` with its subtree up in
+    // their place
+    fn remove_merged(&mut self, target: SlabIndex, merged: &[SlabIndex]) {
+        let topmost = *merged.last().unwrap();
+        let new_parent = self.get_node_by_slab_index(topmost).get_parent();
+        for slab_index in merged {
+            self.detach_node(*slab_index);
+        }
+
+        match new_parent {
+            Some(parent) => {
+                let parent_node = self.get_node_mut_by_slab_index(parent);
+                parent_node.remove_child(&topmost);
+                parent_node.insert_child(target);
+                self.get_node_mut_by_slab_index(target).set_parent(parent);
+            }
+            None => self.get_node_mut_by_slab_index(target).set_as_root(),
+        }
+        for slab_index in self.bfs_subtree(target) {
+            self.get_node_mut_by_slab_index(slab_index)
+                .lower_height(merged.len());
+        }
+    }
+
+    // the sizes of the nodes, the modifications not reclaimed yet included, not counting the
+    // memory owned by the keys and values
+    pub fn memory_usage(&self) -> MemoryStats {
+        let node_size = size_of::<TreeNode<S>>() + size_of::<(S::CommitId, SlabIndex)>();
+        MemoryStats {
+            num_nodes: self.nodes.len(),
+            num_modifications: self
+                .nodes
+                .iter()
+                .map(|(_, node)| node.get_modification_range().len())
+                .sum(),
+            approx_bytes: self.nodes.len() * node_size
+                + self.arena.len() * size_of::<Modification<S>>(),
+        }
+    }
+}
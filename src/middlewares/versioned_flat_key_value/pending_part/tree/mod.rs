@@ -1,16 +1,21 @@
 mod add_node;
+mod arena;
 mod change_root;
 mod checkout;
 mod commands;
+mod compact;
 mod node;
 
 pub type SlabIndex = usize;
 
+/// Default bound on the depth of a pending node below the pending root.
+pub const DEFAULT_MAX_PENDING_DEPTH: usize = 1 << 16;
+
 use std::collections::HashMap;
 
 use slab::Slab;
 
-use self::node::TreeNode;
+use self::{arena::ModificationArena, node::TreeNode};
 
 use super::pending_schema::{PendingKeyValueSchema, Result as PendResult};
 use super::PendingError;
@@ -21,6 +26,8 @@ pub struct Tree<S: PendingKeyValueSchema> {
     height_of_root: usize,
     nodes: Slab<TreeNode<S>>,
     index_map: HashMap<S::CommitId, SlabIndex>,
+    arena: ModificationArena<S>,
+    max_depth: Option<usize>,
 }
 
 // basic methods
@@ -31,9 +38,34 @@ impl<S: PendingKeyValueSchema> Tree<S> {
             height_of_root,
             nodes: Slab::new(),
             index_map: HashMap::new(),
+            arena: ModificationArena::new(),
+            max_depth: Some(DEFAULT_MAX_PENDING_DEPTH),
         }
     }
 
+    // removes every node and moves the root below `parent_of_root`, keeping the settings
+    pub fn reset(&mut self, parent_of_root: Option<S::CommitId>, height_of_root: usize) {
+        *self = Tree {
+            max_depth: self.max_depth,
+            ..Tree::new(parent_of_root, height_of_root)
+        };
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    // the pending root is at depth 0
+    fn check_depth(&self, height: usize) -> PendResult<(), S> {
+        if let Some(limit) = self.max_depth {
+            let depth = height - self.height_of_root;
+            if depth > limit {
+                return Err(PendingError::MaxDepthExceeded { depth, limit });
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn check_consistency(&self, height_of_root: usize) -> bool {
         if self.height_of_root != height_of_root {
@@ -74,25 +106,63 @@ impl<S: PendingKeyValueSchema> Tree<S> {
             }
         }
 
-        // todo: modifications
+        let mut num_live = 0;
+        for (_, node) in self.nodes.iter() {
+            let range = node.get_modification_range();
+            if range.end > self.arena.len() {
+                return false;
+            }
+            if !node
+                .get_modifications(&self.arena)
+                .windows(2)
+                .all(|pair| pair[0].0 < pair[1].0)
+            {
+                return false;
+            }
+            num_live += range.len();
+        }
+        if num_live + self.arena.num_garbage() != self.arena.len() {
+            return false;
+        }
 
         true
     }
 
+    // number of modifications in the arena, including those of detached nodes
+    #[cfg(test)]
+    pub fn num_arena_entries(&self) -> usize {
+        self.arena.len()
+    }
+
     pub fn get_parent_of_root(&self) -> Option<S::CommitId> {
         self.parent_of_root
     }
 
     pub(super) fn contains_commit_id(&self, commit_id: &S::CommitId) -> bool {
+        self.get_slab_index_by_commit_id(*commit_id).is_ok()
+    }
+
+    // whether `commit_id` is in the tree, even if not addressable, e.g. when it is the last
+    // commit modifying a key before another one
+    pub(super) fn is_in_tree(&self, commit_id: &S::CommitId) -> bool {
         self.index_map.contains_key(commit_id)
     }
 
+    // unaddressable nodes are not found
     fn get_slab_index_by_commit_id(&self, commit_id: S::CommitId) -> PendResult<SlabIndex, S> {
-        let slab_index = *self
-            .index_map
-            .get(&commit_id)
-            .ok_or(PendingError::CommitIDNotFound(commit_id))?;
-        Ok(slab_index)
+        match self.index_map.get(&commit_id) {
+            Some(&slab_index) if self.get_node_by_slab_index(slab_index).is_addressable() => {
+                Ok(slab_index)
+            }
+            _ => Err(PendingError::CommitIDNotFound(commit_id)),
+        }
+    }
+
+    // unaddressable nodes are found
+    fn get_node_in_tree(&self, commit_id: &S::CommitId) -> Option<&TreeNode<S>> {
+        self.index_map
+            .get(commit_id)
+            .map(|slab_index| self.get_node_by_slab_index(*slab_index))
     }
 
     fn get_node_by_slab_index(&self, slab_index: SlabIndex) -> &TreeNode<S> {
@@ -108,7 +178,36 @@ impl<S: PendingKeyValueSchema> Tree<S> {
         Ok(self.get_node_by_slab_index(slab_index))
     }
 
-    fn has_root(&self) -> bool {
+    pub(super) fn get_height_by_commit_id(&self, commit_id: S::CommitId) -> PendResult<usize, S> {
+        Ok(self.get_node_by_commit_id(commit_id)?.get_height())
+    }
+
+    pub(super) fn get_height_of_root(&self) -> usize {
+        self.height_of_root
+    }
+
+    // walks up from `commit_id` and stops at the pending root. An unaddressable ancestor is
+    // replaced by the closest addressable node below it.
+    pub(super) fn get_ancestor_at_height(
+        &self,
+        commit_id: S::CommitId,
+        height: usize,
+    ) -> PendResult<S::CommitId, S> {
+        let mut node = self.get_node_by_commit_id(commit_id)?;
+        let mut addressable = node;
+        while node.get_height() > height {
+            match self.get_parent_node(node) {
+                Some(parent) => node = parent,
+                None => break,
+            }
+            if node.is_addressable() {
+                addressable = node;
+            }
+        }
+        Ok(addressable.get_commit_id())
+    }
+
+    pub(super) fn has_root(&self) -> bool {
         !self.index_map.is_empty()
     }
 
@@ -117,13 +216,16 @@ impl<S: PendingKeyValueSchema> Tree<S> {
             .map(|p_slab_index| self.get_node_by_slab_index(p_slab_index))
     }
 
+    // `commit_id` may be unaddressable, as the last commit modifying a key
     fn get_modification_by_commit_id(
         &self,
         commit_id: S::CommitId,
         key: &S::Key,
     ) -> PendResult<Option<ValueEntry<S::Value>>, S> {
-        let node = self.get_node_by_commit_id(commit_id)?;
-        Ok(node.get_modified_value(key))
+        let node = self
+            .get_node_in_tree(&commit_id)
+            .ok_or(PendingError::CommitIDNotFound(commit_id))?;
+        Ok(node.get_modified_value(&self.arena, key))
     }
 
     // including subroot
@@ -144,7 +246,18 @@ impl<S: PendingKeyValueSchema> Tree<S> {
     }
 
     fn detach_node(&mut self, idx: SlabIndex) {
-        self.index_map
-            .remove(&self.nodes.remove(idx).get_commit_id());
+        let node = self.nodes.remove(idx);
+        self.arena.release(node.get_modification_range());
+        self.index_map.remove(&node.get_commit_id());
+    }
+
+    // reclaims the modifications of detached nodes, called after `change_root`, `discard` and
+    // `prune_subtree`
+    fn compact_arena(&mut self) {
+        self.arena.compact(
+            self.nodes
+                .iter_mut()
+                .map(|(_, node)| node.get_modification_range_mut()),
+        );
     }
 }
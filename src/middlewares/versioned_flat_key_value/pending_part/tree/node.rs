@@ -1,36 +1,44 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, ops::Range};
 
 use crate::middlewares::versioned_flat_key_value::pending_part::pending_schema::{
-    ApplyMap, ApplyRecord, KeyValueMap, LastCommitIdMap, PendingKeyValueSchema, RecoverMap,
-    RecoverRecord,
+    ApplyMap, ApplyRecord, KeyValueMap, LastCommitIdMap, PendingKeyValueSchema, RecoverRecord,
 };
 use crate::types::ValueEntry;
 
-use super::SlabIndex;
+use super::{
+    arena::{Modification, ModificationArena},
+    SlabIndex,
+};
 
 pub(super) struct TreeNode<S: PendingKeyValueSchema> {
     parent: Option<SlabIndex>,
     children: BTreeSet<SlabIndex>,
 
     // todo: test lazy height
-    // height will not be changed even when root is changed
+    // height will not be changed even when root is changed, only lowered when the ancestors
+    // are merged by `compact_branch`
     height: usize,
 
     commit_id: S::CommitId,
     // before current node, the old value of this key is modified by which commit_id,
     // if none, this key is absent before current node
     // here must use CommitID instead of SlabIndex (which may be reused, see slab doc)
-    modifications: RecoverMap<S>,
+    // the range of the modifications in the arena of the tree
+    modifications: Range<usize>,
+    // cleared by `mark_unaddressable`: the node is kept for its descendants and its
+    // confirmation, but not found by its commit id, until `compact_branch` removes it
+    addressable: bool,
 }
 
 impl<S: PendingKeyValueSchema> TreeNode<S> {
-    pub fn new_root(commit_id: S::CommitId, modifications: RecoverMap<S>, height: usize) -> Self {
+    pub fn new_root(commit_id: S::CommitId, modifications: Range<usize>, height: usize) -> Self {
         Self {
             height,
             commit_id,
             parent: None,
             children: BTreeSet::new(),
             modifications,
+            addressable: true,
         }
     }
 
@@ -38,7 +46,7 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
         commit_id: S::CommitId,
         parent: SlabIndex,
         height: usize,
-        modifications: RecoverMap<S>,
+        modifications: Range<usize>,
     ) -> Self {
         Self {
             height,
@@ -46,6 +54,7 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
             parent: Some(parent),
             children: BTreeSet::new(),
             modifications,
+            addressable: true,
         }
     }
 
@@ -57,6 +66,10 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
         self.parent = None;
     }
 
+    pub fn set_parent(&mut self, parent: SlabIndex) {
+        self.parent = Some(parent);
+    }
+
     pub fn get_children(&self) -> &BTreeSet<SlabIndex> {
         &self.children
     }
@@ -65,6 +78,10 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
         self.children.insert(new_child);
     }
 
+    pub fn remove_child(&mut self, child_to_remove: &SlabIndex) {
+        self.children.remove(child_to_remove);
+    }
+
     pub fn remove_child_except(&mut self, child_to_remove: &SlabIndex) {
         self.children = BTreeSet::from([*child_to_remove]);
     }
@@ -73,27 +90,66 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
         self.height
     }
 
+    pub fn lower_height(&mut self, by: usize) {
+        self.height -= by;
+    }
+
     pub fn get_commit_id(&self) -> S::CommitId {
         self.commit_id
     }
 
-    pub fn get_modified_value(&self, key: &S::Key) -> Option<ValueEntry<S::Value>> {
-        self.modifications.get(key).map(|v| v.value.clone())
+    pub fn is_addressable(&self) -> bool {
+        self.addressable
+    }
+
+    pub fn set_unaddressable(&mut self) {
+        self.addressable = false;
+    }
+
+    pub fn get_modification_range(&self) -> &Range<usize> {
+        &self.modifications
+    }
+
+    pub fn get_modification_range_mut(&mut self) -> &mut Range<usize> {
+        &mut self.modifications
+    }
+
+    // sorted by key
+    pub fn get_modifications<'a>(&self, arena: &'a ModificationArena<S>) -> &'a [Modification<S>] {
+        arena.get(&self.modifications)
+    }
+
+    pub fn get_modified_value(
+        &self,
+        arena: &ModificationArena<S>,
+        key: &S::Key,
+    ) -> Option<ValueEntry<S::Value>> {
+        arena
+            .find(&self.modifications, key)
+            .map(|v| v.value.clone())
     }
 
-    pub fn get_recover_record(&self, key: &S::Key) -> Option<&RecoverRecord<S>> {
-        self.modifications.get(key)
+    pub fn get_recover_record<'a>(
+        &self,
+        arena: &'a ModificationArena<S>,
+        key: &S::Key,
+    ) -> Option<&'a RecoverRecord<S>> {
+        arena.find(&self.modifications, key)
     }
 
-    pub fn get_updates(&self) -> KeyValueMap<S> {
-        self.modifications
+    pub fn get_updates(&self, arena: &ModificationArena<S>) -> KeyValueMap<S> {
+        self.get_modifications(arena)
             .iter()
             .map(|(k, RecoverRecord { value, .. })| (k.clone(), value.clone()))
             .collect()
     }
 
-    pub fn export_rollback_data<const OVERRIDE: bool>(&self, rollbacks: &mut LastCommitIdMap<S>) {
-        for (key, RecoverRecord { last_commit_id, .. }) in self.modifications.iter() {
+    pub fn export_rollback_data<const OVERRIDE: bool>(
+        &self,
+        arena: &ModificationArena<S>,
+        rollbacks: &mut LastCommitIdMap<S>,
+    ) {
+        for (key, RecoverRecord { last_commit_id, .. }) in self.get_modifications(arena) {
             if OVERRIDE {
                 rollbacks.insert(key.clone(), *last_commit_id);
             } else {
@@ -102,9 +158,13 @@ impl<S: PendingKeyValueSchema> TreeNode<S> {
         }
     }
 
-    pub fn export_commit_data<const OVERRIDE: bool>(&self, commits: &mut ApplyMap<S>) {
+    pub fn export_commit_data<const OVERRIDE: bool>(
+        &self,
+        arena: &ModificationArena<S>,
+        commits: &mut ApplyMap<S>,
+    ) {
         let commit_id = self.commit_id;
-        for (key, RecoverRecord { value, .. }) in self.modifications.iter() {
+        for (key, RecoverRecord { value, .. }) in self.get_modifications(arena) {
             let new_record = || ApplyRecord {
                 commit_id,
                 value: value.clone(),
@@ -1,28 +1,77 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::sync::Arc;
 
+use crate::backends::serde::{Decode, Encode};
+use crate::errors::Result;
 use crate::traits::{IsCompleted, NeedNext};
 use crate::types::ValueEntry;
+use crate::StorageError;
 
 use super::pending_schema::ConfirmedPathInfo;
 use super::{
+    confirmed_cache::{ConfirmedCache, CONFIRMED_CACHE_CAPACITY},
     current_map::CurrentMap,
-    pending_schema::{KeyValueMap, PendingKeyValueSchema, RecoverRecord, Result as PendResult},
-    tree::Tree,
+    pending_schema::{
+        ApplyRecord, CommitIdVec, KeyValueMap, PendingKeyValueSchema, RecoverRecord,
+        Result as PendResult,
+    },
+    tree::{Tree, DEFAULT_MAX_PENDING_DEPTH},
     PendingError,
 };
 
+use crate::middlewares::versioned_flat_key_value::metrics::{NoopMetrics, StorageMetrics};
+use crate::middlewares::versioned_flat_key_value::snapshot::{
+    read_array, read_field, read_u32, write_field,
+};
+use crate::middlewares::HistoryNumber;
+
+use ethereum_types::H256;
 use parking_lot::RwLock;
 
+/// Approximate memory held by a pending part, see [`VersionedMap::memory_usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Pending commits, including those marked unaddressable.
+    pub num_nodes: usize,
+    /// Modifications of keys held by the pending commits.
+    pub num_modifications: usize,
+    /// Bytes of the commits, of their modifications and of the checked out maps. The memory
+    /// owned by the keys and values, e.g. of boxed slices, is not counted.
+    pub approx_bytes: usize,
+}
+
 pub struct VersionedMap<S: PendingKeyValueSchema> {
     tree: Tree<S>,
-    current: RwLock<Option<CurrentMap<S>>>,
+    current: RwLock<Vec<CurrentMap<S>>>,
+    max_current_maps: usize,
+    max_unconfirmed_heights: Option<usize>,
+    confirmed_cache: RwLock<ConfirmedCache<S::CommitId>>,
+    last_added: Option<S::CommitId>,
+    metrics: Arc<dyn StorageMetrics>,
+    // aliases registered since the last confirmation, see `stage_alias`
+    aliases: HashMap<H256, StagedAlias<S::CommitId>>,
+}
+
+#[derive(Clone, Copy)]
+struct StagedAlias<CommitId> {
+    commit_id: CommitId,
+    // whether `commit_id` was pending when the alias was registered
+    pending: bool,
 }
 
 impl<S: PendingKeyValueSchema> VersionedMap<S> {
     pub fn new(parent_of_root: Option<S::CommitId>, height_of_root: usize) -> Self {
         VersionedMap {
             tree: Tree::new(parent_of_root, height_of_root),
-            current: RwLock::new(None),
+            current: RwLock::new(Vec::new()),
+            max_current_maps: 1,
+            max_unconfirmed_heights: None,
+            confirmed_cache: RwLock::new(ConfirmedCache::new(CONFIRMED_CACHE_CAPACITY)),
+            last_added: None,
+            metrics: Arc::new(NoopMetrics),
+            aliases: HashMap::new(),
         }
     }
 
@@ -30,6 +79,75 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
         Self::new(None, 0)
     }
 
+    /// Bounds the depth of pending nodes below the pending root, `None` for no bound.
+    /// Defaults to [`DEFAULT_MAX_PENDING_DEPTH`].
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.tree.set_max_depth(max_depth);
+    }
+
+    /// Keeps up to `max_current_maps` commits checked out, so that reads alternating between
+    /// branches, e.g. during a fork race, do not roll back and apply the whole fork distance
+    /// each time. A checkout moves the map closest to the commit. Defaults to 1.
+    ///
+    /// # Panics
+    ///
+    /// If `max_current_maps` is 0.
+    pub fn set_max_current_maps(&mut self, max_current_maps: usize) {
+        assert!(max_current_maps > 0, "at least one current map is kept");
+        self.max_current_maps = max_current_maps;
+        self.current.get_mut().truncate(max_current_maps);
+    }
+
+    /// Reports the events of the pending part, and of the stores reading it, to `metrics`.
+    /// Defaults to [`NoopMetrics`].
+    pub fn set_metrics(&mut self, metrics: Arc<dyn StorageMetrics>) {
+        self.metrics = metrics;
+    }
+
+    pub fn metrics(&self) -> &dyn StorageMetrics {
+        self.metrics.as_ref()
+    }
+
+    /// Bounds the number of pending heights, from the pending root to the deepest commit, `None`
+    /// for no bound.
+    ///
+    /// The bound is not enforced by `add_node`: the caller confirms the commit
+    /// returned by `get_pending_root_to_confirm` to restore it.
+    ///
+    /// # Panics
+    ///
+    /// If `max_unconfirmed_heights` is `Some(0)`.
+    pub fn set_max_unconfirmed_heights(&mut self, max_unconfirmed_heights: Option<usize>) {
+        assert!(
+            max_unconfirmed_heights != Some(0),
+            "a commit is pending once added"
+        );
+        self.max_unconfirmed_heights = max_unconfirmed_heights;
+    }
+
+    /// Returns the ancestor of `commit_id` on the root path that must become
+    /// the pending root, so that the heights from the pending root to `commit_id`
+    /// are at most `max_unconfirmed_heights`. Returns `None` if the bound already holds.
+    pub fn get_pending_root_to_confirm(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<Option<S::CommitId>, S> {
+        let Some(limit) = self.max_unconfirmed_heights else {
+            return Ok(None);
+        };
+
+        let height = self.tree.get_height_by_commit_id(commit_id)?;
+        // `height - height_of_root + 1` heights are pending up to `commit_id`
+        if height - self.tree.get_height_of_root() < limit {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            self.tree
+                .get_ancestor_at_height(commit_id, height + 1 - limit)?,
+        ))
+    }
+
     #[cfg(test)]
     pub fn check_consistency(&self, height_of_root: usize) -> bool {
         if self.tree.check_consistency(height_of_root) {
@@ -43,6 +161,52 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
     pub fn get_parent_of_root(&self) -> Option<S::CommitId> {
         self.tree.get_parent_of_root()
     }
+
+    /// Returns the cached history number of a confirmed commit.
+    pub fn get_cached_history_number(&self, commit_id: &S::CommitId) -> Option<HistoryNumber> {
+        self.confirmed_cache.read().get(commit_id)
+    }
+
+    /// Caches the history number of a confirmed commit until the next `change_root`.
+    pub fn cache_history_number(&self, commit_id: S::CommitId, history_number: HistoryNumber) {
+        self.confirmed_cache
+            .write()
+            .insert(commit_id, history_number);
+    }
+
+    /// Forgets the cached history numbers below `history_number`, e.g. of pruned commits.
+    pub fn uncache_history_numbers_below(&mut self, history_number: HistoryNumber) {
+        self.confirmed_cache.get_mut().remove_below(history_number);
+    }
+
+    #[cfg(test)]
+    pub fn num_cached_history_numbers(&self) -> usize {
+        self.confirmed_cache.read().len()
+    }
+
+    pub fn contains_commit_id(&self, commit_id: &S::CommitId) -> bool {
+        self.tree.contains_commit_id(commit_id)
+    }
+
+    pub fn get_height_by_commit_id(&self, commit_id: S::CommitId) -> PendResult<usize, S> {
+        self.tree.get_height_by_commit_id(commit_id)
+    }
+
+    /// Returns the children of the pending `commit_id`, in no particular order.
+    pub fn children_of(&self, commit_id: &S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        self.tree.get_children(*commit_id)
+    }
+
+    /// Returns the pending commits without children, in no particular order.
+    pub fn leaves(&self) -> Vec<S::CommitId> {
+        self.tree.get_leaves()
+    }
+
+    /// Returns the pending `commit_id` and its ancestors up to the pending root, `commit_id`
+    /// first.
+    pub fn path_to_root(&self, commit_id: &S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        self.tree.get_path_to_root(*commit_id)
+    }
 }
 
 // add_node
@@ -57,14 +221,101 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
             .into_iter()
             .map(|(key, value)| (key, ValueEntry::from_option(value)));
         if self.get_parent_of_root() == parent_commit_id {
-            self.add_root(updates, commit_id)
+            self.add_root(updates, commit_id)?;
         } else if let Some(parent_commit_id) = parent_commit_id {
-            self.add_non_root_node(updates, commit_id, parent_commit_id)
+            self.add_non_root_node(updates, commit_id, parent_commit_id)?;
+        } else {
+            return Err(PendingError::NonRootNodeShouldHaveParent);
+        }
+
+        self.last_added = Some(commit_id);
+        Ok(())
+    }
+
+    /// Adds the nodes in order as one unit. The whole chain is validated first: each commit is new
+    /// and its parent is either added earlier in `nodes` or already pending. If adding a node still
+    /// fails, the nodes added before it are removed, leaving the map as it was.
+    pub fn add_nodes<U: IntoIterator<Item = (S::Key, Option<S::Value>)>>(
+        &mut self,
+        nodes: Vec<(S::CommitId, Option<S::CommitId>, U)>,
+    ) -> PendResult<(), S> {
+        self.validate_nodes(
+            nodes
+                .iter()
+                .map(|(commit_id, parent, _)| (*commit_id, *parent)),
+        )?;
+
+        let last_added = self.last_added;
+        let mut added = Vec::with_capacity(nodes.len());
+        for (commit_id, parent_commit_id, updates) in nodes {
+            if let Err(err) = self.add_node(updates, commit_id, parent_commit_id) {
+                // later nodes can only be children of earlier ones, so each removed node is a leaf
+                for commit_id in added.into_iter().rev() {
+                    self.tree.remove_leaf(commit_id)?;
+                }
+                self.clear_removed_current();
+                self.last_added = last_added;
+                return Err(err);
+            }
+            added.push(commit_id);
+        }
+
+        Ok(())
+    }
+
+    // the checks of `add_node` that do not depend on the content of the nodes
+    fn validate_nodes(
+        &self,
+        nodes: impl Iterator<Item = (S::CommitId, Option<S::CommitId>)>,
+    ) -> PendResult<(), S> {
+        let mut staged = HashSet::new();
+        let mut has_root = self.tree.has_root();
+        for (commit_id, parent_commit_id) in nodes {
+            if parent_commit_id == self.get_parent_of_root() {
+                if has_root {
+                    return Err(PendingError::MultipleRootsNotAllowed);
+                }
+                has_root = true;
+            } else if let Some(parent_commit_id) = parent_commit_id {
+                if !self.contains_commit_id(&parent_commit_id)
+                    && !staged.contains(&parent_commit_id)
+                {
+                    return Err(PendingError::CommitIDNotFound(parent_commit_id));
+                }
+            } else {
+                return Err(PendingError::NonRootNodeShouldHaveParent);
+            }
+
+            if self.tree.is_in_tree(&commit_id) || !staged.insert(commit_id) {
+                return Err(PendingError::CommitIdAlreadyExists(commit_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the height a node added under `parent_commit_id` would have, without adding it.
+    pub fn get_height_of_new_node(
+        &self,
+        parent_commit_id: Option<S::CommitId>,
+    ) -> PendResult<usize, S> {
+        if self.get_parent_of_root() == parent_commit_id {
+            Ok(self.tree.get_height_of_root())
+        } else if let Some(parent_commit_id) = parent_commit_id {
+            self.tree
+                .get_height_by_commit_id(parent_commit_id)?
+                .checked_add(1)
+                .ok_or(PendingError::HeightOverflow)
         } else {
             Err(PendingError::NonRootNodeShouldHaveParent)
         }
     }
 
+    /// Returns the last added commit, unless it has been confirmed or discarded since.
+    pub fn get_last_added(&self) -> Option<S::CommitId> {
+        self.last_added
+            .filter(|commit_id| self.contains_commit_id(commit_id))
+    }
+
     fn add_root(
         &mut self,
         updates: impl Iterator<Item = (S::Key, ValueEntry<S::Value>)>,
@@ -80,8 +331,8 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
             )
         };
 
-        let modifications = updates.map(enact_update).collect();
-        self.tree.add_root(commit_id, modifications)?;
+        self.tree
+            .add_root(commit_id, updates.map(enact_update), |_| true)?;
 
         Ok(())
     }
@@ -95,23 +346,31 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
         // let parent to be self.current
         // this step is necessary for computing modifications' last_commit_id
         let mut guard = self.current.write();
-        self.tree.checkout_current(parent_commit_id, &mut guard)?;
+        let (index, steps) =
+            self.tree
+                .checkout_current(parent_commit_id, &mut guard, self.max_current_maps)?;
+        self.metrics.on_checkout(steps);
 
         // add node to tree
-        let current = guard.as_ref().unwrap();
-        let mut modifications = BTreeMap::new();
-        for (key, value) in updates {
+        let current = &guard[index];
+        let modifications = updates.map(|(key, value)| {
             let last_commit_id = current.get(&key).map(|s| s.commit_id);
-            modifications.insert(
+            (
                 key,
                 RecoverRecord {
                     value,
                     last_commit_id,
                 },
-            );
-        }
+            )
+        });
+        // a later update of the same key still replaces an earlier one, even when the later
+        // one is dropped for rewriting the current value
+        let keep = |(key, record): &(S::Key, RecoverRecord<S>)| match current.get(key) {
+            Some(last) => !S::is_identical_write(&last.value, &record.value),
+            None => true,
+        };
         self.tree
-            .add_non_root_node(commit_id, parent_commit_id, modifications)?;
+            .add_non_root_node(commit_id, parent_commit_id, modifications, keep)?;
 
         Ok(())
     }
@@ -119,19 +378,64 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
 
 // change_root
 impl<S: PendingKeyValueSchema> VersionedMap<S> {
+    /// Returns the path [`Self::change_root`] would confirm, leaving the map unchanged.
+    pub fn get_confirmed_path(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<ConfirmedPathInfo<S>, S> {
+        self.tree.get_confirmed_path(commit_id)
+    }
+
+    /// As [`Self::get_confirmed_path`], without cloning the changes of the commits: returns the
+    /// height of the pending root and the commits from it up to the parent of `commit_id`.
+    pub fn get_confirmed_commit_ids(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<(usize, CommitIdVec<S>), S> {
+        self.tree.get_confirmed_commit_ids(commit_id)
+    }
+
     pub fn change_root(&mut self, commit_id: S::CommitId) -> PendResult<ConfirmedPathInfo<S>, S> {
-        let confirm_path_info = self.tree.change_root(commit_id)?;
+        let confirm_path_info = self.tree.get_confirmed_path(commit_id)?;
+        self.change_root_to_commit_ids(commit_id)?;
+        Ok(confirm_path_info)
+    }
+
+    /// As [`Self::change_root`], but returns the confirmed commits like
+    /// [`Self::get_confirmed_commit_ids`].
+    pub fn change_root_to_commit_ids(
+        &mut self,
+        commit_id: S::CommitId,
+    ) -> PendResult<(usize, CommitIdVec<S>), S> {
+        let (start_height, commit_ids) = self.tree.change_root(commit_id)?;
 
-        if confirm_path_info.commit_ids.last().is_some() {
+        if commit_ids.last().is_some() {
             // clear current is necessary
             // because apply_commit_id in current.map may be removed from pending part
             self.clear_removed_current();
-            if let Some(current) = self.current.get_mut() {
+            for current in self.current.get_mut() {
                 current.update_rerooted(&self.tree);
             }
+            // the latest confirmed commit changes, drop what was read before it
+            self.confirmed_cache.get_mut().clear();
         }
+        // the aliases of the confirmed commits and of the historical ones are written by the
+        // confirmation, those of the discarded commits are dropped
+        let tree = &self.tree;
+        self.aliases
+            .retain(|_, alias| alias.pending && tree.contains_commit_id(&alias.commit_id));
 
-        Ok(confirm_path_info)
+        Ok((start_height, commit_ids))
+    }
+
+    /// Drops every pending commit and makes `parent_of_root` the latest confirmed commit, with
+    /// the pending root at `height_of_root`. The settings are kept.
+    pub fn reset_root(&mut self, parent_of_root: Option<S::CommitId>, height_of_root: usize) {
+        self.tree.reset(parent_of_root, height_of_root);
+        self.current.get_mut().clear();
+        self.confirmed_cache.get_mut().clear();
+        self.last_added = None;
+        self.aliases.clear();
     }
 }
 
@@ -148,6 +452,26 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
             .iter_historical_changes(&mut accept, commit_id, key)
     }
 
+    /// For each key modified on the path from the pending root to `commit_id`, returns its
+    /// latest value, the height of its latest modification and the number of modifications.
+    #[allow(clippy::type_complexity)]
+    pub fn get_path_key_statuses(
+        &self,
+        commit_id: S::CommitId,
+    ) -> PendResult<BTreeMap<S::Key, (ValueEntry<S::Value>, usize, u64)>, S> {
+        self.tree.get_path_key_statuses(commit_id)
+    }
+
+    /// Returns the keys whose value may differ between the pending commits `from` and `to`: the
+    /// keys modified on the way from one to the other through their closest common ancestor.
+    pub fn get_diff_keys(
+        &self,
+        from: S::CommitId,
+        to: S::CommitId,
+    ) -> PendResult<BTreeSet<S::Key>, S> {
+        self.tree.get_diff_keys(from, to)
+    }
+
     // None: pending_part not know
     // Some(None): pending_part know that this key has been deleted
     // Some(Some(value)): pending_part know this key's value
@@ -159,6 +483,16 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
         self.tree.get_versioned_key(commit_id, key)
     }
 
+    /// Returns the value of `key` at each of `commit_ids`, in order, as [`Self::get_versioned_key`]
+    /// would, with one walk up the tree for the commits on the same branch.
+    pub fn get_versioned_key_multi_commits(
+        &self,
+        commit_ids: &[S::CommitId],
+        key: &S::Key,
+    ) -> PendResult<Vec<Option<ValueEntry<S::Value>>>, S> {
+        self.tree.get_versioned_key_multi_commits(commit_ids, key)
+    }
+
     // alternative method of self.get_versioned_key(),
     // but it invokes self.checkout_current(),
     // thus is only suitable for frequent commit_id
@@ -168,46 +502,288 @@ impl<S: PendingKeyValueSchema> VersionedMap<S> {
         commit_id: S::CommitId,
         key: &S::Key,
     ) -> PendResult<Option<ValueEntry<S::Value>>, S> {
-        // let query node to be self.current
-        let mut guard = self.current.write();
-        self.tree.checkout_current(commit_id, &mut guard)?;
-
-        let current = guard.as_ref().unwrap();
-        Ok(current.get(key).map(|c| c.value.clone()))
+        self.read_current(commit_id, |current| {
+            current.get(key).map(|c| c.value.clone())
+        })
     }
 
     pub fn discard(&mut self, commit_id: S::CommitId) -> PendResult<(), S> {
         self.tree.discard(commit_id)?;
 
         self.clear_removed_current();
+        self.unstage_removed_aliases();
 
         Ok(())
     }
 
-    fn clear_removed_current(&mut self) {
-        let current = self.current.get_mut();
+    /// Removes `commit_id` and all its descendants, e.g. when its block turns out to be invalid,
+    /// and returns the removed commits. The inverse of [`Self::discard`], which keeps
+    /// `commit_id` and removes its siblings. The pending root cannot be removed.
+    pub fn prune_subtree(&mut self, commit_id: S::CommitId) -> PendResult<Vec<S::CommitId>, S> {
+        let removed = self.tree.prune_subtree(commit_id)?;
 
-        let obsoleted_commit_id =
-            |c: &CurrentMap<S>| !self.tree.contains_commit_id(&c.get_commit_id());
+        self.clear_removed_current();
+        self.unstage_removed_aliases();
 
-        if current.as_ref().map_or(false, obsoleted_commit_id) {
-            *current = None;
-        }
+        Ok(removed)
+    }
+
+    fn clear_removed_current(&mut self) {
+        self.current
+            .get_mut()
+            .retain(|c| self.tree.contains_commit_id(&c.get_commit_id()));
+    }
+
+    fn unstage_removed_aliases(&mut self) {
+        let tree = &self.tree;
+        self.aliases
+            .retain(|_, alias| !alias.pending || tree.contains_commit_id(&alias.commit_id));
     }
 
     pub fn get_versioned_store(&self, commit_id: S::CommitId) -> PendResult<KeyValueMap<S>, S> {
+        self.read_current(commit_id, |current| {
+            current
+                .iter()
+                .map(|(k, apply_record)| (k.clone(), apply_record.value.clone()))
+                .collect()
+        })
+    }
+
+    // Reads a current map checked out at `commit_id`. Reads of a commit one already points
+    // to share the read lock, only switching one to another commit takes the write lock.
+    fn read_current<R>(
+        &self,
+        commit_id: S::CommitId,
+        read: impl FnOnce(&CurrentMap<S>) -> R,
+    ) -> PendResult<R, S> {
+        {
+            let guard = self.current.read();
+            if let Some(current) = guard
+                .iter()
+                .find(|current| current.get_commit_id() == commit_id)
+            {
+                return Ok(read(current));
+            }
+        }
+
         // let query node to be self.current
         let mut guard = self.current.write();
-        self.tree.checkout_current(commit_id, &mut guard)?;
+        let (index, steps) =
+            self.tree
+                .checkout_current(commit_id, &mut guard, self.max_current_maps)?;
+        self.metrics.on_checkout(steps);
+        Ok(read(&guard[index]))
+    }
+}
 
-        let current = guard.as_ref().unwrap();
-        Ok(current
+// memory usage and compaction
+impl<S: PendingKeyValueSchema> VersionedMap<S> {
+    /// Returns the approximate memory held by the pending commits and the checked out maps.
+    pub fn memory_usage(&self) -> MemoryStats {
+        let mut stats = self.tree.memory_usage();
+        let num_current_entries: usize = self.current.read().iter().map(|c| c.len()).sum();
+        stats.approx_bytes += num_current_entries * size_of::<(S::Key, ApplyRecord<S>)>();
+        stats
+    }
+
+    /// Tells that the pending `commit_id` is not read any more, e.g. because it is an
+    /// intermediate commit of a long branch. From now on, it is not found by the queries. It is
+    /// kept for its descendants and confirmed with them, until [`Self::compact_branch`] merges
+    /// its modifications into its child.
+    pub fn mark_unaddressable(&mut self, commit_id: S::CommitId) -> PendResult<(), S> {
+        self.tree.mark_unaddressable(commit_id)?;
+        self.clear_removed_current();
+        Ok(())
+    }
+
+    /// Merges the modifications of the commits marked by [`Self::mark_unaddressable`] on the
+    /// path from the pending root to `tip` into the closest commit below them that is not
+    /// merged, keeping the latest modification of each key. A marked commit with several
+    /// children is not merged, as its modifications are shared by its children.
+    ///
+    /// The merged commits are removed, so they are not confirmed: their modifications are
+    /// confirmed with the commit they were merged into, and the heights below them are lowered
+    /// by their number. The reads at the other commits are unchanged.
+    pub fn compact_branch(&mut self, tip: S::CommitId) -> PendResult<(), S> {
+        self.tree.compact_branch(tip)?;
+        // the checked out maps name the merged commits as the last to modify their keys
+        self.current.get_mut().clear();
+        Ok(())
+    }
+}
+
+// aliases of the commits, staged until they are written to the history by a confirmation
+impl<S: PendingKeyValueSchema> VersionedMap<S> {
+    /// Registers `alias` for `commit_id`, pending or already confirmed. The alias of a pending
+    /// commit is written by the confirmation of the commit and dropped if the commit is
+    /// discarded. The alias of a confirmed commit is staged until the next confirmation, which
+    /// writes it again.
+    pub fn stage_alias(&mut self, alias: H256, commit_id: S::CommitId) {
+        let pending = self.contains_commit_id(&commit_id);
+        self.aliases
+            .insert(alias, StagedAlias { commit_id, pending });
+    }
+
+    pub fn get_staged_alias(&self, alias: &H256) -> Option<S::CommitId> {
+        self.aliases.get(alias).map(|alias| alias.commit_id)
+    }
+
+    /// Returns the staged aliases to write when `confirmed` are confirmed: theirs and those of
+    /// the commits already confirmed.
+    pub fn get_aliases_to_confirm(&self, confirmed: &[S::CommitId]) -> Vec<(H256, S::CommitId)> {
+        let confirmed: HashSet<_> = confirmed.iter().collect();
+        self.aliases
             .iter()
-            .map(|(k, apply_record)| (k.clone(), apply_record.value.clone()))
-            .collect())
+            .filter(|(_, alias)| !alias.pending || confirmed.contains(&alias.commit_id))
+            .map(|(name, alias)| (*name, alias.commit_id))
+            .collect()
+    }
+
+    /// Drops the staged aliases of `removed`, confirmed commits removed from the history.
+    pub fn unstage_aliases_of(&mut self, removed: &HashSet<S::CommitId>) {
+        self.aliases
+            .retain(|_, alias| !removed.contains(&alias.commit_id));
     }
 }
 
+// dump and restore
+impl<S: PendingKeyValueSchema> VersionedMap<S>
+where
+    S::Key: Encode + Decode,
+    S::Value: Encode + Decode,
+    S::CommitId: Encode + Decode,
+{
+    /// Writes the pending commits to `writer`, each with its parent, updates and whether it is
+    /// addressable, so that [`Self::restore`] rebuilds them after a restart. The settings and the
+    /// staged aliases are not written.
+    pub fn dump(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(DUMP_MAGIC)?;
+        writer.write_all(&DUMP_FORMAT_VERSION.to_be_bytes())?;
+        write_commit_id::<S>(&mut writer, self.get_parent_of_root())?;
+        writer.write_all(&(self.tree.get_height_of_root() as u64).to_be_bytes())?;
+
+        let nodes = self.tree.get_nodes_in_order();
+        writer.write_all(&(nodes.len() as u64).to_be_bytes())?;
+        for (commit_id, parent, updates, addressable) in nodes {
+            let mut entry = Vec::new();
+            write_field(&mut entry, &commit_id.encode());
+            write_commit_id::<S>(&mut entry, parent)?;
+            entry.push(addressable as u8);
+            entry.extend_from_slice(&(updates.len() as u64).to_be_bytes());
+            for (key, value) in updates {
+                write_field(&mut entry, &key.encode());
+                match value {
+                    ValueEntry::Value(value) => {
+                        entry.push(1);
+                        write_field(&mut entry, &value.encode());
+                    }
+                    ValueEntry::Deleted => entry.push(0),
+                }
+            }
+            writer.write_all(&entry)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Rebuilds the pending part written by [`Self::dump`] by adding its commits again, with
+    /// the default settings.
+    ///
+    /// `parent_of_root` and `height_of_root` are those of the latest confirmed commit of the
+    /// database. Fails with [`StorageError::InvalidBackup`] if the dumped pending part does not
+    /// follow it, e.g. because commits were confirmed after the dump.
+    pub fn restore(
+        mut reader: impl Read,
+        parent_of_root: Option<S::CommitId>,
+        height_of_root: usize,
+    ) -> Result<Self> {
+        if read_array::<8>(&mut reader)? != *DUMP_MAGIC {
+            return Err(StorageError::InvalidBackup("not a pending part dump"));
+        }
+        if read_u32(&mut reader)? != DUMP_FORMAT_VERSION {
+            return Err(StorageError::InvalidBackup("unsupported dump version"));
+        }
+        let dumped_parent_of_root = read_commit_id::<S>(&mut reader)?;
+        let dumped_height_of_root = u64::from_be_bytes(read_array(&mut reader)?);
+        if dumped_parent_of_root != parent_of_root || dumped_height_of_root != height_of_root as u64
+        {
+            return Err(StorageError::InvalidBackup(
+                "pending part does not follow the latest confirmed commit",
+            ));
+        }
+
+        let mut versioned_map = Self::new(parent_of_root, height_of_root);
+        let num_nodes = u64::from_be_bytes(read_array(&mut reader)?);
+        // marked after all the nodes are added, as their children are added under them
+        let mut unaddressable = Vec::new();
+        for _ in 0..num_nodes {
+            let commit_id = read_decoded::<S::CommitId>(&mut reader)?;
+            let parent = read_commit_id::<S>(&mut reader)?;
+            match read_array::<1>(&mut reader)? {
+                [0] => unaddressable.push(commit_id),
+                [1] => {}
+                _ => return Err(StorageError::InvalidBackup("invalid addressable tag")),
+            }
+            let num_updates = u64::from_be_bytes(read_array(&mut reader)?);
+            let mut updates = Vec::new();
+            for _ in 0..num_updates {
+                let key = read_decoded::<S::Key>(&mut reader)?;
+                let value = match read_array::<1>(&mut reader)? {
+                    [0] => None,
+                    [1] => Some(read_decoded::<S::Value>(&mut reader)?),
+                    _ => return Err(StorageError::InvalidBackup("invalid value tag")),
+                };
+                updates.push((key, value));
+            }
+            // the root is dumped without its parent
+            versioned_map.add_node(updates, commit_id, parent.or(parent_of_root))?;
+        }
+        for commit_id in unaddressable {
+            versioned_map.mark_unaddressable(commit_id)?;
+        }
+        versioned_map.last_added = None;
+
+        Ok(versioned_map)
+    }
+}
+
+const DUMP_MAGIC: &[u8; 8] = b"VMPENDNG";
+const DUMP_FORMAT_VERSION: u32 = 2;
+
+fn write_commit_id<S: PendingKeyValueSchema>(
+    writer: &mut impl Write,
+    commit_id: Option<S::CommitId>,
+) -> Result<()>
+where
+    S::CommitId: Encode,
+{
+    match commit_id {
+        Some(commit_id) => {
+            let mut field = vec![1];
+            write_field(&mut field, &commit_id.encode());
+            writer.write_all(&field)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn read_commit_id<S: PendingKeyValueSchema>(reader: &mut impl Read) -> Result<Option<S::CommitId>>
+where
+    S::CommitId: Decode,
+{
+    match read_array::<1>(reader)? {
+        [0] => Ok(None),
+        [1] => Ok(Some(read_decoded::<S::CommitId>(reader)?)),
+        _ => Err(StorageError::InvalidBackup("invalid commit id tag")),
+    }
+}
+
+fn read_decoded<T: ?Sized + Decode>(reader: &mut impl Read) -> Result<T::Owned> {
+    let len = read_u32(reader)?;
+    Ok(T::decode_owned(read_field(reader, len)?)?)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -219,6 +795,7 @@ mod tests {
     };
 
     use super::*;
+    use parking_lot::Mutex;
     use rand::{rngs::StdRng, Rng, SeedableRng};
     use rand_distr::{Distribution, Uniform};
 
@@ -278,14 +855,16 @@ mod tests {
                         },
                     )
                 })
-                .collect();
+                .collect::<Vec<_>>();
 
             if let Some(parent_commit_id) = parent_commit_id {
                 forward_only_tree
-                    .add_non_root_node(i, parent_commit_id, updates_none)
+                    .add_non_root_node(i, parent_commit_id, updates_none, |_| true)
                     .unwrap();
             } else {
-                forward_only_tree.add_root(i, updates_none).unwrap();
+                forward_only_tree
+                    .add_root(i, updates_none, |_| true)
+                    .unwrap();
             }
             versioned_map
                 .add_node(updates, i, parent_commit_id)
@@ -322,6 +901,432 @@ mod tests {
                 assert_eq!(versioned_value_with_checkout, answer);
             }
         }
+
+        for _ in 0..num_query {
+            let commit_ids: Vec<_> = (0..rng.gen_range(0..8))
+                .map(|_| rng.gen_range(1..=num_nodes) as CommitId)
+                .collect();
+            let key = rng.gen_range(0..10);
+            let expected: Vec<_> = commit_ids
+                .iter()
+                .map(|commit_id| versioned_map.get_versioned_key(commit_id, &key).unwrap())
+                .collect();
+            assert_eq!(
+                versioned_map
+                    .get_versioned_key_multi_commits(&commit_ids, &key)
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    fn assert_same_queries(
+        expected: &VersionedMap<TestPendingConfig>,
+        actual: &VersionedMap<TestPendingConfig>,
+        commit_ids: impl Iterator<Item = CommitId>,
+    ) {
+        assert_eq!(expected.get_parent_of_root(), actual.get_parent_of_root());
+        for commit_id in commit_ids {
+            assert_eq!(
+                expected.contains_commit_id(&commit_id),
+                actual.contains_commit_id(&commit_id)
+            );
+            if !expected.contains_commit_id(&commit_id) {
+                continue;
+            }
+            assert_eq!(
+                expected.path_to_root(&commit_id),
+                actual.path_to_root(&commit_id)
+            );
+            assert_eq!(
+                expected.get_height_by_commit_id(commit_id),
+                actual.get_height_by_commit_id(commit_id)
+            );
+            for key in 0..10 {
+                assert_eq!(
+                    expected.get_versioned_key(&commit_id, &key),
+                    actual.get_versioned_key(&commit_id, &key)
+                );
+            }
+            assert_eq!(
+                expected.get_versioned_store(commit_id),
+                actual.get_versioned_store(commit_id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let num_nodes = 30;
+        let mut rng = StdRng::seed_from_u64(7);
+        let (_, mut versioned_map) = generate_random_tree(num_nodes, &mut rng);
+
+        let mut dump = Vec::new();
+        versioned_map.dump(&mut dump).unwrap();
+        let restored = VersionedMap::restore(dump.as_slice(), None, 0).unwrap();
+        assert!(restored.check_consistency(0));
+        assert_same_queries(&versioned_map, &restored, 1..=num_nodes as CommitId);
+
+        // after confirming a child of the root, the remaining commits follow it
+        let new_root = versioned_map.children_of(&1).unwrap()[0];
+        versioned_map.change_root(new_root).unwrap();
+        let mut dump = Vec::new();
+        versioned_map.dump(&mut dump).unwrap();
+        for (parent_of_root, height_of_root) in [(None, 0), (Some(1), 0), (Some(2), 1)] {
+            assert_eq!(
+                VersionedMap::<TestPendingConfig>::restore(
+                    dump.as_slice(),
+                    parent_of_root,
+                    height_of_root
+                )
+                .err(),
+                Some(StorageError::InvalidBackup(
+                    "pending part does not follow the latest confirmed commit"
+                ))
+            );
+        }
+        let restored = VersionedMap::restore(dump.as_slice(), Some(1), 1).unwrap();
+        assert!(restored.check_consistency(1));
+        assert_same_queries(&versioned_map, &restored, 1..=num_nodes as CommitId);
+
+        // an empty pending part
+        let versioned_map = VersionedMap::<TestPendingConfig>::new(Some(5), 3);
+        let mut dump = Vec::new();
+        versioned_map.dump(&mut dump).unwrap();
+        let restored = VersionedMap::restore(dump.as_slice(), Some(5), 3).unwrap();
+        assert!(restored.leaves().is_empty());
+        assert_same_queries(&versioned_map, &restored, std::iter::empty());
+
+        assert_eq!(
+            VersionedMap::<TestPendingConfig>::restore(&b"VKVSNAPS"[..], None, 0).err(),
+            Some(StorageError::InvalidBackup("not a pending part dump"))
+        );
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let num_nodes = 30;
+        let num_threads = 8;
+        let num_query = 200;
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let (forward_only_tree, versioned_map) = generate_random_tree(num_nodes, &mut rng);
+        let tip = num_nodes as CommitId;
+        let answers: Vec<_> = (1..=num_nodes as CommitId)
+            .map(|commit_id| {
+                forward_only_tree
+                    .get_apply_map_from_root_included_for_test(commit_id)
+                    .unwrap()
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for thread in 0..num_threads {
+                let (versioned_map, answers) = (&versioned_map, &answers);
+                scope.spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(thread);
+                    for _ in 0..num_query {
+                        // mostly the tip, with a few switches to other commits
+                        let commit_id = if rng.gen_range(0..10) == 0 {
+                            rng.gen_range(1..=num_nodes) as CommitId
+                        } else {
+                            tip
+                        };
+                        let answer = &answers[commit_id as usize - 1];
+                        let key = rng.gen_range(0..10);
+                        assert_eq!(
+                            versioned_map
+                                .get_versioned_key_with_checkout(commit_id, &key)
+                                .unwrap(),
+                            answer.get(&key).map(|a| a.value)
+                        );
+
+                        let store = versioned_map.get_versioned_store(commit_id).unwrap();
+                        assert_eq!(store.len(), answer.len());
+                        for (key, apply_record) in answer {
+                            assert_eq!(store.get(key), Some(&apply_record.value));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[derive(Default)]
+    struct StepMetrics {
+        checkouts: Mutex<Vec<usize>>,
+    }
+
+    impl StorageMetrics for StepMetrics {
+        fn on_checkout(&self, steps: usize) {
+            self.checkouts.lock().push(steps);
+        }
+    }
+
+    #[test]
+    fn test_change_root_keeps_current() {
+        let metrics = Arc::new(StepMetrics::default());
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+        versioned_map.set_metrics(metrics.clone());
+        // 1 - 2 - 3 - 4 - 5 - 6, each writing key 0 and its own key
+        for commit_id in 1..=6 {
+            let parent = (commit_id > 1).then(|| commit_id - 1);
+            versioned_map
+                .add_node(
+                    [(0, Some(commit_id)), (commit_id, Some(commit_id))],
+                    commit_id,
+                    parent,
+                )
+                .unwrap();
+        }
+        versioned_map
+            .get_versioned_key_with_checkout(6, &0)
+            .unwrap();
+        metrics.checkouts.lock().clear();
+
+        // confirming an ancestor of the checked out commit keeps the current map
+        versioned_map.change_root(4).unwrap();
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(6, &0),
+            Ok(Some(ValueEntry::Value(6)))
+        );
+        // the keys last written by the confirmed commits are left to the history
+        for key in 1..=3 {
+            assert_eq!(
+                versioned_map.get_versioned_key_with_checkout(6, &key),
+                Ok(None)
+            );
+        }
+        for key in 4..=6 {
+            assert_eq!(
+                versioned_map.get_versioned_key_with_checkout(6, &key),
+                Ok(Some(ValueEntry::Value(key)))
+            );
+        }
+        assert_eq!(
+            versioned_map.get_versioned_store(6).unwrap(),
+            versioned_map
+                .tree
+                .get_apply_map_from_root_included_for_test(6)
+                .unwrap()
+                .into_iter()
+                .map(|(key, apply_record)| (key, apply_record.value))
+                .collect()
+        );
+        assert!(metrics.checkouts.lock().is_empty());
+
+        // a child of the checked out commit is added without moving it
+        versioned_map.add_node([(0, Some(7))], 7, Some(6)).unwrap();
+        assert_eq!(*metrics.checkouts.lock(), vec![0]);
+
+        // confirming the checked out commit itself drops it
+        versioned_map.change_root(7).unwrap();
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(7, &0),
+            Ok(Some(ValueEntry::Value(7)))
+        );
+        assert_eq!(*metrics.checkouts.lock(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_multiple_current_maps() {
+        // 1 - 2 - ... - 11
+        //   \ 12 - ... - 21
+        // each writing key 0 and its own key
+        let build = |max_current_maps| {
+            let metrics = Arc::new(StepMetrics::default());
+            let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+            versioned_map.set_metrics(metrics.clone());
+            versioned_map.set_max_current_maps(max_current_maps);
+            for commit_id in 1..=21 {
+                let parent = match commit_id {
+                    1 => None,
+                    12 => Some(1),
+                    _ => Some(commit_id - 1),
+                };
+                versioned_map
+                    .add_node(
+                        [(0, Some(commit_id)), (commit_id, Some(commit_id))],
+                        commit_id,
+                        parent,
+                    )
+                    .unwrap();
+            }
+            metrics.checkouts.lock().clear();
+            (versioned_map, metrics)
+        };
+        let alternate = |versioned_map: &VersionedMap<TestPendingConfig>| {
+            for _ in 0..3 {
+                for commit_id in [11, 21] {
+                    assert_eq!(
+                        versioned_map.get_versioned_key_with_checkout(commit_id, &0),
+                        Ok(Some(ValueEntry::Value(commit_id)))
+                    );
+                    assert_eq!(
+                        versioned_map.get_versioned_store(commit_id).unwrap(),
+                        versioned_map
+                            .tree
+                            .get_apply_map_from_root_included_for_test(commit_id)
+                            .unwrap()
+                            .into_iter()
+                            .map(|(key, apply_record)| (key, apply_record.value))
+                            .collect()
+                    );
+                }
+            }
+        };
+
+        // a single map is rebuilt from the root at each switch, which is cheaper than
+        // crossing the fork
+        let (versioned_map, metrics) = build(1);
+        alternate(&versioned_map);
+        assert_eq!(*metrics.checkouts.lock(), vec![11; 6]);
+
+        // adding the branches left the maps next to their tips, then both stay checked out
+        let (mut versioned_map, metrics) = build(2);
+        alternate(&versioned_map);
+        assert_eq!(*metrics.checkouts.lock(), vec![1, 1]);
+
+        // the closest map moves, the other one is kept
+        metrics.checkouts.lock().clear();
+        versioned_map
+            .add_node([(0, Some(22))], 22, Some(20))
+            .unwrap();
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(22, &0),
+            Ok(Some(ValueEntry::Value(22)))
+        );
+        alternate(&versioned_map);
+        assert_eq!(*metrics.checkouts.lock(), vec![1, 1, 2]);
+
+        // the maps of the removed commits are dropped
+        versioned_map.prune_subtree(12).unwrap();
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(11, &0),
+            Ok(Some(ValueEntry::Value(11)))
+        );
+        assert_eq!(versioned_map.current.read().len(), 1);
+    }
+
+    #[test]
+    fn test_checkout_copies_small_maps() {
+        // 1 - 2 - ... - 10, each writing key 0, and 1 writing `extra_keys` more
+        let build = |extra_keys: u64| {
+            let metrics = Arc::new(StepMetrics::default());
+            let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+            versioned_map.set_metrics(metrics.clone());
+            versioned_map.set_max_current_maps(2);
+            let extra = (100..100 + extra_keys).map(|key| (key, Some(key)));
+            versioned_map
+                .add_node([(0, Some(1))].into_iter().chain(extra), 1, None)
+                .unwrap();
+            for commit_id in 2..=10 {
+                versioned_map
+                    .add_node([(0, Some(commit_id))], commit_id, Some(commit_id - 1))
+                    .unwrap();
+            }
+            versioned_map.current.write().clear();
+            versioned_map
+                .get_versioned_key_with_checkout(10, &0)
+                .unwrap();
+            metrics.checkouts.lock().clear();
+            (versioned_map, metrics)
+        };
+
+        // copying the map of 10 and rolling back one commit is cheaper than the walk from the
+        // root
+        let (versioned_map, metrics) = build(0);
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(9, &0),
+            Ok(Some(ValueEntry::Value(9)))
+        );
+        assert_eq!(*metrics.checkouts.lock(), vec![1]);
+        assert_eq!(versioned_map.current.read().len(), 2);
+
+        // a larger map is not copied
+        let (versioned_map, metrics) = build(20);
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(9, &0),
+            Ok(Some(ValueEntry::Value(9)))
+        );
+        assert_eq!(*metrics.checkouts.lock(), vec![9]);
+        assert_eq!(versioned_map.current.read().len(), 2);
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(10, &100),
+            Ok(Some(ValueEntry::Value(100)))
+        );
+        // the map of 10 is still checked out
+        assert_eq!(*metrics.checkouts.lock(), vec![9]);
+    }
+
+    #[test]
+    fn test_prune_subtree() {
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+        // 1 - 2 - 3 - 4
+        //       \ 5 - 6
+        //       \ 7
+        for (commit_id, parent, value) in [
+            (1, None, 1),
+            (2, Some(1), 2),
+            (3, Some(2), 3),
+            (4, Some(3), 4),
+            (5, Some(2), 5),
+            (6, Some(5), 6),
+            (7, Some(2), 7),
+        ] {
+            versioned_map
+                .add_node([(0, Some(value))], commit_id, parent)
+                .unwrap();
+        }
+        // the current map points into the pruned subtree
+        versioned_map.get_versioned_store(6).unwrap();
+
+        assert_eq!(
+            versioned_map.prune_subtree(1),
+            Err(PendingError::CannotPrunePendingRoot(1))
+        );
+        assert_eq!(
+            versioned_map.prune_subtree(8),
+            Err(PendingError::CommitIDNotFound(8))
+        );
+
+        let mut removed = versioned_map.prune_subtree(5).unwrap();
+        assert_eq!(removed.remove(0), 5);
+        assert_eq!(removed, vec![6]);
+        assert!(versioned_map.check_consistency(0));
+        for commit_id in [5, 6] {
+            assert!(!versioned_map.contains_commit_id(&commit_id));
+            assert_eq!(
+                versioned_map.get_versioned_store(commit_id),
+                Err(PendingError::CommitIDNotFound(commit_id))
+            );
+        }
+        // the remaining branches are unchanged
+        for commit_id in [1, 2, 3, 4, 7] {
+            assert_eq!(
+                versioned_map.get_versioned_key(&commit_id, &0),
+                Ok(Some(ValueEntry::Value(commit_id)))
+            );
+            assert_eq!(
+                versioned_map.get_versioned_key_with_checkout(commit_id, &0),
+                Ok(Some(ValueEntry::Value(commit_id)))
+            );
+        }
+
+        // mid-branch, then confirmation past the fork
+        assert_eq!(versioned_map.prune_subtree(3), Ok(vec![3, 4]));
+        let confirmed = versioned_map.change_root(7).unwrap();
+        assert_eq!(confirmed.start_height, 0);
+        assert_eq!(confirmed.commit_ids, vec![1, 2]);
+        assert!(versioned_map.check_consistency(2));
+        assert_eq!(
+            versioned_map.get_versioned_key(&7, &0),
+            Ok(Some(ValueEntry::Value(7)))
+        );
+        assert_eq!(
+            versioned_map.prune_subtree(7),
+            Err(PendingError::CannotPrunePendingRoot(7))
+        );
     }
 
     #[test]
@@ -329,11 +1334,13 @@ mod tests {
         let mut forward_only_tree = Tree::<TestPendingConfig>::new(None, 0);
         let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
 
-        forward_only_tree.add_root(0, BTreeMap::new()).unwrap();
+        forward_only_tree
+            .add_root(0, BTreeMap::new(), |_| true)
+            .unwrap();
         versioned_map.add_node(BTreeMap::new(), 0, None).unwrap();
 
         assert_eq!(
-            forward_only_tree.add_root(1, BTreeMap::new()),
+            forward_only_tree.add_root(1, BTreeMap::new(), |_| true),
             Err(PendingError::MultipleRootsNotAllowed)
         );
         assert_eq!(
@@ -348,7 +1355,7 @@ mod tests {
         let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
 
         assert_eq!(
-            forward_only_tree.add_non_root_node(1, 0, BTreeMap::new()),
+            forward_only_tree.add_non_root_node(1, 0, BTreeMap::new(), |_| true),
             Err(PendingError::CommitIDNotFound(0))
         );
         assert_eq!(
@@ -362,11 +1369,13 @@ mod tests {
         let mut forward_only_tree = Tree::<TestPendingConfig>::new(None, 0);
         let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
 
-        forward_only_tree.add_root(0, BTreeMap::new()).unwrap();
+        forward_only_tree
+            .add_root(0, BTreeMap::new(), |_| true)
+            .unwrap();
         versioned_map.add_node(BTreeMap::new(), 0, None).unwrap();
 
         assert_eq!(
-            forward_only_tree.add_non_root_node(0, 0, BTreeMap::new()),
+            forward_only_tree.add_non_root_node(0, 0, BTreeMap::new(), |_| true),
             Err(PendingError::CommitIdAlreadyExists(0))
         );
         assert_eq!(
@@ -374,4 +1383,294 @@ mod tests {
             Err(PendingError::CommitIdAlreadyExists(0))
         );
     }
+
+    #[test]
+    fn test_compact_branch() {
+        // 1 - 2 - ... - 10
+        //               \ 11 under 5
+        // each writing key 0 and its own key
+        let build = || {
+            let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+            for commit_id in 1..=11 {
+                let parent = match commit_id {
+                    1 => None,
+                    11 => Some(5),
+                    _ => Some(commit_id - 1),
+                };
+                versioned_map
+                    .add_node(
+                        [(0, Some(commit_id)), (commit_id, Some(commit_id))],
+                        commit_id,
+                        parent,
+                    )
+                    .unwrap();
+            }
+            // 5 has two children, so it is kept with its modifications
+            for commit_id in [2, 3, 4, 5, 7, 8] {
+                versioned_map.mark_unaddressable(commit_id).unwrap();
+            }
+            versioned_map
+        };
+        let expected = build();
+        let mut versioned_map = build();
+        let surviving = [1, 6, 9, 10, 11];
+
+        for commit_id in [3, 7] {
+            assert!(!versioned_map.contains_commit_id(&commit_id));
+            assert_eq!(
+                versioned_map.get_versioned_key(&commit_id, &0),
+                Err(PendingError::CommitIDNotFound(commit_id))
+            );
+        }
+        assert_eq!(versioned_map.path_to_root(&10), Ok(vec![10, 9, 6, 1]));
+        // the current map is moved to 10 and back to 11 through the marked commits
+        versioned_map.get_versioned_store(10).unwrap();
+        versioned_map.get_versioned_store(11).unwrap();
+
+        let before = versioned_map.memory_usage();
+        versioned_map.compact_branch(10).unwrap();
+        let after = versioned_map.memory_usage();
+        assert!(versioned_map.check_consistency(0));
+        assert_eq!(after.num_nodes, before.num_nodes - 5);
+        // key 0 of 2, 3, 4, 7 and 8
+        assert_eq!(after.num_modifications, before.num_modifications - 5);
+        assert!(after.approx_bytes < before.approx_bytes);
+
+        for commit_id in 1..=11 {
+            assert_eq!(
+                versioned_map.contains_commit_id(&commit_id),
+                surviving.contains(&commit_id)
+            );
+        }
+        for commit_id in surviving {
+            assert_eq!(
+                versioned_map.path_to_root(&commit_id),
+                expected.path_to_root(&commit_id)
+            );
+            assert_eq!(
+                versioned_map.get_versioned_store(commit_id),
+                expected.get_versioned_store(commit_id)
+            );
+            for key in 0..=11 {
+                assert_eq!(
+                    versioned_map.get_versioned_key_with_checkout(commit_id, &key),
+                    expected.get_versioned_key(&commit_id, &key)
+                );
+            }
+        }
+        // the modifications of 7 and 8 are now made by 9
+        let mut changes = Vec::new();
+        versioned_map
+            .iter_historical_changes(
+                |commit_id, _, value| {
+                    changes.push((*commit_id, value.copied()));
+                    true
+                },
+                &10,
+                &7,
+            )
+            .unwrap();
+        assert_eq!(changes, vec![(9, Some(7))]);
+
+        // a node added after the compaction follows the merged modifications
+        versioned_map
+            .add_node([(8, Some(12))], 12, Some(10))
+            .unwrap();
+        let mut changes = Vec::new();
+        versioned_map
+            .iter_historical_changes(
+                |commit_id, _, value| {
+                    changes.push((*commit_id, value.copied()));
+                    true
+                },
+                &12,
+                &8,
+            )
+            .unwrap();
+        assert_eq!(changes, vec![(12, Some(12)), (9, Some(8))]);
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(11, &8),
+            Ok(None)
+        );
+
+        // a dump keeps the marks
+        let mut dump = Vec::new();
+        versioned_map.dump(&mut dump).unwrap();
+        let restored = VersionedMap::restore(dump.as_slice(), None, 0).unwrap();
+        assert!(restored.check_consistency(0));
+        assert_same_queries(&versioned_map, &restored, 1..=12);
+
+        // 2, 3 and 4 are merged into 5, and 7 and 8 into 9, lowering the heights below them
+        assert_eq!(versioned_map.get_height_by_commit_id(6), Ok(2));
+        assert_eq!(versioned_map.get_height_by_commit_id(11), Ok(2));
+        assert_eq!(versioned_map.get_height_by_commit_id(12), Ok(5));
+
+        // the merged commits are not confirmed, their modifications are with 5
+        let confirmed = versioned_map.change_root(6).unwrap();
+        assert_eq!(confirmed.commit_ids, vec![1, 5]);
+        assert_eq!(
+            confirmed.key_value_maps[1].get(&3),
+            Some(&ValueEntry::Value(3))
+        );
+        assert!(versioned_map.check_consistency(2));
+        for commit_id in [3, 7] {
+            assert_eq!(
+                versioned_map.get_versioned_store(commit_id),
+                Err(PendingError::CommitIDNotFound(commit_id))
+            );
+        }
+        assert_eq!(
+            versioned_map.get_versioned_key(&12, &0),
+            expected.get_versioned_key(&10, &0)
+        );
+    }
+
+    fn add_chain(
+        versioned_map: &mut VersionedMap<TestPendingConfig>,
+        num_nodes: CommitId,
+    ) -> PendResult<(), TestPendingConfig> {
+        for i in 1..=num_nodes {
+            let parent_commit_id = (i > 1).then_some(i - 1);
+            versioned_map.add_node(vec![(i, Some(i))], i, parent_commit_id)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let limit = 5;
+
+        // the root is at depth 0, so a chain of `limit + 1` nodes reaches the limit exactly
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 10);
+        versioned_map.set_max_depth(Some(limit));
+        add_chain(&mut versioned_map, limit as CommitId + 1).unwrap();
+        assert!(versioned_map.check_consistency(10));
+
+        // one past the limit
+        let next = limit as CommitId + 2;
+        assert_eq!(
+            versioned_map.add_node(vec![], next, Some(next - 1)),
+            Err(PendingError::MaxDepthExceeded {
+                depth: limit + 1,
+                limit
+            })
+        );
+        assert!(!versioned_map.contains_commit_id(&next));
+
+        // siblings at allowed depths are still accepted
+        versioned_map.add_node(vec![], next, Some(1)).unwrap();
+
+        // the depth is measured from the current root
+        versioned_map.change_root(2).unwrap();
+        versioned_map
+            .add_node(vec![], next + 1, Some(next - 1))
+            .unwrap();
+
+        // no limit
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+        versioned_map.set_max_depth(None);
+        add_chain(&mut versioned_map, limit as CommitId * 4).unwrap();
+    }
+
+    #[test]
+    fn test_add_nodes() {
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+        versioned_map.set_max_depth(Some(3));
+        add_chain(&mut versioned_map, 2).unwrap();
+
+        // 1 - 2 - 3 - 4 - 5: validated, but 5 is too deep once 3 and 4 are added
+        let batch = |commit_ids: &[CommitId]| {
+            commit_ids
+                .iter()
+                .map(|&i| (i, Some(i - 1), vec![(1, Some(i))]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            versioned_map.add_nodes(batch(&[3, 4, 5])),
+            Err(PendingError::MaxDepthExceeded { depth: 4, limit: 3 })
+        );
+        assert!(!versioned_map.contains_commit_id(&3));
+        assert!(!versioned_map.contains_commit_id(&4));
+        assert!(versioned_map.check_consistency(0));
+        assert_eq!(versioned_map.get_last_added(), Some(2));
+
+        // the current map was left at the removed node 4
+        assert_eq!(
+            versioned_map
+                .get_versioned_key_with_checkout(2, &1)
+                .unwrap(),
+            Some(ValueEntry::Value(1))
+        );
+        assert_eq!(
+            versioned_map.get_versioned_key_with_checkout(4, &1),
+            Err(PendingError::CommitIDNotFound(4))
+        );
+
+        // nothing is added if the chain does not resolve
+        assert_eq!(
+            versioned_map.add_nodes(vec![(3, Some(2), vec![]), (5, Some(4), vec![])]),
+            Err(PendingError::CommitIDNotFound(4))
+        );
+        assert_eq!(
+            versioned_map.add_nodes(vec![(3, Some(2), vec![]), (3, Some(1), vec![])]),
+            Err(PendingError::CommitIdAlreadyExists(3))
+        );
+        assert!(!versioned_map.contains_commit_id(&3));
+
+        versioned_map.add_nodes(batch(&[3, 4])).unwrap();
+        assert_eq!(
+            versioned_map
+                .get_versioned_key_with_checkout(4, &1)
+                .unwrap(),
+            Some(ValueEntry::Value(4))
+        );
+        assert_eq!(versioned_map.get_last_added(), Some(4));
+    }
+
+    #[test]
+    fn test_arena_compaction() {
+        // 1 - 2 - ... - 20, each with a child 101, 102, ... off the chain
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, 0);
+        add_chain(&mut versioned_map, 20).unwrap();
+        for i in 1..=20 {
+            versioned_map
+                .add_node(vec![(i, None), (0, Some(i))], 100 + i, Some(i))
+                .unwrap();
+        }
+        assert_eq!(versioned_map.tree.num_arena_entries(), 60);
+
+        for new_root in [5, 12, 20] {
+            versioned_map.change_root(new_root).unwrap();
+            assert!(versioned_map.check_consistency(new_root as usize - 1));
+            for i in new_root..=20 {
+                assert_eq!(
+                    versioned_map.get_versioned_key(&i, &i).unwrap(),
+                    Some(ValueEntry::Value(i))
+                );
+                assert_eq!(
+                    versioned_map
+                        .get_versioned_key_with_checkout(100 + i, &0)
+                        .unwrap(),
+                    Some(ValueEntry::Value(i))
+                );
+            }
+        }
+
+        // only 20 and 120 are left
+        assert_eq!(versioned_map.tree.num_arena_entries(), 3);
+    }
+
+    #[test]
+    fn test_height_overflow() {
+        let mut versioned_map = VersionedMap::<TestPendingConfig>::new(None, usize::MAX - 1);
+        versioned_map.set_max_depth(None);
+        add_chain(&mut versioned_map, 2).unwrap();
+
+        assert_eq!(
+            versioned_map.add_node(vec![], 3, Some(2)),
+            Err(PendingError::HeightOverflow)
+        );
+        assert!(!versioned_map.contains_commit_id(&3));
+        assert!(versioned_map.check_consistency(usize::MAX - 1));
+    }
 }
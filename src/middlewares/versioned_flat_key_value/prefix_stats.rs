@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use super::{
+    get_versioned_entry,
+    table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
+};
+use crate::{
+    backends::{serde::Encode, TableReader},
+    errors::Result,
+    middlewares::{HistoryNumber, KeyValueStoreBulks},
+    types::KeyLookup,
+};
+
+/// How [`confirm_maps_to_history_with_stats`](super::confirm_maps_to_history_with_stats)
+/// groups the confirmed keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixStatsConfig {
+    /// Number of leading bytes of the encoded key that form its bucket.
+    pub prefix_len: usize,
+    /// Number of buckets tracked separately. Keys of further prefixes are counted in
+    /// [`PrefixStats::other`].
+    pub max_buckets: usize,
+}
+
+/// Changes to the keys of one bucket. `value_bytes` sums the encoded inserted and updated values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefixCounts {
+    pub inserts: u64,
+    pub updates: u64,
+    pub deletes: u64,
+    pub value_bytes: u64,
+}
+
+/// Changes of a confirmation, grouped by key prefix.
+///
+/// A write to a key without a value before it, never written or deleted, is an insert.
+/// A deletion is counted whether or not the key had a value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    pub buckets: BTreeMap<Vec<u8>, PrefixCounts>,
+    pub other: PrefixCounts,
+}
+
+impl PrefixStats {
+    fn bucket_mut(&mut self, config: &PrefixStatsConfig, encoded_key: &[u8]) -> &mut PrefixCounts {
+        let prefix = &encoded_key[..config.prefix_len.min(encoded_key.len())];
+        if !self.buckets.contains_key(prefix) && self.buckets.len() >= config.max_buckets {
+            return &mut self.other;
+        }
+        self.buckets.entry(prefix.to_vec()).or_default()
+    }
+}
+
+// Collects the stats of one confirmation. Whether a key had a value is read from the history
+// below the confirmation, then tracked in `has_value` for the heights confirmed together.
+pub(super) struct PrefixStatsCollector<'a, T: VersionedKeyValueSchema> {
+    config: &'a PrefixStatsConfig,
+    stats: PrefixStats,
+    has_value: BTreeMap<T::Key, bool>,
+}
+
+impl<'a, T: VersionedKeyValueSchema> PrefixStatsCollector<'a, T> {
+    pub fn new(config: &'a PrefixStatsConfig) -> Self {
+        Self {
+            config,
+            stats: PrefixStats::default(),
+            has_value: BTreeMap::new(),
+        }
+    }
+
+    pub fn record<'db>(
+        &mut self,
+        history_number: HistoryNumber,
+        updates: &[(T::Key, Option<T::Value>)],
+        history_index_table: &TableReader<'db, HistoryIndicesTable<T>>,
+        change_history_table: &KeyValueStoreBulks<'db, HistoryChangeTable<T>>,
+    ) -> Result<()> {
+        for (key, value) in updates {
+            let had_value = match self.has_value.get(key) {
+                Some(had_value) => *had_value,
+                None => matches!(
+                    get_versioned_entry(
+                        history_number - 1,
+                        key,
+                        history_index_table,
+                        change_history_table
+                    )?,
+                    KeyLookup::Value(_)
+                ),
+            };
+            self.has_value.insert(key.clone(), value.is_some());
+
+            let counts = self.stats.bucket_mut(self.config, &key.encode());
+            match value {
+                Some(value) => {
+                    if had_value {
+                        counts.updates += 1;
+                    } else {
+                        counts.inserts += 1;
+                    }
+                    counts.value_bytes += value.encode().len() as u64;
+                }
+                None => counts.deletes += 1,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> PrefixStats {
+        self.stats
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use super::{
+    table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
+    HistoryIndexKey,
+};
+use crate::{
+    backends::{serde::Encode, DatabaseTrait, TableRead},
+    errors::Result,
+    middlewares::{
+        commit_id_schema::{height_to_history_number, history_number_to_height, latest_confirmed},
+        HistoryNumber, KeyValueStoreBulks,
+    },
+    traits::KeyValueStoreBulksTrait,
+};
+
+/// Number of keys reported in [`PolicyEstimate::top_keys`].
+pub const RECLAIM_TOP_KEYS: usize = 20;
+
+/// A rule deciding which historical records may be removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Keep what is needed to read any commit at or above the height.
+    /// Versions of a key superseded at or below the height are reclaimable.
+    PruneBeforeHeight(usize),
+    /// Forget keys whose latest version is a deletion at least this many
+    /// heights below the latest confirmed height. All their versions are reclaimable.
+    TombstoneExpiry(usize),
+}
+
+/// Space a [`RetentionPolicy`] would reclaim, in encoded bytes of keys and values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyEstimate<K> {
+    pub policy: RetentionPolicy,
+    pub index_records: u64,
+    pub index_bytes: u64,
+    pub change_records: u64,
+    pub change_bytes: u64,
+    /// Keys with the most reclaimable bytes, largest first.
+    pub top_keys: Vec<(K, u64)>,
+}
+
+impl<K> PolicyEstimate<K> {
+    fn new(policy: RetentionPolicy) -> Self {
+        Self {
+            policy,
+            index_records: 0,
+            index_bytes: 0,
+            change_records: 0,
+            change_bytes: 0,
+            top_keys: Vec::new(),
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.index_bytes + self.change_bytes
+    }
+
+    fn add_key(&mut self, key: &K, versions: &[VersionSize])
+    where
+        K: Clone,
+    {
+        let mut key_bytes = 0;
+        for version in versions {
+            self.index_records += 1;
+            self.index_bytes += version.index_bytes;
+            key_bytes += version.index_bytes;
+            if let Some(change_bytes) = version.change_bytes {
+                self.change_records += 1;
+                self.change_bytes += change_bytes;
+                key_bytes += change_bytes;
+            }
+        }
+        if key_bytes == 0 {
+            return;
+        }
+
+        if self.top_keys.len() == RECLAIM_TOP_KEYS {
+            if self.top_keys.last().unwrap().1 >= key_bytes {
+                return;
+            }
+            self.top_keys.pop();
+        }
+        let position = self
+            .top_keys
+            .partition_point(|(_, bytes)| *bytes >= key_bytes);
+        self.top_keys.insert(position, (key.clone(), key_bytes));
+    }
+}
+
+/// Result of [`estimate_reclaimable`], with one estimate per policy in the given order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReclaimEstimate<K> {
+    pub index_records_scanned: u64,
+    pub policies: Vec<PolicyEstimate<K>>,
+}
+
+// Encoded size of one version of a key. `change_bytes` is `None` for a deletion,
+// which has no row in the change table.
+struct VersionSize {
+    history_number: HistoryNumber,
+    index_bytes: u64,
+    change_bytes: Option<u64>,
+}
+
+/// Estimates the space each of `policies` would reclaim from the history of `T`, without writing.
+///
+/// The history index is scanned once, and the change table is read for each index record.
+/// `progress` is called with the number of index records scanned after each key.
+pub fn estimate_reclaimable<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    policies: &[RetentionPolicy],
+    mut progress: impl FnMut(u64),
+) -> Result<ReclaimEstimate<T::Key>> {
+    let history_index_table = db.view::<HistoryIndicesTable<T>>()?;
+    let change_history_table =
+        KeyValueStoreBulks::new(Arc::new(db.view::<HistoryChangeTable<T>>()?));
+
+    let mut estimates: Vec<_> = policies.iter().copied().map(PolicyEstimate::new).collect();
+    let Some((latest_history_number, _)) = latest_confirmed(db)? else {
+        return Ok(ReclaimEstimate {
+            index_records_scanned: 0,
+            policies: estimates,
+        });
+    };
+    let latest_height = history_number_to_height(latest_history_number);
+
+    let mut classify = |key: &T::Key, versions: &[VersionSize]| {
+        for estimate in estimates.iter_mut() {
+            let reclaimable = match estimate.policy {
+                RetentionPolicy::PruneBeforeHeight(height) => {
+                    let history_number = height_to_history_number(height);
+                    match versions
+                        .iter()
+                        .position(|version| version.history_number <= history_number)
+                    {
+                        Some(kept) => &versions[kept + 1..],
+                        None => &[],
+                    }
+                }
+                RetentionPolicy::TombstoneExpiry(ttl) => {
+                    let latest = &versions[0];
+                    let expired = history_number_to_height(latest.history_number)
+                        .checked_add(ttl)
+                        .map_or(false, |expiry| expiry <= latest_height);
+                    if latest.change_bytes.is_none() && expired {
+                        versions
+                    } else {
+                        &[]
+                    }
+                }
+            };
+            estimate.add_key(key, reclaimable);
+        }
+    };
+
+    let mut scanned = 0;
+    // versions of the current key, from the latest to the earliest
+    let mut current: Option<(T::Key, Vec<VersionSize>)> = None;
+    for item in history_index_table.iter_from_start()? {
+        let (k_with_history_number, indices) = item?;
+        let HistoryIndexKey(key, history_number) = k_with_history_number.as_ref();
+
+        let index_bytes = (k_with_history_number.encode().len() + indices.encode().len()) as u64;
+        let change_bytes = change_history_table
+            .get_versioned_key(history_number, key)?
+            .map(|value| {
+                (std::mem::size_of::<HistoryNumber>() + key.encode().len() + value.encode().len())
+                    as u64
+            });
+        let version = VersionSize {
+            history_number: *history_number,
+            index_bytes,
+            change_bytes,
+        };
+
+        if matches!(&current, Some((current_key, _)) if current_key == key) {
+            current.as_mut().unwrap().1.push(version);
+        } else if let Some((last_key, versions)) = current.replace((key.clone(), vec![version])) {
+            classify(&last_key, &versions);
+            progress(scanned);
+        }
+        scanned += 1;
+    }
+    if let Some((last_key, versions)) = current {
+        classify(&last_key, &versions);
+        progress(scanned);
+    }
+
+    Ok(ReclaimEstimate {
+        index_records_scanned: scanned,
+        policies: estimates,
+    })
+}
@@ -7,7 +7,9 @@ use crate::middlewares::{decode_history_number_rev, encode_history_number_rev, H
 
 impl<K: Clone + Encode> Encode for HistoryIndexKey<K> {
     fn encode(&self) -> Cow<[u8]> {
-        let mut ans = self.0.encode().into_owned();
+        let key = self.0.encode();
+        let mut ans = Vec::with_capacity(key.len() + std::mem::size_of::<HistoryNumber>());
+        ans.extend_from_slice(&key);
         ans.extend_from_slice(&encode_history_number_rev(self.1));
         Cow::Owned(ans)
     }
@@ -54,10 +56,10 @@ impl<K: Clone + Decode + ToOwned<Owned = K>> Decode for HistoryIndexKey<K> {
             return Err(DecodeError::IncorrectLength);
         }
 
-        let version_raw = input.split_off(input.len() - BYTES);
-        let key_raw = input;
-        let version = decode_history_number_rev(&version_raw);
-        let key = K::decode_owned(key_raw)?;
+        // the key keeps the allocation of the input
+        let version = decode_history_number_rev(&input[input.len() - BYTES..]);
+        input.truncate(input.len() - BYTES);
+        let key = K::decode_owned(input)?;
         Ok(HistoryIndexKey(key, version))
     }
 }
@@ -77,3 +79,84 @@ impl Decode for HistoryIndices {
         Ok(Cow::Owned(HistoryIndices))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::H256;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::middlewares::versioned_flat_key_value::HistoryChangeKey;
+
+    // decoding corrupted records fails instead of panicking, and a decoded record encodes back
+    // to its input
+    fn check_decode<T: ?Sized + Decode + Encode>(input: &[u8]) {
+        if let Ok(decoded) = T::decode(input) {
+            assert_eq!(T::encode(&decoded).as_ref(), input);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn test_decode_arbitrary_bytes(input in prop::collection::vec(any::<u8>(), 0..48)) {
+            check_decode::<HistoryIndices>(&input);
+            check_decode::<HistoryIndexKey<u64>>(&input);
+            check_decode::<HistoryIndexKey<Box<[u8]>>>(&input);
+            check_decode::<HistoryIndexKey<H256>>(&input);
+            check_decode::<HistoryChangeKey<u64>>(&input);
+            check_decode::<HistoryChangeKey<Box<[u8]>>>(&input);
+            check_decode::<HistoryChangeKey<H256>>(&input);
+        }
+    }
+
+    // the shared pair encoding keeps the layout of the tables
+    #[test]
+    fn test_layout() {
+        let change_key = HistoryChangeKey::<u64>::new(3, 7).encode().into_owned();
+        assert_eq!(
+            change_key,
+            [[0, 0, 0, 0, 0, 0, 0, 3], 7u64.to_be_bytes()].concat()
+        );
+        assert_eq!(change_key, (3u64, 7u64).encode().as_ref());
+
+        let bytes_key: Box<[u8]> = Box::new([1, 2]);
+        let change_key = HistoryChangeKey::new(3, bytes_key.clone())
+            .encode()
+            .into_owned();
+        assert_eq!(change_key, [0, 0, 0, 0, 0, 0, 0, 3, 1, 2]);
+        assert_eq!(
+            HistoryChangeKey::<Box<[u8]>>::decode(&change_key)
+                .unwrap()
+                .into_owned(),
+            HistoryChangeKey::new(3, bytes_key)
+        );
+
+        let index_key = HistoryIndexKey(7u64, 3).encode().into_owned();
+        assert_eq!(
+            index_key,
+            [7u64.to_be_bytes(), (!3u64).to_be_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let index_key = HistoryIndexKey(7u64, 3).encode().into_owned();
+        let change_key = HistoryChangeKey::<u64>::new(3, 7).encode().into_owned();
+        for len in 0..index_key.len() {
+            assert_eq!(
+                HistoryIndexKey::<u64>::decode(&index_key[..len]).err(),
+                Some(DecodeError::IncorrectLength)
+            );
+            assert_eq!(
+                HistoryChangeKey::<u64>::decode(&change_key[..len]).err(),
+                Some(DecodeError::IncorrectLength)
+            );
+        }
+        assert_eq!(
+            HistoryIndices::decode(&[0]).err(),
+            Some(DecodeError::IncorrectLength)
+        );
+    }
+}
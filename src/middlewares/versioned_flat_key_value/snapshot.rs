@@ -0,0 +1,204 @@
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use blake2::{Blake2s256, Digest};
+use ethereum_types::H256;
+
+use super::{
+    manager_impl::HistoryIter,
+    table_schema::{HistoryChangeTable, HistoryIndicesTable, VersionedKeyValueSchema},
+    HistoryIndexKey, HistoryIndices,
+};
+use crate::{
+    backends::{
+        serde::{Decode, Encode},
+        DatabaseTrait, TableRead, TableReader, TableSchema, WriteSchemaTrait,
+    },
+    errors::{DecodeError, Result},
+    middlewares::{
+        ChangeKey, CommitID, CommitIDSchema, HistoryNumber, HistoryNumberSchema, KeyValueStoreBulks,
+    },
+    types::ValueEntry,
+    StorageError,
+};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"VKVSNAPS";
+const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+// written in place of the key length after the last entry
+const END_OF_ENTRIES: u32 = u32::MAX;
+
+/// Describes a snapshot written by [`export_snapshot`].
+///
+/// A snapshot holds the value of every key at one confirmed commit, in the order of the keys.
+/// The entries are followed by their number and checksum, so that the state is read only once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    pub format_version: u32,
+    pub commit: CommitID,
+    pub history_number: HistoryNumber,
+    pub num_entries: u64,
+    /// Blake2s digest of the encoded entries.
+    pub checksum: H256,
+}
+
+/// Writes the state of the confirmed `commit` to `writer`. Fails with
+/// [`StorageError::CommitIDNotFound`] if `commit` is not confirmed.
+pub fn export_snapshot<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &D,
+    commit: CommitID,
+    mut writer: impl Write,
+) -> Result<SnapshotManifest> {
+    let history_number = db
+        .view::<CommitIDSchema>()?
+        .get(&commit)?
+        .ok_or(StorageError::CommitIDNotFound)?
+        .into_owned();
+
+    let history_index_table: TableReader<HistoryIndicesTable<T>> =
+        Arc::new(db.view::<HistoryIndicesTable<T>>()?);
+    let change_history_table =
+        KeyValueStoreBulks::new(Arc::new(db.view::<HistoryChangeTable<T>>()?));
+
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_FORMAT_VERSION.to_be_bytes())?;
+    writer.write_all(&history_number.to_be_bytes())?;
+    writer.write_all(&commit.0)?;
+
+    let mut hasher = Blake2s256::new();
+    let mut num_entries = 0u64;
+    for item in HistoryIter::new(
+        history_number,
+        history_index_table,
+        change_history_table,
+        None,
+    )? {
+        let (key, ValueEntry::Value(value)) = item? else {
+            continue;
+        };
+        let mut entry = Vec::new();
+        write_field(&mut entry, &key.encode());
+        write_field(&mut entry, &value.encode());
+        hasher.update(&entry);
+        writer.write_all(&entry)?;
+        num_entries += 1;
+    }
+
+    let checksum = H256(hasher.finalize().into());
+    writer.write_all(&END_OF_ENTRIES.to_be_bytes())?;
+    writer.write_all(&num_entries.to_be_bytes())?;
+    writer.write_all(&checksum.0)?;
+    writer.flush()?;
+
+    Ok(SnapshotManifest {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        commit,
+        history_number,
+        num_entries,
+        checksum,
+    })
+}
+
+/// Loads a snapshot written by [`export_snapshot`] into `db`, which must hold no history, and
+/// returns its commit. The commit becomes the only confirmed commit, at its original height.
+///
+/// Nothing is written unless the whole snapshot is read and its checksum matches. Fails with
+/// [`StorageError::InvalidBackup`] otherwise.
+pub fn import_snapshot<D: DatabaseTrait, T: VersionedKeyValueSchema>(
+    db: &mut D,
+    mut reader: impl Read,
+) -> Result<CommitID> {
+    if !is_empty::<_, HistoryNumberSchema>(db)? || !is_empty::<_, HistoryIndicesTable<T>>(db)? {
+        return Err(StorageError::InvalidBackup("database is not empty"));
+    }
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != *SNAPSHOT_MAGIC {
+        return Err(StorageError::InvalidBackup("not a snapshot"));
+    }
+    if read_u32(&mut reader)? != SNAPSHOT_FORMAT_VERSION {
+        return Err(StorageError::InvalidBackup("unsupported snapshot version"));
+    }
+    let history_number = HistoryNumber::from_be_bytes(read_array(&mut reader)?);
+    let commit = CommitID(read_array(&mut reader)?);
+
+    let write_schema = D::write_schema();
+    let mut hasher = Blake2s256::new();
+    let mut num_entries = 0u64;
+    let mut last_key: Option<T::Key> = None;
+    loop {
+        let key_len = read_u32(&mut reader)?;
+        if key_len == END_OF_ENTRIES {
+            break;
+        }
+        let key = read_field(&mut reader, key_len)?;
+        let value_len = read_u32(&mut reader)?;
+        let value = read_field(&mut reader, value_len)?;
+        let mut entry = Vec::new();
+        write_field(&mut entry, &key);
+        write_field(&mut entry, &value);
+        hasher.update(&entry);
+        num_entries += 1;
+
+        let key = T::Key::decode_owned(key)?;
+        let value = T::Value::decode_owned(value)?;
+        if matches!(&last_key, Some(last_key) if *last_key >= key) {
+            return Err(StorageError::InvalidBackup("unsorted snapshot entries"));
+        }
+
+        write_schema.write::<HistoryIndicesTable<T>>((
+            Cow::Owned(HistoryIndexKey(key.clone(), history_number)),
+            Some(Cow::Owned(HistoryIndices)),
+        ));
+        write_schema.write::<HistoryChangeTable<T>>((
+            Cow::Owned(ChangeKey::new(history_number, key.clone())),
+            Some(Cow::Owned(value)),
+        ));
+        last_key = Some(key);
+    }
+
+    if u64::from_be_bytes(read_array(&mut reader)?) != num_entries {
+        return Err(StorageError::InvalidBackup("mismatched number of entries"));
+    }
+    if read_array(&mut reader)? != <[u8; 32]>::from(hasher.finalize()) {
+        return Err(StorageError::InvalidBackup("mismatched checksum"));
+    }
+
+    write_schema.write::<CommitIDSchema>((Cow::Owned(commit), Some(Cow::Owned(history_number))));
+    write_schema
+        .write::<HistoryNumberSchema>((Cow::Owned(history_number), Some(Cow::Owned(commit))));
+    db.commit(write_schema)?;
+
+    Ok(commit)
+}
+
+fn is_empty<D: DatabaseTrait, S: TableSchema>(db: &D) -> Result<bool> {
+    Ok(db.view::<S>()?.iter_from_start()?.next().is_none())
+}
+
+pub(super) fn write_field(output: &mut Vec<u8>, field: &[u8]) {
+    output.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    output.extend_from_slice(field);
+}
+
+pub(super) fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_be_bytes(read_array(reader)?))
+}
+
+pub(super) fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut output = [0u8; N];
+    reader.read_exact(&mut output)?;
+    Ok(output)
+}
+
+pub(super) fn read_field(reader: &mut impl Read, len: u32) -> Result<Vec<u8>> {
+    if len == END_OF_ENTRIES {
+        return Err(DecodeError::IncorrectLength.into());
+    }
+    let mut output = vec![0u8; len as usize];
+    reader.read_exact(&mut output)?;
+    Ok(output)
+}
@@ -1,7 +1,7 @@
 use std::hash::Hash;
 
 use crate::{
-    backends::{TableKey, TableName, TableSchema, TableValue, VersionedKVName},
+    backends::{Compression, TableKey, TableName, TableSchema, TableValue, VersionedKVName},
     traits::KeyValueStoreRead,
 };
 
@@ -13,6 +13,14 @@ where
     HistoryIndexKey<Self::Key>: TableKey,
 {
     const NAME: VersionedKVName;
+    /// Whether a pending commit drops the writes of the value a key already has at its parent,
+    /// when that value was itself written by a pending commit. A dropped write creates no
+    /// version: it is not confirmed to the history and not visited by `iter_historical_changes`.
+    const DEDUP_IDENTICAL_WRITES: bool = false;
+    /// How the values are compressed in the change history, `None` to store them without a
+    /// compression header, as the change histories written before it. Changing it between
+    /// `Some` settings keeps the values already written readable.
+    const VALUE_COMPRESSION: Option<Compression> = None;
     type Key: TableKey + ToOwned<Owned = Self::Key> + Clone + Hash;
     type Value: TableValue + Clone;
 }
@@ -22,6 +30,8 @@ pub struct HistoryChangeTable<T: VersionedKeyValueSchema>(T);
 
 impl<T: VersionedKeyValueSchema> TableSchema for HistoryChangeTable<T> {
     const NAME: TableName = TableName::HistoryChange(T::NAME);
+    const SUPPORTS_SUBKEY: bool = true;
+    const VALUE_COMPRESSION: Option<Compression> = T::VALUE_COMPRESSION;
     type Key = HistoryChangeKey<T::Key>;
     type Value = T::Value;
 }
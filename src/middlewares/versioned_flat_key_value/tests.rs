@@ -1,14 +1,12 @@
-use ethereum_types::H256;
-
 use super::{
+    get_versioned_entries, get_versioned_entry,
     pending_part::pending_schema::PendingKeyValueConfig, table_schema::VersionedKeyValueSchema,
-    VersionedStore,
+    AddOrSkipOutcome, AddOutcome, ConfirmationCursor, GetSource, StorageMetrics, VersionedStore,
+    VersionedStoreReadOnly,
 };
 use crate::{
-    backends::{
-        impls::kvdb_rocksdb::open_database, DatabaseTrait, InMemoryDatabase, VersionedKVName,
-    },
-    errors::Result,
+    backends::{DatabaseTrait, InMemoryDatabase, TableIter, TableRead, TableReader, TableSchema},
+    errors::{DbResult, Result},
     middlewares::{
         versioned_flat_key_value::{
             confirm_ids_to_history, confirm_maps_to_history, confirmed_pending_to_history,
@@ -16,10 +14,25 @@ use crate::{
         },
         CommitID, PendingError,
     },
-    traits::{IsCompleted, KeyValueStoreManager, KeyValueStoreRead, NeedNext},
+    test_utils::{
+        empty_rocksdb, gen_novel_u64, gen_random_commit_id, gen_updates, get_rng_for_test,
+        select_vec_element, MockVersionedStore, TestSchema,
+    },
+    traits::{KeyValueStoreManager, KeyValueStoreRead, NeedNext},
+    types::KeyLookup,
     StorageError,
 };
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
 use rand_chacha::{
     rand_core::{RngCore, SeedableRng},
@@ -38,70 +51,89 @@ impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
 
     #[cfg(test)]
     fn check_consistency_inner(&self) -> Result<()> {
-        use crate::middlewares::commit_id_schema::{
-            height_to_history_number, history_number_to_height,
+        use crate::middlewares::{
+            checked_height_to_history_number, checked_history_number_to_height,
         };
 
         if let Some(parent) = self.pending_part.get_parent_of_root() {
             let parent_history_number =
-                if let Some(parent_history_number) = self.commit_id_table.get(&parent)? {
+                if let Some(parent_history_number) = self.tables.commit_id_table.get(&parent)? {
                     parent_history_number.into_owned()
                 } else {
                     return Err(StorageError::ConsistencyCheckFailure);
                 };
+            let parent_height = checked_history_number_to_height(parent_history_number)?;
 
-            let mut history_number = parent_history_number;
-            let min_history_number = height_to_history_number(0);
-            while history_number >= min_history_number {
-                let commit_id =
-                    if let Some(commit_id) = self.history_number_table.get(&history_number)? {
-                        commit_id.into_owned()
-                    } else {
-                        return Err(StorageError::ConsistencyCheckFailure);
-                    };
-                let check_history_number =
-                    if let Some(check_history_number) = self.commit_id_table.get(&commit_id)? {
-                        check_history_number.into_owned()
-                    } else {
-                        return Err(StorageError::ConsistencyCheckFailure);
-                    };
+            // the commits below the earliest one may have been pruned
+            let min_height = match self.tables.history_number_table.iter_from_start()?.next() {
+                Some(item) => checked_history_number_to_height(item?.0.into_owned())?,
+                None => 0,
+            };
+            for height in (min_height..=parent_height).rev() {
+                let history_number = checked_height_to_history_number(height)?;
+                let commit_id = if let Some(commit_id) =
+                    self.tables.history_number_table.get(&history_number)?
+                {
+                    commit_id.into_owned()
+                } else {
+                    return Err(StorageError::ConsistencyCheckFailure);
+                };
+                let check_history_number = if let Some(check_history_number) =
+                    self.tables.commit_id_table.get(&commit_id)?
+                {
+                    check_history_number.into_owned()
+                } else {
+                    return Err(StorageError::ConsistencyCheckFailure);
+                };
                 if history_number != check_history_number {
                     return Err(StorageError::ConsistencyCheckFailure);
                 };
-                history_number -= 1;
             }
 
-            let parent_history_number_plus_one = parent_history_number + 1;
+            let height_of_root = parent_height
+                .checked_add(1)
+                .ok_or(StorageError::HeightOverflow)?;
+            let root_history_number = checked_height_to_history_number(height_of_root)?;
             if self
+                .tables
                 .history_number_table
-                .iter(&parent_history_number_plus_one)?
+                .iter(&root_history_number)?
                 .next()
                 .is_some()
             {
                 return Err(StorageError::ConsistencyCheckFailure);
             }
 
-            if self.commit_id_table.iter_from_start()?.count()
-                != self.history_number_table.iter_from_start()?.count()
+            if self.tables.commit_id_table.iter_from_start()?.count()
+                != self.tables.history_number_table.iter_from_start()?.count()
             {
                 return Err(StorageError::ConsistencyCheckFailure);
             }
 
-            if !self
-                .pending_part
-                .check_consistency(history_number_to_height(parent_history_number + 1))
-            {
+            if !self.pending_part.check_consistency(height_of_root) {
                 return Err(StorageError::ConsistencyCheckFailure);
             }
-            // todo: history_index_table, change_table
-        } else if self.commit_id_table.iter_from_start()?.next().is_some()
+            self.check_history_integrity(None)?;
+        } else if self
+            .tables
+            .commit_id_table
+            .iter_from_start()?
+            .next()
+            .is_some()
             || self
+                .tables
                 .history_number_table
                 .iter_from_start()?
                 .next()
                 .is_some()
-            || self.history_index_table.iter_from_start()?.next().is_some()
             || self
+                .tables
+                .history_index_table
+                .iter_from_start()?
+                .next()
+                .is_some()
+            || self
+                .tables
                 .change_history_table
                 .iter_from_start()?
                 .next()
@@ -114,176 +146,6 @@ impl<'cache, 'db, T: VersionedKeyValueSchema> VersionedStore<'cache, 'db, T> {
     }
 }
 
-type MockStore<T> = BTreeMap<
-    <T as VersionedKeyValueSchema>::Key,
-    (Option<<T as VersionedKeyValueSchema>::Value>, bool),
->;
-
-#[derive(PartialEq, Debug)]
-pub struct MockOneStore<K: Ord, V: Clone> {
-    map: BTreeMap<K, V>,
-}
-
-impl<K: Ord + Clone, V: Clone> MockOneStore<K, V> {
-    pub fn from_mock_map(map: &BTreeMap<K, (Option<V>, bool)>) -> Self {
-        let inner_map = map
-            .iter()
-            .filter_map(|(k, (opt_v, _))| opt_v.as_ref().map(|v| (k.clone(), v.clone())))
-            .collect();
-        MockOneStore { map: inner_map }
-    }
-
-    pub fn get_keys(&self) -> Vec<K> {
-        self.map.keys().cloned().collect()
-    }
-}
-
-impl<K: 'static + Ord, V: 'static + Clone> KeyValueStoreRead<K, V> for MockOneStore<K, V> {
-    fn get(&self, key: &K) -> Result<Option<V>> {
-        Ok(self.map.get(key).cloned())
-    }
-}
-
-#[derive(Debug)]
-struct MockVersionedStore<T: VersionedKeyValueSchema> {
-    pending: MockTree<T>,
-    history: HashMap<CommitID, (Option<CommitID>, MockStore<T>)>,
-}
-
-#[derive(Debug)]
-struct MockTree<T: VersionedKeyValueSchema> {
-    tree: HashMap<CommitID, MockNode<T>>,
-    parent_of_root: Option<CommitID>,
-}
-
-#[derive(Debug, Clone)]
-struct MockNode<T: VersionedKeyValueSchema> {
-    commit_id: CommitID,
-    parent: Option<CommitID>,
-    children: HashSet<CommitID>,
-    store: MockStore<T>,
-}
-
-impl<T: VersionedKeyValueSchema> KeyValueStoreManager<T::Key, T::Value, CommitID>
-    for MockVersionedStore<T>
-{
-    type Store = MockOneStore<T::Key, T::Value>;
-
-    fn get_versioned_store(&self, commit: &CommitID) -> Result<Self::Store> {
-        if let Some(pending_res) = self.pending.tree.get(commit) {
-            Ok(MockOneStore::from_mock_map(&pending_res.store))
-        } else if let Some((_, history_res)) = self.history.get(commit) {
-            Ok(MockOneStore::from_mock_map(history_res))
-        } else {
-            Err(StorageError::CommitIDNotFound)
-        }
-    }
-
-    fn iter_historical_changes(
-        &self,
-        mut accept: impl FnMut(&CommitID, &T::Key, Option<&T::Value>) -> NeedNext,
-        commit_id: &CommitID,
-        key: &T::Key,
-    ) -> Result<IsCompleted> {
-        let mut current_node = self.pending.tree.get(commit_id);
-        while let Some(node) = current_node {
-            if let Some((value, true)) = node.store.get(key) {
-                if !accept(&node.commit_id, key, value.as_ref()) {
-                    return Ok(false);
-                }
-            }
-            current_node = node.parent.map(|p| self.pending.tree.get(&p).unwrap());
-        }
-
-        let history_commit_id = if self.pending.tree.contains_key(commit_id) {
-            if let Some(parent_of_pending) = self.pending.parent_of_root {
-                parent_of_pending
-            } else {
-                assert!(self.history.is_empty());
-                return Ok(true);
-            }
-        } else {
-            *commit_id
-        };
-
-        if !self.history.contains_key(&history_commit_id) {
-            return Err(StorageError::CommitIDNotFound);
-        }
-
-        let mut current_cid = Some(history_commit_id);
-        while let Some(cid) = current_cid {
-            let (parent_cid, store) = self.history.get(&cid).unwrap();
-            if let Some((value, true)) = store.get(key) {
-                if !accept(&cid, key, value.as_ref()) {
-                    return Ok(false);
-                }
-            }
-            current_cid = *parent_cid;
-        }
-
-        Ok(true)
-    }
-
-    fn discard(&mut self, commit: CommitID) -> Result<()> {
-        if self.history.contains_key(&commit) {
-            return Ok(());
-        }
-
-        if self.pending.tree.contains_key(&commit) {
-            if let Some(parent) = self.pending.tree.get(&commit).unwrap().parent {
-                let mut to_remove = VecDeque::new();
-
-                assert!(self
-                    .pending
-                    .tree
-                    .get(&parent)
-                    .unwrap()
-                    .children
-                    .contains(&commit));
-                for child in self.pending.tree.get(&parent).unwrap().children.iter() {
-                    if *child != commit {
-                        to_remove.push_back(*child);
-                    }
-                }
-
-                while !to_remove.is_empty() {
-                    let remove_this = to_remove.pop_front().unwrap();
-                    let remove_this_node = self.pending.tree.remove(&remove_this).unwrap();
-                    for child in remove_this_node.children.iter() {
-                        to_remove.push_back(*child);
-                    }
-                }
-
-                self.pending.tree.get_mut(&parent).unwrap().children = HashSet::from([commit]);
-            }
-
-            Ok(())
-        } else {
-            Err(StorageError::PendingError(PendingError::CommitIDNotFound(
-                commit,
-            )))
-        }
-    }
-
-    fn get_versioned_key(&self, commit: &CommitID, key: &T::Key) -> Result<Option<T::Value>> {
-        self.get_versioned_store(commit)?.get(key)
-    }
-}
-
-fn update_last_store_to_store<T: VersionedKeyValueSchema>(
-    last_store: &MockStore<T>,
-    updates: BTreeMap<T::Key, Option<T::Value>>,
-) -> MockStore<T> {
-    let mut store: BTreeMap<_, _> = last_store
-        .iter()
-        .map(|(k, (opt_v, _))| (k.clone(), (opt_v.clone(), false)))
-        .collect();
-    for (k, opt_v) in updates.into_iter() {
-        store.insert(k, (opt_v, true));
-    }
-    store
-}
-
 #[derive(Clone)]
 pub struct UniqueVec<T> {
     items: Vec<T>,
@@ -324,273 +186,6 @@ impl<T: Eq + std::hash::Hash + Clone> UniqueVec<T> {
     }
 }
 
-impl<T: VersionedKeyValueSchema> MockVersionedStore<T> {
-    pub fn build(
-        history_cids: UniqueVec<CommitID>,
-        history_updates: Vec<BTreeMap<T::Key, Option<T::Value>>>,
-    ) -> Self {
-        assert_eq!(history_cids.len(), history_updates.len());
-        let mut history: HashMap<_, _> = Default::default();
-        let mut last_store = Default::default();
-        let mut last_commit_id = None;
-        for (commit_id, updates) in history_cids.items.iter().zip(history_updates.into_iter()) {
-            let store = update_last_store_to_store::<T>(&last_store, updates);
-            history.insert(*commit_id, (last_commit_id, store.clone()));
-            last_store = store;
-            last_commit_id = Some(*commit_id);
-        }
-        MockVersionedStore::<T>::new_unchecked(last_commit_id, history)
-    }
-
-    pub fn check_consistency(&self) {
-        if let Some(parent) = self.get_parent_of_root() {
-            assert!(self.history.contains_key(&parent));
-            let mut num_history = 1;
-            let mut commit_id = parent;
-            while let (Some(parent_commit_id), _) = self.history.get(&commit_id).unwrap() {
-                num_history += 1;
-                commit_id = *parent_commit_id;
-            }
-            assert_eq!(num_history, self.history.len());
-
-            let root = self.get_pending_root();
-            if let Some(root) = root.last() {
-                assert!(self.pending.tree.get(root).unwrap().parent.is_none());
-            }
-
-            assert_eq!(
-                self.get_history().len() + self.get_pending().len(),
-                self.get_commit_ids().len()
-            )
-        } else {
-            assert!(self.history.is_empty());
-        }
-    }
-
-    fn new_unchecked(
-        parent_of_pending: Option<CommitID>,
-        history: HashMap<CommitID, (Option<CommitID>, MockStore<T>)>,
-    ) -> Self {
-        let mock_versioned_store = Self {
-            pending: MockTree {
-                tree: Default::default(),
-                parent_of_root: parent_of_pending,
-            },
-            history,
-        };
-        mock_versioned_store.check_consistency();
-        mock_versioned_store
-    }
-
-    pub fn get_pending_root(&self) -> Vec<CommitID> {
-        let pending_root: Vec<_> = self
-            .pending
-            .tree
-            .iter()
-            .filter(|(_, node)| node.parent.is_none())
-            .map(|(cid, _)| *cid)
-            .collect();
-        if self.pending.tree.is_empty() {
-            assert_eq!(pending_root.len(), 0);
-        } else {
-            assert_eq!(pending_root.len(), 1);
-        }
-        pending_root
-    }
-
-    pub fn get_pending_non_root(&self) -> Vec<CommitID> {
-        let mut pending_non_root: Vec<_> = self
-            .pending
-            .tree
-            .iter()
-            .filter(|(_, node)| node.parent.is_some())
-            .map(|(cid, _)| *cid)
-            .collect();
-        if self.pending.tree.is_empty() {
-            assert_eq!(pending_non_root.len(), 0);
-        } else {
-            assert_eq!(pending_non_root.len() + 1, self.pending.tree.len());
-        }
-        pending_non_root.sort();
-        pending_non_root
-    }
-
-    pub fn get_pending(&self) -> Vec<CommitID> {
-        let mut pending: Vec<_> = self.pending.tree.keys().cloned().collect();
-        pending.sort();
-        pending
-    }
-
-    pub fn get_parent_of_root(&self) -> Option<CommitID> {
-        self.pending.parent_of_root
-    }
-
-    pub fn get_history(&self) -> Vec<CommitID> {
-        let mut history: Vec<_> = self.history.keys().cloned().collect();
-        history.sort();
-        history
-    }
-
-    pub fn get_history_but_parent_of_root(&self) -> Vec<CommitID> {
-        let mut history: HashSet<_> = self.history.keys().cloned().collect();
-        if let Some(parent_of_root) = self.pending.parent_of_root {
-            history.remove(&parent_of_root);
-        }
-        let mut history: Vec<_> = history.into_iter().collect();
-        history.sort();
-        history
-    }
-
-    pub fn get_commit_ids(&self) -> BTreeSet<CommitID> {
-        self.history
-            .keys()
-            .cloned()
-            .chain(self.pending.tree.keys().cloned())
-            .collect()
-    }
-
-    fn get_keys_on_path(&self, commit: &CommitID) -> Vec<T::Key> {
-        let mut keys: Vec<_> = if let Some(pending_res) = self.pending.tree.get(commit) {
-            pending_res.store.keys().cloned().collect()
-        } else if let Some((_, history_res)) = self.history.get(commit) {
-            history_res.keys().cloned().collect()
-        } else {
-            Vec::new()
-        };
-        keys.sort();
-        keys
-    }
-
-    pub fn add_to_pending_part(
-        &mut self,
-        parent_commit: Option<CommitID>,
-        commit: CommitID,
-        updates: BTreeMap<T::Key, Option<T::Value>>,
-    ) -> Result<()> {
-        if self.history.contains_key(&commit) {
-            return Err(StorageError::CommitIdAlreadyExistsInHistory);
-        }
-
-        if parent_commit == self.pending.parent_of_root {
-            if !self.pending.tree.is_empty() {
-                return Err(StorageError::PendingError(
-                    PendingError::MultipleRootsNotAllowed,
-                ));
-            }
-
-            let default_store = Default::default();
-            let last_store = if let Some(parent_commit_id) = parent_commit {
-                let (_, history_store) = self.history.get(&parent_commit_id).unwrap();
-                history_store
-            } else {
-                &default_store
-            };
-            let store = update_last_store_to_store::<T>(last_store, updates);
-
-            let root = MockNode {
-                commit_id: commit,
-                parent: None,
-                children: Default::default(),
-                store,
-            };
-            self.pending.tree.insert(commit, root);
-
-            Ok(())
-        } else if let Some(parent_commit_id) = parent_commit {
-            if !self.pending.tree.contains_key(&parent_commit_id) {
-                return Err(StorageError::PendingError(PendingError::CommitIDNotFound(
-                    parent_commit_id,
-                )));
-            }
-            if self.pending.tree.contains_key(&commit) {
-                return Err(StorageError::PendingError(
-                    PendingError::CommitIdAlreadyExists(commit),
-                ));
-            }
-
-            let last_store = &self.pending.tree.get(&parent_commit_id).unwrap().store;
-            let store = update_last_store_to_store::<T>(last_store, updates);
-
-            let node = MockNode {
-                commit_id: commit,
-                parent: parent_commit,
-                children: Default::default(),
-                store,
-            };
-            self.pending.tree.insert(commit, node);
-            self.pending
-                .tree
-                .get_mut(&parent_commit_id)
-                .unwrap()
-                .children
-                .insert(commit);
-
-            Ok(())
-        } else {
-            Err(StorageError::PendingError(
-                PendingError::NonRootNodeShouldHaveParent,
-            ))
-        }
-    }
-
-    pub fn confirmed_pending_to_history(&mut self, new_root_commit_id: CommitID) -> Result<()> {
-        if !self.pending.tree.contains_key(&new_root_commit_id) {
-            return Err(StorageError::PendingError(PendingError::CommitIDNotFound(
-                new_root_commit_id,
-            )));
-        }
-
-        let mut parent_cid = self
-            .pending
-            .tree
-            .get(&new_root_commit_id)
-            .cloned()
-            .unwrap()
-            .parent;
-        let mut commit_id = new_root_commit_id;
-        while let Some(parent_commit_id) = parent_cid {
-            self.discard(commit_id).unwrap();
-
-            let parent_node = self.pending.tree.remove(&parent_commit_id).unwrap();
-
-            let grandparent = if let Some(grandparent) = parent_node.parent {
-                Some(grandparent)
-            } else {
-                self.pending.parent_of_root
-            };
-            self.history
-                .insert(parent_commit_id, (grandparent, parent_node.store));
-
-            commit_id = parent_commit_id;
-            parent_cid = parent_node.parent;
-        }
-
-        let old_root_commit_id = commit_id;
-        if old_root_commit_id != new_root_commit_id {
-            self.pending.parent_of_root =
-                self.pending.tree.get(&new_root_commit_id).unwrap().parent;
-            self.pending
-                .tree
-                .get_mut(&new_root_commit_id)
-                .unwrap()
-                .parent = None;
-        }
-
-        self.check_consistency();
-
-        Ok(())
-    }
-}
-
-#[derive(Clone, Copy, Debug)]
-struct TestSchema;
-
-impl VersionedKeyValueSchema for TestSchema {
-    const NAME: VersionedKVName = VersionedKVName::FlatKV;
-    type Key = u64;
-    type Value = u64;
-}
-
 #[derive(Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
 enum Operation {
     GetVersionedStore,
@@ -624,65 +219,6 @@ enum KeyType {
     Novel,
 }
 
-pub fn get_rng_for_test() -> ChaChaRng {
-    ChaChaRng::from_seed([123; 32])
-}
-
-fn gen_opt_value(rng: &mut ChaChaRng) -> Option<u64> {
-    let value_is_none = (rng.next_u64() % 3) == 0;
-    if value_is_none {
-        None
-    } else {
-        Some(rng.next_u64())
-    }
-}
-
-fn select_vec_element<T: Clone>(rng: &mut ChaChaRng, vec: &[T]) -> T {
-    assert!(!vec.is_empty());
-    let num_elements = vec.len();
-    vec[rng.next_u64() as usize % num_elements].clone()
-}
-
-pub fn gen_updates(
-    rng: &mut ChaChaRng,
-    previous_keys: &BTreeSet<u64>,
-    num_gen_new_keys: usize,
-    num_gen_previous_keys: usize,
-    all_keys: &mut BTreeSet<u64>,
-) -> BTreeMap<u64, Option<u64>> {
-    // gen previous keys (i.e., replace), allow redundant keys and adopt the newest value for the same key
-    let mut updates: BTreeMap<_, _> = if !previous_keys.is_empty() {
-        let previous_keys_vec: Vec<_> = previous_keys.iter().cloned().collect();
-        (0..num_gen_previous_keys)
-            .map(|_| {
-                (
-                    select_vec_element(rng, &previous_keys_vec),
-                    gen_opt_value(rng),
-                )
-            })
-            .collect()
-    } else {
-        Default::default()
-    };
-
-    // gen new keys (i.e., insert), do not allow repeated keys
-    let mut new_keys = BTreeSet::new();
-    while new_keys.len() < num_gen_new_keys {
-        let key = rng.next_u64();
-        if previous_keys.contains(&key) || new_keys.contains(&key) {
-            continue;
-        }
-        new_keys.insert(key);
-        updates.insert(key, gen_opt_value(rng));
-    }
-
-    for key in updates.keys() {
-        all_keys.insert(*key);
-    }
-
-    updates
-}
-
 #[allow(clippy::type_complexity)]
 fn gen_init<D: DatabaseTrait>(
     db: &D,
@@ -730,25 +266,6 @@ fn gen_init<D: DatabaseTrait>(
     (history_cids, history_updates, pending_part)
 }
 
-fn gen_novel_u64(rng: &mut ChaChaRng, previous: &BTreeSet<u64>) -> u64 {
-    for _ in 0..1 << 4 {
-        let novel = rng.next_u64();
-        if !previous.contains(&novel) {
-            return novel;
-        }
-    }
-    panic!()
-}
-
-pub fn gen_random_commit_id(rng: &mut ChaChaRng) -> CommitID {
-    let mut bytes = [0u8; 32];
-    for i in 0..4 {
-        let num = rng.next_u64().to_ne_bytes();
-        bytes[i * 8..(i + 1) * 8].copy_from_slice(&num);
-    }
-    H256::from_slice(&bytes)
-}
-
 fn gen_key(rng: &mut ChaChaRng, existing_keys: Vec<u64>) -> (KeyType, u64) {
     let key_types = if existing_keys.is_empty() {
         vec![KeyType::Novel]
@@ -766,7 +283,7 @@ fn gen_key(rng: &mut ChaChaRng, existing_keys: Vec<u64>) -> (KeyType, u64) {
 }
 
 struct VersionedStoreProxy<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema> {
-    mock_store: &'a mut MockVersionedStore<T>,
+    mock_store: &'a mut MockVersionedStore<T::Key, T::Value>,
     real_store: &'b mut VersionedStore<'cache, 'db, T>,
     all_keys: &'c mut BTreeSet<T::Key>,
 }
@@ -777,7 +294,7 @@ where
     T::Value: PartialEq,
 {
     fn new(
-        mock_store: &'a mut MockVersionedStore<T>,
+        mock_store: &'a mut MockVersionedStore<T::Key, T::Value>,
         real_store: &'b mut VersionedStore<'cache, 'db, T>,
         all_keys: &'c mut BTreeSet<T::Key>,
     ) -> Self {
@@ -801,12 +318,12 @@ where
 
     fn gen_commit_id(&self, rng: &mut ChaChaRng) -> (CommitIDType, CommitID) {
         let mut commit_id_types = vec![CommitIDType::Novel];
-        if !self.mock_store.history.is_empty() {
+        if !self.mock_store.num_history() == 0 {
             commit_id_types.push(CommitIDType::History);
         }
-        if !self.mock_store.pending.tree.is_empty() {
+        if self.mock_store.num_pending() > 0 {
             commit_id_types.push(CommitIDType::PendingRoot);
-            if self.mock_store.pending.tree.len() > 1 {
+            if self.mock_store.num_pending() > 1 {
                 commit_id_types.push(CommitIDType::PendingNonRoot);
             }
         }
@@ -828,7 +345,7 @@ where
         pending_only: bool,
     ) -> (ParentCommitType, Option<CommitID>) {
         let parent_types = if pending_only {
-            assert!(!self.mock_store.pending.tree.is_empty());
+            assert!(self.mock_store.num_pending() > 0);
             vec![ParentCommitType::Pending]
         } else {
             let mut parent_types = vec![
@@ -838,10 +355,10 @@ where
             if self.mock_store.get_parent_of_root().is_some() {
                 parent_types.push(ParentCommitType::NoneButInvalid)
             }
-            if !self.mock_store.pending.tree.is_empty() {
+            if self.mock_store.num_pending() > 0 {
                 parent_types.push(ParentCommitType::Pending)
             }
-            if self.mock_store.history.len() > 1 {
+            if self.mock_store.num_history() > 1 {
                 parent_types.push(ParentCommitType::HistoryButInvalid)
             }
             parent_types
@@ -882,7 +399,7 @@ where
         if num_pending > 0 {
             // gen root
             let pending_root = self.gen_novel_commit_id(rng);
-            let previous_keys = self.get_previous_keys(self.mock_store.pending.parent_of_root);
+            let previous_keys = self.get_previous_keys(self.mock_store.get_parent_of_root());
             let updates = gen_updates(
                 rng,
                 &previous_keys,
@@ -892,7 +409,7 @@ where
             );
 
             // add root
-            let parent_of_root = self.mock_store.pending.parent_of_root;
+            let parent_of_root = self.mock_store.get_parent_of_root();
             self.mock_store
                 .add_to_pending_part(parent_of_root, pending_root, updates.clone())
                 .unwrap();
@@ -955,10 +472,60 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
                 let real_res = real_res.unwrap();
                 for key in self.all_keys.iter() {
                     assert_eq!(mock_res.get(key), real_res.get(key));
+                    assert_eq!(mock_res.get_entry(key), real_res.get_entry(key).unwrap());
                 }
                 for _ in 0..10 {
                     let key = gen_novel_u64(rng, self.all_keys);
+                    assert_eq!(mock_res.get_entry(&key), KeyLookup::Unknown);
+                    assert_eq!(real_res.get_entry(&key).unwrap(), KeyLookup::Unknown);
+                }
+
+                let keys: Vec<_> = self.all_keys.iter().copied().collect();
+                let mut starts = vec![0, gen_novel_u64(rng, self.all_keys)];
+                if !keys.is_empty() {
+                    starts.push(select_vec_element(rng, &keys));
+                }
+                for start in starts.iter() {
+                    let expected: Vec<_> = self
+                        .all_keys
+                        .range(start..)
+                        .filter_map(|key| mock_res.get(key).unwrap().map(|value| (*key, value)))
+                        .collect();
+                    let real: Vec<_> = real_res
+                        .iter_from(start)
+                        .unwrap()
+                        .collect::<Result<_>>()
+                        .unwrap();
+                    assert_eq!(real, expected);
+
+                    for end in starts.iter() {
+                        let expected: Vec<_> = expected
+                            .iter()
+                            .filter(|(key, _)| key < end)
+                            .cloned()
+                            .collect();
+                        let real: Vec<_> = real_res
+                            .iter_range(start, Some(end))
+                            .unwrap()
+                            .collect::<Result<_>>()
+                            .unwrap();
+                        assert_eq!(real, expected);
+                    }
                 }
+
+                let mut keys: Vec<_> = self.all_keys.iter().copied().collect();
+                keys.push(gen_novel_u64(rng, self.all_keys));
+                keys.reverse();
+                let expected: Vec<_> = keys.iter().map(|key| mock_res.get(key).unwrap()).collect();
+                assert_eq!(real_res.multi_get(&keys).unwrap(), expected);
+
+                let expected: Vec<_> = self
+                    .all_keys
+                    .iter()
+                    .filter_map(|key| mock_res.get(key).unwrap().map(|value| (*key, value)))
+                    .collect();
+                let real: Vec<_> = real_res.iter_all().unwrap().collect::<Result<_>>().unwrap();
+                assert_eq!(real, expected);
                 true
             }
         }
@@ -1009,6 +576,33 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
                     KeyType::Novel => assert!(mock_collected.is_empty()),
                 }
 
+                // drawn from a copy of `rng`, so that the checks below see the same draws as before
+                let mut bounds_rng = rng.clone();
+                let max_results = (bounds_rng.next_u64() % 4) as usize;
+                let skip = (bounds_rng.next_u64() % 4) as usize;
+                let mut bounded_collected = Vec::new();
+                let is_completed = self
+                    .real_store
+                    .iter_historical_changes_bounded(
+                        |cid, k, v| {
+                            bounded_collected.push((*cid, *k, v.copied()));
+                            true
+                        },
+                        commit_id,
+                        &key,
+                        max_results,
+                        skip,
+                    )
+                    .unwrap();
+                let expected: Vec<_> = mock_collected
+                    .iter()
+                    .copied()
+                    .skip(skip)
+                    .take(max_results)
+                    .collect();
+                assert_eq!(bounded_collected, expected);
+                assert_eq!(is_completed, mock_collected.len() <= skip + max_results);
+
                 true
             }
             _ => panic!(),
@@ -1033,6 +627,29 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
         let real_res = self.real_store.get_versioned_key(commit, &key);
 
         assert_eq!(mock_res, real_res);
+        assert_eq!(
+            self.mock_store.contains_versioned_key(commit, &key),
+            self.real_store.contains_versioned_key(commit, &key)
+        );
+
+        let mut commits = vec![*commit];
+        let existing: Vec<_> = self.mock_store.get_commit_ids().into_iter().collect();
+        if !existing.is_empty() {
+            // drawn from a copy of `rng`, so that the checks below see the same draws as before
+            let mut multi_rng = rng.clone();
+            for _ in 0..multi_rng.next_u64() % 8 {
+                commits.push(select_vec_element(&mut multi_rng, &existing));
+            }
+        }
+        let mock_multi_res: Result<Vec<_>> = commits
+            .iter()
+            .map(|commit| self.mock_store.get_versioned_key(commit, &key))
+            .collect();
+        assert_eq!(
+            self.real_store
+                .get_versioned_key_multi_commits(&commits, &key),
+            mock_multi_res
+        );
 
         match (commit_id_type, key_type) {
             (CommitIDType::Novel, _) => {
@@ -1051,6 +668,29 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
         real_res.is_ok()
     }
 
+    // the shape of the pending tree around `commit`, which may be unknown
+    fn check_pending_tree(&self, commit: &CommitID) {
+        let sorted = |mut commits: Vec<CommitID>| {
+            commits.sort();
+            commits
+        };
+
+        assert_eq!(
+            self.mock_store.children_of(commit),
+            self.real_store.children_of(commit).map(sorted)
+        );
+        assert_eq!(self.mock_store.leaves(), sorted(self.real_store.leaves()));
+        let path = self.real_store.path_to_root(commit);
+        assert_eq!(self.mock_store.path_to_root(commit), path);
+        if let Ok(path) = path {
+            let height = |commit| self.real_store.get_height_by_commit_id(commit).unwrap();
+            assert_eq!(
+                height(&path[0]),
+                height(path.last().unwrap()).map(|h| h + path.len() - 1)
+            );
+        }
+    }
+
     fn discard(&mut self, commit_id_type: CommitIDType, commit: CommitID) -> bool {
         let mock_res = self.mock_store.discard(commit);
         let real_res = self.real_store.discard(commit);
@@ -1058,17 +698,22 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
         assert_eq!(mock_res, real_res);
 
         match commit_id_type {
-            CommitIDType::Novel => assert_eq!(
-                mock_res,
-                Err(StorageError::PendingError(PendingError::CommitIDNotFound(
-                    commit
-                )))
-            ),
+            CommitIDType::Novel => {
+                assert_eq!(
+                    mock_res,
+                    Err(StorageError::from(PendingError::CommitIDNotFound(commit)))
+                );
+                assert_eq!(
+                    real_res.as_ref().unwrap_err().pending_commit_id_h256(),
+                    Some(commit)
+                );
+            }
             _ => assert!(mock_res.is_ok()),
         };
 
         self.mock_store.check_consistency();
         self.real_store.check_consistency().unwrap();
+        self.check_pending_tree(&commit);
 
         real_res.is_ok()
     }
@@ -1081,7 +726,7 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
         num_gen_new_keys: usize,
         num_gen_previous_keys: usize,
     ) -> bool {
-        let has_root_before_add = !self.mock_store.pending.tree.is_empty();
+        let has_root_before_add = self.mock_store.num_pending() > 0;
         let (parent_commit_type, parent_commit) = self.gen_parent_commit(rng, false);
         let previous_keys = self.get_previous_keys(parent_commit);
         let updates = gen_updates(
@@ -1097,7 +742,8 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
             .add_to_pending_part(parent_commit, commit, updates.clone());
         let real_res = self
             .real_store
-            .add_to_pending_part(parent_commit, commit, updates);
+            .add_to_pending_part(parent_commit, commit, updates)
+            .map(|outcome| assert_eq!(outcome, AddOutcome::default()));
 
         assert_eq!(mock_res, real_res);
 
@@ -1124,37 +770,203 @@ impl<'a, 'b, 'c, 'cache, 'db, T: VersionedKeyValueSchema<Key = u64, Value = u64>
             (ParentCommitType::HistoryButInvalid, _) | (ParentCommitType::Novel, _) => {
                 assert_eq!(
                     mock_res.unwrap_err(),
-                    StorageError::PendingError(PendingError::CommitIDNotFound(
-                        parent_commit.unwrap()
-                    ))
+                    StorageError::from(PendingError::CommitIDNotFound(parent_commit.unwrap()))
                 )
             }
             (ParentCommitType::Pending, CommitIDType::PendingRoot)
             | (ParentCommitType::Pending, CommitIDType::PendingNonRoot) => assert_eq!(
                 mock_res.unwrap_err(),
-                StorageError::PendingError(PendingError::CommitIdAlreadyExists(commit))
+                StorageError::from(PendingError::CommitIdAlreadyExists(commit))
             ),
             (ParentCommitType::Pending, CommitIDType::Novel) => assert!(mock_res.is_ok()),
         };
+        self.check_pending_tree(&commit);
+        if let Some(parent_commit) = parent_commit {
+            self.check_pending_tree(&parent_commit);
+        }
 
         real_res.is_ok()
     }
 }
 
-fn test_versioned_store<D: DatabaseTrait>(
-    db: &mut D,
-    num_history: usize,
-    num_pending: usize,
-    num_operations: usize,
-) {
-    let mut rng = get_rng_for_test();
-    let num_gen_new_keys = 10;
-    let num_gen_previous_keys = 10;
+// An operation of the randomized harness. Its random choices are drawn from `seed`,
+// so a sequence of operations can be replayed, and reduced, as data.
+#[derive(Clone, Debug)]
+struct RecordedOperation {
+    operation: Operation,
+    seed: u64,
+}
 
-    let mut all_keys = BTreeSet::new();
+// Under the temporary directory, so that a failing run leaves nothing in the working directory.
+fn minimized_case_path() -> PathBuf {
+    let file_name = format!("minimized_versioned_store_case_{}.rs", std::process::id());
+    std::env::temp_dir().join(file_name)
+}
 
-    // init history part
-    let write_schema = D::write_schema();
+fn gen_recorded_operations(rng: &mut ChaChaRng, num_operations: usize) -> Vec<RecordedOperation> {
+    let operations = vec![
+        Operation::GetVersionedStore,
+        Operation::GetVersionedStore,
+        Operation::GetVersionedStore,
+        Operation::IterHisoricalChanges,
+        Operation::Discard,
+        Operation::GetVersionedKey,
+        Operation::AddToPendingPart,
+        Operation::AddToPendingPart,
+        Operation::AddToPendingPart,
+        Operation::AddToPendingPart,
+        Operation::ConfirmedPendingToHistory,
+    ];
+
+    (0..num_operations)
+        .map(|_| RecordedOperation {
+            operation: select_vec_element(rng, &operations),
+            seed: rng.next_u64(),
+        })
+        .collect()
+}
+
+fn test_versioned_store<D: DatabaseTrait>(
+    db: &mut D,
+    num_history: usize,
+    num_pending: usize,
+    num_operations: usize,
+    after_init: impl FnOnce(&mut D),
+) {
+    let operations = gen_recorded_operations(&mut get_rng_for_test(), num_operations);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_operations(db, num_history, num_pending, &operations, after_init)
+    }));
+    match result {
+        Ok(operations_analyses) => print_operations_analyses(&operations_analyses),
+        Err(panic) => {
+            report_minimized_operations(num_history, num_pending, operations);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+// Replays `operations` on a fresh in-memory database, as printed by `report_minimized_operations`.
+fn replay_operations(num_history: usize, num_pending: usize, operations: &[RecordedOperation]) {
+    let mut db = InMemoryDatabase::empty();
+    run_operations(&mut db, num_history, num_pending, operations, |_| {});
+}
+
+// Reduces a failing sequence of operations: first to its shortest failing prefix,
+// found by binary search, then by dropping single operations while it still fails.
+fn minimize_operations(
+    operations: Vec<RecordedOperation>,
+    fails: impl Fn(&[RecordedOperation]) -> bool,
+) -> Vec<RecordedOperation> {
+    if fails(&[]) {
+        return vec![];
+    }
+
+    // operations[..low] passes and operations[..high] fails
+    let (mut low, mut high) = (0, operations.len());
+    while low + 1 < high {
+        let mid = (low + high) / 2;
+        if fails(&operations[..mid]) {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+
+    let mut operations = operations[..high].to_vec();
+    let mut index = 0;
+    while index < operations.len() {
+        let mut candidate = operations.clone();
+        candidate.remove(index);
+        if fails(&candidate) {
+            operations = candidate;
+        } else {
+            index += 1;
+        }
+    }
+    operations
+}
+
+fn report_minimized_operations(
+    num_history: usize,
+    num_pending: usize,
+    operations: Vec<RecordedOperation>,
+) {
+    let fails = |operations: &[RecordedOperation]| {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            replay_operations(num_history, num_pending, operations)
+        }))
+        .is_err()
+    };
+
+    let operations = if fails(&operations) {
+        minimize_operations(operations, fails)
+    } else {
+        println!(
+            "the failure does not reproduce on an in-memory database, reporting all operations"
+        );
+        operations
+    };
+
+    let mut snippet = String::from("#[test]\nfn test_minimized_versioned_store() {\n");
+    snippet += &format!("    replay_operations({num_history}, {num_pending}, &[\n");
+    for RecordedOperation { operation, seed } in &operations {
+        snippet += &format!(
+            "        RecordedOperation {{ operation: Operation::{operation:?}, seed: {seed} }},\n"
+        );
+    }
+    snippet += "    ]);\n}\n";
+
+    let path = minimized_case_path();
+    println!(
+        "minimized failing case, also written to {}:\n{snippet}",
+        path.display()
+    );
+    let _ = std::fs::write(path, snippet);
+}
+
+#[derive(Default)]
+struct CountingMetrics {
+    checkouts: AtomicUsize,
+    confirms: AtomicUsize,
+    pending_gets: AtomicUsize,
+    history_gets: AtomicUsize,
+}
+
+impl StorageMetrics for CountingMetrics {
+    fn on_checkout(&self, _steps: usize) {
+        self.checkouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_confirm(&self, _height: usize, _keys: usize, _duration: Duration) {
+        self.confirms.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_get(&self, source: GetSource) {
+        match source {
+            GetSource::Pending => &self.pending_gets,
+            GetSource::History => &self.history_gets,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn run_operations<D: DatabaseTrait>(
+    db: &mut D,
+    num_history: usize,
+    num_pending: usize,
+    operations: &[RecordedOperation],
+    after_init: impl FnOnce(&mut D),
+) -> HashMap<(Operation, bool), usize> {
+    let mut rng = get_rng_for_test();
+    let num_gen_new_keys = 10;
+    let num_gen_previous_keys = 10;
+
+    let mut all_keys = BTreeSet::new();
+
+    // init history part
+    let write_schema = D::write_schema();
     let (history_cids, history_updates, mut pending_part) = gen_init(
         db,
         num_history,
@@ -1165,10 +977,21 @@ fn test_versioned_store<D: DatabaseTrait>(
         &write_schema,
     );
     db.commit(write_schema).unwrap();
+    after_init(db);
+    let metrics = Arc::new(CountingMetrics::default());
+    pending_part.set_metrics(metrics.clone());
+    // successful confirmations of at least one commit, and successful reads of a key
+    let mut num_confirms = 0;
+    let mut num_gets = 0;
 
     // build proxy
-    let mut mock_versioned_store =
-        MockVersionedStore::build(history_cids.clone(), history_updates.clone());
+    let mut mock_versioned_store = MockVersionedStore::from_history(
+        history_cids
+            .items()
+            .iter()
+            .copied()
+            .zip(history_updates.clone()),
+    );
 
     let mut real_versioned_store = VersionedStore::new(db, &mut pending_part).unwrap();
     real_versioned_store.check_consistency().unwrap();
@@ -1187,23 +1010,9 @@ fn test_versioned_store<D: DatabaseTrait>(
         num_gen_previous_keys,
     );
 
-    let operations = vec![
-        Operation::GetVersionedStore,
-        Operation::GetVersionedStore,
-        Operation::GetVersionedStore,
-        Operation::IterHisoricalChanges,
-        Operation::Discard,
-        Operation::GetVersionedKey,
-        Operation::AddToPendingPart,
-        Operation::AddToPendingPart,
-        Operation::AddToPendingPart,
-        Operation::AddToPendingPart,
-        Operation::ConfirmedPendingToHistory,
-    ];
-
     let mut operations_analyses = HashMap::new();
-    for _ in 0..num_operations {
-        let operation = select_vec_element(&mut rng, &operations);
+    for RecordedOperation { operation, seed } in operations.iter().cloned() {
+        let mut rng = ChaChaRng::seed_from_u64(seed);
         let (commit_id_type, commit_id) = versioned_store_proxy.gen_commit_id(&mut rng);
 
         let this_operation_is_ok = match operation {
@@ -1243,26 +1052,46 @@ fn test_versioned_store<D: DatabaseTrait>(
                     &mut all_keys,
                 );
 
-                assert_eq!(mock_res, real_res);
+                assert_eq!(mock_res, real_res.map(|_| ()));
 
                 match commit_id_type {
-                    CommitIDType::PendingRoot | CommitIDType::PendingNonRoot => {
+                    CommitIDType::PendingRoot => assert!(mock_res.is_ok()),
+                    CommitIDType::PendingNonRoot => {
                         assert!(mock_res.is_ok());
+                        num_confirms += 1;
                     }
                     _ => assert_eq!(
                         mock_res.unwrap_err(),
-                        StorageError::PendingError(PendingError::CommitIDNotFound(commit_id))
+                        StorageError::from(PendingError::CommitIDNotFound(commit_id))
                     ),
                 };
 
                 real_res.is_ok()
             }
         };
+        if operation == Operation::GetVersionedKey && this_operation_is_ok {
+            num_gets += 1;
+        }
         *operations_analyses
             .entry((operation, this_operation_is_ok))
             .or_insert(0) += 1;
     }
 
+    assert_eq!(metrics.confirms.load(Ordering::Relaxed), num_confirms);
+    // each successful read is checked with get_versioned_key and contains_versioned_key
+    assert_eq!(
+        metrics.pending_gets.load(Ordering::Relaxed) + metrics.history_gets.load(Ordering::Relaxed),
+        2 * num_gets
+    );
+    if num_pending > 1 {
+        // adding a commit under a pending commit checks the parent out
+        assert!(metrics.checkouts.load(Ordering::Relaxed) > 0);
+    }
+
+    operations_analyses
+}
+
+fn print_operations_analyses(operations_analyses: &HashMap<(Operation, bool), usize>) {
     println!("operations_analyses");
 
     let operations_set = BTreeSet::from([
@@ -1298,31 +1127,2571 @@ fn test_versioned_store<D: DatabaseTrait>(
     }
 }
 
-pub fn empty_rocksdb(db_path: &str) -> Result<kvdb_rocksdb::Database> {
-    use crate::backends::TableName;
+#[test]
+fn tests_versioned_store_inmemory() {
+    let mut db = InMemoryDatabase::empty();
+    test_versioned_store(&mut db, 2, 10, 1000, |_| {});
+}
+
+#[test]
+fn tests_versioned_store_rocksdb() {
+    let db_path = "__test_database";
+
+    let mut db = empty_rocksdb(db_path).unwrap();
+    test_versioned_store(&mut db, 2, 10, 1000, |_| {});
 
     if std::path::Path::new(db_path).exists() {
         std::fs::remove_dir_all(db_path).unwrap();
     }
-    std::fs::create_dir_all(db_path).unwrap();
+}
+
+#[test]
+fn tests_versioned_store_tiered() {
+    use super::table_schema::{HistoryChangeTable, HistoryIndicesTable};
+    use crate::{
+        backends::{TableSchema, TieredDatabase},
+        middlewares::{commit_id_schema::height_to_history_number, CommitIDSchema},
+    };
+
+    type ChangeTable = HistoryChangeTable<TestSchema>;
+    type IndexTable = HistoryIndicesTable<TestSchema>;
+
+    let num_history = 20;
+    let mut db = TieredDatabase::new(
+        InMemoryDatabase::empty(),
+        InMemoryDatabase::empty(),
+        [ChangeTable::NAME, IndexTable::NAME],
+    );
 
-    open_database(TableName::max_index() + 1, db_path)
+    // demote the older half of the history
+    test_versioned_store(&mut db, num_history, 10, 1000, |db| {
+        let demote_before = height_to_history_number(num_history / 2);
+        let num_changes = db
+            .demote::<ChangeTable>(|key, _| key.version() < demote_before)
+            .unwrap();
+        let num_indices = db
+            .demote::<IndexTable>(|key, _| key.1 < demote_before)
+            .unwrap();
+        assert!(num_changes > 0);
+        assert_eq!(num_changes, num_indices);
+
+        assert_eq!(db.demote::<ChangeTable>(|_, _| false).unwrap(), 0);
+        assert_eq!(
+            db.demote::<CommitIDSchema>(|_, _| true),
+            Err(StorageError::TableNotTiered)
+        );
+    });
 }
 
 #[test]
-fn tests_versioned_store_inmemory() {
+fn test_read_headerless_change_rows() {
+    use super::table_schema::HistoryChangeTable;
+    use crate::{
+        backends::{serde::Encode, TableName, WriteSchemaTrait},
+        middlewares::{ChangeKey, KeyValueStoreBulks},
+        traits::KeyValueStoreBulksTrait,
+    };
+    use std::{borrow::Cow, sync::Arc};
+
+    // the change table of `TestSchema` as written before the values had a compression header
+    #[derive(Clone, Copy)]
+    struct RawChangeTable;
+    impl TableSchema for RawChangeTable {
+        const NAME: TableName = TableName::HistoryChange(TestSchema::NAME);
+        type Key = [u8];
+        type Value = [u8];
+    }
+
+    // the first bytes of the values look like each compression header, and an unknown one
+    let rows: Vec<(u64, u64)> = vec![(0, 1), (1, 1 << 56), (2, 2 << 56), (3, u64::MAX)];
     let mut db = InMemoryDatabase::empty();
-    test_versioned_store(&mut db, 2, 10, 1000);
+    let write_schema = InMemoryDatabase::write_schema();
+    for (key, value) in &rows {
+        write_schema.write::<RawChangeTable>((
+            Cow::Owned(ChangeKey::new(1, *key).encode().into_owned()),
+            Some(Cow::Owned(value.encode().into_owned())),
+        ));
+    }
+    db.commit(write_schema).unwrap();
+
+    let change_history_table = KeyValueStoreBulks::new(Arc::new(
+        db.view::<HistoryChangeTable<TestSchema>>().unwrap(),
+    ));
+    for (key, value) in &rows {
+        assert_eq!(
+            change_history_table.get_versioned_key(&1, key).unwrap(),
+            Some(*value)
+        );
+    }
+    assert_eq!(change_history_table.get_bulk(&1).unwrap(), rows);
 }
 
 #[test]
-fn tests_versioned_store_rocksdb() {
-    let db_path = "__test_database";
+fn test_verify_key_history() {
+    use super::{
+        table_schema::{HistoryChangeTable, HistoryIndicesTable},
+        HistoryIndexKey, HistoryIndices, KeyHistoryInconsistency, KeyHistoryReport,
+    };
+    use crate::{
+        backends::WriteSchemaTrait, middlewares::KeyValueStoreBulks,
+        traits::KeyValueStoreBulksTrait,
+    };
+    use std::{borrow::Cow, sync::Arc};
 
-    let mut db = empty_rocksdb(db_path).unwrap();
-    test_versioned_store(&mut db, 2, 10, 1000);
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
 
-    if std::path::Path::new(db_path).exists() {
-        std::fs::remove_dir_all(db_path).unwrap();
+    let write_schema = InMemoryDatabase::write_schema();
+    let (_, _, mut pending_part) = gen_init(&db, 5, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    {
+        let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        for key in all_keys.iter() {
+            let report = store.verify_key_history(key).unwrap();
+            assert!(report.is_consistent());
+            assert!(report.records_examined > 0);
+            assert_eq!(report.records_examined, report.versions_verified);
+            assert!(report.changes_found <= report.versions_verified);
+            assert_eq!(store.verify_key_history_with_orphans(key).unwrap(), report);
+        }
+    }
+
+    // seed an orphan change entry and a dangling index record
+    let orphan_key = gen_novel_u64(&mut rng, &all_keys);
+    let dangling_key = gen_novel_u64(&mut rng, &all_keys);
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let change_history_table = KeyValueStoreBulks::new(Arc::new(
+        db.view::<HistoryChangeTable<TestSchema>>().unwrap(),
+    ));
+    change_history_table
+        .commit(2, std::iter::once((orphan_key, Some(0))), &write_schema)
+        .unwrap();
+    drop(change_history_table);
+    write_schema.write::<HistoryIndicesTable<TestSchema>>((
+        Cow::Owned(HistoryIndexKey(dangling_key, 100)),
+        Some(Cow::Owned(HistoryIndices)),
+    ));
+    db.commit(write_schema).unwrap();
+
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+
+    // orphans are only found by the scan of the change table
+    let report = store.verify_key_history(&orphan_key).unwrap();
+    assert_eq!(report, KeyHistoryReport::default());
+    let report = store.verify_key_history_with_orphans(&orphan_key).unwrap();
+    assert_eq!(report.records_examined, 0);
+    assert_eq!(
+        report.first_inconsistency,
+        Some(KeyHistoryInconsistency::OrphanChange(2))
+    );
+
+    let report = store.verify_key_history(&dangling_key).unwrap();
+    assert_eq!(report.records_examined, 1);
+    assert_eq!(report.versions_verified, 0);
+    assert_eq!(report.changes_found, 0);
+    assert_eq!(
+        report.first_inconsistency,
+        Some(KeyHistoryInconsistency::DanglingIndexRecord(100))
+    );
+}
+
+#[test]
+fn test_bulks() {
+    use super::table_schema::HistoryChangeTable;
+    use crate::{middlewares::KeyValueStoreBulks, traits::KeyValueStoreBulksTrait};
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+
+    let bulks: Vec<BTreeMap<u64, u64>> = (0..4)
+        .map(|_| {
+            (0..8)
+                .map(|_| (rng.next_u64() % 32, rng.next_u64()))
+                .collect()
+        })
+        .collect();
+    let write_schema = InMemoryDatabase::write_schema();
+    let change_history_table = KeyValueStoreBulks::new(Arc::new(
+        db.view::<HistoryChangeTable<TestSchema>>().unwrap(),
+    ));
+    for (history_number, bulk) in (1..).zip(&bulks) {
+        // a deletion is not stored
+        let updates = bulk
+            .iter()
+            .map(|(k, v)| (*k, Some(*v)))
+            .chain(std::iter::once((32 + history_number, None)));
+        change_history_table
+            .commit(history_number, updates, &write_schema)
+            .unwrap();
+    }
+    drop(change_history_table);
+    db.commit(write_schema).unwrap();
+
+    let change_history_table = KeyValueStoreBulks::new(Arc::new(
+        db.view::<HistoryChangeTable<TestSchema>>().unwrap(),
+    ));
+    for (history_number, bulk) in (1..).zip(&bulks) {
+        let expected: Vec<_> = bulk.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(
+            change_history_table.get_bulk(&history_number).unwrap(),
+            expected
+        );
+    }
+    assert_eq!(change_history_table.get_bulk(&0).unwrap(), vec![]);
+    assert_eq!(change_history_table.get_bulk(&5).unwrap(), vec![]);
+
+    let write_schema = InMemoryDatabase::write_schema();
+    change_history_table.delete_bulk(&2, &write_schema).unwrap();
+    drop(change_history_table);
+    db.commit(write_schema).unwrap();
+
+    let change_history_table = KeyValueStoreBulks::new(Arc::new(
+        db.view::<HistoryChangeTable<TestSchema>>().unwrap(),
+    ));
+    for (history_number, bulk) in (1..).zip(&bulks) {
+        for (key, value) in bulk {
+            let expected = (history_number != 2).then_some(*value);
+            assert_eq!(
+                change_history_table
+                    .get_versioned_key(&history_number, key)
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+    assert_eq!(change_history_table.get_bulk(&2).unwrap(), vec![]);
+    assert_eq!(
+        change_history_table.get_bulk(&3).unwrap().len(),
+        bulks[2].len()
+    );
+}
+
+#[test]
+fn test_commit_alias() {
+    use crate::middlewares::CommitAliasSchema;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let parent_of_root = history_cids.items().last().copied();
+    let root = gen_random_commit_id(&mut rng);
+    let fork_a = gen_random_commit_id(&mut rng);
+    let fork_b = gen_random_commit_id(&mut rng);
+    let tip_a = gen_random_commit_id(&mut rng);
+    let alias_a = gen_random_commit_id(&mut rng);
+    let alias_b = gen_random_commit_id(&mut rng);
+    let alias_tip = gen_random_commit_id(&mut rng);
+    let alias_history = gen_random_commit_id(&mut rng);
+
+    let write_schema = InMemoryDatabase::write_schema();
+    {
+        let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        store
+            .add_to_pending_part(parent_of_root, root, BTreeMap::new())
+            .unwrap();
+        store
+            .add_to_pending_part(Some(root), fork_a, BTreeMap::from([(0, Some(1))]))
+            .unwrap();
+        store
+            .add_to_pending_part(Some(root), fork_b, BTreeMap::from([(0, Some(2))]))
+            .unwrap();
+        store
+            .add_to_pending_part(Some(fork_a), tip_a, BTreeMap::new())
+            .unwrap();
+
+        store
+            .register_alias(alias_a, fork_a, &write_schema)
+            .unwrap();
+        // the registration not confirmed yet is already taken
+        assert_eq!(
+            store.register_alias(alias_a, fork_b, &write_schema),
+            Err(StorageError::AliasAlreadyRegistered)
+        );
+        store
+            .register_alias(alias_a, fork_a, &write_schema)
+            .unwrap();
+        store
+            .register_alias(alias_b, fork_b, &write_schema)
+            .unwrap();
+        store
+            .register_alias(alias_tip, tip_a, &write_schema)
+            .unwrap();
+        store
+            .register_alias(alias_history, history_cids.items()[0], &write_schema)
+            .unwrap();
+        // so is the registration of a historical commit not committed yet
+        assert_eq!(
+            store.register_alias(alias_history, fork_a, &write_schema),
+            Err(StorageError::AliasAlreadyRegistered)
+        );
+        assert_eq!(
+            store.register_alias(alias_a, gen_random_commit_id(&mut rng), &write_schema),
+            Err(StorageError::CommitIDNotFound)
+        );
+    }
+    db.commit(write_schema).unwrap();
+
+    // only the alias of the historical commit is written before a confirmation
+    let alias_table = db.view::<CommitAliasSchema>().unwrap();
+    assert!(alias_table.get(&alias_a).unwrap().is_none());
+    assert!(alias_table.get(&alias_b).unwrap().is_none());
+    assert_eq!(
+        alias_table.get(&alias_history).unwrap().as_deref(),
+        Some(&history_cids.items()[0])
+    );
+    drop(alias_table);
+
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.resolve(&alias_a).unwrap(), Some(fork_a));
+    assert_eq!(store.resolve(&alias_b).unwrap(), Some(fork_b));
+    assert_eq!(
+        store.resolve(&alias_history).unwrap(),
+        Some(history_cids.items()[0])
+    );
+    assert_eq!(
+        store.get_versioned_key_by_alias(&alias_b, &0).unwrap(),
+        Some(2)
+    );
+    assert_eq!(
+        store.path_to_root_by_alias(&alias_a),
+        Ok(vec![fork_a, root])
+    );
+    assert_eq!(store.is_pending_by_alias(&alias_history), Ok(false));
+    assert_eq!(
+        store.get_versioned_key_multi_commits_by_alias(&[alias_a, alias_b], &0),
+        Ok(vec![Some(1), Some(2)])
+    );
+
+    // pruning tip_a drops its alias
+    assert_eq!(store.prune_subtree(tip_a), Ok(vec![tip_a]));
+    assert_eq!(store.resolve(&alias_tip).unwrap(), None);
+
+    // discarding the siblings of fork_a removes fork_b and drops its alias, which is free again
+    store.discard(fork_a).unwrap();
+    assert_eq!(store.resolve(&alias_b).unwrap(), None);
+    assert_eq!(
+        store.get_versioned_key_by_alias(&alias_b, &0),
+        Err(StorageError::CommitIDNotFound)
+    );
+    assert_eq!(
+        store.get_versioned_key_by_alias(&alias_a, &0).unwrap(),
+        Some(1)
+    );
+    let write_schema = InMemoryDatabase::write_schema();
+    store
+        .register_alias(alias_b, fork_a, &write_schema)
+        .unwrap();
+    let new_tip = gen_random_commit_id(&mut rng);
+    store
+        .add_to_pending_part(Some(fork_a), new_tip, BTreeMap::new())
+        .unwrap();
+    drop(store);
+    db.commit(write_schema).unwrap();
+
+    // the confirmation of fork_a writes its aliases
+    let write_schema = InMemoryDatabase::write_schema();
+    confirmed_pending_to_history(&db, &mut pending_part, new_tip, &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+
+    let alias_table = db.view::<CommitAliasSchema>().unwrap();
+    for alias in [alias_a, alias_b] {
+        assert_eq!(alias_table.get(&alias).unwrap().as_deref(), Some(&fork_a));
+    }
+    assert!(alias_table.get(&alias_tip).unwrap().is_none());
+    drop(alias_table);
+
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.resolve(&alias_a).unwrap(), Some(fork_a));
+    assert_eq!(
+        store.get_versioned_key_by_alias(&alias_a, &0).unwrap(),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_max_unconfirmed_heights() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    pending_part.set_max_unconfirmed_heights(Some(3));
+
+    // root - a1 - a2 - a3 - a4
+    //      \ b1 - b2
+    let parent_of_root = history_cids.items().last().copied();
+    let commits: Vec<_> = (0..7).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let [root, a1, a2, a3, a4, b1, b2] = commits[..] else {
+        unreachable!()
+    };
+
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    let mut add = |parent: Option<CommitID>, commit: CommitID| {
+        store
+            .add_to_pending_part(parent, commit, BTreeMap::from([(0, Some(0))]))
+            .unwrap()
+            .confirm_to_restore_limit
+    };
+    assert_eq!(add(parent_of_root, root), None);
+    assert_eq!(add(Some(root), a1), None);
+    assert_eq!(add(Some(root), b1), None);
+    // exactly 3 pending heights
+    assert_eq!(add(Some(b1), b2), None);
+    assert_eq!(add(Some(a1), a2), None);
+
+    // 4 pending heights, the confirmation point is above the fork of b1
+    assert_eq!(add(Some(a2), a3), Some(a1));
+    drop(store);
+
+    let write_schema = InMemoryDatabase::write_schema();
+    confirmed_pending_to_history(&db, &mut pending_part, a1, &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+    assert!(!pending_part.contains_commit_id(&b1));
+    assert!(!pending_part.contains_commit_id(&b2));
+
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(
+        store
+            .add_to_pending_part(Some(a3), a4, BTreeMap::new())
+            .unwrap(),
+        AddOutcome {
+            confirm_to_restore_limit: Some(a2)
+        }
+    );
+    drop(store);
+
+    pending_part.set_max_unconfirmed_heights(None);
+    assert_eq!(pending_part.get_pending_root_to_confirm(a4), Ok(None));
+}
+
+#[test]
+fn test_confirm_compacted_branch() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut pending_part = VersionedMap::new_empty();
+
+    // c0 - c1 - c2 - c3 - c4, each writing key 0 and its own key
+    let commits: Vec<_> = (0..5).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let mut parent = None;
+    for (i, commit) in commits.iter().enumerate() {
+        let i = i as u64;
+        store
+            .add_to_pending_part(parent, *commit, [(0, Some(i)), (i, Some(i))])
+            .unwrap();
+        parent = Some(*commit);
+    }
+    drop(store);
+
+    // c1 and c2 are merged into c3
+    for commit in &commits[1..3] {
+        pending_part.mark_unaddressable(*commit).unwrap();
+    }
+    pending_part.compact_branch(commits[4]).unwrap();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let confirmed =
+        confirmed_pending_to_history(&db, &mut pending_part, commits[4], &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+    assert_eq!(confirmed.commit_ids, vec![commits[0], commits[3]]);
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    for commit in &commits[1..3] {
+        assert_eq!(
+            store.get_versioned_store(commit).err(),
+            Some(StorageError::CommitIDNotFound)
+        );
+    }
+    assert_eq!(store.get_commit_id_by_height(1).unwrap(), Some(commits[3]));
+    for key in 0..4 {
+        let expected = if key == 0 { 3 } else { key };
+        assert_eq!(
+            store.get_versioned_key(&commits[3], &key).unwrap(),
+            Some(expected)
+        );
+    }
+    store.check_consistency().unwrap();
+}
+
+#[test]
+fn test_pending_batch() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    // root - a1 - a2
+    //      \ b1 - b2
+    //            \ c2
+    let parent_of_root = history_cids.items().last().copied();
+    let commits: Vec<_> = (0..6).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let [root, a1, a2, b1, b2, c2] = commits[..] else {
+        unreachable!()
+    };
+    let key = gen_novel_u64(&mut rng, &all_keys);
+    let set = |value: u64| BTreeMap::from([(key, Some(value))]);
+
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+
+    // the parent of b2 is neither pending nor staged before it
+    let mut batch = store.begin_pending_batch();
+    batch.add(parent_of_root, root, set(0));
+    batch.add(Some(root), a1, set(1));
+    batch.add(Some(b1), b2, set(2));
+    assert_eq!(
+        batch.commit(),
+        Err(StorageError::from(PendingError::CommitIDNotFound(b1)))
+    );
+    for commit in &commits {
+        assert!(!store.is_pending(commit));
+    }
+
+    // a commit already in the history
+    let mut batch = store.begin_pending_batch();
+    batch.add(parent_of_root, root, set(0));
+    batch.add(Some(root), history_cids.items()[0], set(1));
+    assert_eq!(
+        batch.commit(),
+        Err(StorageError::CommitIdAlreadyExistsInHistory)
+    );
+    assert!(!store.is_pending(&root));
+
+    let mut batch = store.begin_pending_batch();
+    batch.add(parent_of_root, root, set(0));
+    batch.abort();
+    assert!(!store.is_pending(&root));
+
+    let mut batch = store.begin_pending_batch();
+    batch.add(parent_of_root, root, set(0));
+    batch.add(Some(root), a1, set(1));
+    batch.add(Some(root), b1, BTreeMap::new());
+    batch.add(Some(a1), a2, BTreeMap::from([(key, None)]));
+    batch.add(Some(b1), b2, set(2));
+    batch.add(Some(b1), c2, set(3));
+    assert_eq!(batch.commit().unwrap(), vec![AddOutcome::default(); 6]);
+
+    let expected = [Some(0), Some(1), None, Some(0), Some(2), Some(3)];
+    for (commit, expected) in commits.iter().zip(expected) {
+        assert!(store.is_pending(commit));
+        assert_eq!(store.get_versioned_key(commit, &key).unwrap(), expected);
     }
+    store.check_consistency().unwrap();
+
+    // a batch can extend the pending part, but not add a second root
+    let d3 = gen_random_commit_id(&mut rng);
+    let mut batch = store.begin_pending_batch();
+    batch.add(Some(c2), d3, set(4));
+    batch.add(parent_of_root, gen_random_commit_id(&mut rng), set(5));
+    assert_eq!(
+        batch.commit(),
+        Err(StorageError::from(PendingError::MultipleRootsNotAllowed))
+    );
+    assert!(!store.is_pending(&d3));
+
+    let mut batch = store.begin_pending_batch();
+    batch.add(Some(c2), d3, set(4));
+    batch.commit().unwrap();
+    assert_eq!(store.get_versioned_key(&d3, &key).unwrap(), Some(4));
+}
+
+#[test]
+fn test_historical_reads_after_restart() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, history_updates, _) =
+        gen_init(&db, 4, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let mut state = BTreeMap::new();
+    let expected: Vec<_> = history_updates
+        .iter()
+        .map(|updates| {
+            state.extend(updates.iter().map(|(key, value)| (*key, *value)));
+            state.clone()
+        })
+        .collect();
+
+    // reopened with the pending part rooted at the tip, or not rooted at all
+    let latest = history_cids.items().last().copied();
+    for mut pending_part in [
+        VersionedMap::new(latest, history_cids.len()),
+        VersionedMap::new_empty(),
+    ] {
+        let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+        // the latest and older commits
+        for (commit, expected) in history_cids.items().iter().zip(&expected).rev() {
+            let snapshot = store.get_versioned_store(commit).unwrap();
+            for key in all_keys.iter() {
+                let value = expected.get(key).copied().flatten();
+                assert_eq!(snapshot.get(key), Ok(value));
+                assert_eq!(store.get_versioned_key(commit, key), Ok(value));
+            }
+        }
+        assert!(matches!(
+            store.get_versioned_store(&gen_random_commit_id(&mut rng)),
+            Err(StorageError::CommitIDNotFound)
+        ));
+    }
+}
+
+#[test]
+fn test_add_to_pending_part_or_skip() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 3, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let history = history_cids.items();
+    let pending: Vec<_> = (0..2).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let key = gen_novel_u64(&mut rng, &all_keys);
+
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    assert_eq!(
+        store.add_to_pending_part_or_skip(Some(history[2]), pending[0], [(key, Some(0))]),
+        Ok(AddOrSkipOutcome::AddedAsRoot(AddOutcome::default()))
+    );
+    assert_eq!(
+        store.add_to_pending_part_or_skip(Some(pending[0]), pending[1], [(key, Some(1))]),
+        Ok(AddOrSkipOutcome::AddedAsChild(AddOutcome::default()))
+    );
+
+    // replayed, with the updates ignored
+    for (parent, commit) in [(history[2], pending[0]), (pending[0], pending[1])] {
+        assert_eq!(
+            store.add_to_pending_part_or_skip(Some(parent), commit, [(key, Some(2))]),
+            Ok(AddOrSkipOutcome::AlreadyPending)
+        );
+    }
+    assert_eq!(store.get_versioned_key(&pending[1], &key), Ok(Some(1)));
+    for (height, parent) in [(0, None), (1, Some(history[0])), (2, Some(history[1]))] {
+        assert_eq!(
+            store.add_to_pending_part_or_skip(parent, history[height], [(key, Some(2))]),
+            Ok(AddOrSkipOutcome::AlreadyInHistory { height })
+        );
+    }
+
+    // known under another parent
+    for (parent, commit) in [
+        (Some(pending[1]), pending[0]),
+        (Some(history[2]), pending[1]),
+        (None, history[2]),
+        (Some(history[0]), history[2]),
+        (Some(history[0]), history[0]),
+    ] {
+        assert_eq!(
+            store.add_to_pending_part_or_skip(parent, commit, [(key, Some(2))]),
+            Err(StorageError::ParentMismatch)
+        );
+    }
+    assert_eq!(store.get_versioned_key(&pending[1], &key), Ok(Some(1)));
+    assert_eq!(store.leaves(), vec![pending[1]]);
+}
+
+#[test]
+fn test_latest_confirmed() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let key = gen_novel_u64(&mut rng, &BTreeSet::new());
+
+    // no history
+    let mut pending_part = VersionedMap::new_empty();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    assert!(store.latest_confirmed_snapshot().unwrap().is_none());
+    assert_eq!(store.get_latest_confirmed(&key), Ok(None));
+    assert_eq!(
+        store.get_key_at_height(0, &key),
+        Err(StorageError::HeightNotConfirmed(0))
+    );
+
+    let commits: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let mut parent = None;
+    for (value, commit) in commits.iter().enumerate() {
+        store
+            .add_to_pending_part(parent, *commit, BTreeMap::from([(key, Some(value as u64))]))
+            .unwrap();
+        parent = Some(*commit);
+    }
+    assert_eq!(store.get_parent_of_root(), None);
+    assert_eq!(store.get_versioned_key(&commits[2], &key), Ok(Some(2)));
+    drop(store);
+
+    // the parent of the pending root changes with each confirmation
+    for (confirmed, new_root) in [(0, commits[1]), (1, commits[2])] {
+        let write_schema = InMemoryDatabase::write_schema();
+        confirmed_pending_to_history(&db, &mut pending_part, new_root, &write_schema).unwrap();
+        db.commit(write_schema).unwrap();
+
+        let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+        assert_eq!(store.get_parent_of_root(), Some(commits[confirmed]));
+        assert_eq!(store.get_latest_confirmed(&key), Ok(Some(confirmed as u64)));
+        let snapshot = store.latest_confirmed_snapshot().unwrap().unwrap();
+        assert_eq!(snapshot.get(&key), Ok(Some(confirmed as u64)));
+        assert_eq!(
+            snapshot.get(&gen_novel_u64(&mut rng, &BTreeSet::from([key]))),
+            Ok(None)
+        );
+
+        // reads at the pending commits fall back to the latest confirmed commit
+        assert_eq!(store.get_versioned_key(&commits[2], &key), Ok(Some(2)));
+        assert_eq!(
+            store.get_versioned_store(&new_root).unwrap().get(&key),
+            Ok(Some(confirmed as u64 + 1))
+        );
+    }
+}
+
+// Counts the seeks of a table, to compare batched reads against reads key by key.
+struct SeekCounter<'a, T: TableSchema> {
+    inner: TableReader<'a, T>,
+    seeks: Cell<usize>,
+}
+
+impl<'a, T: TableSchema> TableRead<T> for SeekCounter<'a, T> {
+    fn get(&self, key: &T::Key) -> DbResult<Option<Cow<T::Value>>> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.get(key)
+    }
+
+    fn iter<'b>(&'b self, key: &T::Key) -> DbResult<TableIter<'b, '_, T>> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.iter(key)
+    }
+
+    fn iter_from_start(&self) -> DbResult<TableIter<T>> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.iter_from_start()
+    }
+
+    fn iter_rev<'b>(&'b self, key: &T::Key) -> DbResult<TableIter<'b, '_, T>> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.iter_rev(key)
+    }
+
+    fn iter_from_end(&self) -> DbResult<TableIter<T>> {
+        self.seeks.set(self.seeks.get() + 1);
+        self.inner.iter_from_end()
+    }
+}
+
+#[test]
+fn test_get_versioned_entries() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 5, &mut rng, 100, 20, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let counter = Arc::new(SeekCounter {
+        inner: store.tables.history_index_table.clone(),
+        seeks: Cell::new(0),
+    });
+    let history_index_table: TableReader<_> = counter.clone();
+
+    let mut keys: Vec<_> = all_keys.iter().copied().collect();
+    for _ in 0..10 {
+        keys.push(gen_novel_u64(&mut rng, &all_keys));
+    }
+    keys.reverse();
+
+    for history_number in 1..=history_cids.items().len() as u64 {
+        counter.seeks.set(0);
+        let expected: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                get_versioned_entry(
+                    history_number,
+                    key,
+                    &history_index_table,
+                    &store.tables.change_history_table,
+                )
+                .unwrap()
+            })
+            .collect();
+        let naive_seeks = counter.seeks.replace(0);
+
+        let entries = get_versioned_entries(
+            history_number,
+            &keys,
+            &history_index_table,
+            &store.tables.change_history_table,
+        )
+        .unwrap();
+        assert_eq!(entries, expected);
+        assert_eq!(naive_seeks, keys.len());
+        assert!(counter.seeks.get() < naive_seeks / 2);
+    }
+}
+
+#[test]
+fn test_height_lookup() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 3, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let history_cids = history_cids.items().to_vec();
+
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    // a fork at height 3, and height 4 on one branch
+    let pending: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let parent_of_root = history_cids.last().copied();
+    for (parent, commit) in [
+        (parent_of_root, pending[0]),
+        (parent_of_root, pending[1]),
+        (Some(pending[0]), pending[2]),
+    ] {
+        store
+            .add_to_pending_part(parent, commit, BTreeMap::new())
+            .unwrap();
+    }
+
+    for (height, commit) in history_cids.iter().enumerate() {
+        assert_eq!(store.get_commit_id_by_height(height), Ok(Some(*commit)));
+        assert_eq!(store.get_height_by_commit_id(commit), Ok(Some(height)));
+    }
+    for (commit, height) in pending.iter().zip([3, 3, 4]) {
+        assert_eq!(store.get_height_by_commit_id(commit), Ok(Some(height)));
+    }
+    for height in [3, 4, 5, usize::MAX] {
+        assert_eq!(store.get_commit_id_by_height(height), Ok(None));
+    }
+    assert_eq!(
+        store.get_height_by_commit_id(&gen_random_commit_id(&mut rng)),
+        Ok(None)
+    );
+
+    for key in &all_keys {
+        for (height, commit) in history_cids.iter().enumerate() {
+            assert_eq!(
+                store.get_key_at_height(height, key),
+                store.get_versioned_key(commit, key)
+            );
+        }
+        for height in [3, 4, 5, usize::MAX] {
+            assert_eq!(
+                store.get_key_at_height(height, key),
+                Err(StorageError::HeightNotConfirmed(height))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_add_to_pending_part_from_iter() {
+    let db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let commits: Vec<_> = (0..2).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+    let mut pending_part = VersionedMap::new_empty();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let mut mock_store = MockVersionedStore::<u64, u64>::new();
+
+    // the last change of a key wins
+    let updates = vec![(1, Some(10)), (2, Some(20)), (1, None), (2, Some(21))];
+    store
+        .add_to_pending_part(None, commits[0], updates.clone())
+        .unwrap();
+    mock_store
+        .add_to_pending_part(None, commits[0], updates)
+        .unwrap();
+    let updates = [(3, Some(30)), (1, Some(11)), (3, Some(31))];
+    store
+        .add_to_pending_part(Some(commits[0]), commits[1], updates.iter().copied())
+        .unwrap();
+    mock_store
+        .add_to_pending_part(Some(commits[0]), commits[1], updates)
+        .unwrap();
+
+    for (commit, expected) in commits.iter().zip([
+        [(1, None), (2, Some(21)), (3, None)],
+        [(1, Some(11)), (2, Some(21)), (3, Some(31))],
+    ]) {
+        for (key, value) in expected {
+            assert_eq!(store.get_versioned_key(commit, &key), Ok(value));
+            assert_eq!(mock_store.get_versioned_key(commit, &key), Ok(value));
+        }
+    }
+}
+
+// An in-memory database whose commits fail while `fail_commits` is set.
+struct FailingDatabase {
+    inner: InMemoryDatabase,
+    fail_commits: bool,
+}
+
+impl DatabaseTrait for FailingDatabase {
+    type TableID = <InMemoryDatabase as DatabaseTrait>::TableID;
+    type WriteSchema = <InMemoryDatabase as DatabaseTrait>::WriteSchema;
+
+    fn view<T: TableSchema>(&self) -> Result<impl '_ + TableRead<T>> {
+        self.inner.view::<T>()
+    }
+
+    fn write_schema() -> Self::WriteSchema {
+        InMemoryDatabase::write_schema()
+    }
+
+    fn commit(&mut self, changes: Self::WriteSchema) -> Result<()> {
+        if self.fail_commits {
+            return Err(std::io::Error::other("injected commit failure").into());
+        }
+        self.inner.commit(changes)
+    }
+
+    fn create_checkpoint(&self, path: &Path) -> Result<()> {
+        self.inner.create_checkpoint(path)
+    }
+
+    fn open_checkpoint(path: &Path) -> Result<Self> {
+        Ok(Self {
+            inner: InMemoryDatabase::open_checkpoint(path)?,
+            fail_commits: false,
+        })
+    }
+}
+
+#[test]
+fn test_two_phase_confirm() {
+    use super::{finalize_confirm, prepare_confirm};
+
+    let mut db = FailingDatabase {
+        inner: InMemoryDatabase::empty(),
+        fail_commits: false,
+    };
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = FailingDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let commits: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let mut parent = history_cids.items().last().copied();
+    for commit in commits.iter() {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 5, 5, &mut all_keys);
+        store.add_to_pending_part(parent, *commit, updates).unwrap();
+        parent = Some(*commit);
+    }
+    let read_all = |store: &VersionedStore<TestSchema>| -> Vec<Vec<Option<u64>>> {
+        let keys: Vec<_> = all_keys.iter().copied().collect();
+        commits
+            .iter()
+            .map(|commit| {
+                store
+                    .get_versioned_store(commit)
+                    .unwrap()
+                    .multi_get(&keys)
+                    .unwrap()
+            })
+            .collect()
+    };
+    let expected = read_all(&store);
+    drop(store);
+
+    // the commit fails: the pending part is left as it was
+    let write_schema = FailingDatabase::write_schema();
+    let stale_ticket =
+        prepare_confirm::<_, TestSchema>(&db, &pending_part, commits[2], &write_schema).unwrap();
+    db.fail_commits = true;
+    assert!(db.commit(write_schema).is_err());
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    store.check_consistency().unwrap();
+    assert!(commits.iter().all(|commit| store.is_pending(commit)));
+    assert_eq!(read_all(&store), expected);
+    drop(store);
+
+    // retried, then finalized once committed
+    let write_schema = FailingDatabase::write_schema();
+    let ticket =
+        prepare_confirm::<_, TestSchema>(&db, &pending_part, commits[1], &write_schema).unwrap();
+    db.fail_commits = false;
+    db.commit(write_schema).unwrap();
+    finalize_confirm::<TestSchema>(&mut pending_part, ticket).unwrap();
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    store.check_consistency().unwrap();
+    assert_eq!(store.get_parent_of_root(), Some(commits[0]));
+    assert!(!store.is_pending(&commits[0]));
+    assert_eq!(read_all(&store), expected);
+    drop(store);
+
+    // the ticket of the failed commit no longer matches the pending part
+    assert_eq!(
+        finalize_confirm::<TestSchema>(&mut pending_part, stale_ticket),
+        Err(StorageError::ConsistencyCheckFailure)
+    );
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    store.check_consistency().unwrap();
+    assert_eq!(store.get_parent_of_root(), Some(commits[0]));
+}
+
+#[test]
+fn test_add_to_pending_part_checked() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let parent_of_root = history_cids.items().last().copied();
+    let commits: Vec<_> = (0..4).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+
+    // the history holds heights 0 and 1
+    assert_eq!(
+        store.add_to_pending_part_checked(parent_of_root, commits[0], BTreeMap::new(), Some(3)),
+        Err(StorageError::HeightMismatch {
+            expected: 3,
+            actual: 2
+        })
+    );
+    assert!(!store.is_pending(&commits[0]));
+    store
+        .add_to_pending_part_checked(parent_of_root, commits[0], BTreeMap::new(), Some(2))
+        .unwrap();
+
+    let mut parent = commits[0];
+    for (height, commit) in (3..).zip(&commits[1..3]) {
+        store
+            .add_to_pending_part_checked(Some(parent), *commit, BTreeMap::new(), Some(height))
+            .unwrap();
+        parent = *commit;
+    }
+
+    // a parent deeper than intended
+    assert_eq!(
+        store.add_to_pending_part_checked(Some(commits[2]), commits[3], BTreeMap::new(), Some(4)),
+        Err(StorageError::HeightMismatch {
+            expected: 4,
+            actual: 5
+        })
+    );
+    assert!(!store.is_pending(&commits[3]));
+
+    // no expectation
+    store
+        .add_to_pending_part_checked(Some(commits[2]), commits[3], BTreeMap::new(), None)
+        .unwrap();
+}
+
+#[test]
+fn test_confirm_prefix_stats() {
+    use super::{confirm_maps_to_history_with_stats, PrefixCounts, PrefixStatsConfig};
+
+    let mut db = InMemoryDatabase::empty();
+    // u64 keys are encoded in big endian, so the first byte is the prefix
+    let key = |prefix: u64, index: u64| (prefix << 56) | index;
+    let counts = |inserts, updates, deletes, value_bytes| PrefixCounts {
+        inserts,
+        updates,
+        deletes,
+        value_bytes,
+    };
+    let config = PrefixStatsConfig {
+        prefix_len: 1,
+        max_buckets: 3,
+    };
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let stats = confirm_maps_to_history_with_stats::<_, TestSchema>(
+        &db,
+        0,
+        vec![BTreeMap::from([
+            (key(1, 0), Some(0)),
+            (key(1, 1), Some(0)),
+            (key(2, 0), Some(0)),
+        ])],
+        &write_schema,
+        &config,
+    )
+    .unwrap();
+    db.commit(write_schema).unwrap();
+    assert_eq!(
+        stats.buckets,
+        BTreeMap::from([
+            (vec![1], counts(2, 0, 0, 16)),
+            (vec![2], counts(1, 0, 0, 8))
+        ])
+    );
+    assert_eq!(stats.other, PrefixCounts::default());
+
+    // key(1, 2) was never written, key(2, 0) is written again after its deletion,
+    // and key(3, 0) is inserted then updated within the confirmation
+    let maps = vec![
+        BTreeMap::from([
+            (key(1, 0), Some(1)),
+            (key(1, 2), None),
+            (key(2, 0), None),
+            (key(3, 0), Some(1)),
+        ]),
+        BTreeMap::from([(key(2, 0), Some(2)), (key(3, 0), Some(2))]),
+    ];
+    let expected_buckets = [
+        (vec![1], counts(0, 1, 1, 8)),
+        (vec![2], counts(1, 0, 1, 8)),
+        (vec![3], counts(1, 1, 0, 16)),
+    ];
+
+    let stats = confirm_maps_to_history_with_stats::<_, TestSchema>(
+        &db,
+        1,
+        maps.clone(),
+        &InMemoryDatabase::write_schema(),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(stats.buckets, BTreeMap::from(expected_buckets.clone()));
+    assert_eq!(stats.other, PrefixCounts::default());
+
+    // buckets are taken in the order of the confirmed keys, the rest overflows to `other`
+    let stats = confirm_maps_to_history_with_stats::<_, TestSchema>(
+        &db,
+        1,
+        maps,
+        &InMemoryDatabase::write_schema(),
+        &PrefixStatsConfig {
+            prefix_len: 1,
+            max_buckets: 2,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        stats.buckets,
+        BTreeMap::from([expected_buckets[0].clone(), expected_buckets[1].clone()])
+    );
+    assert_eq!(stats.other, counts(1, 1, 0, 16));
+}
+
+#[test]
+fn test_iter_all_keys_with_status() {
+    use super::KeyStatus;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, mut updates, mut pending_part) =
+        gen_init(&db, 5, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let mut commits = history_cids.into_vec();
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    for _ in 0..3 {
+        let previous_keys = all_keys.clone();
+        let pending_updates = gen_updates(&mut rng, &previous_keys, 5, 5, &mut all_keys);
+        let commit = gen_random_commit_id(&mut rng);
+        store
+            .add_to_pending_part(commits.last().copied(), commit, pending_updates.clone())
+            .unwrap();
+        commits.push(commit);
+        updates.push(pending_updates);
+    }
+
+    for (height, commit) in commits.iter().enumerate() {
+        let mut expected: BTreeMap<u64, KeyStatus<u64>> = BTreeMap::new();
+        for (update_height, update) in updates[..=height].iter().enumerate() {
+            for (key, value) in update {
+                let status = expected.entry(*key).or_insert(KeyStatus {
+                    key: *key,
+                    live: false,
+                    last_modified_height: 0,
+                    version_count: 0,
+                });
+                status.live = value.is_some();
+                status.last_modified_height = update_height;
+                status.version_count += 1;
+            }
+        }
+        let expected: Vec<_> = expected.into_values().collect();
+
+        for limit in [1, 3, expected.len() + 1] {
+            let mut statuses = Vec::new();
+            let mut cursor = None;
+            loop {
+                let (page, next) = store
+                    .iter_all_keys_with_status(commit, cursor.as_ref(), limit)
+                    .unwrap();
+                assert!(page.len() <= limit);
+                statuses.extend(page);
+                match next {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(statuses, expected);
+        }
+    }
+}
+
+#[test]
+fn test_estimate_reclaimable() {
+    use super::{estimate_reclaimable, RetentionPolicy, RECLAIM_TOP_KEYS};
+
+    // encoded bytes of a u64 key with a history number, and of a u64 value
+    const INDEX_BYTES: u64 = 16;
+    const CHANGE_BYTES: u64 = 24;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (_, history_updates, _) = gen_init(&db, 8, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    // versions of each key, from the latest to the earliest: (height, is deletion)
+    let mut versions: BTreeMap<u64, Vec<(usize, bool)>> = BTreeMap::new();
+    for (height, updates) in history_updates.iter().enumerate() {
+        for (key, value) in updates {
+            versions
+                .entry(*key)
+                .or_default()
+                .insert(0, (height, value.is_none()));
+        }
+    }
+    let latest_height = history_updates.len() - 1;
+
+    let policies = [
+        RetentionPolicy::PruneBeforeHeight(0),
+        RetentionPolicy::PruneBeforeHeight(4),
+        RetentionPolicy::PruneBeforeHeight(latest_height),
+        RetentionPolicy::TombstoneExpiry(0),
+        RetentionPolicy::TombstoneExpiry(3),
+    ];
+
+    let mut last_progress = 0;
+    let estimate = estimate_reclaimable::<_, TestSchema>(&db, &policies, |scanned| {
+        assert!(scanned >= last_progress);
+        last_progress = scanned;
+    })
+    .unwrap();
+
+    let num_records: usize = versions.values().map(Vec::len).sum();
+    assert_eq!(estimate.index_records_scanned, num_records as u64);
+    assert_eq!(last_progress, num_records as u64);
+
+    for (policy, policy_estimate) in policies.iter().zip(estimate.policies.iter()) {
+        assert_eq!(policy_estimate.policy, *policy);
+
+        let mut expected_keys = Vec::new();
+        let (mut index_records, mut change_records) = (0, 0);
+        for (key, key_versions) in versions.iter() {
+            let reclaimable = match *policy {
+                RetentionPolicy::PruneBeforeHeight(height) => {
+                    match key_versions.iter().position(|(h, _)| *h <= height) {
+                        Some(kept) => &key_versions[kept + 1..],
+                        None => &[],
+                    }
+                }
+                RetentionPolicy::TombstoneExpiry(ttl) => {
+                    let (height, deleted) = key_versions[0];
+                    if deleted && height + ttl <= latest_height {
+                        &key_versions[..]
+                    } else {
+                        &[]
+                    }
+                }
+            };
+            let changes = reclaimable.iter().filter(|(_, deleted)| !deleted).count();
+            index_records += reclaimable.len() as u64;
+            change_records += changes as u64;
+            let key_bytes = reclaimable.len() as u64 * INDEX_BYTES + changes as u64 * CHANGE_BYTES;
+            if key_bytes > 0 {
+                expected_keys.push(key_bytes);
+            }
+        }
+
+        assert_eq!(policy_estimate.index_records, index_records);
+        assert_eq!(policy_estimate.index_bytes, index_records * INDEX_BYTES);
+        assert_eq!(policy_estimate.change_records, change_records);
+        assert_eq!(policy_estimate.change_bytes, change_records * CHANGE_BYTES);
+
+        expected_keys.sort_unstable_by(|a, b| b.cmp(a));
+        expected_keys.truncate(RECLAIM_TOP_KEYS);
+        let top_key_bytes: Vec<_> = policy_estimate
+            .top_keys
+            .iter()
+            .map(|(_, bytes)| *bytes)
+            .collect();
+        assert_eq!(top_key_bytes, expected_keys);
+    }
+
+    // the latest height keeps only the latest version of each key
+    assert_eq!(
+        estimate.policies[2].index_records,
+        (num_records - versions.len()) as u64
+    );
+}
+
+#[test]
+fn test_analyze_history() {
+    use super::{analyze_history, HistoryStats};
+
+    let db = InMemoryDatabase::empty();
+    assert_eq!(
+        analyze_history::<_, TestSchema>(&db).unwrap(),
+        Default::default()
+    );
+    assert_eq!(
+        analyze_history::<_, TestSchema>(&db)
+            .unwrap()
+            .versions_percentile(50),
+        None
+    );
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (_, history_updates, _) = gen_init(&db, 8, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let mut versions: BTreeMap<u64, u64> = BTreeMap::new();
+    let (mut index_records, mut change_records) = (0, 0);
+    for updates in history_updates.iter() {
+        for (key, value) in updates {
+            *versions.entry(*key).or_default() += 1;
+            index_records += 1;
+            change_records += value.is_some() as u64;
+        }
+    }
+    let mut versions_per_key = BTreeMap::new();
+    for num_versions in versions.values() {
+        *versions_per_key.entry(*num_versions).or_default() += 1;
+    }
+
+    let stats = analyze_history::<_, TestSchema>(&db).unwrap();
+    assert_eq!(stats.num_keys, versions.len() as u64);
+    assert_eq!(stats.index_records, index_records);
+    assert_eq!(stats.change_records, change_records);
+    assert_eq!(stats.deletions(), index_records - change_records);
+    // each value is an encoded u64
+    assert_eq!(stats.value_bytes, change_records * 8);
+    assert_eq!(stats.average_value_bytes(), Some(8.0));
+    assert_eq!(stats.versions_per_key, versions_per_key);
+
+    // more change records than index records, as in a partly pruned history
+    let partial = HistoryStats {
+        index_records: 1,
+        change_records: 2,
+        ..stats.clone()
+    };
+    assert_eq!(partial.deletions(), 0);
+
+    let mut sorted: Vec<_> = versions.values().copied().collect();
+    sorted.sort_unstable();
+    for percent in [0, 1, 25, 50, 90, 99, 100] {
+        let rank = (sorted.len() * percent as usize).div_ceil(100).max(1);
+        assert_eq!(stats.versions_percentile(percent), Some(sorted[rank - 1]));
+    }
+}
+
+#[test]
+fn test_restore_pending_part() {
+    use super::restore_pending_part;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 4, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let keys: Vec<_> = all_keys.iter().copied().collect();
+
+    let pending_cids: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let mut parent = history_cids.items().last().copied();
+    for (i, commit) in pending_cids.iter().enumerate() {
+        store
+            .add_to_pending_part(
+                parent,
+                *commit,
+                [(keys[i], Some(i as u64)), (keys[i + 1], None)],
+            )
+            .unwrap();
+        parent = Some(*commit);
+    }
+    let mut dump = Vec::new();
+    pending_part.dump(&mut dump).unwrap();
+
+    let mut restored = restore_pending_part::<_, TestSchema>(&db, dump.as_slice()).unwrap();
+    let expected = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let actual = VersionedStore::<TestSchema>::new(&db, &mut restored).unwrap();
+    for commit in &pending_cids {
+        for key in &keys {
+            assert_eq!(
+                actual.get_versioned_key(commit, key),
+                expected.get_versioned_key(commit, key)
+            );
+        }
+    }
+
+    // the confirmed tip of another database does not match
+    let other_db = InMemoryDatabase::empty();
+    assert_eq!(
+        restore_pending_part::<_, TestSchema>(&other_db, dump.as_slice()).err(),
+        Some(StorageError::InvalidBackup(
+            "pending part does not follow the latest confirmed commit"
+        ))
+    );
+
+    // nor after confirming more commits
+    let write_schema = InMemoryDatabase::write_schema();
+    confirm_ids_to_history::<InMemoryDatabase>(&db, 4, &pending_cids[..1], &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+    assert_eq!(
+        restore_pending_part::<_, TestSchema>(&db, dump.as_slice()).err(),
+        Some(StorageError::InvalidBackup(
+            "pending part does not follow the latest confirmed commit"
+        ))
+    );
+}
+
+#[test]
+fn test_prune_history_before() {
+    use super::{estimate_reclaimable, prune_history_before, RetentionPolicy};
+    use crate::middlewares::{CommitMetadata, CommitMetadataSchema};
+
+    const CUTOFF_HEIGHT: usize = 4;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 8, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let history_cids = history_cids.items().to_vec();
+    let keys: Vec<_> = all_keys.iter().copied().collect();
+
+    fn count_records(
+        db: &InMemoryDatabase,
+        pending_part: &mut VersionedMap<PendingKeyValueConfig<TestSchema, CommitID>>,
+    ) -> (u64, u64) {
+        let store = VersionedStore::<TestSchema>::new(db, pending_part).unwrap();
+        (
+            store
+                .tables
+                .history_index_table
+                .iter_from_start()
+                .unwrap()
+                .count() as u64,
+            store
+                .tables
+                .change_history_table
+                .iter_from_start()
+                .unwrap()
+                .count() as u64,
+        )
+    }
+    let (index_records, change_records) = count_records(&db, &mut pending_part);
+    let estimate = estimate_reclaimable::<_, TestSchema>(
+        &db,
+        &[RetentionPolicy::PruneBeforeHeight(CUTOFF_HEIGHT)],
+        |_| {},
+    )
+    .unwrap();
+    let estimate = &estimate.policies[0];
+
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let expected: Vec<Vec<_>> = history_cids[CUTOFF_HEIGHT..]
+        .iter()
+        .map(|commit| {
+            let snapshot = store.get_versioned_store(commit).unwrap();
+            keys.iter()
+                .map(|key| snapshot.get_entry(key).unwrap())
+                .collect()
+        })
+        .collect();
+
+    let pruned_alias = gen_random_commit_id(&mut rng);
+    let kept_alias = gen_random_commit_id(&mut rng);
+    let write_schema = InMemoryDatabase::write_schema();
+    store
+        .register_alias(pruned_alias, history_cids[0], &write_schema)
+        .unwrap();
+    store
+        .register_alias(kept_alias, history_cids[CUTOFF_HEIGHT], &write_schema)
+        .unwrap();
+    let metadata = CommitMetadata {
+        timestamp_ms: 1_700_000_000_000,
+        extra: [1].into(),
+    };
+    for history_number in [1, CUTOFF_HEIGHT as u64 + 1] {
+        write_schema.write::<CommitMetadataSchema>((
+            Cow::Owned(history_number),
+            Some(Cow::Borrowed(&metadata)),
+        ));
+    }
+    // the history numbers of the commits to prune are cached by reading them
+    for commit in &history_cids[..CUTOFF_HEIGHT] {
+        store.get_versioned_store(commit).unwrap();
+    }
+    drop(store);
+    assert!(history_cids[..CUTOFF_HEIGHT]
+        .iter()
+        .all(|commit| pending_part.get_cached_history_number(commit).is_some()));
+    db.commit(write_schema).unwrap();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    assert_eq!(
+        prune_history_before::<_, TestSchema>(
+            &db,
+            &mut pending_part,
+            history_cids.len(),
+            &write_schema
+        ),
+        Err(StorageError::HeightNotConfirmed(history_cids.len()))
+    );
+    prune_history_before::<_, TestSchema>(&db, &mut pending_part, CUTOFF_HEIGHT, &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+
+    // exactly the records estimated as reclaimable are removed
+    assert_eq!(
+        count_records(&db, &mut pending_part),
+        (
+            index_records - estimate.index_records,
+            change_records - estimate.change_records
+        )
+    );
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    store.check_consistency().unwrap();
+    // the aliases and the metadata of the pruned commits are deleted
+    assert!(store.alias_table.get(&pruned_alias).unwrap().is_none());
+    assert_eq!(store.resolve(&pruned_alias), Ok(None));
+    assert!(db
+        .view::<CommitMetadataSchema>()
+        .unwrap()
+        .get(&1)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        store.get_commit_metadata(&history_cids[CUTOFF_HEIGHT]),
+        Ok(Some(metadata))
+    );
+    assert_eq!(
+        store.resolve(&kept_alias),
+        Ok(Some(history_cids[CUTOFF_HEIGHT]))
+    );
+    for commit in &history_cids[..CUTOFF_HEIGHT] {
+        assert_eq!(
+            store.get_versioned_store(commit).err(),
+            Some(StorageError::CommitIDNotFound)
+        );
+    }
+    for (commit, expected) in history_cids[CUTOFF_HEIGHT..].iter().zip(expected) {
+        let snapshot = store.get_versioned_store(commit).unwrap();
+        let entries: Vec<_> = keys
+            .iter()
+            .map(|key| snapshot.get_entry(key).unwrap())
+            .collect();
+        assert_eq!(entries, expected);
+    }
+}
+
+#[test]
+fn test_check_history_integrity() {
+    use super::{
+        prune_history_before,
+        table_schema::{HistoryChangeTable, HistoryIndicesTable},
+        HistoryIndexKey, HistoryIndices,
+    };
+    use crate::{
+        errors::{ErrorContext, InconsistencyReason},
+        middlewares::{commit_id_schema::height_to_history_number, ChangeKey},
+    };
+
+    fn write_index(db: &mut InMemoryDatabase, key: u64, height: usize, present: bool) {
+        let write_schema = InMemoryDatabase::write_schema();
+        write_schema.write::<HistoryIndicesTable<TestSchema>>((
+            Cow::Owned(HistoryIndexKey(key, height_to_history_number(height))),
+            present.then_some(Cow::Owned(HistoryIndices)),
+        ));
+        db.commit(write_schema).unwrap();
+    }
+
+    fn write_change(db: &mut InMemoryDatabase, key: u64, height: usize, value: Option<u64>) {
+        let write_schema = InMemoryDatabase::write_schema();
+        write_schema.write::<HistoryChangeTable<TestSchema>>((
+            Cow::Owned(ChangeKey::new(height_to_history_number(height), key)),
+            value.map(Cow::Owned),
+        ));
+        db.commit(write_schema).unwrap();
+    }
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 5, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let write_schema = InMemoryDatabase::write_schema();
+    prune_history_before::<_, TestSchema>(&db, &mut pending_part, 2, &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+
+    let mut check = |db: &InMemoryDatabase, sample| {
+        VersionedStore::<TestSchema>::new(db, &mut pending_part)
+            .unwrap()
+            .check_history_integrity(sample)
+    };
+    // the reason of a detected inconsistency, `None` if there is none
+    let reason = |result: Result<()>| match result {
+        Ok(()) => None,
+        Err(e) => {
+            assert!(matches!(e.root(), StorageError::ConsistencyCheckFailure));
+            Some(e.context().unwrap().reason.unwrap())
+        }
+    };
+    assert_eq!(reason(check(&db, None)), None);
+    let key = gen_novel_u64(&mut rng, &all_keys);
+
+    // an index record above the latest confirmed commit
+    write_index(&mut db, key, 5, true);
+    let error = check(&db, None).unwrap_err();
+    assert_eq!(
+        error.context(),
+        Some(
+            &ErrorContext::new::<HistoryIndicesTable<TestSchema>>(
+                &HistoryIndexKey(key, height_to_history_number(5)),
+                Some(height_to_history_number(5))
+            )
+            .with_reason(InconsistencyReason::AboveLatestConfirmed)
+        )
+    );
+    assert!(error
+        .to_string()
+        .contains("above the latest confirmed commit"));
+    write_index(&mut db, key, 5, false);
+
+    // a deletion is an index record without a change row
+    write_index(&mut db, key, 3, true);
+    assert_eq!(reason(check(&db, None)), None);
+
+    // pruning keeps one record at or below the earliest confirmed commit
+    write_index(&mut db, key, 0, true);
+    assert_eq!(reason(check(&db, None)), None);
+    write_index(&mut db, key, 1, true);
+    assert_eq!(
+        reason(check(&db, None)),
+        Some(InconsistencyReason::SeveralPrunedVersions)
+    );
+    write_index(&mut db, key, 1, false);
+
+    // a change row without an index record
+    write_change(&mut db, key, 4, Some(1));
+    assert_eq!(
+        reason(check(&db, None)),
+        Some(InconsistencyReason::UnreachableChange)
+    );
+    // the sample does not reach the rows of the last heights
+    assert_eq!(reason(check(&db, Some(1))), None);
+    write_index(&mut db, key, 4, true);
+    assert_eq!(reason(check(&db, None)), None);
+
+    // confirming a commit again is detected by the confirm path
+    let error = confirm_ids_to_history::<InMemoryDatabase>(
+        &db,
+        5,
+        &history_cids.items()[4..],
+        &InMemoryDatabase::write_schema(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.root(),
+        StorageError::ConsistencyCheckFailure
+    ));
+    assert_eq!(
+        error.context().unwrap().reason,
+        Some(InconsistencyReason::AlreadyConfirmed)
+    );
+
+    // the store checks the tables with the other tables
+    VersionedStore::<TestSchema>::new(&db, &mut pending_part)
+        .unwrap()
+        .check_consistency()
+        .unwrap();
+}
+
+#[test]
+fn test_rollback_history_to() {
+    use super::{
+        rollback_history_to,
+        table_schema::{HistoryChangeTable, HistoryIndicesTable},
+    };
+    use crate::middlewares::commit_id_schema::{height_to_history_number, CommitAliasSchema};
+
+    type PendingPart = VersionedMap<PendingKeyValueConfig<TestSchema, CommitID>>;
+
+    // adds a chain of commits on `parent` and confirms all of them but the last one
+    fn add_and_confirm(
+        db: &mut InMemoryDatabase,
+        pending_part: &mut PendingPart,
+        mut parent: Option<CommitID>,
+        changes: Vec<Vec<(u64, Option<u64>)>>,
+        rng: &mut ChaChaRng,
+    ) -> Vec<CommitID> {
+        let mut store = VersionedStore::<TestSchema>::new(db, pending_part).unwrap();
+        let mut commits = Vec::new();
+        for updates in changes {
+            let commit = gen_random_commit_id(rng);
+            store
+                .add_to_pending_part(parent, commit, BTreeMap::from_iter(updates))
+                .unwrap();
+            commits.push(commit);
+            parent = Some(commit);
+        }
+        drop(store);
+
+        let write_schema = InMemoryDatabase::write_schema();
+        confirmed_pending_to_history(db, pending_part, parent.unwrap(), &write_schema).unwrap();
+        db.commit(write_schema).unwrap();
+        commits
+    }
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut pending_part = VersionedMap::new_empty();
+
+    let old_branch = add_and_confirm(
+        &mut db,
+        &mut pending_part,
+        None,
+        vec![
+            vec![(1, Some(0))],
+            vec![(1, Some(1)), (2, Some(100))],
+            vec![(1, Some(2))],
+            vec![(1, Some(3)), (2, None)],
+            vec![(1, Some(4)), (3, Some(300))],
+            vec![(1, Some(5))],
+        ],
+        &mut rng,
+    );
+
+    let kept_alias = gen_random_commit_id(&mut rng);
+    let removed_alias = gen_random_commit_id(&mut rng);
+    let write_schema = InMemoryDatabase::write_schema();
+    {
+        let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+        store
+            .register_alias(kept_alias, old_branch[1], &write_schema)
+            .unwrap();
+        store
+            .register_alias(removed_alias, old_branch[4], &write_schema)
+            .unwrap();
+    }
+    db.commit(write_schema).unwrap();
+
+    rollback_history_to::<_, TestSchema>(&mut db, &mut pending_part, old_branch[2]).unwrap();
+    assert_eq!(
+        rollback_history_to::<_, TestSchema>(&mut db, &mut pending_part, old_branch[3]),
+        Err(StorageError::CommitIDNotFound)
+    );
+
+    // the aliases of the removed commits are deleted
+    let alias_table = db.view::<CommitAliasSchema>().unwrap();
+    assert_eq!(
+        alias_table.get(&kept_alias).unwrap().as_deref(),
+        Some(&old_branch[1])
+    );
+    assert!(alias_table.get(&removed_alias).unwrap().is_none());
+    drop(alias_table);
+
+    // only the history up to height 2 is left
+    let max_history_number = height_to_history_number(2);
+    let change_table = db.view::<HistoryChangeTable<TestSchema>>().unwrap();
+    for item in change_table.iter_from_start().unwrap() {
+        assert!(item.unwrap().0.version() <= max_history_number);
+    }
+    let index_table = db.view::<HistoryIndicesTable<TestSchema>>().unwrap();
+    for item in index_table.iter_from_start().unwrap() {
+        assert!(item.unwrap().0 .1 <= max_history_number);
+    }
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.get_parent_of_root(), Some(old_branch[2]));
+    assert!(store.leaves().is_empty());
+    assert_eq!(store.get_commit_id_by_height(3), Ok(None));
+    for commit in &old_branch[3..] {
+        assert_eq!(
+            store.get_versioned_key(commit, &1),
+            Err(StorageError::CommitIDNotFound)
+        );
+    }
+    assert_eq!(store.get_latest_confirmed(&2), Ok(Some(100)));
+    drop(store);
+
+    let new_branch = add_and_confirm(
+        &mut db,
+        &mut pending_part,
+        Some(old_branch[2]),
+        vec![
+            vec![(1, Some(33)), (3, Some(333))],
+            vec![(2, Some(222))],
+            vec![],
+        ],
+        &mut rng,
+    );
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let expected = [
+        [Some(0), None, None],
+        [Some(1), Some(100), None],
+        [Some(2), Some(100), None],
+        [Some(33), Some(100), Some(333)],
+        [Some(33), Some(222), Some(333)],
+    ];
+    let confirmed = old_branch[..3].iter().chain(&new_branch[..2]);
+    for (height, (commit, values)) in confirmed.zip(expected).enumerate() {
+        assert_eq!(store.get_commit_id_by_height(height), Ok(Some(*commit)));
+        for (key, value) in (1..).zip(values) {
+            assert_eq!(store.get_key_at_height(height, &key), Ok(value));
+            assert_eq!(store.get_versioned_key(commit, &key), Ok(value));
+        }
+    }
+    assert_eq!(store.get_height_by_commit_id(&new_branch[2]), Ok(Some(5)));
+    assert_eq!(store.get_versioned_key(&new_branch[2], &2), Ok(Some(222)));
+}
+
+#[test]
+fn test_snapshot_export_import() {
+    use super::{export_snapshot, import_snapshot};
+    use crate::middlewares::{commit_id_schema::history_number_to_height, CommitIDSchema};
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 8, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+    let history_cids = history_cids.items().to_vec();
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    for commit in [history_cids[3], *history_cids.last().unwrap()] {
+        let expected: Vec<_> = store
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter_all()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let mut snapshot = Vec::new();
+        let manifest = export_snapshot::<_, TestSchema>(&db, commit, &mut snapshot).unwrap();
+        assert_eq!(manifest.commit, commit);
+        assert_eq!(manifest.num_entries, expected.len() as u64);
+
+        let mut imported = InMemoryDatabase::empty();
+        assert_eq!(
+            import_snapshot::<_, TestSchema>(&mut imported, snapshot.as_slice()),
+            Ok(commit)
+        );
+        let height = history_number_to_height(manifest.history_number);
+        let mut imported_pending_part = VersionedMap::new(Some(commit), height + 1);
+        let imported_store =
+            VersionedStore::<TestSchema>::new(&imported, &mut imported_pending_part).unwrap();
+        imported_store.check_consistency().unwrap();
+        let entries: Vec<_> = imported_store
+            .get_versioned_store(&commit)
+            .unwrap()
+            .iter_all()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries, expected);
+        drop(imported_store);
+
+        // a snapshot only loads into an empty database
+        assert_eq!(
+            import_snapshot::<_, TestSchema>(&mut imported, snapshot.as_slice()),
+            Err(StorageError::InvalidBackup("database is not empty"))
+        );
+
+        // a corrupted entry is rejected, and nothing is written
+        let mut corrupted = snapshot.clone();
+        let checksum_start = corrupted.len() - 32;
+        corrupted[checksum_start - 13] ^= 1;
+        let mut imported = InMemoryDatabase::empty();
+        assert_eq!(
+            import_snapshot::<_, TestSchema>(&mut imported, corrupted.as_slice()),
+            Err(StorageError::InvalidBackup("mismatched checksum"))
+        );
+        assert!(imported
+            .view::<CommitIDSchema>()
+            .unwrap()
+            .iter_from_start()
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    assert_eq!(
+        export_snapshot::<_, TestSchema>(&db, CommitID::repeat_byte(0xff), Vec::new()).err(),
+        Some(StorageError::CommitIDNotFound)
+    );
+}
+
+#[test]
+fn test_history_number_cache() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, history_updates, mut pending_part) =
+        gen_init(&db, 3, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let old_tip = *history_cids.items().last().unwrap();
+    let (key, value) = history_updates[2].iter().next().unwrap();
+    let (key, value) = (*key, *value);
+
+    let new_tip = gen_random_commit_id(&mut rng);
+    {
+        let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        assert_eq!(store.get_versioned_key(&old_tip, &key).unwrap(), value);
+        store
+            .add_to_pending_part(
+                Some(old_tip),
+                new_tip,
+                BTreeMap::from([(key, Some(u64::MAX))]),
+            )
+            .unwrap();
+
+        // reads of the pending commit do not touch the cache
+        assert_eq!(
+            store.get_versioned_key(&new_tip, &key).unwrap(),
+            Some(u64::MAX)
+        );
+    }
+    assert_eq!(pending_part.get_cached_history_number(&old_tip), Some(3));
+    assert_eq!(pending_part.num_cached_history_numbers(), 1);
+
+    let write_schema = InMemoryDatabase::write_schema();
+    confirmed_pending_to_history(&db, &mut pending_part, new_tip, &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+    assert_eq!(pending_part.num_cached_history_numbers(), 0);
+
+    // the former tip is now read from the history below the new tip
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.get_versioned_key(&old_tip, &key).unwrap(), value);
+    assert_eq!(
+        store.get_versioned_key(&new_tip, &key).unwrap(),
+        Some(u64::MAX)
+    );
+    drop(store);
+    assert_eq!(pending_part.get_cached_history_number(&old_tip), Some(3));
+    assert_eq!(pending_part.get_cached_history_number(&new_tip), Some(4));
+}
+
+#[test]
+fn test_dedup_identical_writes() {
+    use crate::backends::VersionedKVName;
+
+    #[derive(Clone, Copy, Debug)]
+    struct DedupSchema;
+
+    impl VersionedKeyValueSchema for DedupSchema {
+        const NAME: VersionedKVName = VersionedKVName::FlatKV;
+        const DEDUP_IDENTICAL_WRITES: bool = true;
+        type Key = u64;
+        type Value = u64;
+    }
+
+    const KEY: u64 = 0;
+    let values = [Some(1), Some(1), Some(1), None, None, Some(2), Some(2)];
+
+    // writes `values` to KEY in a chain of commits and confirms them, then returns the number of
+    // history index records and the changes visited from the last commit
+    fn write_and_confirm<T: VersionedKeyValueSchema<Key = u64, Value = u64>>(
+        values: &[Option<u64>],
+    ) -> (usize, Vec<Option<u64>>) {
+        let mut db = InMemoryDatabase::empty();
+        let mut rng = get_rng_for_test();
+        let mut pending_part = VersionedMap::new_empty();
+        let commits: Vec<_> = (0..=values.len())
+            .map(|_| gen_random_commit_id(&mut rng))
+            .collect();
+
+        let mut store = VersionedStore::<T>::new(&db, &mut pending_part).unwrap();
+        let mut parent = None;
+        for (commit, value) in commits.iter().zip(values) {
+            store
+                .add_to_pending_part(parent, *commit, [(KEY, *value)])
+                .unwrap();
+            parent = Some(*commit);
+        }
+        let new_root = *commits.last().unwrap();
+        store
+            .add_to_pending_part(parent, new_root, std::iter::empty())
+            .unwrap();
+        drop(store);
+
+        let write_schema = InMemoryDatabase::write_schema();
+        confirmed_pending_to_history::<_, T>(&db, &mut pending_part, new_root, &write_schema)
+            .unwrap();
+        db.commit(write_schema).unwrap();
+
+        let store = VersionedStore::<T>::new(&db, &mut pending_part).unwrap();
+        store.check_consistency().unwrap();
+        for (commit, value) in commits.iter().zip(values) {
+            assert_eq!(store.get_versioned_key(commit, &KEY).unwrap(), *value);
+        }
+        let mut changes = Vec::new();
+        store
+            .iter_historical_changes(
+                |_, _, value| {
+                    changes.push(value.copied());
+                    true
+                },
+                &new_root,
+                &KEY,
+            )
+            .unwrap();
+        let index_records = store
+            .tables
+            .history_index_table
+            .iter_from_start()
+            .unwrap()
+            .count();
+        (index_records, changes)
+    }
+
+    assert_eq!(
+        write_and_confirm::<TestSchema>(&values),
+        (values.len(), values.iter().rev().copied().collect())
+    );
+    assert_eq!(
+        write_and_confirm::<DedupSchema>(&values),
+        (3, vec![Some(2), None, Some(1)])
+    );
+}
+
+#[test]
+fn test_confirmation_cursor() {
+    // the same history and pending commits in two databases
+    let setup = || {
+        let mut db = InMemoryDatabase::empty();
+        let mut rng = get_rng_for_test();
+        let mut all_keys = BTreeSet::new();
+
+        let write_schema = InMemoryDatabase::write_schema();
+        let (history_cids, _, mut pending_part) =
+            gen_init(&db, 3, &mut rng, 10, 10, &mut all_keys, &write_schema);
+        db.commit(write_schema).unwrap();
+
+        let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        let mut parent = history_cids.items().last().copied();
+        let mut commits = Vec::new();
+        for _ in 0..6 {
+            let commit = gen_random_commit_id(&mut rng);
+            let previous_keys = all_keys.clone();
+            let updates = gen_updates(&mut rng, &previous_keys, 3, 3, &mut all_keys);
+            store.add_to_pending_part(parent, commit, updates).unwrap();
+            parent = Some(commit);
+            commits.push(commit);
+        }
+        drop(store);
+        (db, pending_part, commits, all_keys)
+    };
+    let (mut db, mut pending_part, commits, all_keys) = setup();
+    let (mut cursor_db, mut cursor_pending_part, _, _) = setup();
+
+    // one commit at a time, in two write schemas
+    for new_roots in [&commits[1..4], &commits[4..]] {
+        let write_schema = InMemoryDatabase::write_schema();
+        let mut paths = Vec::new();
+        for new_root in new_roots {
+            paths.push(
+                confirmed_pending_to_history(&db, &mut pending_part, *new_root, &write_schema)
+                    .unwrap(),
+            );
+        }
+
+        let cursor_write_schema = InMemoryDatabase::write_schema();
+        let cursor = ConfirmationCursor::new(&cursor_db).unwrap();
+        for (new_root, path) in new_roots.iter().zip(&paths) {
+            let cursor_path = cursor
+                .confirm_next(&mut cursor_pending_part, *new_root, &cursor_write_schema)
+                .unwrap();
+            assert_eq!(&cursor_path, path);
+        }
+        drop(cursor);
+
+        let ops = write_schema.drain();
+        assert_eq!(cursor_write_schema.drain(), ops);
+        type WriteSchema = <InMemoryDatabase as DatabaseTrait>::WriteSchema;
+        db.commit(WriteSchema::from_ops(ops.clone())).unwrap();
+        cursor_db.commit(WriteSchema::from_ops(ops)).unwrap();
+    }
+
+    let store = VersionedStore::new(&cursor_db, &mut cursor_pending_part).unwrap();
+    store.check_consistency().unwrap();
+    assert_eq!(store.get_parent_of_root(), Some(commits[4]));
+    let expected = VersionedStore::new(&db, &mut pending_part).unwrap();
+    for key in all_keys {
+        assert_eq!(
+            store.get_versioned_key(&commits[5], &key),
+            expected.get_versioned_key(&commits[5], &key)
+        );
+    }
+}
+
+#[test]
+fn test_confirmed_path() {
+    let mut rng = get_rng_for_test();
+    let mut db = InMemoryDatabase::empty();
+    let mut pending_part = VersionedMap::new_empty();
+
+    // a chain changing 1, 2, ... keys, with a sibling of the third commit
+    let commits: Vec<_> = (0..5).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let sibling = gen_random_commit_id(&mut rng);
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    let mut parent = None;
+    for (i, commit) in commits.iter().enumerate() {
+        let updates: BTreeMap<_, _> = (0..=i as u64).map(|key| (key, Some(key))).collect();
+        store.add_to_pending_part(parent, *commit, updates).unwrap();
+        parent = Some(*commit);
+    }
+    store
+        .add_to_pending_part(Some(commits[1]), sibling, [(0, None)])
+        .unwrap();
+    drop(store);
+
+    let mut confirm = |new_root: CommitID| {
+        let write_schema = InMemoryDatabase::write_schema();
+        let path =
+            confirmed_pending_to_history(&db, &mut pending_part, new_root, &write_schema).unwrap();
+        db.commit(write_schema).unwrap();
+
+        let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        for (offset, commit) in path.commit_ids.iter().enumerate() {
+            assert_eq!(
+                store.get_commit_id_by_height(path.start_height + offset),
+                Ok(Some(*commit))
+            );
+        }
+        path
+    };
+
+    let path = confirm(commits[3]);
+    assert_eq!(path.start_height, 0);
+    assert_eq!(path.commit_ids, commits[..3]);
+    assert_eq!(path.num_keys_per_commit, [1, 2, 3]);
+
+    // the pending root is unchanged
+    let path = confirm(commits[3]);
+    assert_eq!(path.start_height, 3);
+    assert!(path.commit_ids.is_empty());
+    assert!(path.num_keys_per_commit.is_empty());
+
+    let path = confirm(commits[4]);
+    assert_eq!(path.start_height, 3);
+    assert_eq!(path.commit_ids, [commits[3]]);
+    assert_eq!(path.num_keys_per_commit, [4]);
+}
+
+#[test]
+fn test_read_only_stores() {
+    let num_readers = 4;
+    let num_commits = 20;
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let key = gen_novel_u64(&mut rng, &all_keys);
+    let commits: Vec<_> = (0..num_commits)
+        .map(|_| gen_random_commit_id(&mut rng))
+        .collect();
+    let pending_part = RwLock::new(pending_part);
+
+    std::thread::scope(|scope| {
+        let (db, commits, pending_part) = (&db, &commits, &pending_part);
+        for _ in 0..num_readers {
+            scope.spawn(move || loop {
+                let pending_part = pending_part.read().unwrap();
+                let store = VersionedStoreReadOnly::new(db, &pending_part).unwrap();
+                let added = commits
+                    .iter()
+                    .take_while(|commit| pending_part.contains_commit_id(commit))
+                    .count();
+                for (i, commit) in commits[..added].iter().enumerate() {
+                    let value = Some(i as u64);
+                    assert_eq!(store.get_versioned_key(commit, &key).unwrap(), value);
+                    assert!(store.contains_versioned_key(commit, &key).unwrap());
+                    assert_eq!(
+                        store
+                            .get_versioned_store(commit)
+                            .unwrap()
+                            .get(&key)
+                            .unwrap(),
+                        value
+                    );
+
+                    let mut num_changes = 0;
+                    let is_completed = store
+                        .iter_historical_changes(
+                            |_, _, _| {
+                                num_changes += 1;
+                                true
+                            },
+                            commit,
+                            &key,
+                        )
+                        .unwrap();
+                    assert!(is_completed);
+                    assert_eq!(num_changes, i + 1);
+                }
+                if added == commits.len() {
+                    break;
+                }
+            });
+        }
+
+        let mut parent = history_cids.items().last().copied();
+        for (i, commit) in commits.iter().enumerate() {
+            let mut pending_part = pending_part.write().unwrap();
+            let mut store = VersionedStore::new(db, &mut pending_part).unwrap();
+            store
+                .add_to_pending_part(parent, *commit, [(key, Some(i as u64))])
+                .unwrap();
+            parent = Some(*commit);
+            drop(store);
+            drop(pending_part);
+            std::thread::yield_now();
+        }
+    });
+
+    // a read-only store and the store agree
+    let mut pending_part = pending_part.into_inner().unwrap();
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    let tip = commits.last().unwrap();
+    assert_eq!(
+        store.as_read_only().get_versioned_key(tip, &key).unwrap(),
+        store.get_versioned_key(tip, &key).unwrap()
+    );
+}
+
+#[test]
+fn test_empty_commits() {
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+    let mut all_keys = BTreeSet::new();
+
+    let write_schema = InMemoryDatabase::write_schema();
+    let (history_cids, _, mut pending_part) =
+        gen_init(&db, 2, &mut rng, 10, 10, &mut all_keys, &write_schema);
+    db.commit(write_schema).unwrap();
+
+    let key = gen_novel_u64(&mut rng, &all_keys);
+    let expected = [None, Some(1), Some(1), None, None, None, Some(2)];
+    let updates = [
+        BTreeMap::new(),
+        BTreeMap::from([(key, Some(1))]),
+        BTreeMap::new(),
+        BTreeMap::from([(key, None)]),
+        BTreeMap::new(),
+        BTreeMap::new(),
+        BTreeMap::from([(key, Some(2))]),
+    ];
+    let commits: Vec<_> = (0..updates.len())
+        .map(|_| gen_random_commit_id(&mut rng))
+        .collect();
+
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    let mut parent = history_cids.items().last().copied();
+    for (commit, updates) in commits.iter().zip(updates) {
+        store.add_to_pending_part(parent, *commit, updates).unwrap();
+        parent = Some(*commit);
+    }
+    drop(store);
+
+    // reads must not change while all commits are pending, after confirming
+    // up to the empty commits[2], and after confirming up to commits[5],
+    // which ends the run of empty commits[4..=5]
+    for confirm in [None, Some(commits[2]), Some(commits[5])] {
+        if let Some(confirm) = confirm {
+            let write_schema = InMemoryDatabase::write_schema();
+            confirmed_pending_to_history(&db, &mut pending_part, confirm, &write_schema).unwrap();
+            db.commit(write_schema).unwrap();
+        }
+
+        let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+        store.check_consistency().unwrap();
+        for (commit, expected) in commits.iter().zip(expected) {
+            assert_eq!(store.get_versioned_key(commit, &key).unwrap(), expected);
+        }
+
+        let mut changes = Vec::new();
+        let is_completed = store
+            .iter_historical_changes(
+                |commit, _, value| {
+                    changes.push((*commit, value.copied()));
+                    true
+                },
+                commits.last().unwrap(),
+                &key,
+            )
+            .unwrap();
+        assert!(is_completed);
+        assert_eq!(
+            changes,
+            vec![
+                (commits[6], Some(2)),
+                (commits[3], None),
+                (commits[1], Some(1))
+            ]
+        );
+
+        let mut changes = Vec::new();
+        let is_completed = store
+            .iter_historical_changes_bounded(
+                |commit, _, value| {
+                    changes.push((*commit, value.copied()));
+                    true
+                },
+                commits.last().unwrap(),
+                &key,
+                1,
+                1,
+            )
+            .unwrap();
+        assert!(!is_completed);
+        assert_eq!(changes, vec![(commits[3], None)]);
+
+        assert_eq!(
+            store.get_versioned_key(&gen_random_commit_id(&mut rng), &key),
+            Err(StorageError::CommitIDNotFound)
+        );
+    }
+}
+
+#[test]
+fn test_minimize_operations() {
+    let operations = gen_recorded_operations(&mut get_rng_for_test(), 100);
+    let culprits = [operations[17].seed, operations[60].seed];
+
+    // fails once both culprits have run
+    let fails = |operations: &[RecordedOperation]| {
+        culprits
+            .iter()
+            .all(|culprit| operations.iter().any(|op| op.seed == *culprit))
+    };
+
+    let minimized = minimize_operations(operations, fails);
+    let seeds: Vec<_> = minimized.iter().map(|op| op.seed).collect();
+    assert_eq!(seeds, culprits);
+}
+
+#[test]
+fn test_diff() {
+    let mut rng = get_rng_for_test();
+    let mut db = InMemoryDatabase::empty();
+    let mut pending_part = VersionedMap::new_empty();
+
+    // commits[0] <- commits[1] <- commits[2] <- commits[3], with the forks
+    // commits[2] <- commits[4] <- commits[5] and commits[3] <- commits[6]
+    let commits: Vec<_> = (0..7).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let parents = [None, Some(0), Some(1), Some(2), Some(2), Some(4), Some(3)];
+    let mut all_keys = BTreeSet::new();
+    let mut store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    for (commit, parent) in commits.iter().zip(parents) {
+        let previous_keys = all_keys.clone();
+        let updates = gen_updates(&mut rng, &previous_keys, 10, 20, &mut all_keys);
+        store
+            .add_to_pending_part(parent.map(|p| commits[p]), *commit, updates)
+            .unwrap();
+    }
+    drop(store);
+
+    // commits[0] and commits[1] are confirmed
+    let write_schema = InMemoryDatabase::write_schema();
+    confirmed_pending_to_history(&db, &mut pending_part, commits[2], &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+
+    let store = VersionedStore::<TestSchema>::new(&db, &mut pending_part).unwrap();
+    for from in &commits {
+        for to in &commits {
+            let mut expected = BTreeMap::new();
+            for key in &all_keys {
+                let old = store.get_versioned_key(from, key).unwrap();
+                let new = store.get_versioned_key(to, key).unwrap();
+                if old != new {
+                    expected.insert(*key, (old, new));
+                }
+            }
+
+            let entries: Vec<_> = store
+                .diff_iter(from, to)
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(entries, expected.clone().into_iter().collect::<Vec<_>>());
+            assert_eq!(store.diff(from, to).unwrap(), expected);
+        }
+    }
+
+    let unknown = gen_random_commit_id(&mut rng);
+    assert!(store.diff(&commits[0], &unknown).is_err());
+    assert!(store.diff(&unknown, &commits[3]).is_err());
+}
+
+#[test]
+fn test_commit_metadata() {
+    use super::{confirm_ids_with_metadata, rollback_history_to, VersionedStoreCache};
+    use crate::middlewares::{CommitMetadata, CommitMetadataSchema};
+
+    let mut db = InMemoryDatabase::empty();
+    let mut rng = get_rng_for_test();
+
+    // heights 0..3 are confirmed without metadata, heights 3..5 with
+    let old_commits: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let new_commits: Vec<_> = (0..2).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let metadata: Vec<_> = (0..2)
+        .map(|i| CommitMetadata {
+            timestamp_ms: 1_700_000_000_000 + i,
+            extra: vec![i as u8; i as usize].into(),
+        })
+        .collect();
+    let write_schema = InMemoryDatabase::write_schema();
+    confirm_ids_to_history::<_>(&db, 0, &old_commits, &write_schema).unwrap();
+    confirm_ids_with_metadata::<_>(&db, 3, &new_commits, &metadata, &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+
+    let mut pending_part = VersionedStoreCache::<TestSchema>::new(Some(new_commits[1]), 5);
+    let pending_commit = gen_random_commit_id(&mut rng);
+    let mut store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    store
+        .add_to_pending_part(Some(new_commits[1]), pending_commit, BTreeMap::new())
+        .unwrap();
+
+    for commit in &old_commits {
+        assert_eq!(store.get_commit_metadata(commit), Ok(None));
+    }
+    for (commit, metadata) in new_commits.iter().zip(&metadata) {
+        assert_eq!(
+            store.get_commit_metadata(commit),
+            Ok(Some(metadata.clone()))
+        );
+    }
+    assert_eq!(store.get_commit_metadata(&pending_commit), Ok(None));
+    assert_eq!(
+        store.get_commit_metadata(&gen_random_commit_id(&mut rng)),
+        Ok(None)
+    );
+    drop(store);
+
+    // the metadata of the removed commits is removed with them
+    rollback_history_to::<_, TestSchema>(&mut db, &mut pending_part, new_commits[0]).unwrap();
+    let metadata_table = db.view::<CommitMetadataSchema>().unwrap();
+    assert!(metadata_table.get(&5).unwrap().is_none());
+    drop(metadata_table);
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(
+        store.get_commit_metadata(&new_commits[0]),
+        Ok(Some(metadata[0].clone()))
+    );
+    assert_eq!(store.get_commit_metadata(&new_commits[1]), Ok(None));
+}
+
+#[test]
+fn test_commit_metadata_added_column() {
+    use super::{confirm_ids_with_metadata, VersionedStoreCache};
+    use crate::{
+        backends::{impls::kvdb_rocksdb::open_database, TableName},
+        middlewares::CommitMetadata,
+    };
+
+    let db_path = "__test_commit_metadata";
+    if Path::new(db_path).exists() {
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+    std::fs::create_dir_all(db_path).unwrap();
+
+    let mut rng = get_rng_for_test();
+    let commits: Vec<_> = (0..2).map(|_| gen_random_commit_id(&mut rng)).collect();
+    let metadata = CommitMetadata {
+        timestamp_ms: 42,
+        extra: b"chain".to_vec().into(),
+    };
+
+    // a database created before the metadata table, and the tables after it
+    let mut db = open_database(u32::from(TableName::CommitMetadata), db_path).unwrap();
+    let write_schema = <kvdb_rocksdb::Database as DatabaseTrait>::write_schema();
+    confirm_ids_to_history::<_>(&db, 0, &commits[..1], &write_schema).unwrap();
+    db.commit(write_schema).unwrap();
+    drop(db);
+
+    // reopened with every column, the commits confirmed before have no metadata
+    let mut db = open_database(TableName::max_index() + 1, db_path).unwrap();
+    let mut pending_part = VersionedStoreCache::<TestSchema>::new_empty();
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.get_commit_metadata(&commits[0]), Ok(None));
+    drop(store);
+
+    let write_schema = <kvdb_rocksdb::Database as DatabaseTrait>::write_schema();
+    confirm_ids_with_metadata::<_>(&db, 1, &commits[1..], &[metadata.clone()], &write_schema)
+        .unwrap();
+    db.commit(write_schema).unwrap();
+    drop(db);
+
+    let db = open_database(TableName::max_index() + 1, db_path).unwrap();
+    let store = VersionedStore::new(&db, &mut pending_part).unwrap();
+    assert_eq!(store.get_commit_metadata(&commits[0]), Ok(None));
+    assert_eq!(store.get_commit_metadata(&commits[1]), Ok(Some(metadata)));
+    drop(store);
+    drop(db);
+
+    std::fs::remove_dir_all(db_path).unwrap();
 }
@@ -0,0 +1,295 @@
+use std::{marker::PhantomData, path::PathBuf};
+
+use crate::{
+    backends::{impls::kvdb_rocksdb::open_database, DatabaseTrait, InMemoryDatabase, TableName},
+    errors::Result,
+    example::FlatKeyValue,
+    middlewares::{
+        checked_history_number_to_height, confirmed_pending_to_history, latest_confirmed,
+        table_schema::VersionedKeyValueSchema, CommitID, ConfirmedPath, VersionedStore,
+        VersionedStoreCache,
+    },
+    StorageError,
+};
+
+/// Opens a database with the pending part of a versioned table, placed on top of the latest
+/// confirmed commit found in the database.
+///
+/// ```ignore
+/// let mut storage = StorageBuilder::rocksdb(path).with_schema::<FlatKeyValue>().open()?;
+/// let mut store = storage.as_manager()?;
+/// ```
+pub struct StorageBuilder<D: DatabaseTrait, T: VersionedKeyValueSchema = FlatKeyValue> {
+    open_backend: Box<dyn FnOnce() -> Result<D>>,
+    history_check: Option<Option<usize>>,
+    _schema: PhantomData<T>,
+}
+
+impl StorageBuilder<kvdb_rocksdb::Database> {
+    /// Opens the RocksDB database at `path`, with a column for each table, creating it if missing.
+    pub fn rocksdb(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::from_opener(Box::new(move || {
+            open_database(TableName::max_index() + 1, path)
+        }))
+    }
+}
+
+impl StorageBuilder<InMemoryDatabase> {
+    /// Opens an empty in-memory database.
+    pub fn in_memory() -> Self {
+        Self::from_opener(Box::new(|| Ok(InMemoryDatabase::empty())))
+    }
+}
+
+impl<D: DatabaseTrait + 'static> StorageBuilder<D> {
+    /// Opens `backend`, already opened, e.g. the one returned by [`FlatStorage::into_backend`].
+    pub fn with_backend(backend: D) -> Self {
+        Self::from_opener(Box::new(move || Ok(backend)))
+    }
+}
+
+impl<D: DatabaseTrait> StorageBuilder<D> {
+    fn from_opener(open_backend: Box<dyn FnOnce() -> Result<D>>) -> Self {
+        Self {
+            open_backend,
+            history_check: None,
+            _schema: PhantomData,
+        }
+    }
+}
+
+impl<D: DatabaseTrait, T: VersionedKeyValueSchema> StorageBuilder<D, T> {
+    /// Sets the versioned table whose pending part is opened.
+    pub fn with_schema<S: VersionedKeyValueSchema>(self) -> StorageBuilder<D, S> {
+        StorageBuilder {
+            open_backend: self.open_backend,
+            history_check: self.history_check,
+            _schema: PhantomData,
+        }
+    }
+
+    /// Also checks the history of the table when opening, see
+    /// [`VersionedStore::check_history_integrity`] for `sample`.
+    pub fn with_history_check(mut self, sample: Option<usize>) -> Self {
+        self.history_check = Some(sample);
+        self
+    }
+
+    /// Opens the database and starts an empty pending part on top of its latest confirmed
+    /// commit, at the next height.
+    ///
+    /// Fails with [`StorageError::ConsistencyCheckFailure`] if the tables of the confirmed
+    /// commits disagree on the latest one.
+    pub fn open(self) -> Result<FlatStorage<D, T>> {
+        let backend = (self.open_backend)()?;
+
+        let (parent_of_root, height_of_root) = match latest_confirmed(&backend)? {
+            Some((history_number, commit)) => (
+                Some(commit),
+                checked_history_number_to_height(history_number)?
+                    .checked_add(1)
+                    .ok_or(StorageError::HeightOverflow)?,
+            ),
+            None => (None, 0),
+        };
+        let mut cache = VersionedStoreCache::new(parent_of_root, height_of_root);
+
+        if let Some(sample) = self.history_check {
+            VersionedStore::new(&backend, &mut cache)?.check_history_integrity(sample)?;
+        }
+
+        Ok(FlatStorage { backend, cache })
+    }
+}
+
+/// A database with the pending part of the versioned table `T`, opened by [`StorageBuilder`].
+pub struct FlatStorage<D: DatabaseTrait, T: VersionedKeyValueSchema = FlatKeyValue> {
+    backend: D,
+    cache: VersionedStoreCache<T>,
+}
+
+impl<D: DatabaseTrait, T: VersionedKeyValueSchema> FlatStorage<D, T> {
+    pub fn as_manager(&mut self) -> Result<VersionedStore<'_, '_, T>> {
+        VersionedStore::new(&self.backend, &mut self.cache)
+    }
+
+    /// Confirms the pending commits up to the parent of `new_root_commit_id` and commits them to
+    /// the database, and returns them.
+    pub fn confirmed_pending_to_history(
+        &mut self,
+        new_root_commit_id: CommitID,
+    ) -> Result<ConfirmedPath> {
+        let write_schema = D::write_schema();
+        let confirmed_path = confirmed_pending_to_history(
+            &self.backend,
+            &mut self.cache,
+            new_root_commit_id,
+            &write_schema,
+        )?;
+        self.backend.commit(write_schema)?;
+        Ok(confirmed_path)
+    }
+
+    pub fn backend(&self) -> &D {
+        &self.backend
+    }
+
+    /// Closes the storage, dropping its pending part, and returns the database.
+    pub fn into_backend(self) -> D {
+        self.backend
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::BTreeMap, path::Path};
+
+    use super::{FlatStorage, StorageBuilder};
+    use crate::{
+        backends::{DatabaseTrait, InMemoryDatabase, WriteSchemaTrait},
+        errors::InconsistencyReason,
+        middlewares::{CommitID, HistoryNumberSchema},
+        test_utils::{gen_random_commit_id, get_rng_for_test},
+        traits::KeyValueStoreManager,
+        StorageError,
+    };
+
+    fn key(i: u8) -> Box<[u8]> {
+        [i].into()
+    }
+
+    // commits[i] sets the keys 0..=i to i, commits[..3] are confirmed and commits[3] is pending
+    fn write_history<D: DatabaseTrait>(storage: &mut FlatStorage<D>, commits: &[CommitID]) {
+        let mut parent = None;
+        for (i, commit) in commits.iter().enumerate() {
+            let updates: BTreeMap<_, _> = (0..=i as u8)
+                .map(|k| (key(k), Some(key(i as u8))))
+                .collect();
+            storage
+                .as_manager()
+                .unwrap()
+                .add_to_pending_part(parent, *commit, updates)
+                .unwrap();
+            parent = Some(*commit);
+        }
+        storage.confirmed_pending_to_history(commits[3]).unwrap();
+    }
+
+    fn check_reopened<D: DatabaseTrait>(storage: &mut FlatStorage<D>, commits: &[CommitID]) {
+        let mut store = storage.as_manager().unwrap();
+        for (i, commit) in commits[..3].iter().enumerate() {
+            assert_eq!(store.get_height_by_commit_id(commit).unwrap(), Some(i));
+            for k in 0..=i as u8 {
+                assert_eq!(
+                    store.get_versioned_key(commit, &key(k)).unwrap(),
+                    Some(key(i as u8))
+                );
+            }
+        }
+        // the pending part is not persisted
+        assert!(store.get_versioned_store(&commits[3]).is_err());
+
+        // a new commit goes on top of the latest confirmed one
+        let commit = commits[4];
+        store
+            .add_to_pending_part(Some(commits[2]), commit, [(key(0), None)])
+            .unwrap();
+        assert_eq!(store.get_height_by_commit_id(&commit).unwrap(), Some(3));
+        assert_eq!(store.get_versioned_key(&commit, &key(0)).unwrap(), None);
+        assert_eq!(
+            store.get_versioned_key(&commit, &key(1)).unwrap(),
+            Some(key(2))
+        );
+        drop(store);
+        storage.confirmed_pending_to_history(commit).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_in_memory() {
+        let mut rng = get_rng_for_test();
+        let commits: Vec<_> = (0..5).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+        let mut storage = StorageBuilder::in_memory().open().unwrap();
+        write_history(&mut storage, &commits);
+        let backend = storage.into_backend();
+
+        let mut storage = StorageBuilder::with_backend(backend)
+            .with_history_check(None)
+            .open()
+            .unwrap();
+        check_reopened(&mut storage, &commits);
+    }
+
+    #[test]
+    fn test_reopen_rocksdb() {
+        let db_path = "__test_storage_builder";
+        if Path::new(db_path).exists() {
+            std::fs::remove_dir_all(db_path).unwrap();
+        }
+
+        let mut rng = get_rng_for_test();
+        let commits: Vec<_> = (0..5).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+        let mut storage = StorageBuilder::rocksdb(db_path).open().unwrap();
+        write_history(&mut storage, &commits);
+        drop(storage);
+
+        let mut storage = StorageBuilder::rocksdb(db_path)
+            .with_history_check(None)
+            .open()
+            .unwrap();
+        check_reopened(&mut storage, &commits);
+        drop(storage);
+
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+
+    #[test]
+    fn test_open_inconsistent() {
+        let mut rng = get_rng_for_test();
+
+        // a height without its commit in the commit table
+        let mut db = InMemoryDatabase::empty();
+        let write_schema = InMemoryDatabase::write_schema();
+        write_schema.write::<HistoryNumberSchema>((
+            Cow::Owned(1),
+            Some(Cow::Owned(gen_random_commit_id(&mut rng))),
+        ));
+        db.commit(write_schema).unwrap();
+
+        let error = StorageBuilder::with_backend(db).open().err().unwrap();
+        assert_eq!(error.root(), &StorageError::ConsistencyCheckFailure);
+        assert_eq!(
+            error.context().unwrap().reason,
+            Some(InconsistencyReason::MismatchedCommitId)
+        );
+    }
+
+    #[test]
+    fn test_open_mismatched_latest_commit() {
+        let mut rng = get_rng_for_test();
+        let commits: Vec<_> = (0..5).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+        let mut storage = StorageBuilder::in_memory().open().unwrap();
+        write_history(&mut storage, &commits);
+        let mut db = storage.into_backend();
+
+        // the last of the three confirmed heights names another commit, found by the forward
+        // search from the first one
+        let write_schema = InMemoryDatabase::write_schema();
+        write_schema.write::<HistoryNumberSchema>((
+            Cow::Owned(3),
+            Some(Cow::Owned(gen_random_commit_id(&mut rng))),
+        ));
+        db.commit(write_schema).unwrap();
+
+        // checked without `with_history_check`
+        let error = StorageBuilder::with_backend(db).open().err().unwrap();
+        assert_eq!(error.root(), &StorageError::ConsistencyCheckFailure);
+        assert_eq!(
+            error.context().unwrap().reason,
+            Some(InconsistencyReason::MismatchedCommitId)
+        );
+    }
+}
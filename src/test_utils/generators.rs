@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ethereum_types::H256;
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaChaRng,
+};
+
+use crate::middlewares::CommitID;
+
+/// Returns a generator with a fixed seed, so that failures can be reproduced.
+///
+/// ```
+/// use cfx_storage2::test_utils::{gen_random_commit_id, get_rng_for_test};
+///
+/// let commit = gen_random_commit_id(&mut get_rng_for_test());
+/// assert_eq!(commit, gen_random_commit_id(&mut get_rng_for_test()));
+/// ```
+pub fn get_rng_for_test() -> ChaChaRng {
+    ChaChaRng::from_seed([123; 32])
+}
+
+fn gen_opt_value(rng: &mut ChaChaRng) -> Option<u64> {
+    let value_is_none = (rng.next_u64() % 3) == 0;
+    if value_is_none {
+        None
+    } else {
+        Some(rng.next_u64())
+    }
+}
+
+pub(crate) fn select_vec_element<T: Clone>(rng: &mut ChaChaRng, vec: &[T]) -> T {
+    assert!(!vec.is_empty());
+    let num_elements = vec.len();
+    vec[rng.next_u64() as usize % num_elements].clone()
+}
+
+/// Generates the updates of a commit: `num_gen_previous_keys` updates of keys picked from
+/// `previous_keys`, possibly repeated, and `num_gen_new_keys` distinct keys not in `previous_keys`.
+/// A third of the values are deletions. The updated keys are added to `all_keys`.
+///
+/// ```
+/// use std::collections::BTreeSet;
+///
+/// use cfx_storage2::test_utils::{gen_updates, get_rng_for_test};
+///
+/// let mut rng = get_rng_for_test();
+/// let mut all_keys = BTreeSet::new();
+/// let first = gen_updates(&mut rng, &BTreeSet::new(), 10, 0, &mut all_keys);
+/// assert_eq!(first.len(), 10);
+///
+/// let previous_keys = all_keys.clone();
+/// let second = gen_updates(&mut rng, &previous_keys, 5, 5, &mut all_keys);
+/// assert!(second.keys().all(|key| all_keys.contains(key)));
+/// ```
+pub fn gen_updates(
+    rng: &mut ChaChaRng,
+    previous_keys: &BTreeSet<u64>,
+    num_gen_new_keys: usize,
+    num_gen_previous_keys: usize,
+    all_keys: &mut BTreeSet<u64>,
+) -> BTreeMap<u64, Option<u64>> {
+    // gen previous keys (i.e., replace), allow redundant keys and adopt the newest value for the same key
+    let mut updates: BTreeMap<_, _> = if !previous_keys.is_empty() {
+        let previous_keys_vec: Vec<_> = previous_keys.iter().cloned().collect();
+        (0..num_gen_previous_keys)
+            .map(|_| {
+                (
+                    select_vec_element(rng, &previous_keys_vec),
+                    gen_opt_value(rng),
+                )
+            })
+            .collect()
+    } else {
+        Default::default()
+    };
+
+    // gen new keys (i.e., insert), do not allow repeated keys
+    let mut new_keys = BTreeSet::new();
+    while new_keys.len() < num_gen_new_keys {
+        let key = rng.next_u64();
+        if previous_keys.contains(&key) || new_keys.contains(&key) {
+            continue;
+        }
+        new_keys.insert(key);
+        updates.insert(key, gen_opt_value(rng));
+    }
+
+    for key in updates.keys() {
+        all_keys.insert(*key);
+    }
+
+    updates
+}
+
+/// Returns a key not in `previous`.
+pub fn gen_novel_u64(rng: &mut ChaChaRng, previous: &BTreeSet<u64>) -> u64 {
+    for _ in 0..1 << 4 {
+        let novel = rng.next_u64();
+        if !previous.contains(&novel) {
+            return novel;
+        }
+    }
+    panic!()
+}
+
+pub fn gen_random_commit_id(rng: &mut ChaChaRng) -> CommitID {
+    let mut bytes = [0u8; 32];
+    for i in 0..4 {
+        let num = rng.next_u64().to_ne_bytes();
+        bytes[i * 8..(i + 1) * 8].copy_from_slice(&num);
+    }
+    H256::from_slice(&bytes)
+}
@@ -0,0 +1,563 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::{
+    backends::VersionedKVName,
+    errors::Result,
+    middlewares::{table_schema::VersionedKeyValueSchema, CommitID, PendingError},
+    traits::{IsCompleted, KeyValueStoreManager, KeyValueStoreRead, NeedNext},
+    types::KeyLookup,
+    StorageError,
+};
+
+/// A schema from `u64` keys to `u64` values, as produced by [`gen_updates`](super::gen_updates).
+#[derive(Clone, Copy, Debug)]
+pub struct TestSchema;
+
+impl VersionedKeyValueSchema for TestSchema {
+    const NAME: VersionedKVName = VersionedKVName::FlatKV;
+    type Key = u64;
+    type Value = u64;
+}
+
+type MockStore<K, V> = BTreeMap<K, (Option<V>, bool)>;
+
+/// The snapshot of a commit in a [`MockVersionedStore`].
+#[derive(PartialEq, Debug)]
+pub struct MockOneStore<K: Ord, V: Clone> {
+    map: BTreeMap<K, V>,
+    tombstones: BTreeSet<K>,
+}
+
+impl<K: Ord + Clone, V: Clone> MockOneStore<K, V> {
+    fn from_mock_map(map: &BTreeMap<K, (Option<V>, bool)>) -> Self {
+        let inner_map = map
+            .iter()
+            .filter_map(|(k, (opt_v, _))| opt_v.as_ref().map(|v| (k.clone(), v.clone())))
+            .collect();
+        let tombstones = map
+            .iter()
+            .filter(|(_, (opt_v, _))| opt_v.is_none())
+            .map(|(k, _)| k.clone())
+            .collect();
+        MockOneStore {
+            map: inner_map,
+            tombstones,
+        }
+    }
+
+    pub fn get_keys(&self) -> Vec<K> {
+        self.map.keys().cloned().collect()
+    }
+
+    pub fn get_entry(&self, key: &K) -> KeyLookup<V> {
+        if let Some(v) = self.map.get(key) {
+            KeyLookup::Value(v.clone())
+        } else if self.tombstones.contains(key) {
+            KeyLookup::Tombstone
+        } else {
+            KeyLookup::Unknown
+        }
+    }
+}
+
+impl<K: 'static + Ord, V: 'static + Clone> KeyValueStoreRead<K, V> for MockOneStore<K, V> {
+    fn get(&self, key: &K) -> Result<Option<V>> {
+        Ok(self.map.get(key).cloned())
+    }
+}
+
+/// A reference implementation of [`KeyValueStoreManager`] keeping every commit in memory.
+///
+/// The stores of a `VersionedStore` and of a mock given the same operations must give the same answers.
+/// The mock only needs ordered keys: a `VersionedStore<T>` is compared with a
+/// `MockVersionedStore<T::Key, T::Value>`.
+#[derive(Debug)]
+pub struct MockVersionedStore<K, V> {
+    pending: MockTree<K, V>,
+    history: HashMap<CommitID, (Option<CommitID>, MockStore<K, V>)>,
+}
+
+#[derive(Debug)]
+struct MockTree<K, V> {
+    tree: HashMap<CommitID, MockNode<K, V>>,
+    parent_of_root: Option<CommitID>,
+}
+
+#[derive(Debug, Clone)]
+struct MockNode<K, V> {
+    commit_id: CommitID,
+    parent: Option<CommitID>,
+    children: HashSet<CommitID>,
+    store: MockStore<K, V>,
+}
+
+impl<K: 'static + Ord + Clone, V: 'static + Clone> KeyValueStoreManager<K, V, CommitID>
+    for MockVersionedStore<K, V>
+{
+    type Store = MockOneStore<K, V>;
+
+    fn get_versioned_store(&self, commit: &CommitID) -> Result<Self::Store> {
+        if let Some(pending_res) = self.pending.tree.get(commit) {
+            Ok(MockOneStore::from_mock_map(&pending_res.store))
+        } else if let Some((_, history_res)) = self.history.get(commit) {
+            Ok(MockOneStore::from_mock_map(history_res))
+        } else {
+            Err(StorageError::CommitIDNotFound)
+        }
+    }
+
+    fn iter_historical_changes(
+        &self,
+        mut accept: impl FnMut(&CommitID, &K, Option<&V>) -> NeedNext,
+        commit_id: &CommitID,
+        key: &K,
+    ) -> Result<IsCompleted> {
+        let mut current_node = self.pending.tree.get(commit_id);
+        while let Some(node) = current_node {
+            if let Some((value, true)) = node.store.get(key) {
+                if !accept(&node.commit_id, key, value.as_ref()) {
+                    return Ok(false);
+                }
+            }
+            current_node = node.parent.map(|p| self.pending.tree.get(&p).unwrap());
+        }
+
+        let history_commit_id = if self.pending.tree.contains_key(commit_id) {
+            if let Some(parent_of_pending) = self.pending.parent_of_root {
+                parent_of_pending
+            } else {
+                assert!(self.history.is_empty());
+                return Ok(true);
+            }
+        } else {
+            *commit_id
+        };
+
+        if !self.history.contains_key(&history_commit_id) {
+            return Err(StorageError::CommitIDNotFound);
+        }
+
+        let mut current_cid = Some(history_commit_id);
+        while let Some(cid) = current_cid {
+            let (parent_cid, store) = self.history.get(&cid).unwrap();
+            if let Some((value, true)) = store.get(key) {
+                if !accept(&cid, key, value.as_ref()) {
+                    return Ok(false);
+                }
+            }
+            current_cid = *parent_cid;
+        }
+
+        Ok(true)
+    }
+
+    fn discard(&mut self, commit: CommitID) -> Result<()> {
+        if self.history.contains_key(&commit) {
+            return Ok(());
+        }
+
+        if self.pending.tree.contains_key(&commit) {
+            if let Some(parent) = self.pending.tree.get(&commit).unwrap().parent {
+                let mut to_remove = VecDeque::new();
+
+                assert!(self
+                    .pending
+                    .tree
+                    .get(&parent)
+                    .unwrap()
+                    .children
+                    .contains(&commit));
+                for child in self.pending.tree.get(&parent).unwrap().children.iter() {
+                    if *child != commit {
+                        to_remove.push_back(*child);
+                    }
+                }
+
+                while !to_remove.is_empty() {
+                    let remove_this = to_remove.pop_front().unwrap();
+                    let remove_this_node = self.pending.tree.remove(&remove_this).unwrap();
+                    for child in remove_this_node.children.iter() {
+                        to_remove.push_back(*child);
+                    }
+                }
+
+                self.pending.tree.get_mut(&parent).unwrap().children = HashSet::from([commit]);
+            }
+
+            Ok(())
+        } else {
+            Err(StorageError::from(PendingError::CommitIDNotFound(commit)))
+        }
+    }
+
+    fn get_versioned_key(&self, commit: &CommitID, key: &K) -> Result<Option<V>> {
+        self.get_versioned_store(commit)?.get(key)
+    }
+}
+
+fn update_last_store_to_store<K: Ord + Clone, V: Clone>(
+    last_store: &MockStore<K, V>,
+    updates: BTreeMap<K, Option<V>>,
+) -> MockStore<K, V> {
+    let mut store: BTreeMap<_, _> = last_store
+        .iter()
+        .map(|(k, (opt_v, _))| (k.clone(), (opt_v.clone(), false)))
+        .collect();
+    for (k, opt_v) in updates.into_iter() {
+        store.insert(k, (opt_v, true));
+    }
+    store
+}
+
+impl<K: 'static + Ord + Clone, V: 'static + Clone> MockVersionedStore<K, V> {
+    /// Creates a store without any commit.
+    pub fn new() -> Self {
+        Self::new_unchecked(None, Default::default())
+    }
+
+    /// Creates a store whose history is the chain of `history`, from the earliest commit.
+    /// The pending part starts empty, with the last commit as its parent.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    ///
+    /// use cfx_storage2::{
+    ///     test_utils::{gen_random_commit_id, get_rng_for_test, MockVersionedStore},
+    ///     traits::{KeyValueStoreManager, KeyValueStoreRead},
+    /// };
+    ///
+    /// let mut rng = get_rng_for_test();
+    /// let (confirmed, pending) = (gen_random_commit_id(&mut rng), gen_random_commit_id(&mut rng));
+    ///
+    /// let mut store =
+    ///     MockVersionedStore::<u64, u64>::from_history([(confirmed, BTreeMap::from([(1, Some(10))]))]);
+    /// store
+    ///     .add_to_pending_part(Some(confirmed), pending, BTreeMap::from([(1, None), (2, Some(20))]))
+    ///     .unwrap();
+    ///
+    /// let snapshot = store.get_versioned_store(&pending).unwrap();
+    /// assert_eq!(snapshot.get(&1).unwrap(), None);
+    /// assert_eq!(snapshot.get(&2).unwrap(), Some(20));
+    /// assert_eq!(store.get_versioned_key(&confirmed, &1).unwrap(), Some(10));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a commit appears twice.
+    pub fn from_history(
+        history: impl IntoIterator<Item = (CommitID, BTreeMap<K, Option<V>>)>,
+    ) -> Self {
+        let mut history_stores: HashMap<_, _> = Default::default();
+        let mut last_store = Default::default();
+        let mut last_commit_id = None;
+        for (commit_id, updates) in history {
+            let store = update_last_store_to_store(&last_store, updates);
+            let duplicated = history_stores
+                .insert(commit_id, (last_commit_id, store.clone()))
+                .is_some();
+            assert!(
+                !duplicated,
+                "duplicated commit {commit_id:?} in the history"
+            );
+            last_store = store;
+            last_commit_id = Some(commit_id);
+        }
+        Self::new_unchecked(last_commit_id, history_stores)
+    }
+
+    pub fn check_consistency(&self) {
+        if let Some(parent) = self.get_parent_of_root() {
+            assert!(self.history.contains_key(&parent));
+            let mut num_history = 1;
+            let mut commit_id = parent;
+            while let (Some(parent_commit_id), _) = self.history.get(&commit_id).unwrap() {
+                num_history += 1;
+                commit_id = *parent_commit_id;
+            }
+            assert_eq!(num_history, self.history.len());
+
+            let root = self.get_pending_root();
+            if let Some(root) = root.last() {
+                assert!(self.pending.tree.get(root).unwrap().parent.is_none());
+            }
+
+            assert_eq!(
+                self.get_history().len() + self.get_pending().len(),
+                self.get_commit_ids().len()
+            )
+        } else {
+            assert!(self.history.is_empty());
+        }
+    }
+
+    fn new_unchecked(
+        parent_of_pending: Option<CommitID>,
+        history: HashMap<CommitID, (Option<CommitID>, MockStore<K, V>)>,
+    ) -> Self {
+        let mock_versioned_store = Self {
+            pending: MockTree {
+                tree: Default::default(),
+                parent_of_root: parent_of_pending,
+            },
+            history,
+        };
+        mock_versioned_store.check_consistency();
+        mock_versioned_store
+    }
+
+    pub fn get_pending_root(&self) -> Vec<CommitID> {
+        let pending_root: Vec<_> = self
+            .pending
+            .tree
+            .iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(cid, _)| *cid)
+            .collect();
+        if self.pending.tree.is_empty() {
+            assert_eq!(pending_root.len(), 0);
+        } else {
+            assert_eq!(pending_root.len(), 1);
+        }
+        pending_root
+    }
+
+    pub fn get_pending_non_root(&self) -> Vec<CommitID> {
+        let mut pending_non_root: Vec<_> = self
+            .pending
+            .tree
+            .iter()
+            .filter(|(_, node)| node.parent.is_some())
+            .map(|(cid, _)| *cid)
+            .collect();
+        if self.pending.tree.is_empty() {
+            assert_eq!(pending_non_root.len(), 0);
+        } else {
+            assert_eq!(pending_non_root.len() + 1, self.pending.tree.len());
+        }
+        pending_non_root.sort();
+        pending_non_root
+    }
+
+    pub fn get_pending(&self) -> Vec<CommitID> {
+        let mut pending: Vec<_> = self.pending.tree.keys().cloned().collect();
+        pending.sort();
+        pending
+    }
+
+    pub fn num_pending(&self) -> usize {
+        self.pending.tree.len()
+    }
+
+    pub fn num_history(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn get_parent_of_root(&self) -> Option<CommitID> {
+        self.pending.parent_of_root
+    }
+
+    pub fn get_history(&self) -> Vec<CommitID> {
+        let mut history: Vec<_> = self.history.keys().cloned().collect();
+        history.sort();
+        history
+    }
+
+    pub fn get_history_but_parent_of_root(&self) -> Vec<CommitID> {
+        let mut history: HashSet<_> = self.history.keys().cloned().collect();
+        if let Some(parent_of_root) = self.pending.parent_of_root {
+            history.remove(&parent_of_root);
+        }
+        let mut history: Vec<_> = history.into_iter().collect();
+        history.sort();
+        history
+    }
+
+    /// Returns the children of the pending `commit`, sorted.
+    pub fn children_of(&self, commit: &CommitID) -> Result<Vec<CommitID>> {
+        let node = self
+            .pending
+            .tree
+            .get(commit)
+            .ok_or(PendingError::CommitIDNotFound(*commit))?;
+        let mut children: Vec<_> = node.children.iter().copied().collect();
+        children.sort();
+        Ok(children)
+    }
+
+    /// Returns the pending commits without children, sorted.
+    pub fn leaves(&self) -> Vec<CommitID> {
+        let mut leaves: Vec<_> = self
+            .pending
+            .tree
+            .values()
+            .filter(|node| node.children.is_empty())
+            .map(|node| node.commit_id)
+            .collect();
+        leaves.sort();
+        leaves
+    }
+
+    /// Returns the pending `commit` and its ancestors up to the pending root, `commit` first.
+    pub fn path_to_root(&self, commit: &CommitID) -> Result<Vec<CommitID>> {
+        let mut node = self
+            .pending
+            .tree
+            .get(commit)
+            .ok_or(PendingError::CommitIDNotFound(*commit))?;
+        let mut path = vec![*commit];
+        while let Some(parent) = node.parent {
+            path.push(parent);
+            node = self.pending.tree.get(&parent).unwrap();
+        }
+        Ok(path)
+    }
+
+    pub fn get_commit_ids(&self) -> BTreeSet<CommitID> {
+        self.history
+            .keys()
+            .cloned()
+            .chain(self.pending.tree.keys().cloned())
+            .collect()
+    }
+
+    /// Returns the keys modified up to `commit`, deleted keys included.
+    pub fn get_keys_on_path(&self, commit: &CommitID) -> Vec<K> {
+        let mut keys: Vec<_> = if let Some(pending_res) = self.pending.tree.get(commit) {
+            pending_res.store.keys().cloned().collect()
+        } else if let Some((_, history_res)) = self.history.get(commit) {
+            history_res.keys().cloned().collect()
+        } else {
+            Vec::new()
+        };
+        keys.sort();
+        keys
+    }
+
+    pub fn add_to_pending_part(
+        &mut self,
+        parent_commit: Option<CommitID>,
+        commit: CommitID,
+        updates: impl IntoIterator<Item = (K, Option<V>)>,
+    ) -> Result<()> {
+        // the last change of a key wins
+        let updates: BTreeMap<_, _> = updates.into_iter().collect();
+        if self.history.contains_key(&commit) {
+            return Err(StorageError::CommitIdAlreadyExistsInHistory);
+        }
+
+        if parent_commit == self.pending.parent_of_root {
+            if !self.pending.tree.is_empty() {
+                return Err(StorageError::PendingError(
+                    PendingError::MultipleRootsNotAllowed,
+                ));
+            }
+
+            let default_store = Default::default();
+            let last_store = if let Some(parent_commit_id) = parent_commit {
+                let (_, history_store) = self.history.get(&parent_commit_id).unwrap();
+                history_store
+            } else {
+                &default_store
+            };
+            let store = update_last_store_to_store(last_store, updates);
+
+            let root = MockNode {
+                commit_id: commit,
+                parent: None,
+                children: Default::default(),
+                store,
+            };
+            self.pending.tree.insert(commit, root);
+
+            Ok(())
+        } else if let Some(parent_commit_id) = parent_commit {
+            if !self.pending.tree.contains_key(&parent_commit_id) {
+                return Err(StorageError::from(PendingError::CommitIDNotFound(
+                    parent_commit_id,
+                )));
+            }
+            if self.pending.tree.contains_key(&commit) {
+                return Err(StorageError::from(PendingError::CommitIdAlreadyExists(
+                    commit,
+                )));
+            }
+
+            let last_store = &self.pending.tree.get(&parent_commit_id).unwrap().store;
+            let store = update_last_store_to_store(last_store, updates);
+
+            let node = MockNode {
+                commit_id: commit,
+                parent: parent_commit,
+                children: Default::default(),
+                store,
+            };
+            self.pending.tree.insert(commit, node);
+            self.pending
+                .tree
+                .get_mut(&parent_commit_id)
+                .unwrap()
+                .children
+                .insert(commit);
+
+            Ok(())
+        } else {
+            Err(StorageError::PendingError(
+                PendingError::NonRootNodeShouldHaveParent,
+            ))
+        }
+    }
+
+    pub fn confirmed_pending_to_history(&mut self, new_root_commit_id: CommitID) -> Result<()> {
+        if !self.pending.tree.contains_key(&new_root_commit_id) {
+            return Err(StorageError::from(PendingError::CommitIDNotFound(
+                new_root_commit_id,
+            )));
+        }
+
+        let mut parent_cid = self
+            .pending
+            .tree
+            .get(&new_root_commit_id)
+            .cloned()
+            .unwrap()
+            .parent;
+        let mut commit_id = new_root_commit_id;
+        while let Some(parent_commit_id) = parent_cid {
+            self.discard(commit_id).unwrap();
+
+            let parent_node = self.pending.tree.remove(&parent_commit_id).unwrap();
+
+            let grandparent = if let Some(grandparent) = parent_node.parent {
+                Some(grandparent)
+            } else {
+                self.pending.parent_of_root
+            };
+            self.history
+                .insert(parent_commit_id, (grandparent, parent_node.store));
+
+            commit_id = parent_commit_id;
+            parent_cid = parent_node.parent;
+        }
+
+        let old_root_commit_id = commit_id;
+        if old_root_commit_id != new_root_commit_id {
+            self.pending.parent_of_root =
+                self.pending.tree.get(&new_root_commit_id).unwrap().parent;
+            self.pending
+                .tree
+                .get_mut(&new_root_commit_id)
+                .unwrap()
+                .parent = None;
+        }
+
+        self.check_consistency();
+
+        Ok(())
+    }
+}
+
+impl<K: 'static + Ord + Clone, V: 'static + Clone> Default for MockVersionedStore<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,23 @@
+//! Generators, a mock versioned store and backend helpers for tests, enabled by the `test-utils` feature.
+
+mod generators;
+mod mock_versioned_store;
+
+pub(crate) use generators::select_vec_element;
+pub use generators::{gen_novel_u64, gen_random_commit_id, gen_updates, get_rng_for_test};
+pub use mock_versioned_store::{MockOneStore, MockVersionedStore, TestSchema};
+
+use crate::{
+    backends::{impls::kvdb_rocksdb::open_database, TableName},
+    errors::Result,
+};
+
+/// Opens a RocksDB database with a column for each table at `db_path`, removing what was there.
+pub fn empty_rocksdb(db_path: &str) -> Result<kvdb_rocksdb::Database> {
+    if std::path::Path::new(db_path).exists() {
+        std::fs::remove_dir_all(db_path).unwrap();
+    }
+    std::fs::create_dir_all(db_path).unwrap();
+
+    open_database(TableName::max_index() + 1, db_path)
+}
@@ -39,11 +39,47 @@ where
         key: &K,
     ) -> Result<IsCompleted>;
 
+    /// As [`Self::iter_historical_changes`], but passes over the first `skip` changes and stops
+    /// before a change past the first `max_results` accepted ones. Returns `false` if stopped
+    /// by `accept` or by `max_results` with changes left.
+    #[allow(clippy::type_complexity)]
+    fn iter_historical_changes_bounded(
+        &self,
+        mut accept: impl FnMut(&C, &K, Option<&V>) -> NeedNext,
+        commit_id: &C,
+        key: &K,
+        max_results: usize,
+        mut skip: usize,
+    ) -> Result<IsCompleted> {
+        let mut remaining = max_results;
+        self.iter_historical_changes(
+            |commit, key, value| {
+                if skip > 0 {
+                    skip -= 1;
+                    return true;
+                }
+                if remaining == 0 {
+                    return false;
+                }
+                remaining -= 1;
+                accept(commit, key, value)
+            },
+            commit_id,
+            key,
+        )
+    }
+
     /// make commit the unique child of its parent
     /// do nothing if commit is in history or if commit is pending root
     fn discard(&mut self, commit: C) -> Result<()>;
 
     fn get_versioned_key(&self, commit: &C, key: &K) -> Result<Option<V>>;
+
+    /// Whether `key` has a value after the commit of given id, as
+    /// [`Self::get_versioned_key`] would return `Some`, without reading the value if possible.
+    fn contains_versioned_key(&self, commit: &C, key: &K) -> Result<bool> {
+        Ok(self.get_versioned_key(commit, key)?.is_some())
+    }
 }
 
 pub trait KeyValueStoreBulksTrait<K, V, C> {
@@ -58,6 +94,21 @@ pub trait KeyValueStoreBulksTrait<K, V, C> {
     /// Get with the given commit version and key.
     fn get_versioned_key(&self, commit: &C, key: &K) -> Result<Option<V>>;
 
+    /// Whether [`Self::get_versioned_key`] would return `Some`.
+    fn contains_versioned_key(&self, commit: &C, key: &K) -> Result<bool> {
+        Ok(self.get_versioned_key(commit, key)?.is_some())
+    }
+
+    /// Get the key-values committed with the given commit version, in the order of the keys.
+    /// Deletions are not stored, so they are not returned.
+    ///
+    /// The smallest key of a version depends on the encoding of the keys, so the rows are read
+    /// from the start of the table up to the end of the bulk.
+    fn get_bulk(&self, commit: &C) -> Result<Vec<(K, V)>>;
+
+    /// Delete the key-values committed with the given commit version.
+    fn delete_bulk(&self, commit: &C, write_schema: &impl WriteSchemaTrait) -> Result<()>;
+
     /// Commit changes for garbage collection only
     fn gc_commit(
         &self,
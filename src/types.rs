@@ -40,3 +40,31 @@ impl<T: Clone> ValueEntry<T> {
         }
     }
 }
+
+/// Result of a read that tells a deleted key from a key that was never written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyLookup<T> {
+    Value(T),
+    /// The latest version of the key is a deletion.
+    Tombstone,
+    /// The key has no version at all.
+    Unknown,
+}
+
+impl<T> KeyLookup<T> {
+    pub fn into_option(self) -> Option<T> {
+        match self {
+            KeyLookup::Value(v) => Some(v),
+            KeyLookup::Tombstone | KeyLookup::Unknown => None,
+        }
+    }
+}
+
+impl<T: Clone> From<&ValueEntry<T>> for KeyLookup<T> {
+    fn from(value: &ValueEntry<T>) -> Self {
+        match value {
+            ValueEntry::Value(v) => KeyLookup::Value(v.clone()),
+            ValueEntry::Deleted => KeyLookup::Tombstone,
+        }
+    }
+}
@@ -1,2 +1,6 @@
+// The allocations are counted by a global allocator, which would replace the allocator of every
+// test of the crate, so it is only built with the `count-allocations` feature.
+#[cfg(all(test, feature = "count-allocations"))]
+pub mod allocations;
 pub mod hash;
 mod macros;
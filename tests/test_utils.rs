@@ -0,0 +1,50 @@
+//! Builds against the public surface of the `test-utils` feature only, as a downstream crate would.
+
+use std::collections::BTreeSet;
+
+use cfx_storage2::{
+    test_utils::{
+        gen_novel_u64, gen_random_commit_id, gen_updates, get_rng_for_test, MockVersionedStore,
+    },
+    traits::{KeyValueStoreManager, KeyValueStoreRead},
+    types::KeyLookup,
+};
+
+#[test]
+fn test_mock_versioned_store() {
+    let mut rng = get_rng_for_test();
+    let commits: Vec<_> = (0..3).map(|_| gen_random_commit_id(&mut rng)).collect();
+
+    let mut all_keys = BTreeSet::new();
+    let history_updates = gen_updates(&mut rng, &BTreeSet::new(), 10, 0, &mut all_keys);
+    let mut store =
+        MockVersionedStore::<u64, u64>::from_history([(commits[0], history_updates.clone())]);
+    assert_eq!(store.get_parent_of_root(), Some(commits[0]));
+
+    let previous_keys = all_keys.clone();
+    let pending_updates = gen_updates(&mut rng, &previous_keys, 10, 10, &mut all_keys);
+    store
+        .add_to_pending_part(Some(commits[0]), commits[1], pending_updates.clone())
+        .unwrap();
+    assert_eq!(store.num_history(), 1);
+    assert_eq!(store.num_pending(), 1);
+
+    let snapshot = store.get_versioned_store(&commits[1]).unwrap();
+    for key in &all_keys {
+        let expected = pending_updates
+            .get(key)
+            .or_else(|| history_updates.get(key))
+            .unwrap();
+        assert_eq!(snapshot.get(key).unwrap(), *expected);
+        let expected_entry = match expected {
+            Some(value) => KeyLookup::Value(*value),
+            None => KeyLookup::Tombstone,
+        };
+        assert_eq!(snapshot.get_entry(key), expected_entry);
+    }
+    let novel_key = gen_novel_u64(&mut rng, &all_keys);
+    assert_eq!(snapshot.get_entry(&novel_key), KeyLookup::Unknown);
+
+    store.confirmed_pending_to_history(commits[1]).unwrap();
+    assert!(store.get_versioned_store(&commits[2]).is_err());
+}